@@ -1,6 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt;
-use std::num;
+use std::io;
+use std::path::PathBuf;
 use std::result;
 use std::str;
 use std::sync::{Arc, Condvar, Mutex, RwLock};
@@ -8,26 +9,100 @@ use std::time;
 use std::thread;
 
 use actuator_controller::*;
+use audit::{AuditEntry, AuditLogHandle};
+use condition::ConditionSourcesHandle;
+use events::{EventBroadcasterHandle, EventSource, StateEvent};
+use follow::Follow;
+use history::{HistoryEntry, HistoryHandle};
+use metadata;
+use metadata::Metadata;
+use metrics::{ActuatorMetrics, MetricsHandle};
 use schedule;
+use schedule::DefaultSchedule;
 use time::*;
 use time_slot::*;
+use transition_log::TransitionLog;
 use utils::*;
 
 use rpc::InvalArgError as IAE;
+use rpc::Error;
 use rpc::Error::*;
 pub type Result<T> = result::Result<T, ::rpc::Error>;
 
+// Tolerance when checking that a FloatValue is on its step, relative to the step, so that e.g.
+// 15 + 3 * 0.1 is accepted.
+const STEP_EPSILON: f64 = 1e-6;
+
+// Changes kept for the clients following all the actuators, see StateChanges.
+const MAX_STATE_CHANGES: usize = 1000;
+
+// Days for which the time spent in each state is kept, see StateDurations.
+const MAX_STATE_DURATION_DAYS: i64 = 366;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub enum ActuatorType {
     Toggle,
-    FloatValue { min: f64, max: f64 },
+    // If step is set, only min + k * step values are accepted (see ActuatorInfo::quantize).
+    FloatValue {
+        min: f64,
+        max: f64,
+        #[serde(default)]
+        step: Option<f64>,
+    },
+    IntValue { min: i64, max: i64 },
+    // Named levels, e.g. off/low/high.
+    Enum { values: Vec<String> },
+    // RGB color, e.g. for LED strips.
+    Color,
+}
+
+impl ActuatorType {
+    // Whether state is of this type (and within its bounds).
+    pub fn accepts(&self, state: &ActuatorState) -> bool {
+        match *self {
+            ActuatorType::Toggle => match state {
+                &ActuatorState::Toggle(_) => true,
+                _ => false,
+            },
+            ActuatorType::FloatValue { min, max, step } => match state {
+                // NaN never compares within bounds, but make it explicit.
+                &ActuatorState::FloatValue(value) =>
+                    value.is_finite() && min <= value && value <= max && match step {
+                        Some(step) => {
+                            let steps = (value - min) / step;
+                            (steps - steps.round()).abs() < STEP_EPSILON
+                        },
+                        None => true,
+                    },
+                _ => false
+            },
+            ActuatorType::IntValue { min, max } => match state {
+                &ActuatorState::IntValue(value) => min <= value && value <= max,
+                _ => false
+            },
+            ActuatorType::Enum { ref values } => match state {
+                &ActuatorState::Enum(ref name) => values.contains(name),
+                _ => false
+            },
+            ActuatorType::Color => match state {
+                &ActuatorState::Color { .. } => true,
+                _ => false
+            },
+        }
+    }
 }
 
 impl fmt::Display for ActuatorType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ActuatorType::Toggle => write!(f, "Toggle"),
-            ActuatorType::FloatValue { min, max } => write!(f, "Float [{}, {}]", min, max),
+            ActuatorType::FloatValue { min, max, step: Some(step) } =>
+                write!(f, "Float [{}, {}] step {}", min, max, step),
+            ActuatorType::FloatValue { min, max, step: None } =>
+                write!(f, "Float [{}, {}]", min, max),
+            ActuatorType::IntValue { min, max } => write!(f, "Int [{}, {}]", min, max),
+            ActuatorType::Enum { values } => write!(f, "Enum [{}]", values.join(", ")),
+            ActuatorType::Color => write!(f, "Color"),
         }
     }
 }
@@ -36,6 +111,9 @@ impl fmt::Display for ActuatorType {
 pub enum ActuatorState {
     Toggle(bool),
     FloatValue(f64),
+    IntValue(i64),
+    Enum(String),
+    Color { r: u8, g: u8, b: u8 },
 }
 
 impl fmt::Display for ActuatorState {
@@ -43,18 +121,82 @@ impl fmt::Display for ActuatorState {
         match self {
             ActuatorState::Toggle(value) => write!(f, "{}", if *value { "On" } else { "Off" }),
             ActuatorState::FloatValue(value) => write!(f, "{}", value),
+            ActuatorState::IntValue(value) => write!(f, "{}", value),
+            ActuatorState::Enum(name) => write!(f, "{}", name),
+            ActuatorState::Color { r, g, b } => write!(f, "#{:02X}{:02X}{:02X}", r, g, b),
+        }
+    }
+}
+
+impl ActuatorState {
+    // Same as to_string(), followed by unit for FloatValue and IntValue states, e.g. 21.5 °C (see
+    // ActuatorInfo::unit).
+    pub fn to_string_with_unit(&self, unit: Option<&str>) -> String {
+        match (self, unit) {
+            (&ActuatorState::FloatValue(_), Some(unit)) |
+            (&ActuatorState::IntValue(_), Some(unit)) => format!("{} {}", self, unit),
+            _ => self.to_string(),
+        }
+    }
+
+    // False for NaN and infinite FloatValues, which no actuator accepts, whatever its bounds.
+    pub fn is_finite(&self) -> bool {
+        match *self {
+            ActuatorState::FloatValue(value) => value.is_finite(),
+            _ => true,
+        }
+    }
+
+    // Parse s as a state of type actuator_type, which resolves the ambiguities of from_str(): plain
+    // integers are accepted for IntValue, and Enum names are matched case-insensitively.
+    pub fn parse_as(s: &str, actuator_type: &ActuatorType) -> result::Result<Self, String> {
+        match *actuator_type {
+            ActuatorType::IntValue { .. } if !s.starts_with("i:") =>
+                s.parse::<i64>().map(ActuatorState::IntValue).map_err(|e| e.to_string()),
+            ActuatorType::Enum { ref values } => {
+                let name = if s.starts_with("e:") { &s[2..] } else { s };
+                values.iter().find(|v| v.to_lowercase() == name.to_lowercase())
+                    .map(|v| ActuatorState::Enum(v.clone()))
+                    .ok_or(format!("expected one of: {}", values.join(", ")))
+            },
+            _ => s.parse(),
         }
     }
 }
 
+// A plain number is parsed as a FloatValue, integers must be prefixed with "i:" (e.g. "i:3") and
+// Enum names with "e:" (e.g. "e:low"). Colors are specified as #RRGGBB. See also
+// ActuatorState::parse_as(). Non-finite numbers (NaN, inf) are rejected, they are never valid
+// states.
 impl str::FromStr for ActuatorState {
-    type Err = num::ParseFloatError;
+    type Err = String;
 
     fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        if s.starts_with("i:") {
+            return i64::from_str(&s[2..]).map(|i| ActuatorState::IntValue(i))
+                .map_err(|e| e.to_string())
+        }
+        if s.starts_with("e:") {
+            return Ok(ActuatorState::Enum(s[2..].to_string()))
+        }
+        if s.starts_with('#') {
+            let component = |i: usize| s.get(i..i + 2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            return match (s.len(), component(1), component(3), component(5)) {
+                (7, Some(r), Some(g), Some(b)) => Ok(ActuatorState::Color { r, g, b }),
+                _ => Err("invalid color, expected #RRGGBB".to_string()),
+            }
+        }
+
         match s.to_lowercase().as_ref() {
             "on" => Ok(ActuatorState::Toggle(true)),
             "off" => Ok(ActuatorState::Toggle(false)),
-            _ => f64::from_str(s).map(|f| ActuatorState::FloatValue(f))
+            _ => match f64::from_str(s) {
+                Ok(f) if f.is_finite() => Ok(ActuatorState::FloatValue(f)),
+                Ok(_) => Err(format!("{} is not a finite number", s)),
+                Err(e) => Err(e.to_string()),
+            }
         }
     }
 }
@@ -63,14 +205,305 @@ impl str::FromStr for ActuatorState {
 pub struct ActuatorInfo {
     pub name: String,
     pub actuator_type: ActuatorType,
+    // If set, FloatValue/IntValue states outside of [min, max] are clamped instead of rejected.
+    pub clamp: bool,
+    // If set, FloatValue states are snapped to the nearest step instead of rejected if not on one.
+    #[serde(default)]
+    pub quantize: bool,
+    // A disabled actuator ignores its schedule and never touches its controller. Only modify it
+    // via Actuator::set_enabled().
+    pub enabled: bool,
+    // When the days of the actuator start: its timeslots, default schedule and the dates it
+    // reports (e.g. in its schedule) are all relative to it. See DayStart.
+    #[serde(default)]
+    pub day_start: DayStart,
+    // A simulated actuator goes through its schedule as usual (logs, history, events...), but
+    // never writes to its controller. Only modify it via Actuator::set_simulate().
+    #[serde(default)]
+    pub simulate: bool,
+    // What FloatValue/IntValue states are measured in (e.g. °C), only used to display them.
+    #[serde(default)]
+    pub unit: Option<String>,
+    // If set, adding timeslots beyond this number fails with LimitExceeded, to keep a runaway
+    // client from exhausting the resources of the server.
+    #[serde(default)]
+    pub max_timeslots: Option<u32>,
 }
 
 impl ValidCheck for ActuatorInfo {
     fn valid(&self) -> bool {
         match self.actuator_type {
             ActuatorType::Toggle => true,
-            ActuatorType::FloatValue { min, max } => min < max,
+            // NaN bounds would make any comparison false.
+            ActuatorType::FloatValue { min, max, step } =>
+                min.is_finite() && max.is_finite() && min < max && match step {
+                    Some(step) => step.is_finite() && step > 0.0 && step <= max - min,
+                    None => true,
+                },
+            ActuatorType::IntValue { min, max } => min < max,
+            // Names are matched case-insensitively, so they must differ regardless of case.
+            ActuatorType::Enum { ref values } => {
+                let lowercase: BTreeSet<String> = values.iter().map(|v| v.to_lowercase()).collect();
+                !values.is_empty() && lowercase.len() == values.len()
+            },
+            ActuatorType::Color => true,
+        }
+    }
+}
+
+// What the actuator thread does once it gave up applying a scheduled state (see FailurePolicy).
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureFallback {
+    // Leave the actuator as it is, in an unknown state, until the next transition.
+    Hold,
+    // Apply the default state instead (once), e.g. to stop a pump.
+    RevertToDefault,
+}
+
+impl Default for FailureFallback {
+    fn default() -> Self {
+        FailureFallback::Hold
+    }
+}
+
+// How the actuator thread reacts to its controller failing to apply a scheduled state: try again
+// up to retries times, retry_delay_sec apart (unless the state changes in the meantime), then give
+// up with an error and apply the fallback. Manual states are not retried, the error is reported to
+// the client instead.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+pub struct FailurePolicy {
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default = "FailurePolicy::default_retry_delay_sec")]
+    pub retry_delay_sec: u32,
+    #[serde(default)]
+    pub fallback: FailureFallback,
+}
+
+impl FailurePolicy {
+    fn default_retry_delay_sec() -> u32 {
+        10
+    }
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        FailurePolicy {
+            retries: 0,
+            retry_delay_sec: Self::default_retry_delay_sec(),
+            fallback: FailureFallback::default(),
+        }
+    }
+}
+
+// E.g. "retry 3 times every 10s, then revert to default", or "hold".
+impl fmt::Display for FailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.retries > 0 {
+            write!(f, "retry {} times every {}s, then ", self.retries, self.retry_delay_sec)?;
+        }
+        match self.fallback {
+            FailureFallback::Hold => write!(f, "hold"),
+            FailureFallback::RevertToDefault => write!(f, "revert to default"),
+        }
+    }
+}
+
+// Suspends the schedule of an actuator until the start of the day until: the actuator stays in
+// state (its default state if None) in the meantime, whatever its timeslots say. See
+// Actuator::set_vacation().
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Vacation {
+    pub until: Date,
+    pub state: Option<ActuatorState>,
+}
+
+// A state applied outside of the schedule (manually, or by a Trigger), which holds until the
+// schedule takes over again: at its next transition, or when the active timeslot is modified.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ActiveOverrideInfo {
+    pub state: ActuatorState,
+    // What applied it, e.g. "manual" or "trigger 0".
+    pub source: String,
+    pub since: DateTime,
+    // The next transition of the schedule, None if the actuator is disabled (the override then
+    // holds until it is enabled again).
+    pub until: Option<DateTime>,
+}
+
+// The state last applied to the actuator (None if none was applied yet). seq is incremented every
+// time the state changes, so that clients can wait for the next change.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CurrentState {
+    pub seq: u64,
+    pub state: Option<ActuatorState>,
+}
+
+// Time spent in each state (by its string representation), for each day (with the default
+// DayStart, as for DateTime), since the actuator was created. Only the states actually applied are
+// counted, and time keeps being counted while the actuator is disabled.
+#[derive(Clone, Default)]
+struct StateDurations {
+    // The state the actuator is in, and since when (not counted in days yet).
+    current: Option<(String, DateTime)>,
+    days: BTreeMap<(Date, String), Duration>,
+}
+
+impl StateDurations {
+    fn state_applied(&mut self, now: &DateTime, state: &ActuatorState) {
+        let state = state.to_string();
+        if let Some((ref current, _)) = self.current {
+            if *current == state {
+                return
+            }
+        }
+
+        self.count_current(now);
+        self.current = Some((state, now.clone()));
+
+        let oldest = now.date - MAX_STATE_DURATION_DAYS;
+        self.days = self.days.split_off(&(oldest, String::new()));
+    }
+
+    // Add the time spent in the current state up to now to the days it spans, and clear it.
+    fn count_current(&mut self, now: &DateTime) {
+        if let Some((state, mut from)) = self.current.take() {
+            while from < *now {
+                let next_day = DateTime { date: from.date + 1, time: DayStart::default().first() };
+                let end = if next_day < *now { next_day } else { now.clone() };
+
+                *self.days.entry((from.date, state.clone())).or_insert(Duration::minutes(0)) +=
+                    Duration::minutes(end.minutes_since(&from) as i32);
+                from = end;
+            }
+        }
+    }
+
+    fn totals(&self, since: Date, now: &DateTime) -> BTreeMap<String, Duration> {
+        let mut durations = self.clone();
+        durations.count_current(now);
+
+        // Include the current state even if it was applied less than a minute ago.
+        let mut totals = BTreeMap::new();
+        if let Some((ref state, _)) = self.current {
+            if since <= now.date {
+                totals.insert(state.clone(), Duration::minutes(0));
+            }
+        }
+        for (day_state, duration) in durations.days.range((since, String::new())..) {
+            *totals.entry(day_state.1.clone()).or_insert(Duration::minutes(0)) += *duration;
+        }
+        totals
+    }
+}
+
+pub struct StateNotifier {
+    current: Mutex<CurrentState>,
+    cv: Condvar,
+}
+pub type StateNotifierHandle = Arc<StateNotifier>;
+
+impl StateNotifier {
+    fn new() -> StateNotifierHandle {
+        Arc::new(StateNotifier {
+            current: Mutex::new(CurrentState { seq: 0, state: None }),
+            cv: Condvar::new(),
+        })
+    }
+
+    pub fn current(&self) -> CurrentState {
+        self.current.lock().unwrap().clone()
+    }
+
+    // Returns true if the state changed.
+    fn state_applied(&self, state: &ActuatorState) -> bool {
+        let mut current = self.current.lock().unwrap();
+
+        if current.state.as_ref() != Some(state) {
+            current.seq += 1;
+            current.state = Some(state.clone());
+            self.cv.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Wait until the sequence number differs from seq, or timeout expires. Returns the current
+    // state in any case.
+    pub fn wait_change(&self, seq: u64, timeout: time::Duration) -> CurrentState {
+        let deadline = time::Instant::now() + timeout;
+        let mut current = self.current.lock().unwrap();
+
+        while current.seq == seq {
+            let now = time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            current = self.cv.wait_timeout(current, deadline - now).unwrap().0;
+        }
+
+        current.clone()
+    }
+}
+
+// The state changes of all the actuators of a server, numbered by a sequence number shared by all
+// of them, so that a client can follow them all at once. Only the last MAX_STATE_CHANGES changes
+// are kept: a client that does not keep up sees a gap in the sequence numbers.
+pub struct StateChanges {
+    // The sequence number of the last change, and the changes kept (in order).
+    log: Mutex<(u64, VecDeque<StateChange>)>,
+    cv: Condvar,
+}
+pub type StateChangesHandle = Arc<StateChanges>;
+// Sequence number, actuator ID and new state.
+pub type StateChange = (u64, u32, ActuatorState);
+
+impl StateChanges {
+    pub fn new() -> StateChangesHandle {
+        Arc::new(StateChanges {
+            log: Mutex::new((0, VecDeque::new())),
+            cv: Condvar::new(),
+        })
+    }
+
+    fn record(&self, actuator_id: u32, state: &ActuatorState) {
+        let mut log = self.log.lock().unwrap();
+
+        log.0 += 1;
+        let seq = log.0;
+        log.1.push_back((seq, actuator_id, state.clone()));
+        if log.1.len() > MAX_STATE_CHANGES {
+            log.1.pop_front();
+        }
+        self.cv.notify_all();
+    }
+
+    // The sequence number of the last change, to wait for the ones after it.
+    pub fn last_seq(&self) -> u64 {
+        self.log.lock().unwrap().0
+    }
+
+    // The first change after since (the oldest kept if it was dropped), waiting at most timeout
+    // for one. A since after the last change (e.g. from before a restart of the server) is taken
+    // as the last change, so that u64::max_value() only returns the next change.
+    pub fn wait_after(&self, since: u64, timeout: time::Duration) -> Option<StateChange> {
+        let deadline = time::Instant::now() + timeout;
+        let mut log = self.log.lock().unwrap();
+        let since = since.min(log.0);
+
+        while log.0 == since {
+            let now = time::Instant::now();
+            if now >= deadline {
+                return None
+            }
+
+            log = self.cv.wait_timeout(log, deadline - now).unwrap().0;
         }
+
+        log.1.iter().find(|change| change.0 > since).cloned()
     }
 }
 
@@ -78,13 +511,46 @@ pub struct Actuator {
     pub info: ActuatorInfo,
 
     timeslots: BTreeMap<u32, TimeSlot>,
-    default_state: ActuatorState,
+    default_schedule: DefaultSchedule,
 
     next_timeslot_id: u32,
-    // TODO: would be nice to be per-timeslot, but shouldn't be exposed via RPC either...
-    next_override_id: u32,
+    // Incremented every time the timeslots are modified.
+    timeslots_generation: u64,
+    metadata: Metadata,
 
     actuator_controller: ActuatorControllerHandle,
+    // Read by the thread whenever applying a state fails.
+    failure_policy: FailurePolicy,
+    // May be over already (see vacation()).
+    vacation: Option<Vacation>,
+    // Takes precedence over the vacation, see set_follow().
+    follow: Option<Follow>,
+    // Incremented every time follow is modified, so that the thread passing the state changes of
+    // the previous source on (see follow::spawn()) stops.
+    follow_generation: u64,
+    // The state last applied to the source, if any. Written with only read access to the actuator.
+    followed_state: Mutex<Option<ActuatorState>>,
+    clock: ClockHandle,
+    // Only set if metrics are exposed.
+    metrics: Option<MetricsHandle>,
+    state_notifier: StateNotifierHandle,
+    // Only set if the history is persisted.
+    history: Option<HistoryHandle>,
+    // Only set if state events are published, along with the ID of the actuator.
+    events: Option<(u32, EventBroadcasterHandle)>,
+    // Only set if the actuator is part of a server, along with its ID.
+    state_changes: Option<(u32, StateChangesHandle)>,
+    // Same as state_changes. Without it, timeslots cannot have a condition.
+    condition_sources: Option<(u32, ConditionSourcesHandle)>,
+    // Only set if state changes are audited, along with the ID of the actuator.
+    audit_log: Option<(u32, AuditLogHandle)>,
+    // Only set if the actuator has a log file. Written with only read access to the actuator.
+    transition_log: Option<Mutex<TransitionLog>>,
+    // Set when a state is applied outside of the schedule, cleared when the schedule's is (until is
+    // only filled by overrides()). Written with only read access to the actuator, hence the Mutex.
+    manual_override: Mutex<Option<ActiveOverrideInfo>>,
+    // Same as manual_override.
+    state_durations: Mutex<StateDurations>,
 
     thread_comm: Arc<Mutex<ThreadComm>>,
     thread_comm_cv: Arc<Condvar>,
@@ -95,16 +561,50 @@ impl Actuator {
     pub fn new(info: ActuatorInfo,
                default_state: ActuatorState,
                actuator_controller: ActuatorControllerHandle) -> ActuatorHandle {
+        Self::with_clock(info, default_state, actuator_controller, SystemClock::new())
+    }
+
+    pub fn with_clock(info: ActuatorInfo,
+                      default_state: ActuatorState,
+                      actuator_controller: ActuatorControllerHandle,
+                      clock: ClockHandle) -> ActuatorHandle {
+        let now = info.day_start.date_time(&clock.now(), DayStart::default());
+        let default_schedule = DefaultSchedule::new(default_state);
+        let active_timeslot = ActiveTimeSlot::compute(&now, &BTreeMap::new(), &default_schedule,
+                                                      info.day_start);
+        let enabled = info.enabled;
         let result_handle = Arc::new(RwLock::new(Actuator {
             info,
             timeslots: BTreeMap::new(),
-            default_state: default_state.clone(),
+            default_schedule,
             next_timeslot_id: 0,
-            next_override_id: 0,
+            timeslots_generation: 0,
+            metadata: Metadata::new(),
             actuator_controller,
+            failure_policy: FailurePolicy::default(),
+            vacation: None,
+            follow: None,
+            follow_generation: 0,
+            followed_state: Mutex::new(None),
+            clock,
+            metrics: None,
+            state_notifier: StateNotifier::new(),
+            history: None,
+            events: None,
+            state_changes: None,
+            condition_sources: None,
+            audit_log: None,
+            transition_log: None,
+            manual_override: Mutex::new(None),
+            state_durations: Mutex::new(StateDurations::default()),
             thread_comm: Arc::new(Mutex::new(ThreadComm {
-                active_timeslot: ActiveTimeSlot::default_state(default_state),
-                modified: false,
+                active_timeslot,
+                // Have the thread apply the initial state.
+                modified: true,
+                modified_time: now,
+                enabled,
+                stopped: false,
+                max_wait_sec: None,
             })),
             thread_comm_cv: Arc::new(Condvar::new()),
         }));
@@ -116,289 +616,1345 @@ impl Actuator {
         result_handle
     }
 
-    pub fn timeslots(&self) -> &BTreeMap<u32, TimeSlot> {
-        &self.timeslots
+    // Start collecting metrics (from the next time a state is applied).
+    pub fn enable_metrics(&mut self) -> MetricsHandle {
+        self.metrics.get_or_insert_with(ActuatorMetrics::new).clone()
     }
 
-    pub fn default_state(&self) -> &ActuatorState {
-        &self.default_state
+    pub fn enable_history(&mut self, history: HistoryHandle) {
+        self.history = Some(history);
     }
 
-    pub fn set_default_state(&mut self, default_state: ActuatorState) -> Result<()> {
-        if !self.valid_state(&default_state) {
-            return Err(InvalidArgument(IAE::ActuatorState))
-        }
+    pub fn history(&self) -> Option<HistoryHandle> {
+        self.history.clone()
+    }
 
-        self.default_state = default_state;
+    // Publish state events from now on, identifying this actuator as actuator_id.
+    pub fn enable_events(&mut self, actuator_id: u32, events: EventBroadcasterHandle) {
+        self.events = Some((actuator_id, events));
+    }
 
-        self.update_active_timeslot_and_notify(|active_timeslot| {
-            if let DefaultStateActive { .. } = active_timeslot.state {
-                // The default state is active, update the actuator state.
-                active_timeslot.actuator_state = self.default_state.clone();
-            }
-        });
+    // Append the states applied to path from now on (see TransitionLog).
+    pub fn enable_transition_log(&mut self, path: PathBuf) {
+        self.transition_log = Some(Mutex::new(TransitionLog::new(path)));
+    }
 
-        Ok(())
+    // Record the state changes in audit_log from now on, identifying this actuator as actuator_id.
+    pub fn enable_audit_log(&mut self, actuator_id: u32, audit_log: AuditLogHandle) {
+        self.audit_log = Some((actuator_id, audit_log));
     }
 
-    pub fn add_time_slot(&mut self,
-                         time_period: TimePeriod,
-                         actuator_state: ActuatorState,
-                         enabled: bool) -> Result<u32> {
-        if !time_period.valid() {
-            return Err(InvalidArgument(IAE::TimePeriod))
-        }
+    // Record the state changes in state_changes from now on, identifying this actuator as
+    // actuator_id.
+    pub fn enable_state_changes(&mut self, actuator_id: u32, state_changes: StateChangesHandle) {
+        self.state_changes = Some((actuator_id, state_changes));
+    }
 
-        if !self.valid_state(&actuator_state) {
-            return Err(InvalidArgument(IAE::ActuatorState))
+    // Allow the timeslots to depend on the actuators in condition_sources (see TimeSlotCondition),
+    // this actuator being actuator_id among them.
+    pub fn enable_conditions(&mut self, actuator_id: u32,
+                             condition_sources: ConditionSourcesHandle) {
+        self.condition_sources = Some((actuator_id, condition_sources));
+    }
+
+    // Record that state was applied (or only simulated), or that applying it failed: in the history
+    // and the log file (if enabled), and if the state changed, for the waiting clients, the event
+    // subscribers and in the audit log (if enabled).
+    fn state_applied(&self, now: &DateTime, state: &ActuatorState, source: EventSource,
+                     result: &io::Result<()>) {
+        if let Some(ref history) = self.history {
+            history.record(&self.info.name, HistoryEntry {
+                time: now.clone(),
+                state: state.clone(),
+                provenance: source.to_string(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+                simulated: self.info.simulate,
+            });
         }
 
-        // Check for overlaps.
-        for (id, ts) in self.timeslots.iter() {
-            if ts.overlaps(&time_period) {
-                return Err(TimeSlotOverlap(*id))
-            }
+        if let Some(ref transition_log) = self.transition_log {
+            transition_log.lock().unwrap().record(now, state, &source, result,
+                                                  self.info.simulate);
         }
 
-        // All good, insert the timeslot.
-        let id = self.next_timeslot_id;
-        self.timeslots.insert(id, TimeSlot::new(enabled, actuator_state, time_period));
-        self.next_timeslot_id += 1;
+        if result.is_ok() {
+            *self.manual_override.lock().unwrap() = match source {
+                EventSource::Manual | EventSource::Trigger { .. } => Some(ActiveOverrideInfo {
+                    state: state.clone(),
+                    source: source.to_string(),
+                    since: now.clone(),
+                    until: None,
+                }),
+                _ => None,
+            };
+        }
 
-        self.update_active_timeslot_and_notify(|active_timeslot| {
-            active_timeslot.update_timeslot_added(self.timeslots.get(&id).unwrap(), id);
-        });
+        if result.is_ok() {
+            self.state_durations.lock().unwrap().state_applied(now, state);
+        }
 
-        Ok(id)
-    }
+        // Only needed for the audit log, before the notifier is updated.
+        let old_state = match self.audit_log {
+            Some(_) => self.state_notifier.current().state,
+            None => None,
+        };
 
-    pub fn remove_time_slot(&mut self, time_slot_id: u32) -> Result<()> {
-        if self.timeslots.remove(&time_slot_id).is_none() {
-            return Err(InvalidArgument(IAE::TimeSlotId))
+        if result.is_ok() && self.state_notifier.state_applied(state) {
+            if let Some((actuator_id, ref audit_log)) = self.audit_log {
+                audit_log.record(&AuditEntry {
+                    timestamp: now,
+                    actuator_id,
+                    name: &self.info.name,
+                    old_state: old_state.as_ref(),
+                    new_state: state,
+                    source: &source,
+                    simulated: self.info.simulate,
+                });
+            }
+            if let Some((actuator_id, ref state_changes)) = self.state_changes {
+                state_changes.record(actuator_id, state);
+            }
+            if let Some((actuator_id, ref events)) = self.events {
+                events.publish(&StateEvent {
+                    actuator_id,
+                    name: self.info.name.clone(),
+                    state: state.clone(),
+                    source,
+                    timestamp: now.clone(),
+                    simulated: self.info.simulate,
+                });
+            }
         }
+    }
 
-        self.update_active_timeslot_and_notify(|active_timeslot| {
-            active_timeslot.update_timeslot_removed(time_slot_id,
-                                                    &self.timeslots, &self.default_state);
-        });
+    // Time spent in each state from the start of since (with the default DayStart) until now. Only
+    // the last MAX_STATE_DURATION_DAYS days since the actuator was created are known.
+    pub fn state_durations(&self, since: Date) -> BTreeMap<String, Duration> {
+        self.state_durations.lock().unwrap().totals(since, &self.clock.now())
+    }
 
-        Ok(())
+    // The notifier can be used without holding the actuator lock (which must not be held while
+    // waiting for a change).
+    pub fn state_notifier(&self) -> StateNotifierHandle {
+        self.state_notifier.clone()
     }
 
-    // TODO: the timeslot management logic should be moved to TimeSlot itself (which would also
-    // make reference management easier)
-    pub fn time_slot_set_time_period(&mut self, time_slot_id: u32,
-                                     time_period: TimePeriod) -> Result<()> {
-        {
-            // Find the matching timeslot and check for overlaps.
-            let mut target_ts: Result<&mut TimeSlot> = Err(InvalidArgument(IAE::TimeSlotId));
-            for (id, ts) in self.timeslots.iter_mut() {
-                if *id == time_slot_id {
-                    target_ts = Ok(ts);
-                    continue;
-                }
+    pub fn timeslots(&self) -> &BTreeMap<u32, TimeSlot> {
+        &self.timeslots
+    }
 
-                if ts.overlaps(&time_period) {
-                    target_ts = Err(TimeSlotOverlap(*id));
-                    break;
-                }
+    // Summaries are only returned if the timeslots were modified since the generation if_changed
+    // (if specified), and so are the shadowed timeslots.
+    pub fn timeslot_summaries(&self, if_changed: Option<u64>) -> TimeSlotSummaries {
+        if if_changed == Some(self.timeslots_generation) {
+            return TimeSlotSummaries {
+                generation: self.timeslots_generation,
+                summaries: None,
+                shadowed: None,
             }
+        }
 
-            let ts = target_ts?;
-
-            // Update specified fields.
-            let mut new_time_period = ts.time_period.clone();
+        let summaries = self.timeslots.iter().map(|(id, ts)| ts.summary(*id)).collect();
+        let shadowed = schedule::find_shadowed(&self.timeslots, &self.default_schedule,
+                                               self.info.day_start, self.now().date,
+                                               schedule::SHADOWED_CHECK_DAYS);
 
-            if time_period.time_interval.start != Time::EMPTY {
-                new_time_period.time_interval.start = time_period.time_interval.start;
-            }
-            if time_period.time_interval.end != Time::EMPTY {
-                new_time_period.time_interval.end = time_period.time_interval.end;
-            }
-            if time_period.date_range.start != Date::empty_date() {
-                new_time_period.date_range.start = time_period.date_range.start;
-            }
-            if time_period.date_range.end != Date::empty_date() {
-                new_time_period.date_range.end = time_period.date_range.end;
-            }
-            if !time_period.days.is_empty() {
-                new_time_period.days = time_period.days;
-            }
+        TimeSlotSummaries {
+            generation: self.timeslots_generation,
+            summaries: Some(summaries),
+            shadowed: Some(shadowed),
+        }
+    }
 
-            // Check that the specified fields were valid.
-            if !new_time_period.valid() {
-                return Err(InvalidArgument(IAE::TimePeriod))
-            }
+    pub fn default_state(&self) -> &ActuatorState {
+        &self.default_schedule.base_state
+    }
 
-            // All good, modify the timeslot.
-            ts.time_period = new_time_period;
-        };
+    pub fn default_schedule(&self) -> &DefaultSchedule {
+        &self.default_schedule
+    }
 
-        self.update_active_timeslot_and_notify(|active_timeslot| {
-            // Get the modified timeslot (immutable reference this time).
-            let ts = self.timeslots.get(&time_slot_id).unwrap();
-            active_timeslot.update_timeslot_modified(ts, time_slot_id,
-                                                     &self.timeslots, &self.default_state);
-        });
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
 
-        Ok(())
+    // See metadata::set().
+    pub fn set_metadata(&mut self, key: String, value: Option<String>) -> Result<()> {
+        metadata::set(&mut self.metadata, key, value)
     }
 
-    pub fn time_slot_set_enabled(&mut self, time_slot_id: u32,
-                                 enabled: bool) -> Result<()> {
-        let old_enabled = {
-            let time_slot = self.timeslots.get_mut(&time_slot_id)
-                .ok_or(InvalidArgument(IAE::TimeSlotId))?;
+    // The state the schedule currently calls for, and the timeslot it comes from (None for the
+    // default state). It may not have been applied yet (or successfully).
+    pub fn intended_state(&self) -> (ActuatorState, Option<u32>) {
+        let active_timeslot = self.thread_comm.lock().unwrap().active_timeslot.clone();
+        let active_timeslot = self.apply_condition(active_timeslot, &self.now());
+        let id = match active_timeslot.state {
+            TimeSlotActive { id, .. } => Some(id),
+            DefaultStateActive { .. } | VacationActive { .. } | FollowActive { .. } => None,
+        };
+        (active_timeslot.actuator_state, id)
+    }
 
-            let old_enabled = time_slot.enabled;
-            time_slot.enabled = enabled;
-            old_enabled
+    // The state applied outside of the schedule that currently holds, if any. A Vec to leave room
+    // for several kinds of overrides, there is at most one for now.
+    pub fn overrides(&self) -> Vec<ActiveOverrideInfo> {
+        let mut info = match *self.manual_override.lock().unwrap() {
+            Some(ref info) => info.clone(),
+            None => return Vec::new(),
         };
 
-        if old_enabled != enabled {
-            self.update_active_timeslot_and_notify(|active_timeslot| {
-                if enabled {
-                    // Handle as if a new timeslot were added.
-                    let ts = self.timeslots.get(&time_slot_id).unwrap();
-                    active_timeslot.update_timeslot_added(ts, time_slot_id);
-                } else {
-                    // Handle as if the timeslot had been removed.
-                    active_timeslot.update_timeslot_removed(time_slot_id,
-                                                            &self.timeslots, &self.default_state);
-                }
-            });
+        let thread_comm = self.thread_comm.lock().unwrap();
+        if thread_comm.enabled {
+            let end = end_date_time(&self.now(), thread_comm.active_timeslot.end_time,
+                                    self.info.day_start);
+            info.until = Some(DayStart::default().date_time(&end, self.info.day_start));
         }
-
-        Ok(())
+        vec![info]
     }
 
-    pub fn time_slot_set_actuator_state(&mut self, time_slot_id: u32,
-                                        actuator_state: ActuatorState) -> Result<()> {
-        if !self.valid_state(&actuator_state) {
-            return Err(InvalidArgument(IAE::ActuatorState))
+    // Drop the override (if any) and apply the state the schedule calls for right away.
+    pub fn cancel_override(&self) {
+        if self.manual_override.lock().unwrap().take().is_some() {
+            self.recompute_active_timeslot();
         }
+    }
 
-        self.timeslots.get_mut(&time_slot_id)
-            .ok_or(InvalidArgument(IAE::TimeSlotId))?
-            .actuator_state = actuator_state.clone();
+    // Make the actuator thread exit, after which the actuator is never applied again. The thread
+    // holds a handle to the actuator, which is only released once the thread has exited: this
+    // must be called for the Actuator to be dropped.
+    pub fn stop(&mut self) {
+        self.thread_comm.lock().unwrap().stopped = true;
+        self.update_active_timeslot(|_, _| {}, true);
+    }
 
-        self.update_active_timeslot_and_notify(|active_timeslot| {
-            match active_timeslot.state {
-                TimeSlotActive { id, .. } if id == time_slot_id => {
-                    // This timeslot is active, update the actuator state.
-                    active_timeslot.actuator_state = actuator_state;
-                },
-                _ => (),
-            }
-        });
+    // Wake up the actuator thread at least every max_wait, to check the time even if the active
+    // timeslot ends much later. This catches the clock jumping (e.g. an NTP step) long before
+    // the end of the timeslot, while the condvar's timeout does not follow the wall clock. None
+    // (the default) to only wake up at the end of the timeslot.
+    pub fn set_max_wait(&self, max_wait: Option<time::Duration>) {
+        self.thread_comm.lock().unwrap().max_wait_sec = max_wait.map(|d| d.as_secs().max(1));
+        // The thread waits again with the new maximum.
+        self.thread_comm_cv.notify_one();
+    }
 
-        Ok(())
+    pub fn failure_policy(&self) -> FailurePolicy {
+        self.failure_policy
     }
 
-    pub fn time_slot_add_time_override(&mut self, time_slot_id: u32,
-                                       time_period: TimePeriod) -> Result<u32> {
-        if !time_period.valid() {
-            return Err(InvalidArgument(IAE::TimePeriod))
+    // Applies from the next failure on, not to a state being retried.
+    pub fn set_failure_policy(&mut self, failure_policy: FailurePolicy) {
+        self.failure_policy = failure_policy;
+    }
+
+    // The vacation in progress, if any.
+    pub fn vacation(&self) -> Option<Vacation> {
+        match self.vacation {
+            Some(ref vacation) if self.now().date < vacation.until => Some(vacation.clone()),
+            _ => None,
         }
+    }
 
-        let new_override_id = self.next_override_id;
+    // Start (or replace) a vacation, or end it if None. The state is checked (and clamped if
+    // configured to) first.
+    pub fn set_vacation(&mut self, vacation: Option<Vacation>) -> Result<()> {
+        self.vacation = match vacation {
+            Some(Vacation { until, state: Some(state) }) =>
+                Some(Vacation { until, state: Some(self.accept_state(state)?) }),
+            vacation => vacation,
+        };
 
-        {
-            // Find the matching timeslot and check for overlaps.
-            let mut target_ts: Option<&mut TimeSlot> = None;
-            for (id, ts) in self.timeslots.iter_mut() {
-                if *id == time_slot_id {
-                    target_ts = Some(ts);
-                    continue;
-                }
+        self.recompute_active_timeslot();
+        Ok(())
+    }
 
-                if ts.overlaps(&time_period) {
-                    return Err(TimeSlotOverlap(*id))
-                }
-            }
+    // The vacation end and state at now, if on vacation.
+    fn vacation_state(&self, now: &DateTime) -> Option<(Date, ActuatorState)> {
+        match self.vacation {
+            Some(ref vacation) if now.date < vacation.until => {
+                let state = vacation.state.as_ref().unwrap_or(self.default_state());
+                Some((vacation.until, state.clone()))
+            },
+            _ => None,
+        }
+    }
 
-            if let Some(ts) = target_ts {
-                // Also check there is no overlap with other overrides. The requirement is stronger:
-                // two overrides cannot apply to the same day (not just day and time).
-                for (id, or) in ts.time_override.iter() {
-                    if or.overlaps_dates(&time_period) {
-                        return Err(TimeOverrideOverlap(*id))
-                    }
-                }
+    pub fn follow(&self) -> Option<&Follow> {
+        self.follow.as_ref()
+    }
 
-                // All good, add the override.
-                ts.time_override.insert(new_override_id, time_period);
-                self.next_override_id += 1;
-            } else {
-                return Err(InvalidArgument(IAE::TimeSlotId))
-            }
+    // Drive the actuator with the state of another one (see Follow), whose current state is
+    // source_state, or go back to its schedule if None. The follow must have been checked against
+    // both types. Returns the generation to pass to source_state_changed().
+    pub fn set_follow(&mut self, follow: Option<Follow>,
+                      source_state: Option<ActuatorState>) -> u64 {
+        self.follow = follow;
+        self.follow_generation += 1;
+        *self.followed_state.lock().unwrap() = source_state;
+
+        self.recompute_active_timeslot();
+        self.follow_generation
+    }
+
+    // Called whenever the state of the source changes. Returns false if the actuator no longer
+    // follows it with that generation (see set_follow()).
+    pub fn source_state_changed(&self, generation: u64, state: Option<ActuatorState>) -> bool {
+        if generation != self.follow_generation {
+            return false
         }
 
-        self.update_active_timeslot_and_notify(|active_timeslot| {
-            // Same handling as set_time_period().
-            let ts = self.timeslots.get(&time_slot_id).unwrap();
-            active_timeslot.update_timeslot_modified(ts, time_slot_id,
-                                                     &self.timeslots, &self.default_state);
-        });
+        let changed = {
+            let mut followed_state = self.followed_state.lock().unwrap();
+            let changed = *followed_state != state;
+            *followed_state = state;
+            changed
+        };
+        if changed {
+            self.recompute_active_timeslot();
+        }
+        true
+    }
 
-        Ok(new_override_id)
+    // The source followed and the state it calls for, if following one. The default state is used
+    // until the source applies a state, and for the states it cannot be translated from.
+    fn follow_state(&self) -> Option<(u32, ActuatorState)> {
+        let follow = self.follow.as_ref()?;
+        let state = self.followed_state.lock().unwrap().as_ref()
+            .and_then(|state| follow.apply(state))
+            .and_then(|state| if self.valid_state(&state) { Some(state) } else { None })
+            .unwrap_or_else(|| self.default_state().clone());
+        Some((follow.actuator_id, state))
     }
 
-    pub fn time_slot_remove_time_override(&mut self, time_slot_id: u32,
-                                          time_override_id: u32) -> Result<()> {
-        if self.timeslots.get_mut(&time_slot_id)
-            .ok_or(InvalidArgument(IAE::TimeSlotId))?
-            .time_override.remove(&time_override_id).is_none()
-        {
-            return Err(InvalidArgument(IAE::TimeOverrideId))
+    // The active timeslot if the schedule is suspended: while following another actuator, or on
+    // vacation.
+    fn suspended_schedule(&self, now: &DateTime) -> Option<ActiveTimeSlot> {
+        if let Some((actuator_id, state)) = self.follow_state() {
+            return Some(ActiveTimeSlot::follow(actuator_id, state, self.info.day_start))
         }
+        self.vacation_state(now)
+            .map(|(until, state)| ActiveTimeSlot::vacation(until, state, self.info.day_start))
+    }
+
+    // Same as ActiveTimeSlot::compute(), unless the schedule is suspended.
+    fn compute_active_timeslot(&self, now: &DateTime) -> ActiveTimeSlot {
+        match self.suspended_schedule(now) {
+            Some(active_timeslot) => active_timeslot,
+            None => ActiveTimeSlot::compute(now, &self.timeslots, &self.default_schedule,
+                                            self.info.day_start),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled == self.info.enabled {
+            return
+        }
+
+        self.info.enabled = enabled;
+        self.thread_comm.lock().unwrap().enabled = enabled;
+
+        if enabled {
+            // The schedule went on while the actuator was disabled, start over from scratch.
+            self.recompute_active_timeslot();
+        } else {
+            // Wake up the thread so that it goes idle.
+            self.update_active_timeslot(|_, _| {}, true);
+        }
+    }
+
+    pub fn set_simulate(&mut self, simulate: bool) {
+        if simulate == self.info.simulate {
+            return
+        }
+
+        self.info.simulate = simulate;
+
+        if !simulate {
+            // The controller may be in any state, apply the one the schedule calls for.
+            self.recompute_active_timeslot();
+        }
+    }
+
+    // Returns the state actually set (which differs if it was clamped).
+    pub fn set_default_state(&mut self, default_state: ActuatorState) -> Result<ActuatorState> {
+        let default_state = self.accept_state(default_state)?;
+
+        self.default_schedule.base_state = default_state.clone();
+        self.update_default_state();
+
+        Ok(default_state)
+    }
+
+    pub fn set_default_change(&mut self, time: Time, state: ActuatorState) -> Result<()> {
+        if !time.valid() {
+            return Err(InvalidArgument(IAE::DefaultChangeTime, format!("invalid time {}", time)))
+        }
+
+        let state = self.accept_state(state)?;
+
+        self.default_schedule.changes.insert(time, state);
+        self.update_default_state();
 
-        self.update_active_timeslot_and_notify(|active_timeslot| {
+        Ok(())
+    }
+
+    pub fn remove_default_change(&mut self, time: Time) -> Result<()> {
+        if self.default_schedule.changes.remove(&time).is_none() {
+            return Err(InvalidArgument(IAE::DefaultChangeTime,
+                                       format!("no default state change at {}", time)))
+        }
+
+        self.update_default_state();
+
+        Ok(())
+    }
+
+    fn update_default_state(&self) {
+        self.update_active_timeslot_and_notify(|active_timeslot, now| {
+            if let DefaultStateActive { .. } = active_timeslot.state {
+                // The default state is active, both its state and its end time may have changed.
+                *active_timeslot = ActiveTimeSlot::compute(now, &self.timeslots,
+                                                           &self.default_schedule,
+                                                           self.info.day_start);
+            }
+        });
+    }
+
+    pub fn add_time_slot(&mut self, time_slot: NewTimeSlot) -> Result<u32> {
+        let ts = self.check_new_time_slot(time_slot)?;
+
+        // All good, insert the timeslot.
+        let id = self.next_timeslot_id;
+        self.timeslots.insert(id, ts);
+        self.next_timeslot_id += 1;
+
+        self.timeslots_generation += 1;
+
+        self.update_active_timeslot_and_notify(|active_timeslot, now| {
+            active_timeslot.update_timeslot_added(self.timeslots.get(&id).unwrap(), id, now,
+                                                  self.info.day_start);
+        });
+
+        Ok(id)
+    }
+
+    // Same as add_time_slot(), but only fill the parts of time_period that no other timeslot
+    // overlaps, instead of failing. A weekday is considered busy at the times another timeslot
+    // overlaps on any of its dates. The free parts may differ from one weekday to another, so
+    // several timeslots may be added (one per free time interval and set of weekdays), all or
+    // nothing. Returns the added timeslots, none if there is no free part.
+    pub fn add_time_slot_fit(&mut self, time_slot: NewTimeSlot) -> Result<Vec<(u32, TimePeriod)>> {
+        let day_start = self.info.day_start;
+        let new_ts = self.new_time_slot(time_slot)?;
+        let time_period = &new_ts.time_period;
+
+        let mut periods: Vec<TimePeriod> = Vec::new();
+        for day in (0..7).filter_map(|i| WeekdaySet::from_bits(1 << i)) {
+            if !time_period.days.contains(day) {
+                continue
+            }
+
+            let day_period = TimePeriod { days: day, ..time_period.clone() };
+            let busy: Vec<TimeInterval> = self.timeslots.values()
+                .flat_map(|ts| ts.effective_time_periods(day_start))
+                .filter_map(|p| p.intersection(&day_period, day_start))
+                .map(|p| p.time_interval)
+                .collect();
+
+            // Merge the weekdays with the same free time interval.
+            for time_interval in day_start.subtract(&time_period.time_interval, &busy) {
+                match periods.iter().position(|p| p.time_interval == time_interval) {
+                    Some(pos) => periods[pos].days |= day,
+                    None => periods.push(TimePeriod { time_interval, ..day_period.clone() }),
+                }
+            }
+        }
+
+        self.check_room(periods.len())?;
+        // Free parts do not overlap anything by construction, but jitter may still make them
+        // overlap other timeslots, or each other.
+        let new_timeslots: Vec<TimeSlot> = periods.into_iter()
+            .map(|time_period| {
+                let mut ts = new_ts.clone();
+                ts.time_period = time_period;
+                ts
+            })
+            .collect();
+        for (index, ts) in new_timeslots.iter().enumerate() {
+            self.check_overlap(ts)?;
+            if new_timeslots[..index].iter().any(|other| other.overlaps_time_slot(ts, day_start)) {
+                let detail = "the free parts would overlap each other".to_string();
+                return Err(InvalidArgument(IAE::Jitter, detail))
+            }
+        }
+
+        let mut added = Vec::new();
+        for ts in new_timeslots {
+            let id = self.next_timeslot_id;
+            added.push((id, ts.time_period.clone()));
+            self.timeslots.insert(id, ts);
+            self.next_timeslot_id += 1;
+        }
+
+        if !added.is_empty() {
+            self.timeslots_generation += 1;
+            // Same as copy_day().
+            self.recompute_active_timeslot();
+        }
+
+        Ok(added)
+    }
+
+    // Same as add_time_slot(), but instead of failing, make room for the new timeslot: the
+    // timeslots overlapping it are narrowed (see TimePeriod::narrow_around()), or removed if that
+    // is not possible (including if their overrides or jitter would still overlap). All of this
+    // happens at once, so there is never a gap between the old timeslots and the new one. Returns
+    // the ID of the new timeslot, and what happened to the overlapping ones.
+    pub fn add_time_slot_replace(&mut self,
+                                 time_slot: NewTimeSlot) -> Result<(u32, Vec<ReplacedTimeSlot>)> {
+        let day_start = self.info.day_start;
+        let new_ts = self.new_time_slot(time_slot)?;
+        let time_period = new_ts.time_period.clone();
+        let now = self.clock.now();
+
+        let mut new_timeslots = self.timeslots.clone();
+        let mut replaced = Vec::new();
+
+        for (id, ts) in self.timeslots.iter() {
+            if !ts.overlaps_time_slot(&new_ts, day_start) {
+                continue
+            }
+
+            let narrowed = ts.time_period.narrow_around(&time_period, day_start)
+                .and_then(|narrowed_period| {
+                    let mut narrowed = ts.clone();
+                    narrowed.time_period = narrowed_period;
+                    narrowed.touch(now.clone());
+                    if narrowed.overlaps_time_slot(&new_ts, day_start) {
+                        None
+                    } else {
+                        Some(narrowed)
+                    }
+                });
+
+            replaced.push(ReplacedTimeSlot {
+                id: *id,
+                old_time_period: ts.time_period.clone(),
+                new_time_period: narrowed.as_ref().map(|narrowed| narrowed.time_period.clone()),
+            });
+            match narrowed {
+                Some(narrowed) => new_timeslots.insert(*id, narrowed),
+                None => new_timeslots.remove(id),
+            };
+        }
+
+        // The replaced timeslots may have made room for the new one.
+        if let Some(max) = self.info.max_timeslots {
+            if new_timeslots.len() >= max as usize {
+                return Err(LimitExceeded { max_timeslots: max })
+            }
+        }
+
+        let id = self.next_timeslot_id;
+        new_timeslots.insert(id, new_ts);
+        self.timeslots = new_timeslots;
+        self.next_timeslot_id += 1;
+        self.timeslots_generation += 1;
+
+        // Several timeslots may have been modified, start over (once).
+        self.recompute_active_timeslot();
+
+        Ok((id, replaced))
+    }
+
+    // Add several timeslots at once, all or nothing: each of them is checked as by add_time_slot(),
+    // and against the previous ones of the batch. Errors are wrapped in BatchEntry to tell which
+    // one was rejected. Returns the IDs of the added timeslots, in order.
+    pub fn add_time_slots(&mut self, time_slots: Vec<NewTimeSlot>) -> Result<Vec<u32>> {
+        // Not the fault of any entry in particular.
+        self.check_room(time_slots.len())?;
+
+        let mut added: Vec<(u32, TimeSlot)> = Vec::new();
+
+        for (index, new_ts) in time_slots.into_iter().enumerate() {
+            let entry_error = |error: Error| BatchEntry { index: index as u32,
+                                                          error: Box::new(error) };
+
+            let ts = self.check_new_time_slot(new_ts).map_err(&entry_error)?;
+            for (other_index, &(_, ref other_ts)) in added.iter().enumerate() {
+                if other_ts.overlaps_time_slot(&ts, self.info.day_start) {
+                    return Err(entry_error(InvalidArgument(
+                        IAE::TimePeriod, format!("overlaps entry {}", other_index))))
+                }
+            }
+
+            added.push((self.next_timeslot_id + index as u32, ts));
+        }
+
+        if added.is_empty() {
+            return Ok(Vec::new())
+        }
+
+        let ids = added.iter().map(|&(id, _)| id).collect();
+        self.next_timeslot_id += added.len() as u32;
+        self.timeslots.extend(added);
+        self.timeslots_generation += 1;
+
+        // Several timeslots may have been added, start over (once).
+        self.recompute_active_timeslot();
+
+        Ok(ids)
+    }
+
+    pub fn remove_time_slot(&mut self, time_slot_id: u32) -> Result<()> {
+        if self.timeslots.remove(&time_slot_id).is_none() {
+            return Err(TimeSlotNotFound { actuator: self.info.name.clone(), id: time_slot_id })
+        }
+
+        self.timeslots_generation += 1;
+
+        self.update_active_timeslot_and_notify(|active_timeslot, now| {
+            active_timeslot.update_timeslot_removed(time_slot_id, &self.timeslots,
+                                                    &self.default_schedule, now,
+                                                    self.info.day_start);
+        });
+
+        Ok(())
+    }
+
+    // Remove all the timeslots (or only the disabled ones), returning how many were removed.
+    pub fn clear_time_slots(&mut self, disabled_only: bool) -> u32 {
+        let count = self.timeslots.len();
+        self.timeslots.retain(|_, ts| disabled_only && ts.enabled);
+        let removed = (count - self.timeslots.len()) as u32;
+
+        if removed == 0 {
+            return 0
+        }
+
+        self.timeslots_generation += 1;
+
+        // Disabled timeslots are never active, there is nothing to recompute.
+        if !disabled_only {
+            self.recompute_active_timeslot();
+        }
+
+        removed
+    }
+
+    // TODO: the timeslot management logic should be moved to TimeSlot itself (which would also
+    // make reference management easier)
+    pub fn time_slot_set_time_period(&mut self, time_slot_id: u32,
+                                     time_period_update: TimePeriodUpdate) -> Result<()> {
+        let new_ts = {
+            let ts = self.timeslot(time_slot_id)?;
+
+            // Update specified fields.
+            let new_time_period = time_period_update.apply(&ts.time_period);
+
+            // Check that the specified fields were valid.
+            new_time_period.check(self.info.day_start)
+                .map_err(|e| InvalidArgument(IAE::TimePeriod, e))?;
+
+            // Check the modified timeslot (including its overrides, which may now apply on
+            // different days) for overlaps.
+            let mut new_ts = ts.clone();
+            new_ts.time_period = new_time_period;
+            new_ts.touch(self.clock.now());
+
+            for (id, other_ts) in self.timeslots.iter() {
+                if *id != time_slot_id {
+                    let overlap = other_ts.find_overlap_time_slot(&new_ts, self.info.day_start);
+                    if let Some(overlap) = overlap {
+                        return Err(overlap_error(*id, overlap))
+                    }
+                }
+            }
+
+            new_ts
+        };
+
+        // All good, modify the timeslot.
+        self.timeslots.insert(time_slot_id, new_ts);
+
+        self.timeslots_generation += 1;
+
+        self.update_active_timeslot_and_notify(|active_timeslot, now| {
+            // Get the modified timeslot (immutable reference this time).
+            let ts = self.timeslots.get(&time_slot_id).unwrap();
+            active_timeslot.update_timeslot_modified(ts, time_slot_id, &self.timeslots,
+                                                     &self.default_schedule, now,
+                                                     self.info.day_start);
+        });
+
+        Ok(())
+    }
+
+    pub fn time_slot_set_enabled(&mut self, time_slot_id: u32,
+                                 enabled: bool) -> Result<()> {
+        let now = self.clock.now();
+        let old_enabled = {
+            let time_slot = self.timeslot_mut(time_slot_id)?;
+
+            let old_enabled = time_slot.enabled;
+            if old_enabled != enabled {
+                time_slot.enabled = enabled;
+                time_slot.touch(now);
+            }
+            old_enabled
+        };
+
+        if old_enabled != enabled {
+            self.timeslots_generation += 1;
+
+            self.update_active_timeslot_and_notify(|active_timeslot, now| {
+                if enabled {
+                    // Handle as if a new timeslot were added.
+                    let ts = self.timeslots.get(&time_slot_id).unwrap();
+                    active_timeslot.update_timeslot_added(ts, time_slot_id, now,
+                                                          self.info.day_start);
+                } else {
+                    // Handle as if the timeslot had been removed.
+                    active_timeslot.update_timeslot_removed(time_slot_id,
+                                                            &self.timeslots,
+                                                            &self.default_schedule, now,
+                                                            self.info.day_start);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    // None to remove the label.
+    pub fn time_slot_set_label(&mut self, time_slot_id: u32, label: Option<String>) -> Result<()> {
+        check_label(&label)?;
+
+        let now = self.clock.now();
+        {
+            let time_slot = self.timeslot_mut(time_slot_id)?;
+            time_slot.label = label;
+            time_slot.touch(now);
+        }
+
+        // The label has no effect on the active timeslot.
+        self.timeslots_generation += 1;
+
+        Ok(())
+    }
+
+    pub fn time_slot_set_jitter(&mut self, time_slot_id: u32, jitter_minutes: u32) -> Result<()> {
+        check_jitter(jitter_minutes)?;
+
+        let new_ts = {
+            let ts = self.timeslot(time_slot_id)?;
+
+            // More jitter means the timeslot may start earlier or end later, check for overlaps
+            // again.
+            let mut new_ts = ts.clone();
+            new_ts.jitter_minutes = jitter_minutes;
+            new_ts.touch(self.clock.now());
+
+            for (id, other_ts) in self.timeslots.iter() {
+                if *id != time_slot_id {
+                    let overlap = other_ts.find_overlap_time_slot(&new_ts, self.info.day_start);
+                    if let Some(overlap) = overlap {
+                        return Err(overlap_error(*id, overlap))
+                    }
+                }
+            }
+
+            new_ts
+        };
+
+        self.timeslots.insert(time_slot_id, new_ts);
+
+        self.timeslots_generation += 1;
+
+        self.update_active_timeslot_and_notify(|active_timeslot, now| {
             // Same handling as set_time_period().
             let ts = self.timeslots.get(&time_slot_id).unwrap();
-            active_timeslot.update_timeslot_modified(ts, time_slot_id,
-                                                     &self.timeslots, &self.default_state);
+            active_timeslot.update_timeslot_modified(ts, time_slot_id, &self.timeslots,
+                                                     &self.default_schedule, now,
+                                                     self.info.day_start);
         });
 
         Ok(())
     }
 
-    pub fn set_state(&self, state: ActuatorState) -> Result<()> {
-        if !self.valid_state(&state) {
-            return Err(InvalidArgument(IAE::ActuatorState))
+    // None to remove the condition. The actuator it depends on must be another one of the same
+    // server, and accept the state.
+    pub fn time_slot_set_condition(&mut self, time_slot_id: u32,
+                                   condition: Option<TimeSlotCondition>) -> Result<()> {
+        self.check_condition(&condition)?;
+
+        let now = self.clock.now();
+        {
+            let time_slot = self.timeslot_mut(time_slot_id)?;
+            time_slot.condition = condition;
+            time_slot.touch(now);
         }
 
-        self.actuator_controller.lock().unwrap().set_state(&state);
+        self.timeslots_generation += 1;
+        self.reapply_if_active(time_slot_id);
 
         Ok(())
     }
 
-    fn valid_state(&self, state: &ActuatorState) -> bool {
+    // Make every enabled timeslot occurring on the from weekday also occur on the to weekdays
+    // (their overrides are left as they are). Nothing is modified if any timeslot would then
+    // overlap another one, or if dry_run is set. Returns the IDs of the timeslots that are (or
+    // would be) modified.
+    pub fn copy_day(&mut self, from: WeekdaySet, to: WeekdaySet,
+                    dry_run: bool) -> Result<Vec<u32>> {
+        if from.bits().count_ones() != 1 {
+            return Err(InvalidArgument(IAE::Weekdays,
+                                       format!("expected a single source weekday, got {}", from)))
+        }
+        if to.is_empty() {
+            return Err(InvalidArgument(IAE::Weekdays, "no target weekday".to_string()))
+        }
+
+        let now = self.clock.now();
+        let mut new_timeslots = self.timeslots.clone();
+        let mut copied = Vec::new();
+
+        for (id, ts) in new_timeslots.iter_mut() {
+            if ts.enabled && ts.time_period.days.contains(from) &&
+                !ts.time_period.days.contains(to) {
+                ts.time_period.days |= to;
+                ts.touch(now.clone());
+                copied.push(*id);
+            }
+        }
+
+        for id in copied.iter() {
+            let ts = &new_timeslots[id];
+            for (other_id, other_ts) in new_timeslots.iter() {
+                if other_id != id && other_ts.overlaps_time_slot(ts, self.info.day_start) {
+                    return Err(CopyDayConflict { id: *id, conflicting_id: *other_id })
+                }
+            }
+        }
+
+        if dry_run || copied.is_empty() {
+            return Ok(copied)
+        }
+
+        self.timeslots = new_timeslots;
+        self.timeslots_generation += 1;
+
+        // Several timeslots may have been modified, start over.
+        self.recompute_active_timeslot();
+
+        Ok(copied)
+    }
+
+    // Move the time interval of the selected timeslots (all of them if None), and of their
+    // overrides, by minutes. Nothing is modified if any of them would then cross the start of the
+    // day or overlap another timeslot, or if dry_run is set. Returns the new time interval of each
+    // shifted timeslot.
+    pub fn shift_time_slots(&mut self, slot_ids: Option<Vec<u32>>, minutes: i32,
+                            dry_run: bool) -> Result<BTreeMap<u32, TimeInterval>> {
+        let slot_ids: BTreeSet<u32> = match slot_ids {
+            Some(ids) => {
+                for id in ids.iter() {
+                    self.timeslot(*id)?;
+                }
+                ids.into_iter().collect()
+            },
+            None => self.timeslots.keys().cloned().collect(),
+        };
+
+        let now = self.clock.now();
+        let day_start = self.info.day_start;
+        let mut new_timeslots = self.timeslots.clone();
+        let mut shifted = BTreeMap::new();
+
+        for id in slot_ids.iter() {
+            let ts = new_timeslots.get_mut(id).unwrap();
+            let out_of_day = || InvalidArgument(
+                IAE::TimePeriod, format!("timeslot {} would cross the start of the day", id));
+
+            ts.time_period.time_interval = day_start.shift(&ts.time_period.time_interval, minutes)
+                .ok_or_else(&out_of_day)?;
+            for or in ts.time_override.values_mut() {
+                or.time_interval = day_start.shift(&or.time_interval, minutes)
+                    .ok_or_else(&out_of_day)?;
+            }
+            ts.touch(now.clone());
+
+            shifted.insert(*id, ts.time_period.time_interval.clone());
+        }
+
+        for id in slot_ids.iter() {
+            let ts = &new_timeslots[id];
+            for (other_id, other_ts) in new_timeslots.iter() {
+                if other_id != id && other_ts.overlaps_time_slot(ts, day_start) {
+                    return Err(ShiftConflict { id: *id, conflicting_id: *other_id })
+                }
+            }
+        }
+
+        if dry_run || shifted.is_empty() {
+            return Ok(shifted)
+        }
+
+        self.timeslots = new_timeslots;
+        self.timeslots_generation += 1;
+
+        // Same as copy_day().
+        self.recompute_active_timeslot();
+
+        Ok(shifted)
+    }
+
+    // Returns the state actually set (which differs if it was clamped).
+    pub fn time_slot_set_actuator_state(&mut self, time_slot_id: u32,
+                                        actuator_state: ActuatorState) -> Result<ActuatorState> {
+        let actuator_state = self.accept_state(actuator_state)?;
+
+        let now = self.clock.now();
+        {
+            let time_slot = self.timeslot_mut(time_slot_id)?;
+            time_slot.actuator_state = actuator_state.clone();
+            time_slot.touch(now);
+        }
+
+        self.timeslots_generation += 1;
+
+        self.update_active_timeslot_and_notify(|active_timeslot, _| {
+            match active_timeslot.state {
+                TimeSlotActive { id, .. } if id == time_slot_id => {
+                    // This timeslot is active, update the actuator state.
+                    active_timeslot.actuator_state = actuator_state.clone();
+                },
+                _ => (),
+            }
+        });
+
+        Ok(actuator_state)
+    }
+
+    // An override replaces the time interval of the timeslot on the days it occurs on, and only
+    // on these days (the timeslot's own date range and weekdays are not extended). Therefore:
+    // - it must share at least one day with the timeslot, otherwise it would never apply;
+    // - it may overlap the timeslot's own time interval, which it replaces;
+    // - on the days it applies, it must not overlap any other timeslot or their overrides
+    //   (including where one of their overrides replaces them, as it may be removed later);
+    // - at most one override can apply on a given day.
+    pub fn time_slot_add_time_override(&mut self, time_slot_id: u32,
+                                       time_period: TimePeriod) -> Result<u32> {
+        let day_start = self.info.day_start;
+        time_period.check(day_start).map_err(|e| InvalidArgument(IAE::TimePeriod, e))?;
+
+        {
+            let ts = self.timeslot(time_slot_id)?;
+
+            // Only the days on which the override applies need to be checked.
+            let effective_period = time_period.restrict_dates(&ts.time_period).ok_or_else(|| {
+                InvalidArgument(IAE::TimePeriod,
+                                format!("override would never apply: time slot {} does not occur \
+                                         on any of its days", time_slot_id))
+            })?;
+
+            // Check for overlaps with other timeslots (including with the timeslot's jitter).
+            let jittered_period = effective_period.with_jitter(ts.jitter_minutes, day_start);
+            for (id, other_ts) in self.timeslots.iter() {
+                if *id != time_slot_id {
+                    if let Some(overlap) = other_ts.find_overlap(&jittered_period, day_start) {
+                        return Err(overlap_error(*id, overlap))
+                    }
+                }
+            }
+
+            // Also check there is no overlap with other overrides. The requirement is stronger:
+            // two overrides cannot apply to the same day (not just day and time).
+            for (id, or) in ts.time_override.iter() {
+                if let Some(overlap) = or.restrict_dates(&effective_period) {
+                    return Err(TimeOverrideOverlap {
+                        id: *id,
+                        date_range: overlap.date_range,
+                        days: overlap.days,
+                    })
+                }
+            }
+        }
+
+        // All good, add the override.
+        let now = self.clock.now();
+        let new_override_id = {
+            let time_slot = self.timeslots.get_mut(&time_slot_id).unwrap();
+            time_slot.touch(now);
+            time_slot.add_time_override(time_period)
+        };
+
+        self.timeslots_generation += 1;
+
+        self.update_active_timeslot_and_notify(|active_timeslot, now| {
+            // Same handling as set_time_period().
+            let ts = self.timeslots.get(&time_slot_id).unwrap();
+            active_timeslot.update_timeslot_modified(ts, time_slot_id, &self.timeslots,
+                                                     &self.default_schedule, now,
+                                                     self.info.day_start);
+        });
+
+        Ok(new_override_id)
+    }
+
+    pub fn time_slot_remove_time_override(&mut self, time_slot_id: u32,
+                                          time_override_id: u32) -> Result<()> {
+        let now = self.clock.now();
+        let removed = {
+            let time_slot = self.timeslot_mut(time_slot_id)?;
+            let removed = time_slot.time_override.remove(&time_override_id).is_some();
+            if removed {
+                time_slot.touch(now);
+            }
+            removed
+        };
+        if !removed {
+            return Err(TimeOverrideNotFound {
+                actuator: self.info.name.clone(),
+                time_slot_id,
+                id: time_override_id,
+            })
+        }
+
+        self.timeslots_generation += 1;
+
+        self.update_active_timeslot_and_notify(|active_timeslot, now| {
+            // Same handling as set_time_period().
+            let ts = self.timeslots.get(&time_slot_id).unwrap();
+            active_timeslot.update_timeslot_modified(ts, time_slot_id, &self.timeslots,
+                                                     &self.default_schedule, now,
+                                                     self.info.day_start);
+        });
+
+        Ok(())
+    }
+
+    // Returns the state actually set (which differs if it was clamped).
+    pub fn set_state(&self, state: ActuatorState) -> Result<ActuatorState> {
+        self.set_state_from(state, EventSource::Manual)
+    }
+
+    // Same as set_state(), reporting the state as caused by source in the history and events.
+    pub fn set_state_from(&self, state: ActuatorState,
+                          source: EventSource) -> Result<ActuatorState> {
+        let state = self.check_set_state(state)?;
+
+        let result = if self.info.simulate {
+            info!("[AT {}] simulation: not writing state {}", self.info.name, state);
+            Ok(())
+        } else {
+            self.actuator_controller.lock().unwrap().set_state(&state)
+        };
+        self.state_applied(&self.clock.now(), &state, source, &result);
+        result.map(|_| state).map_err(|e| ControllerFailure(e.to_string()))
+    }
+
+    // Set the opposite of the current state, for Toggle actuators only. If no state has been
+    // applied yet, the state the schedule calls for is inverted instead. Like set_state(), this
+    // is a manual override. Takes &mut self so that it is called with the actuator locked for
+    // writing: no other state (e.g. from the actuator thread or another client) can be applied
+    // between reading the current state and applying its opposite.
+    pub fn toggle_state(&mut self) -> Result<ActuatorState> {
         match self.info.actuator_type {
-            ActuatorType::Toggle => match state {
-                &ActuatorState::Toggle(_) => true,
-                _ => false,
+            ActuatorType::Toggle => (),
+            ref other => {
+                let detail = format!("actuator {} is of type {}, not Toggle", self.info.name,
+                                     other);
+                return Err(InvalidArgument(IAE::ActuatorId, detail))
             },
-            ActuatorType::FloatValue { min, max } => match state {
-                &ActuatorState::FloatValue(value) => (min <= value && value <= max),
-                _ => false
+        }
+
+        let current = match self.state_notifier.current().state {
+            Some(state) => state,
+            None => self.intended_state().0,
+        };
+        match current {
+            ActuatorState::Toggle(value) => self.set_state(ActuatorState::Toggle(!value)),
+            // The type has been checked.
+            _ => unreachable!(),
+        }
+    }
+
+    // The next timeslot starting now or later, within max_days days (including today).
+    pub fn next_timeslot(&self, max_days: u32) -> Option<(Date, schedule::ScheduleSlot)> {
+        schedule::find_next_timeslot_from(&self.timeslots, self.info.day_start, &self.now(),
+                                          max_days)
+    }
+
+    // When each timeslot next starts (see TimeSlot::next_occurrence()). Timeslots not starting
+    // within max_days days are omitted.
+    pub fn next_occurrences(&self, max_days: u32) -> BTreeMap<u32, DateTime> {
+        let now = self.now();
+        let day_start = self.info.day_start;
+        self.timeslots.iter()
+            .filter_map(|(id, ts)| {
+                ts.next_occurrence(*id, &now, max_days, day_start).map(|dt| (*id, dt))
+            })
+            .collect()
+    }
+
+    // The state scheduled at dt, relative to the actuator's days (see schedule::resolve_state()).
+    pub fn state_at(&self, dt: &DateTime) -> Result<schedule::ResolvedState> {
+        if !dt.time.valid() {
+            return Err(InvalidArgument(IAE::DateTime, format!("invalid time {}", dt.time)))
+        }
+
+        Ok(schedule::resolve_state(&self.timeslots, &self.default_schedule, self.info.day_start,
+                                   dt))
+    }
+
+    // The states scheduled on date, in the order the actuator goes through them (see
+    // schedule::resolve_transitions()). As for state_at(), the vacation is not taken into account.
+    pub fn states_on(&self, date: Date) -> Vec<schedule::ResolvedState> {
+        schedule::resolve_transitions(&self.timeslots, &self.default_schedule, self.info.day_start,
+                                      date)
+    }
+
+    // Check that set_state() would accept state, returning the state it would set.
+    pub fn check_set_state(&self, state: ActuatorState) -> Result<ActuatorState> {
+        if !self.info.enabled {
+            return Err(ActuatorDisabled)
+        }
+        self.accept_state(state)
+    }
+
+    // Check that add_time_slot() would accept the timeslot, returning the timeslot it would add.
+    pub fn check_new_time_slot(&self, time_slot: NewTimeSlot) -> Result<TimeSlot> {
+        self.check_room(1)?;
+        let ts = self.new_time_slot(time_slot)?;
+        self.check_overlap(&ts)?;
+        Ok(ts)
+    }
+
+    // Check everything about time_slot but how it fits with the existing timeslots, returning the
+    // timeslot to add, created now.
+    fn new_time_slot(&self, time_slot: NewTimeSlot) -> Result<TimeSlot> {
+        time_slot.time_period.check(self.info.day_start)
+            .map_err(|e| InvalidArgument(IAE::TimePeriod, e))?;
+        let actuator_state = self.accept_state(time_slot.actuator_state)?;
+        check_label(&time_slot.label)?;
+        check_jitter(time_slot.jitter_minutes)?;
+        self.check_condition(&time_slot.condition)?;
+
+        let mut ts = TimeSlot::new(time_slot.enabled, actuator_state, time_slot.time_period);
+        ts.label = time_slot.label;
+        ts.jitter_minutes = time_slot.jitter_minutes;
+        ts.condition = time_slot.condition;
+        let now = self.clock.now();
+        ts.created_at = Some(now.clone());
+        ts.touch(now);
+        Ok(ts)
+    }
+
+    // None is always valid (no condition).
+    fn check_condition(&self, condition: &Option<TimeSlotCondition>) -> Result<()> {
+        if let Some(ref condition) = *condition {
+            let detail = match self.condition_sources {
+                Some((actuator_id, _)) if condition.actuator_id == actuator_id =>
+                    Some("a timeslot cannot depend on its own actuator".to_string()),
+                Some((_, ref sources)) => match sources.get(condition.actuator_id as usize) {
+                    Some(source) if !source.actuator_type.accepts(&condition.state) =>
+                        Some(format!("{} does not match type {}", condition.state,
+                                     source.actuator_type)),
+                    Some(_) => None,
+                    None => Some(format!("no actuator with ID {}", condition.actuator_id)),
+                },
+                None => Some("not part of a server".to_string()),
+            };
+            if let Some(detail) = detail {
+                return Err(InvalidArgument(IAE::Condition, detail))
+            }
+        }
+        Ok(())
+    }
+
+    // Jitter included, on both sides.
+    fn check_overlap(&self, new_ts: &TimeSlot) -> Result<()> {
+        for (id, ts) in self.timeslots.iter() {
+            if let Some(overlap) = ts.find_overlap_time_slot(new_ts, self.info.day_start) {
+                return Err(overlap_error(*id, overlap))
+            }
+        }
+        Ok(())
+    }
+
+    // Check that count timeslots can be added (see ActuatorInfo::max_timeslots).
+    fn check_room(&self, count: usize) -> Result<()> {
+        match self.info.max_timeslots {
+            Some(max) if self.timeslots.len() + count > max as usize =>
+                Err(LimitExceeded { max_timeslots: max }),
+            _ => Ok(()),
+        }
+    }
+
+    // Check a state passed by a client, clamping and quantizing it first if the actuator is
+    // configured to.
+    pub fn accept_state(&self, state: ActuatorState) -> Result<ActuatorState> {
+        // Checked first, as clamping would turn infinities into a bound.
+        if !state.is_finite() {
+            let detail = format!("{} is not a finite number", state);
+            return Err(InvalidArgument(IAE::ActuatorState, detail))
+        }
+
+        let state = match (&self.info.actuator_type, state) {
+            (&ActuatorType::FloatValue { min, max, step }, ActuatorState::FloatValue(value))
+                if self.info.clamp || self.info.quantize => {
+                let mut value = if self.info.clamp { value.max(min).min(max) } else { value };
+                if let (true, Some(step)) = (self.info.quantize, step) {
+                    value = min + ((value - min) / step).round() * step;
+                    // max may not be on a step.
+                    if value > max {
+                        value -= step;
+                    }
+                }
+                ActuatorState::FloatValue(value)
+            },
+            (&ActuatorType::IntValue { min, max }, ActuatorState::IntValue(value))
+                if self.info.clamp =>
+                ActuatorState::IntValue(value.max(min).min(max)),
+            (_, state) => state,
+        };
+
+        if self.valid_state(&state) {
+            return Ok(state)
+        }
+
+        let detail = match (&self.info.actuator_type, &state) {
+            (&ActuatorType::FloatValue { min, max, step: Some(step) },
+             &ActuatorState::FloatValue(value)) if min <= value && value <= max =>
+                format!("{} is not on a step of {} from {}, the nearest value accepted is {}",
+                        value, step, min, min + ((value - min) / step).round() * step),
+            _ => format!("{} not accepted by type {}", state, self.info.actuator_type),
+        };
+        Err(InvalidArgument(IAE::ActuatorState, detail))
+    }
+
+    pub fn timeslot(&self, time_slot_id: u32) -> Result<&TimeSlot> {
+        let actuator = &self.info.name;
+        self.timeslots.get(&time_slot_id)
+            .ok_or_else(|| TimeSlotNotFound { actuator: actuator.clone(), id: time_slot_id })
+    }
+
+    fn timeslot_mut(&mut self, time_slot_id: u32) -> Result<&mut TimeSlot> {
+        let actuator = &self.info.name;
+        self.timeslots.get_mut(&time_slot_id)
+            .ok_or_else(|| TimeSlotNotFound { actuator: actuator.clone(), id: time_slot_id })
+    }
+
+    fn valid_state(&self, state: &ActuatorState) -> bool {
+        self.info.actuator_type.accepts(state)
+    }
+
+    // The current date and time, relative to the actuator's days (as the schedule is). Timestamps
+    // (e.g. TimeSlot::modified_at) keep using the clock's time directly.
+    fn now(&self) -> DateTime {
+        self.info.day_start.date_time(&self.clock.now(), DayStart::default())
+    }
+
+    // Recompute the active timeslot from scratch, and have the actuator thread apply it even if it
+    // did not change. This is a way to recover if the active timeslot got out of sync with the
+    // schedule (or the actuator with the active timeslot).
+    pub fn recompute_active_timeslot(&self) {
+        self.update_active_timeslot(|active_timeslot, now| {
+            *active_timeslot = ActiveTimeSlot::compute(now, &self.timeslots,
+                                                       &self.default_schedule,
+                                                       self.info.day_start);
+        }, true);
+    }
+
+    // Called whenever the state of the actuator actuator_id changes, to apply the active timeslot
+    // again if its condition depends on it.
+    pub fn condition_source_changed(&self, actuator_id: u32) {
+        let active_id = match self.thread_comm.lock().unwrap().active_timeslot.state {
+            TimeSlotActive { id, .. } => id,
+            _ => return,
+        };
+
+        let depends = match self.timeslots.get(&active_id).and_then(|ts| ts.condition.as_ref()) {
+            Some(condition) => condition.actuator_id == actuator_id,
+            None => false,
+        };
+        if depends {
+            self.reapply_if_active(active_id);
+        }
+    }
+
+    // Have the thread apply the active timeslot again if it is time_slot_id, e.g. to check its
+    // condition again.
+    fn reapply_if_active(&self, time_slot_id: u32) {
+        let active = match self.thread_comm.lock().unwrap().active_timeslot.state {
+            TimeSlotActive { id, .. } => id == time_slot_id,
+            _ => false,
+        };
+        if active {
+            self.update_active_timeslot(|_, _| {}, true);
+        }
+    }
+
+    // The active timeslot as it is applied: if it is a timeslot whose condition does not hold, the
+    // default state at now instead, until the end of the timeslot.
+    fn apply_condition(&self, active_timeslot: ActiveTimeSlot, now: &DateTime) -> ActiveTimeSlot {
+        let id = match active_timeslot.state {
+            TimeSlotActive { id, .. } => id,
+            _ => return active_timeslot,
+        };
+
+        match self.timeslots.get(&id).and_then(|ts| ts.condition.as_ref()) {
+            Some(condition) if !self.condition_holds(condition) => ActiveTimeSlot {
+                state: DefaultStateActive { next_id: None, next_override_id: None },
+                end_time: active_timeslot.end_time,
+                actuator_state: self.default_schedule.state_at(now.time, self.info.day_start)
+                    .clone(),
             },
+            _ => active_timeslot,
+        }
+    }
+
+    // Whether the state last applied to the actuator condition depends on is the expected one.
+    fn condition_holds(&self, condition: &TimeSlotCondition) -> bool {
+        let source = match self.condition_sources {
+            Some((_, ref sources)) => sources.get(condition.actuator_id as usize),
+            None => None,
+        };
+        match source {
+            Some(source) => source.notifier.current().state.as_ref() == Some(&condition.state),
+            None => false,
         }
     }
 
     fn update_active_timeslot_and_notify<F>(&self, func: F)
     where
-        F: FnOnce(&mut ActiveTimeSlot)
+        F: FnOnce(&mut ActiveTimeSlot, &DateTime)
+    {
+        self.update_active_timeslot(func, false);
+    }
+
+    // Notify the actuator thread if func modified the active timeslot, or unconditionally if
+    // always_notify is true.
+    fn update_active_timeslot<F>(&self, func: F, always_notify: bool)
+    where
+        F: FnOnce(&mut ActiveTimeSlot, &DateTime)
     {
+        let now = self.now();
         let mut thread_comm_guard = self.thread_comm.lock().unwrap();
-        let ThreadComm { active_timeslot, modified } = &mut *thread_comm_guard;
+        let ThreadComm { active_timeslot, modified, modified_time, .. } = &mut *thread_comm_guard;
 
         let mut new_active_ts = active_timeslot.clone();
-        func(&mut new_active_ts);
+        match self.suspended_schedule(&now) {
+            // The schedule is suspended, whatever changed. The state itself may have changed
+            // though (e.g. if it is the default state).
+            Some(active_timeslot) => new_active_ts = active_timeslot,
+            None => func(&mut new_active_ts, &now),
+        }
 
-        if &new_active_ts != active_timeslot {
+        if always_notify || &new_active_ts != active_timeslot {
             *active_timeslot = new_active_ts;
             *modified = true;
+            *modified_time = now;
             self.thread_comm_cv.notify_one();
         }
     }
@@ -406,11 +1962,12 @@ impl Actuator {
 
 impl ValidCheck for Actuator {
     fn valid(&self) -> bool {
-        self.info.valid() && self.valid_state(&self.default_state)
+        self.info.valid() &&
+            self.default_schedule.states().all(|state| self.valid_state(state))
     }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 enum ActiveTimeSlotState {
     TimeSlotActive {
         id: u32,
@@ -420,10 +1977,18 @@ enum ActiveTimeSlotState {
         next_id: Option<u32>,
         next_override_id: Option<u32>,
     },
+    // Until the end of the day, when the thread checks whether the vacation is over.
+    VacationActive {
+        until: Date,
+    },
+    // Same as VacationActive, the source's changes are notified (see Actuator::set_follow()).
+    FollowActive {
+        actuator_id: u32,
+    },
 }
 use self::ActiveTimeSlotState::*;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 struct ActiveTimeSlot {
     state: ActiveTimeSlotState,
     end_time: Time,
@@ -440,55 +2005,66 @@ impl ActiveTimeSlot {
         }
     }
 
-    fn default_state(actuator_state: ActuatorState) -> ActiveTimeSlot {
+    fn vacation(until: Date, actuator_state: ActuatorState, day_start: DayStart) -> ActiveTimeSlot {
         ActiveTimeSlot {
-            state: DefaultStateActive {
-                next_id: None,
-                next_override_id: None,
-            },
-            end_time: Time::MAX,
+            state: VacationActive { until },
+            end_time: day_start.last(),
             actuator_state,
         }
     }
 
-    fn default_state_until(next_id: u32, next_override_id: Option<u32>,
-                           end_time: Time, actuator_state: ActuatorState) -> ActiveTimeSlot {
+    fn follow(actuator_id: u32, actuator_state: ActuatorState,
+              day_start: DayStart) -> ActiveTimeSlot {
         ActiveTimeSlot {
-            state: DefaultStateActive {
-                next_id: Some(next_id),
-                next_override_id,
-            },
-            end_time,
+            state: FollowActive { actuator_id },
+            end_time: day_start.last(),
             actuator_state,
         }
     }
 
-    fn compute(now: &DateTime, timeslots: &BTreeMap<u32, TimeSlot>, default_state: ActuatorState)
-        -> ActiveTimeSlot
+    // Only valid if the default state is active and timeslot_start is before its end time: the
+    // timeslot becomes the next one, without any change to the current default state.
+    fn set_next_timeslot(&mut self, next_id: u32, next_override_id: Option<u32>,
+                         timeslot_start: Time) {
+        self.state = DefaultStateActive {
+            next_id: Some(next_id),
+            next_override_id,
+        };
+        self.end_time = timeslot_start;
+    }
+
+    // As everywhere below, now and the times are relative to day_start (the actuator's). now may be
+    // in the middle of a timeslot (e.g. after the clock jumped).
+    fn compute(now: &DateTime, timeslots: &BTreeMap<u32, TimeSlot>,
+               default_schedule: &DefaultSchedule, day_start: DayStart) -> ActiveTimeSlot
     {
-        let next_slot = schedule::find_next_timeslot(timeslots, now);
+        let resolved = schedule::resolve_state(timeslots, default_schedule, day_start, now);
+        let state = match resolved.origin {
+            schedule::StateOrigin::TimeSlot { id, override_id } =>
+                TimeSlotActive { id, override_id },
+            schedule::StateOrigin::Default { next_id, next_override_id } =>
+                DefaultStateActive { next_id, next_override_id },
+        };
 
-        if let Some(slot) = next_slot {
-            if slot.time_interval.start == now.time {
-                Self::timeslot(slot.id, slot.override_id, slot.time_interval.end,
-                               slot.actuator_state)
-            } else {
-                Self::default_state_until(slot.id, slot.override_id, slot.time_interval.start,
-                                          default_state)
-            }
-        } else {
-            Self::default_state(default_state)
+        ActiveTimeSlot {
+            state,
+            end_time: resolved.time_interval.end,
+            actuator_state: resolved.actuator_state,
         }
     }
 
-    fn update_timeslot_added(&mut self, timeslot: &TimeSlot, id: u32) {
-        let now = DateTime::now();
+    fn update_timeslot_added(&mut self, timeslot: &TimeSlot, id: u32, now: &DateTime,
+                             day_start: DayStart) {
+        if !timeslot.enabled {
+            // A disabled timeslot never becomes active.
+            return
+        }
 
         if let DefaultStateActive { .. } = self.state {
             if let Some((time_interval_today, override_id))
-                = timeslot.time_interval_on(now.date)
+                = timeslot.time_interval_on(id, now.date, day_start)
             {
-                if time_interval_today.contains(&now.time) {
+                if day_start.contains(&time_interval_today, now.time) {
                     // The new timeslot is currently active.
                     *self = Self::timeslot(
                         id,
@@ -496,23 +2072,20 @@ impl ActiveTimeSlot {
                         time_interval_today.end,
                         timeslot.actuator_state.clone(),
                     );
-                } else if now.time < time_interval_today.start &&
-                    time_interval_today.start < self.end_time
+                } else if day_start.is_before(now.time, time_interval_today.start) &&
+                    day_start.is_before(time_interval_today.start, self.end_time)
                 {
-                    // The new timeslot will become active before any other.
-                    *self = Self::default_state_until(
-                        id,
-                        override_id,
-                        time_interval_today.start,
-                        self.actuator_state.clone(),
-                    );
+                    // The new timeslot will become active before any other (and before the
+                    // default state changes).
+                    self.set_next_timeslot(id, override_id, time_interval_today.start);
                 }
             }
         }
     }
 
     fn update_timeslot_removed(&mut self, timeslot_id: u32, timeslots: &BTreeMap<u32, TimeSlot>,
-                               default_state: &ActuatorState) {
+                               default_schedule: &DefaultSchedule, now: &DateTime,
+                               day_start: DayStart) {
         let recompute = match self.state {
             // The removed timeslot was active, the default state becomes active.
             TimeSlotActive { id, .. } if id == timeslot_id => true,
@@ -523,24 +2096,31 @@ impl ActiveTimeSlot {
         };
 
         if recompute {
-            *self = Self::compute(&DateTime::now(), &timeslots, default_state.clone());
+            *self = Self::compute(now, &timeslots, default_schedule, day_start);
         }
     }
 
     fn update_timeslot_modified(&mut self, timeslot: &TimeSlot, timeslot_id: u32,
                                 timeslots: &BTreeMap<u32, TimeSlot>,
-                                default_state: &ActuatorState) {
+                                default_schedule: &DefaultSchedule, now: &DateTime,
+                                day_start: DayStart) {
         // It would be possible to make a finer-grained analysis, based on exactly how the timeslot
         // was modified, to avoid recalculating today's next timeslot. However, handling this
         // becomes very complex and error-prone, so the focus here is on correctness.
 
+        if !timeslot.enabled {
+            // A disabled timeslot never becomes active, handle it like a removed one to be safe.
+            self.update_timeslot_removed(timeslot_id, timeslots, default_schedule, now,
+                                         day_start);
+            return
+        }
+
         let mut recompute = false;
-        let now = DateTime::now();
 
         if let Some((time_interval_today, override_id))
-            = timeslot.time_interval_on(now.date)
+            = timeslot.time_interval_on(timeslot_id, now.date, day_start)
         {
-            if time_interval_today.contains(&now.time) {
+            if day_start.contains(&time_interval_today, now.time) {
                 // The timeslot is active.
                 *self = Self::timeslot(
                     timeslot_id,
@@ -556,16 +2136,12 @@ impl ActiveTimeSlot {
                         recompute = true;
                     },
                     DefaultStateActive { next_id, .. } => {
-                        if now.time < time_interval_today.start &&
-                            time_interval_today.start <= self.end_time
+                        if day_start.is_before(now.time, time_interval_today.start) &&
+                            !day_start.is_before(self.end_time, time_interval_today.start)
                         {
                             // The timeslot is the next to become active.
-                            *self = Self::default_state_until(
-                                timeslot_id,
-                                override_id,
-                                time_interval_today.start,
-                                self.actuator_state.clone(),
-                            );
+                            self.set_next_timeslot(timeslot_id, override_id,
+                                                   time_interval_today.start);
                         } else if next_id == Some(timeslot_id) {
                             // The timeslot was the next to become active and its start time has
                             // been delayed, we need to recalculate the next timeslot.
@@ -590,56 +2166,204 @@ impl ActiveTimeSlot {
         }
 
         if recompute {
-            *self = Self::compute(&now, &timeslots, default_state.clone());
+            *self = Self::compute(now, &timeslots, default_schedule, day_start);
         }
     }
 }
 
+fn check_jitter(jitter_minutes: u32) -> Result<()> {
+    if jitter_minutes > TimeSlot::MAX_JITTER_MINUTES {
+        return Err(InvalidArgument(IAE::Jitter,
+                                   format!("{} min is more than the maximum ({} min)",
+                                           jitter_minutes, TimeSlot::MAX_JITTER_MINUTES)))
+    }
+    Ok(())
+}
+
+// None is always valid (no label).
+fn check_label(label: &Option<String>) -> Result<()> {
+    match *label {
+        Some(ref label) if label.is_empty() || label.chars().count() > TimeSlot::MAX_LABEL_LEN => {
+            let detail = format!("must be 1 to {} characters long", TimeSlot::MAX_LABEL_LEN);
+            Err(InvalidArgument(IAE::Label, detail))
+        },
+        _ => Ok(()),
+    }
+}
+
+fn overlap_error(id: u32, overlap: Overlap) -> Error {
+    TimeSlotOverlap {
+        id,
+        slot_interval: overlap.time_interval,
+        time_interval: overlap.intersection.time_interval,
+        date_range: overlap.intersection.date_range,
+        days: overlap.intersection.days,
+    }
+}
+
+// If the time at which the actuator thread wakes up differs from the expected one by more than
+// this, assume the clock jumped (suspend/resume, NTP step...).
+const CLOCK_JUMP_THRESHOLD_MIN: i64 = 2;
+
+fn clock_jumped(expected: &DateTime, actual: &DateTime, day_start: DayStart) -> bool {
+    day_start.minutes_since(actual, expected).abs() > CLOCK_JUMP_THRESHOLD_MIN
+}
+
+// How far ahead the actuator thread looks for the next timeslot when logging the default state.
+const NEXT_TIMESLOT_LOG_DAYS: u32 = 7;
+
+// When a slot starting at now and ending at end_time ends. In case the slot lasts until the end of
+// the day, that is the start of the next day.
+fn end_date_time(now: &DateTime, end_time: Time, day_start: DayStart) -> DateTime {
+    if end_time == day_start.last() {
+        DateTime { date: now.date + 1, time: day_start.first() }
+    } else {
+        DateTime { date: now.date, time: end_time }
+    }
+}
+
+// How many seconds to wait from now (second seconds into the minute) until expected_end, or None
+// if it has already been reached. As now is only known to the minute, ignoring second would make
+// us wake up to a minute late.
+fn seconds_until(expected_end: &DateTime, now: &DateTime, second: u32,
+                 day_start: DayStart) -> Option<u64> {
+    let wait_sec = day_start.minutes_since(expected_end, now) * 60 - second as i64;
+    // wait_sec can be negative (huge latency between the active timeslot being modified and us
+    // being woken up, or the clock jumped forward), handle like wait_sec=0 (timeout).
+    if wait_sec > 0 {
+        Some(wait_sec as u64)
+    } else {
+        None
+    }
+}
+
 #[derive(Clone)]
 struct ThreadComm {
     active_timeslot: ActiveTimeSlot,
     // The bool is set to true when the active timeslot is modified (to be used with the condvar).
     modified: bool,
+    // When the active timeslot was last modified.
+    modified_time: DateTime,
+    // If false, the thread does nothing until the actuator is enabled again.
+    enabled: bool,
+    // Once set, the thread exits.
+    stopped: bool,
+    // If set, the thread never sleeps longer than that before checking the time again, instead of
+    // relying on the timeout of the condvar only (see Actuator::set_max_wait()).
+    max_wait_sec: Option<u64>,
+}
+
+// Log the state the actuator thread is about to apply, and where it comes from.
+fn log_new_state(actuator: &Actuator, active_timeslot: &ActiveTimeSlot, now: &DateTime) {
+    let state_str = match active_timeslot.state {
+        TimeSlotActive { id, override_id } => format!("timeslot {:?}:{:?}", id, override_id),
+        DefaultStateActive { next_id: None, .. } => {
+            // No timeslot until end_time, tell when the next one is (possibly on another day).
+            match schedule::find_next_timeslot_from(&actuator.timeslots, actuator.info.day_start,
+                                                    now, NEXT_TIMESLOT_LOG_DAYS) {
+                Some((date, slot)) => format!("default, next timeslot {:?}:{:?} on {} {}",
+                                              slot.id, slot.override_id, date,
+                                              slot.time_interval.start),
+                None => format!("default"),
+            }
+        },
+        DefaultStateActive { next_id, next_override_id } =>
+            format!("default until {:?}:{:?}", next_id, next_override_id),
+        VacationActive { until } => format!("vacation until {}", until),
+        FollowActive { actuator_id } => format!("following actuator {}", actuator_id),
+    };
+
+    info!(
+        "[AT {}] {} {}: new state {} ({}) until {}",
+        actuator.info.name,
+        now.date,
+        now.time,
+        active_timeslot.actuator_state,
+        state_str,
+        active_timeslot.end_time
+    );
 }
 
 fn actuator_thread(actuator: ActuatorHandle) {
-    let (thread_comm_lock, thread_comm_cv, actuator_controller) = {
+    let (thread_comm_lock, thread_comm_cv, actuator_controller, clock, day_start) = {
         let guard = actuator.read().unwrap();
-        (guard.thread_comm.clone(), guard.thread_comm_cv.clone(), guard.actuator_controller.clone())
+        (guard.thread_comm.clone(), guard.thread_comm_cv.clone(), guard.actuator_controller.clone(),
+         guard.clock.clone(), guard.info.day_start)
     };
-
-    let mut now = DateTime::now();
+    // Same as Actuator::now(). Timestamps passed outside (history, events, metrics) are converted
+    // back to the default days.
+    let clock_now = || day_start.date_time(&clock.now(), DayStart::default());
+    let timestamp = |dt: &DateTime| DayStart::default().date_time(dt, day_start);
+
+    let mut now = clock_now();
+    // The physical state is only unknown before the first write.
+    let mut first_write = true;
+    // Failed attempts at applying the active timeslot's state so far, and when to try again if
+    // the failure policy allows it.
+    let mut failed_attempts = 0;
+    let mut retry_at: Option<time::Instant> = None;
 
     loop {
         // Note: we never keep the lock. If the active timeslot has been modified, we don't need to
         // keep it (if it gets modified again later on, we will realise during the next iteration),
         // and if we have reached end_time, then we cannot keep it because we need to lock the
         // actuator (risk of deadlock).
-        let ThreadComm { active_timeslot, modified } = {
+        let (ThreadComm { active_timeslot, modified, modified_time, enabled, stopped, .. },
+             expected_end, retry_due) = {
             let mut thread_comm_guard = thread_comm_lock.lock().unwrap();
 
-            // Wait until either end_time, or the active timeslot is modified.
-            let end_time = thread_comm_guard.active_timeslot.end_time;
-            // In case the timeslot lasts until the end of the day, wait until the start of the
-            // next day (one more minute).
-            let adjust_min = if end_time == Time::MAX { 1 } else { 0 };
+            // Wait until either end_time, or the active timeslot is modified (or a retry is due).
+            let expected_end = end_date_time(&now, thread_comm_guard.active_timeslot.end_time,
+                                             day_start);
+            let mut last_check = clock_now();
+            let mut retry_due = false;
 
             while !thread_comm_guard.modified {
-                now.time = Time::now();
-                let wait_sec = (end_time.sub_minute(now.time) + adjust_min) * 60;
-                // Theoretically wait_sec can be negative (huge latency between the active timeslot
-                // being modified and us being woken up), handle like wait_sec=0 (timeout).
-                if wait_sec <= 0 {
+                if !thread_comm_guard.enabled {
+                    // No end_time to wait for, only wait until we are enabled again.
+                    thread_comm_guard = thread_comm_cv.wait(thread_comm_guard).unwrap();
+                    continue;
+                }
+
+                let (real_now, second) = clock.now_precise();
+                let real_now = day_start.date_time(&real_now, DayStart::default());
+                // The clock jumped backwards while we were waiting, end_time is meaningless: stop
+                // waiting, the check below recomputes the active timeslot. A jump forwards is
+                // caught the same way, as end_time is reached.
+                if real_now < last_check && clock_jumped(&last_check, &real_now, day_start) {
                     break;
                 }
+                last_check = real_now.clone();
+
+                let wait_sec = match seconds_until(&expected_end, &real_now, second, day_start) {
+                    Some(wait_sec) => wait_sec,
+                    None => break,
+                };
+
+                let (wait_sec, mut capped) = match thread_comm_guard.max_wait_sec {
+                    Some(max_wait_sec) if wait_sec > max_wait_sec => (max_wait_sec, true),
+                    _ => (wait_sec, false),
+                };
+                let mut wait = time::Duration::from_secs(wait_sec);
+
+                if let Some(retry_at) = retry_at {
+                    let instant_now = time::Instant::now();
+                    if instant_now >= retry_at {
+                        retry_due = true;
+                        break;
+                    }
+                    if retry_at - instant_now < wait {
+                        wait = retry_at - instant_now;
+                        capped = true;
+                    }
+                }
 
-                let res = thread_comm_cv.wait_timeout(
-                    thread_comm_guard,
-                    time::Duration::from_secs(wait_sec as u64),
-                ).unwrap();
+                let res = thread_comm_cv.wait_timeout(thread_comm_guard, wait).unwrap();
                 thread_comm_guard = res.0;
 
-                if res.1.timed_out() {
+                // If the wait was capped, check the time again before deciding whether end_time
+                // was reached.
+                if res.1.timed_out() && !capped {
                     break;
                 }
             }
@@ -647,30 +2371,165 @@ fn actuator_thread(actuator: ActuatorHandle) {
             let thread_comm = thread_comm_guard.clone();
             if thread_comm_guard.modified {
                 thread_comm_guard.modified = false;
+                // A new state to apply, the pending retry is obsolete.
+                retry_due = false;
             }
-            thread_comm
+            (thread_comm, expected_end, retry_due)
         };
 
-        if modified {
-            // The active timeslot has been modified, read it.
-            let state_str = match active_timeslot.state {
-                TimeSlotActive { id, override_id } => format!("timeslot {:?}:{:?}", id, override_id),
-                DefaultStateActive { next_id, next_override_id } => format!("default until {:?}:{:?}", next_id, next_override_id),
-            };
+        if stopped {
+            return
+        }
+
+        if !enabled {
+            // Enabling the actuator recomputes the active timeslot, nothing to keep track of.
+            continue;
+        }
+
+        // Where the clock should be: when the active timeslot was computed if it has been
+        // modified, or else its end (nothing to check if we only woke up to retry).
+        let expected = if modified {
+            Some(&modified_time)
+        } else if retry_due {
+            None
+        } else {
+            Some(&expected_end)
+        };
+        let real_now = clock_now();
+        let jumped = match expected {
+            Some(expected) => clock_jumped(expected, &real_now, day_start),
+            None => false,
+        };
+
+        if jumped {
+            // The clock jumped (either way), or the host was suspended: the active timeslot we
+            // know about is meaningless. Recompute it from the actual time, and go through the
+            // loop again to apply it.
+            let actuator_guard = actuator.read().unwrap();
+            let mut thread_comm_guard = thread_comm_lock.lock().unwrap();
+
+            // Unless it was modified again in the meantime, in which case it is up to date.
+            if !thread_comm_guard.modified {
+                warn!(
+                    "[AT {}] clock jump detected: expected {}, now {}",
+                    actuator_guard.info.name,
+                    expected.unwrap(),
+                    real_now
+                );
+
+                thread_comm_guard.active_timeslot =
+                    actuator_guard.compute_active_timeslot(&real_now);
+                thread_comm_guard.modified = true;
+                thread_comm_guard.modified_time = real_now;
+            }
+            continue;
+        }
 
+        if modified || retry_due {
             let actuator_guard = actuator.read().unwrap();
 
-            println!(
-                "[AT {}] {} {}: new state {} ({}) until {}",
-                actuator_guard.info.name,
-                now.date,
-                now.time,
-                active_timeslot.actuator_state,
-                state_str,
-                active_timeslot.end_time
-            );
-
-            actuator_controller.lock().unwrap().set_state(&active_timeslot.actuator_state);
+            // A timeslot whose condition does not hold leaves the default state in place.
+            let applied_timeslot = actuator_guard.apply_condition(active_timeslot.clone(),
+                                                                  &modified_time);
+            if applied_timeslot != active_timeslot {
+                info!("[AT {}] condition of the active timeslot not met, applying the default \
+                       state", actuator_guard.info.name);
+            }
+            let active_timeslot = applied_timeslot;
+
+            if retry_due {
+                // Same active timeslot (and now) as the failed attempt.
+                info!("[AT {}] retrying to apply state {} (attempt {} of {})",
+                      actuator_guard.info.name, active_timeslot.actuator_state,
+                      failed_attempts + 1, actuator_guard.failure_policy.retries + 1);
+            } else {
+                // The active timeslot has been modified, read it.
+                now = modified_time;
+                failed_attempts = 0;
+
+                log_new_state(&actuator_guard, &active_timeslot, &now);
+            }
+
+            let result = if actuator_guard.info.simulate {
+                info!("[AT {}] simulation: not writing state {}", actuator_guard.info.name,
+                      active_timeslot.actuator_state);
+                Ok(())
+            } else {
+                let mut controller = actuator_controller.lock().unwrap();
+
+                // If the actuator is already in the right state (e.g. the daemon restarted), don't
+                // actuate it needlessly.
+                let already_set = first_write &&
+                    controller.get_state(&actuator_guard.info.actuator_type).as_ref()
+                        == Some(&active_timeslot.actuator_state);
+                first_write = false;
+
+                if already_set {
+                    debug!("[AT {}] already in state {}, not writing it",
+                           actuator_guard.info.name, active_timeslot.actuator_state);
+                    Ok(())
+                } else {
+                    controller.set_state(&active_timeslot.actuator_state)
+                }
+            };
+
+            if let Err(ref e) = result {
+                error!("[AT {}] failed to set state: {}", actuator_guard.info.name, e);
+            }
+
+            let source = match active_timeslot.state {
+                TimeSlotActive { id, override_id } => EventSource::TimeSlot { id, override_id },
+                DefaultStateActive { .. } => EventSource::Default,
+                VacationActive { .. } => EventSource::Vacation,
+                FollowActive { actuator_id } => EventSource::Follow { actuator_id },
+            };
+            actuator_guard.state_applied(&timestamp(&now), &active_timeslot.actuator_state, source,
+                                         &result);
+
+            let next_transition = timestamp(&end_date_time(&now, active_timeslot.end_time,
+                                                           day_start));
+            if let Some(ref metrics) = actuator_guard.metrics {
+                metrics.lock().unwrap().record_state(&active_timeslot.actuator_state,
+                                                     result.is_ok(), next_transition.clone());
+            }
+
+            let policy = actuator_guard.failure_policy;
+            if result.is_ok() {
+                failed_attempts = 0;
+                retry_at = None;
+            } else if failed_attempts < policy.retries {
+                failed_attempts += 1;
+                retry_at = Some(time::Instant::now() +
+                                time::Duration::from_secs(policy.retry_delay_sec as u64));
+            } else {
+                error!("[AT {}] giving up applying state {} after {} attempt(s)",
+                       actuator_guard.info.name, active_timeslot.actuator_state,
+                       failed_attempts + 1);
+                failed_attempts = 0;
+                retry_at = None;
+                if let Some(ref metrics) = actuator_guard.metrics {
+                    metrics.lock().unwrap().gave_up();
+                }
+
+                let default_state = actuator_guard.default_state();
+                if policy.fallback == FailureFallback::RevertToDefault &&
+                    active_timeslot.actuator_state != *default_state {
+                    warn!("[AT {}] reverting to the default state {}", actuator_guard.info.name,
+                          default_state);
+
+                    let result = actuator_controller.lock().unwrap().set_state(default_state);
+                    if let Err(ref e) = result {
+                        error!("[AT {}] failed to set the default state: {}",
+                               actuator_guard.info.name, e);
+                    }
+                    actuator_guard.state_applied(&timestamp(&now), default_state,
+                                                 EventSource::Fallback, &result);
+                    if let Some(ref metrics) = actuator_guard.metrics {
+                        metrics.lock().unwrap().record_state(default_state, result.is_ok(),
+                                                             next_transition);
+                    }
+                }
+            }
         } else {
             // We have reached end_time. Find the new active timeslot.
 
@@ -688,32 +2547,969 @@ fn actuator_thread(actuator: ActuatorHandle) {
             if let DefaultStateActive { next_id: Some(next_id), next_override_id }
                 = active_timeslot.state
             {
-                // The next timeslot becomes the active one.
-                let next_timeslot = actuator_guard.timeslots.get(&next_id).unwrap();
-                thread_comm_guard.active_timeslot = ActiveTimeSlot::timeslot(
-                    next_id,
-                    next_override_id,
-                    next_timeslot.time_interval_on(now.date).unwrap().0.end,
-                    next_timeslot.actuator_state.clone(),
-                );
+                now = expected_end;
+
+                // The next timeslot becomes the active one. It should still be there and occur
+                // today, but this is not guaranteed: it may have been modified in a way that did
+                // not affect the active timeslot from the point of view of the modifier (e.g. if
+                // its idea of today differs from ours near the day boundary). In that case,
+                // compute the new active timeslot from scratch instead.
+                let next_slot = match actuator_guard.timeslots.get(&next_id) {
+                    Some(ts) if ts.enabled => ts.time_interval_on(next_id, now.date, day_start)
+                        .map(|(time_interval, _)| (time_interval, ts.actuator_state.clone())),
+                    _ => None,
+                };
+
+                thread_comm_guard.active_timeslot = match next_slot {
+                    Some((time_interval, actuator_state)) => ActiveTimeSlot::timeslot(
+                        next_id,
+                        next_override_id,
+                        time_interval.end,
+                        actuator_state,
+                    ),
+                    None => actuator_guard.compute_active_timeslot(&now),
+                };
             } else {
-                if active_timeslot.end_time == Time::MAX {
-                    // This was the last timeslot for today. Move to the next day.
-                    now.date += 1;
-                    now.time = Time::MIN;
-                } else {
-                    now.time = active_timeslot.end_time;
-                }
+                // If this was the last timeslot for today, expected_end is already on the next
+                // day. We do not look further ahead to sleep across days
+                // (find_next_timeslot_from() is only used for logging): the default schedule, the
+                // vacation and the timeslot conditions are all evaluated day by day, so the active
+                // timeslot must be recomputed at every day boundary anyway.
+                now = expected_end;
 
                 // Find the next timeslot.
-                thread_comm_guard.active_timeslot = ActiveTimeSlot::compute(
-                    &now,
-                    &actuator_guard.timeslots,
-                    actuator_guard.default_state.clone(),
-                );
+                thread_comm_guard.active_timeslot = actuator_guard.compute_active_timeslot(&now);
             }
 
             thread_comm_guard.modified = true;
+            thread_comm_guard.modified_time = now.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only moves when told to, in the default days (as the system clock).
+    struct TestClock(Mutex<DateTime>);
+
+    impl TestClock {
+        fn at(dt: &str) -> Arc<TestClock> {
+            Arc::new(TestClock(Mutex::new(dt.parse().unwrap())))
+        }
+
+        fn set(&self, dt: &str) {
+            *self.0.lock().unwrap() = dt.parse().unwrap();
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> DateTime {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    // Records the states written to it.
+    struct TestController(Arc<Mutex<Vec<ActuatorState>>>);
+
+    impl ActuatorController for TestController {
+        fn set_state(&mut self, state: &ActuatorState) -> io::Result<()> {
+            self.0.lock().unwrap().push(state.clone());
+            Ok(())
+        }
+    }
+
+    fn info(actuator_type: ActuatorType) -> ActuatorInfo {
+        ActuatorInfo {
+            name: "test".to_string(),
+            actuator_type,
+            clamp: false,
+            quantize: false,
+            enabled: true,
+            day_start: DayStart::default(),
+            simulate: false,
+            unit: None,
+            max_timeslots: None,
+        }
+    }
+
+    // A Toggle actuator, off by default, along with what gets written to its controller.
+    fn actuator(clock: &Arc<TestClock>) -> (ActuatorHandle, Arc<Mutex<Vec<ActuatorState>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let controller = Arc::new(Mutex::new(TestController(written.clone())));
+        let actuator = Actuator::with_clock(info(ActuatorType::Toggle),
+                                            ActuatorState::Toggle(false), controller,
+                                            clock.clone());
+        (actuator, written)
+    }
+
+    fn period(time_interval: &str, days: &str) -> TimePeriod {
+        TimePeriod {
+            time_interval: time_interval.parse().unwrap(),
+            date_range: DateRange { start: Date::MIN, end: Date::MAX },
+            days: days.parse().unwrap(),
+        }
+    }
+
+    fn new_slot(time_interval: &str, days: &str, actuator_state: ActuatorState) -> NewTimeSlot {
+        NewTimeSlot::new(period(time_interval, days), actuator_state, true)
+    }
+
+    fn on() -> ActuatorState {
+        ActuatorState::Toggle(true)
+    }
+
+    fn off() -> ActuatorState {
+        ActuatorState::Toggle(false)
+    }
+
+    // Wait (a few seconds at most) for the actuator thread to write state last.
+    fn wait_written(written: &Arc<Mutex<Vec<ActuatorState>>>, state: ActuatorState) {
+        for _ in 0..500 {
+            if written.lock().unwrap().last() == Some(&state) {
+                return
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        panic!("{} never written, only {:?}", state, *written.lock().unwrap());
+    }
+
+    #[test]
+    fn overlaps_on_disjoint_weekdays() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, _) = actuator(&clock);
+        let mut actuator = actuator.write().unwrap();
+
+        let weekdays = actuator.add_time_slot(new_slot("07:00-08:00", "mon-fri", on()))
+            .unwrap();
+        let weekend = actuator.add_time_slot(new_slot("07:00-08:00", "sat,sun", on()))
+            .unwrap();
+
+        let update = TimePeriodUpdate { days: Some("fri-sun".parse().unwrap()),
+                                        ..Default::default() };
+        match actuator.time_slot_set_time_period(weekend, update) {
+            Err(TimeSlotOverlap { id, .. }) => assert_eq!(id, weekdays),
+            result => panic!("{:?}", result),
+        }
+
+        // Overrides only conflict on the days they apply.
+        actuator.time_slot_add_time_override(weekdays, period("07:30-08:30", "mon")).unwrap();
+        actuator.time_slot_add_time_override(weekend, period("06:00-07:30", "sun")).unwrap();
+        assert!(actuator.time_slot_add_time_override(weekend, period("07:00-07:30", "sat,mon"))
+                .is_ok());
+        match actuator.add_time_slot(new_slot("08:00-09:00", "mon", on())) {
+            Err(TimeSlotOverlap { id, .. }) => assert_eq!(id, weekdays),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn clock_jumps() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, written) = actuator(&clock);
+        {
+            let mut actuator = actuator.write().unwrap();
+            actuator.add_time_slot(new_slot("13:00-14:00", "all", on())).unwrap();
+            // The thread would otherwise only check the time again at 13:00 (real time).
+            actuator.set_max_wait(Some(time::Duration::from_secs(1)));
+        }
+        wait_written(&written, off());
+
+        // Forwards into the timeslot.
+        clock.set("01/01/2018 13:30");
+        wait_written(&written, on());
+
+        // Backwards, before it.
+        clock.set("01/01/2018 12:30");
+        wait_written(&written, off());
+
+        // Into it again, a day later.
+        clock.set("02/01/2018 13:30");
+        wait_written(&written, on());
+
+        // Past its end, and back into it on the same day.
+        clock.set("02/01/2018 18:00");
+        wait_written(&written, off());
+        clock.set("02/01/2018 13:10");
+        wait_written(&written, on());
+        // The initial state is written again if the thread had already written it when the
+        // timeslot was added, depending on which of them gets there first.
+        let mut written = written.lock().unwrap().clone();
+        written.dedup();
+        assert_eq!(written, vec![off(), on(), off(), on(), off(), on()]);
+    }
+
+    #[test]
+    fn stale_active_timeslot() {
+        let clock = TestClock::at("01/01/2018 13:30");
+        let (actuator, written) = actuator(&clock);
+        actuator.write().unwrap().add_time_slot(new_slot("13:00-14:00", "all", on())).unwrap();
+        wait_written(&written, on());
+        let write_count = written.lock().unwrap().len();
+
+        // Hand the thread an active timeslot computed long ago, as if it had been held up since
+        // (e.g. the host was suspended).
+        {
+            let actuator = actuator.read().unwrap();
+            let computed_at: DateTime = "01/01/2018 12:00".parse().unwrap();
+            let mut thread_comm = actuator.thread_comm.lock().unwrap();
+            thread_comm.active_timeslot = actuator.compute_active_timeslot(&computed_at);
+            thread_comm.modified = true;
+            thread_comm.modified_time = computed_at;
+            actuator.thread_comm_cv.notify_one();
+        }
+
+        // It is recomputed from the actual time instead of being applied.
+        for _ in 0..500 {
+            if written.lock().unwrap().len() > write_count {
+                break
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        assert_eq!(written.lock().unwrap()[write_count..], [on()]);
+    }
+
+    // Drives an ActiveTimeSlot through changes to the timeslots the way Actuator does, checking
+    // after each that it matches what ActiveTimeSlot::compute() gives from scratch.
+    struct StateMachine {
+        timeslots: BTreeMap<u32, TimeSlot>,
+        default_schedule: DefaultSchedule,
+        now: DateTime,
+        active: ActiveTimeSlot,
+    }
+
+    impl StateMachine {
+        fn new(now: &str) -> StateMachine {
+            StateMachine::with_default_schedule(now, DefaultSchedule::new(off()))
+        }
+
+        fn with_default_schedule(now: &str, default_schedule: DefaultSchedule) -> StateMachine {
+            let now = now.parse().unwrap();
+            let timeslots = BTreeMap::new();
+            let active = ActiveTimeSlot::compute(&now, &timeslots, &default_schedule,
+                                                 DayStart::default());
+            StateMachine { timeslots, default_schedule, now, active }
+        }
+
+        fn add(&mut self, id: u32, timeslot: TimeSlot) {
+            self.timeslots.insert(id, timeslot);
+            self.active.update_timeslot_added(&self.timeslots[&id], id, &self.now,
+                                              DayStart::default());
+            self.check_consistent();
+        }
+
+        fn modify(&mut self, id: u32, timeslot: TimeSlot) {
+            self.timeslots.insert(id, timeslot);
+            self.active.update_timeslot_modified(&self.timeslots[&id], id, &self.timeslots,
+                                                 &self.default_schedule, &self.now,
+                                                 DayStart::default());
+            self.check_consistent();
+        }
+
+        fn remove(&mut self, id: u32) {
+            self.timeslots.remove(&id);
+            self.active.update_timeslot_removed(id, &self.timeslots, &self.default_schedule,
+                                                &self.now, DayStart::default());
+            self.check_consistent();
+        }
+
+        fn check_consistent(&self) {
+            assert_eq!(self.active, ActiveTimeSlot::compute(&self.now, &self.timeslots,
+                                                            &self.default_schedule,
+                                                            DayStart::default()));
+        }
+
+        fn expect(&self, state: ActiveTimeSlotState, end_time: &str,
+                  actuator_state: ActuatorState) {
+            assert_eq!(self.active, ActiveTimeSlot {
+                state,
+                end_time: end_time.parse().unwrap(),
+                actuator_state,
+            });
+        }
+    }
+
+    fn slot(time_interval: &str, days: &str, actuator_state: ActuatorState) -> TimeSlot {
+        TimeSlot::new(true, actuator_state, period(time_interval, days))
+    }
+
+    fn active(id: u32) -> ActiveTimeSlotState {
+        TimeSlotActive { id, override_id: None }
+    }
+
+    fn default_until(next_id: Option<u32>) -> ActiveTimeSlotState {
+        DefaultStateActive { next_id, next_override_id: None }
+    }
+
+    // 01/01/2018 is a Monday.
+
+    #[test]
+    fn state_machine_added() {
+        let mut sm = StateMachine::new("01/01/2018 08:00");
+        sm.expect(default_until(None), "03:59", off());
+
+        sm.add(0, slot("09:00-11:00", "all", on()));
+        sm.expect(default_until(Some(0)), "09:00", off());
+        // Later than the next one.
+        sm.add(1, slot("12:00-13:00", "all", on()));
+        sm.expect(default_until(Some(0)), "09:00", off());
+        // Earlier than the next one.
+        sm.add(2, slot("08:30-08:45", "all", on()));
+        sm.expect(default_until(Some(2)), "08:30", off());
+        // Not today.
+        sm.add(3, slot("08:10-08:20", "tue", on()));
+        sm.expect(default_until(Some(2)), "08:30", off());
+        // Already over.
+        sm.add(4, slot("06:00-07:00", "all", on()));
+        sm.expect(default_until(Some(2)), "08:30", off());
+
+        let mut disabled = slot("08:05-08:10", "all", on());
+        disabled.enabled = false;
+        sm.add(5, disabled);
+        sm.expect(default_until(Some(2)), "08:30", off());
+
+        // Immediately active.
+        let mut sm = StateMachine::new("01/01/2018 10:00");
+        sm.add(0, slot("09:00-11:00", "all", on()));
+        sm.expect(active(0), "11:00", on());
+        // Not while another one is active.
+        sm.add(1, slot("11:00-12:00", "all", on()));
+        sm.expect(active(0), "11:00", on());
+
+        let mut disabled = slot("09:00-11:00", "all", on());
+        disabled.enabled = false;
+        let mut sm = StateMachine::new("01/01/2018 10:00");
+        sm.add(0, disabled);
+        sm.expect(default_until(None), "03:59", off());
+    }
+
+    #[test]
+    fn state_machine_modified() {
+        let mut sm = StateMachine::new("01/01/2018 08:00");
+        sm.add(0, slot("09:00-10:00", "all", on()));
+        sm.add(1, slot("12:00-13:00", "all", on()));
+        sm.expect(default_until(Some(0)), "09:00", off());
+
+        // Delayed past the next one.
+        sm.modify(0, slot("14:00-15:00", "all", on()));
+        sm.expect(default_until(Some(1)), "12:00", off());
+        // Moved before the next one.
+        sm.modify(0, slot("10:00-11:00", "all", on()));
+        sm.expect(default_until(Some(0)), "10:00", off());
+        sm.modify(0, slot("14:00-15:00", "all", on()));
+        // No longer occurring today.
+        sm.modify(1, slot("12:00-13:00", "tue", on()));
+        sm.expect(default_until(Some(0)), "14:00", off());
+        // Now starting now.
+        sm.modify(1, slot("08:00-08:30", "mon", on()));
+        sm.expect(active(1), "08:30", on());
+        // Made longer while active.
+        sm.modify(1, slot("08:00-09:00", "mon", on()));
+        sm.expect(active(1), "09:00", on());
+        // No longer containing now.
+        sm.modify(1, slot("08:30-09:00", "mon", on()));
+        sm.expect(default_until(Some(1)), "08:30", off());
+
+        // Disabled while active, then enabled again.
+        let mut sm = StateMachine::new("01/01/2018 14:30");
+        sm.add(0, slot("14:00-15:00", "all", on()));
+        sm.expect(active(0), "15:00", on());
+        let mut disabled = slot("14:00-15:00", "all", on());
+        disabled.enabled = false;
+        sm.modify(0, disabled);
+        sm.expect(default_until(None), "03:59", off());
+        sm.modify(0, slot("14:00-15:00", "all", on()));
+        sm.expect(active(0), "15:00", on());
+
+        // An override applying today.
+        let mut overridden = slot("14:00-15:00", "all", on());
+        overridden.add_time_override(period("14:00-16:00", "mon"));
+        sm.modify(0, overridden);
+        sm.expect(TimeSlotActive { id: 0, override_id: Some(0) }, "16:00", on());
+    }
+
+    #[test]
+    fn state_machine_removed() {
+        let mut sm = StateMachine::new("01/01/2018 14:30");
+        sm.add(0, slot("14:00-15:00", "all", on()));
+        sm.add(1, slot("16:00-17:00", "all", on()));
+        sm.add(2, slot("18:00-19:00", "all", on()));
+        sm.add(3, slot("20:00-21:00", "all", on()));
+        sm.expect(active(0), "15:00", on());
+
+        // Neither active nor next.
+        sm.remove(2);
+        sm.expect(active(0), "15:00", on());
+        // Active.
+        sm.remove(0);
+        sm.expect(default_until(Some(1)), "16:00", off());
+        // Next.
+        sm.remove(1);
+        sm.expect(default_until(Some(3)), "20:00", off());
+        sm.remove(3);
+        sm.expect(default_until(None), "03:59", off());
+    }
+
+    #[test]
+    fn state_machine_default_changes() {
+        let mut default_schedule = DefaultSchedule::new(off());
+        default_schedule.changes.insert("12:00".parse().unwrap(), on());
+        default_schedule.changes.insert("20:00".parse().unwrap(), off());
+
+        let mut sm = StateMachine::with_default_schedule("01/01/2018 08:00", default_schedule);
+        sm.expect(default_until(None), "12:00", off());
+        // After the next change of the default state.
+        sm.add(0, slot("13:00-14:00", "all", off()));
+        sm.expect(default_until(None), "12:00", off());
+        // Before it.
+        sm.add(1, slot("09:00-10:00", "all", on()));
+        sm.expect(default_until(Some(1)), "09:00", off());
+        sm.remove(1);
+        sm.expect(default_until(None), "12:00", off());
+
+        let mut sm = StateMachine {
+            now: "01/01/2018 12:30".parse().unwrap(),
+            ..sm
+        };
+        sm.active = ActiveTimeSlot::compute(&sm.now, &sm.timeslots, &sm.default_schedule,
+                                            DayStart::default());
+        sm.expect(default_until(Some(0)), "13:00", on());
+        sm.remove(0);
+        sm.expect(default_until(None), "20:00", on());
+    }
+
+    // Moves forward a minute every time it is read, until frozen.
+    struct SteppingClock {
+        now: Mutex<DateTime>,
+        frozen: Mutex<bool>,
+    }
+
+    impl Clock for SteppingClock {
+        fn now(&self) -> DateTime {
+            let mut now = self.now.lock().unwrap();
+            if !*self.frozen.lock().unwrap() {
+                *now = now.add_minutes(1);
+            }
+            now.clone()
+        }
+    }
+
+    #[test]
+    fn add_remove_stress() {
+        let clock = Arc::new(SteppingClock {
+            now: Mutex::new("01/01/2018 00:00".parse().unwrap()),
+            frozen: Mutex::new(false),
+        });
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let controller = Arc::new(Mutex::new(TestController(written.clone())));
+        let actuator = Actuator::with_clock(info(ActuatorType::Toggle), off(), controller,
+                                            clock.clone());
+        actuator.read().unwrap().set_max_wait(Some(time::Duration::from_secs(1)));
+
+        // Timeslots come and go (becoming active or next, or neither) while the thread keeps
+        // applying them, and the clock keeps moving (often enough to look like jumps).
+        let hammer = {
+            let actuator = actuator.clone();
+            thread::spawn(move || {
+                for i in 0..2000 {
+                    let start = Time { hour: (i % 24) as u8, minute: (i % 60) as u8 };
+                    let end = DayStart::default().add_minutes(start, 30).0;
+                    let time_period = TimePeriod {
+                        time_interval: TimeInterval { start, end },
+                        ..period("00:00-00:30", "all")
+                    };
+                    let time_slot = NewTimeSlot::new(time_period, on(), true);
+                    let id = actuator.write().unwrap().add_time_slot(time_slot);
+                    thread::yield_now();
+                    if let Ok(id) = id {
+                        actuator.write().unwrap().remove_time_slot(id).unwrap();
+                    }
+                }
+            })
+        };
+        hammer.join().unwrap();
+
+        // Still alive, and applying the right state.
+        *clock.frozen.lock().unwrap() = true;
+        let id = actuator.write().unwrap().add_time_slot(new_slot("04:00-03:59", "all", on()))
+            .unwrap();
+        wait_written(&written, on());
+        actuator.write().unwrap().remove_time_slot(id).unwrap();
+        wait_written(&written, off());
+    }
+
+    #[test]
+    fn timeslot_summaries_size() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, _) = actuator(&clock);
+        let mut actuator = actuator.write().unwrap();
+        // Many timeslots with many overrides, as a long-lived schedule ends up with.
+        let (time_period, time_override) = (period("08:00-09:00", "all"),
+                                            period("10:00-11:00", "mon"));
+        for id in 0..200 {
+            let mut timeslot = TimeSlot::new(true, on(), time_period.clone());
+            for _ in 0..20 {
+                timeslot.add_time_override(time_override.clone());
+            }
+            actuator.timeslots.insert(id, timeslot);
+        }
+        actuator.timeslots_generation += 1;
+
+        // What list_timeslots and list_timeslot_summaries respectively send.
+        let full = bincode::serialize(&actuator.timeslots().clone()).unwrap();
+        let summaries = actuator.timeslot_summaries(None);
+        let summaries_size = bincode::serialize(&summaries).unwrap().len();
+        assert_eq!(summaries.summaries.as_ref().unwrap().len(), 200);
+        // They all overlap, only the first one is ever active.
+        assert_eq!(summaries.shadowed, Some((1..200).collect()));
+        assert!(summaries_size * 5 < full.len(), "{} vs {} bytes", summaries_size, full.len());
+
+        // Nothing but the generation if nothing changed.
+        let not_modified = actuator.timeslot_summaries(Some(summaries.generation));
+        assert!(not_modified.summaries.is_none() && not_modified.shadowed.is_none());
+        assert!(bincode::serialize(&not_modified).unwrap().len() < 16);
+        actuator.remove_time_slot(0).unwrap();
+        assert!(actuator.timeslot_summaries(Some(summaries.generation)).summaries.is_some());
+    }
+
+    // Captures what all the tests log (the logger is global, so each test only looks at the
+    // records mentioning its own actuator).
+    struct TestLogger(Mutex<Vec<(::log::Level, String)>>);
+
+    impl ::log::Log for TestLogger {
+        fn enabled(&self, _: &::log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &::log::Record) {
+            self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn test_logger() -> &'static TestLogger {
+        static INIT: ::std::sync::Once = ::std::sync::Once::new();
+        static mut LOGGER: Option<&'static TestLogger> = None;
+
+        unsafe {
+            INIT.call_once(|| {
+                let logger = Box::leak(Box::new(TestLogger(Mutex::new(Vec::new()))));
+                ::log::set_logger(logger).unwrap();
+                ::log::set_max_level(::log::LevelFilter::Info);
+                LOGGER = Some(logger);
+            });
+            LOGGER.unwrap()
+        }
+    }
+
+    // Wait (a few seconds at most) for a record at level containing all the parts.
+    fn wait_logged(logger: &TestLogger, level: ::log::Level, parts: &[&str]) {
+        for _ in 0..500 {
+            if logger.0.lock().unwrap().iter()
+                .any(|&(l, ref msg)| l == level && parts.iter().all(|p| msg.contains(p)))
+            {
+                return
+            }
+            thread::sleep(time::Duration::from_millis(10));
         }
+        panic!("{:?} never logged at level {}", parts, level);
+    }
+
+    #[test]
+    fn logged_events() {
+        let logger = test_logger();
+        let clock = TestClock::at("01/01/2018 12:00");
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let actuator = Actuator::with_clock(
+            ActuatorInfo { name: "logged".to_string(), ..info(ActuatorType::Toggle) },
+            off(), Arc::new(Mutex::new(TestController(written.clone()))), clock.clone());
+
+        let id = {
+            let mut actuator = actuator.write().unwrap();
+            actuator.set_max_wait(Some(time::Duration::from_secs(1)));
+            actuator.add_time_slot(new_slot("11:00-13:00", "all", on())).unwrap()
+        };
+        wait_written(&written, on());
+        wait_logged(logger, ::log::Level::Info,
+                    &["[AT logged]", "new state", &format!("(timeslot {}:None)", id)]);
+
+        clock.set("01/01/2018 18:00");
+        wait_written(&written, off());
+        wait_logged(logger, ::log::Level::Warn, &["[AT logged] clock jump detected"]);
+    }
+
+    fn fan(clock: &Arc<TestClock>, clamp: bool)
+        -> (ActuatorHandle, Arc<Mutex<Vec<ActuatorState>>>)
+    {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let controller = Arc::new(Mutex::new(TestController(written.clone())));
+        let info = ActuatorInfo { clamp, ..info(ActuatorType::IntValue { min: 0, max: 3 }) };
+        let actuator = Actuator::with_clock(info, ActuatorState::IntValue(0), controller,
+                                            clock.clone());
+        (actuator, written)
+    }
+
+    fn rejected<T: fmt::Debug>(result: Result<T>) -> bool {
+        match result {
+            Err(InvalidArgument(IAE::ActuatorState, _)) => true,
+            Err(BatchEntry { error, .. }) => rejected::<()>(Err(*error)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn int_value_states() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, written) = fan(&clock, false);
+        let mut actuator = actuator.write().unwrap();
+        let int = ActuatorState::IntValue;
+
+        // Adding timeslots.
+        let float = ActuatorState::FloatValue(2.0);
+        assert!(rejected(actuator.add_time_slot(new_slot("07:00-08:00", "all", float))));
+        assert!(rejected(actuator.add_time_slot(new_slot("07:00-08:00", "all", int(4)))));
+        assert!(rejected(actuator.add_time_slot(new_slot("07:00-08:00", "all", int(-1)))));
+        let id = actuator.add_time_slot(new_slot("07:00-08:00", "all", int(3))).unwrap();
+        assert!(rejected(actuator.add_time_slots(vec![
+            new_slot("09:00-10:00", "all", int(1)),
+            new_slot("10:00-11:00", "all", int(7)),
+        ])));
+        assert_eq!(actuator.timeslots().len(), 1);
+
+        // Modifying them.
+        assert!(rejected(actuator.time_slot_set_actuator_state(id, int(5))));
+        assert!(rejected(actuator.time_slot_set_actuator_state(id, ActuatorState::Toggle(true))));
+        actuator.time_slot_set_actuator_state(id, int(0)).unwrap();
+        assert_eq!(actuator.timeslot(id).unwrap().actuator_state, int(0));
+
+        // The default state and schedule.
+        assert!(rejected(actuator.set_default_state(int(9))));
+        assert!(rejected(actuator.set_default_change("20:00".parse().unwrap(), int(9))));
+        actuator.set_default_state(int(1)).unwrap();
+        actuator.set_default_change("20:00".parse().unwrap(), int(2)).unwrap();
+
+        // Manual overrides.
+        assert!(rejected(actuator.set_state(int(4))));
+        assert!(rejected(actuator.set_state(ActuatorState::FloatValue(1.0))));
+        assert_eq!(actuator.set_state(int(2)).unwrap(), int(2));
+        assert!(written.lock().unwrap().contains(&int(2)));
+    }
+
+    #[test]
+    fn int_value_states_clamped() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, written) = fan(&clock, true);
+        let mut actuator = actuator.write().unwrap();
+        let int = ActuatorState::IntValue;
+
+        let id = actuator.add_time_slot(new_slot("07:00-08:00", "all", int(9))).unwrap();
+        assert_eq!(actuator.timeslot(id).unwrap().actuator_state, int(3));
+        actuator.time_slot_set_actuator_state(id, int(-5)).unwrap();
+        assert_eq!(actuator.timeslot(id).unwrap().actuator_state, int(0));
+        assert_eq!(actuator.set_default_state(int(-1)).unwrap(), int(0));
+        assert_eq!(actuator.set_state(int(i64::max_value())).unwrap(), int(3));
+        assert!(written.lock().unwrap().contains(&int(3)));
+        // Clamping does not convert between types.
+        assert!(rejected(actuator.set_state(ActuatorState::FloatValue(1.0))));
+    }
+
+    #[test]
+    fn enum_states() {
+        let levels = ActuatorType::Enum {
+            values: vec!["off".to_string(), "low".to_string(), "high".to_string()],
+        };
+        let level = |name: &str| ActuatorState::Enum(name.to_string());
+
+        // Names are resolved case-insensitively, and only against the values of the type.
+        assert_eq!(ActuatorState::parse_as("Low", &levels), Ok(level("low")));
+        assert_eq!(ActuatorState::parse_as("e:HIGH", &levels), Ok(level("high")));
+        assert_eq!(ActuatorState::parse_as("medium", &levels),
+                   Err("expected one of: off, low, high".to_string()));
+        assert!(ActuatorState::parse_as("1", &levels).is_err());
+        // Without the type, a name cannot be checked.
+        assert_eq!("e:medium".parse(), Ok(level("medium")));
+
+        let clock = TestClock::at("01/01/2018 12:00");
+        let controller = Arc::new(Mutex::new(TestController(Arc::new(Mutex::new(Vec::new())))));
+        let actuator = Actuator::with_clock(info(levels), level("off"), controller, clock);
+        let mut actuator = actuator.write().unwrap();
+
+        // The server only accepts the exact names.
+        for state in [level("medium"), level("LOW"), level(""), ActuatorState::Toggle(true),
+                      ActuatorState::IntValue(1)].iter() {
+            let time_slot = new_slot("07:00-08:00", "all", state.clone());
+            assert!(rejected(actuator.add_time_slot(time_slot)));
+            assert!(rejected(actuator.set_default_state(state.clone())));
+            assert!(rejected(actuator.set_state(state.clone())));
+        }
+        let id = actuator.add_time_slot(new_slot("07:00-08:00", "all", level("high")))
+            .unwrap();
+        assert!(rejected(actuator.time_slot_set_actuator_state(id, level("medium"))));
+        actuator.time_slot_set_actuator_state(id, level("low")).unwrap();
+        assert_eq!(actuator.set_state(level("high")).unwrap(), level("high"));
+    }
+
+    #[test]
+    fn non_finite_states_not_parsed() {
+        for s in ["NaN", "nan", "-NaN", "inf", "-inf", "+inf", "infinity", "-Infinity", "1e400",
+                  "-1e400"].iter() {
+            assert_eq!(s.parse::<ActuatorState>(), Err(format!("{} is not a finite number", s)));
+        }
+
+        // Still parsed: everything finite, and what is not a number at all fails as before.
+        assert_eq!("1e300".parse(), Ok(ActuatorState::FloatValue(1e300)));
+        assert_eq!("-0".parse(), Ok(ActuatorState::FloatValue(0.0)));
+        assert_eq!("18.5".parse(), Ok(ActuatorState::FloatValue(18.5)));
+        let error = "infinite".parse::<ActuatorState>().unwrap_err();
+        assert!(!error.contains("not a finite number"), "{}", error);
+    }
+
+    #[test]
+    fn labels_on_add() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, _) = actuator(&clock);
+        let mut actuator = actuator.write().unwrap();
+        let labelled = |time_interval: &str, label: &str| NewTimeSlot {
+            label: Some(label.to_string()),
+            ..new_slot(time_interval, "all", on())
+        };
+        let too_long: String = (0..TimeSlot::MAX_LABEL_LEN + 1).map(|_| 'x').collect();
+
+        // An invalid label rejects the whole timeslot, whichever way it is added.
+        for label in ["", too_long.as_str()].iter() {
+            let time_slot = labelled("07:00-08:00", label);
+            match actuator.add_time_slot(time_slot.clone()) {
+                Err(InvalidArgument(IAE::Label, _)) => (),
+                result => panic!("{:?}", result),
+            }
+            assert!(actuator.add_time_slot_fit(time_slot.clone()).is_err());
+            assert!(actuator.add_time_slot_replace(time_slot.clone()).is_err());
+            match actuator.add_time_slots(vec![new_slot("05:00-06:00", "all", on()), time_slot]) {
+                Err(BatchEntry { index: 1, .. }) => (),
+                result => panic!("{:?}", result),
+            }
+            assert!(actuator.timeslots().is_empty());
+        }
+
+        let id = actuator.add_time_slot(labelled("07:00-08:00", "morning")).unwrap();
+        assert_eq!(actuator.timeslots()[&id].label, Some("morning".to_string()));
+        let added = actuator.add_time_slot_fit(labelled("06:00-09:00", "around")).unwrap();
+        assert_eq!(added.len(), 2);
+        for (id, _) in added {
+            assert_eq!(actuator.timeslots()[&id].label, Some("around".to_string()));
+        }
+        let (id, _) = actuator.add_time_slot_replace(labelled("07:30-08:30", "late")).unwrap();
+        assert_eq!(actuator.timeslots()[&id].label, Some("late".to_string()));
+        let id = actuator.add_time_slot(new_slot("10:00-11:00", "all", on())).unwrap();
+        assert_eq!(actuator.timeslots()[&id].label, None);
+    }
+
+    #[test]
+    fn toggle_state() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, written) = actuator(&clock);
+        wait_written(&written, off());
+
+        assert_eq!(actuator.write().unwrap().toggle_state().unwrap(), on());
+        assert_eq!(written.lock().unwrap().last(), Some(&on()));
+        let overrides = actuator.read().unwrap().overrides();
+        assert_eq!(overrides.len(), 1);
+        assert_eq!((&overrides[0].state, overrides[0].source.as_str()), (&on(), "manual"));
+
+        // Each toggle sees the state the previous one applied, so an even number of them gets
+        // back to the initial state.
+        let togglers: Vec<_> = (0..4).map(|_| {
+            let actuator = actuator.clone();
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    actuator.write().unwrap().toggle_state().unwrap();
+                }
+            })
+        }).collect();
+        for toggler in togglers {
+            toggler.join().unwrap();
+        }
+        let written = written.lock().unwrap();
+        assert_eq!(written.len(), 102);
+        for states in written[1..].windows(2) {
+            assert!(states[0] != states[1], "{:?}", *written);
+        }
+        assert_eq!(actuator.read().unwrap().overrides()[0].state, on());
+
+        let (fan, _) = fan(&clock, false);
+        let result = fan.write().unwrap().toggle_state();
+        match result {
+            Err(InvalidArgument(IAE::ActuatorId, _)) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn time_override_contract() {
+        // Overrides of the first of these timeslots (in 2018 only, 01/01/2018 being a Monday),
+        // the last one being overridden on Wednesdays.
+        let clock = TestClock::at("01/01/2018 06:00");
+        let in_2018 = |time_interval: &str, days: &str, start: &str, end: &str| TimePeriod {
+            date_range: DateRange { start: start.parse().unwrap(), end: end.parse().unwrap() },
+            ..period(time_interval, days)
+        };
+        let setup = || {
+            let (actuator, _) = actuator(&clock);
+            {
+                let mut actuator = actuator.write().unwrap();
+                let time_period = in_2018("07:00-08:00", "mon-fri", "01/01/2018", "31/12/2018");
+                actuator.add_time_slot(NewTimeSlot::new(time_period, on(), true)).unwrap();
+                actuator.add_time_slot(new_slot("12:00-13:00", "sat,sun", on())).unwrap();
+                actuator.add_time_slot(new_slot("18:00-19:00", "all", on())).unwrap();
+                actuator.time_slot_add_time_override(2, period("20:00-21:00", "wed")).unwrap();
+            }
+            actuator
+        };
+        let never_applies = |result: Result<u32>| match result {
+            Err(InvalidArgument(IAE::TimePeriod, ref detail)) => detail.contains("never apply"),
+            _ => false,
+        };
+        let overlaps = |result: Result<u32>, with: u32| match result {
+            Err(TimeSlotOverlap { id, .. }) => id == with,
+            _ => false,
+        };
+
+        // It replaces the timeslot's own time interval, which it may overlap.
+        let accepted = [
+            period("07:30-08:30", "mon"),
+            // The other timeslots only matter on the days the override applies: never on
+            // weekends, as the timeslot does not occur on them.
+            period("12:00-13:00", "mon-fri"),
+            period("12:00-13:00", "all"),
+            // Dates outside of the timeslot's are ignored.
+            in_2018("09:00-10:00", "all", "01/12/2018", "31/01/2019"),
+        ];
+        for time_period in accepted.iter() {
+            let actuator = setup();
+            let mut actuator = actuator.write().unwrap();
+            let id = actuator.time_slot_add_time_override(0, time_period.clone()).unwrap();
+            assert_eq!(actuator.timeslot(0).unwrap().time_override.len(), 1);
+            assert!(actuator.timeslot(0).unwrap().time_override.contains_key(&id));
+        }
+
+        let handle = setup();
+        let mut actuator = handle.write().unwrap();
+        // No day in common with the timeslot: not a weekday, not in 2018, or no weekday within
+        // the dates (06/01/2018 is a Saturday).
+        assert!(never_applies(actuator.time_slot_add_time_override(0, period("07:00-08:00",
+                                                                             "sat"))));
+        let period_2017 = in_2018("07:00-08:00", "all", "01/01/2017", "31/12/2017");
+        assert!(never_applies(actuator.time_slot_add_time_override(0, period_2017)));
+        let weekend = in_2018("07:00-08:00", "all", "06/01/2018", "07/01/2018");
+        assert!(never_applies(actuator.time_slot_add_time_override(0, weekend)));
+        // Even if it would overlap another timeslot on these days.
+        assert!(never_applies(actuator.time_slot_add_time_override(0, period("12:30-13:30",
+                                                                             "sat"))));
+
+        // Overlapping another timeslot, or its override, on one of the days it applies.
+        assert!(overlaps(actuator.time_slot_add_time_override(0, period("18:30-19:30", "thu")),
+                         2));
+        assert!(overlaps(actuator.time_slot_add_time_override(0, period("20:30-21:30", "wed")),
+                         2));
+        assert!(overlaps(actuator.time_slot_add_time_override(0, period("17:00-21:00", "all")),
+                         2));
+        // Even where the other timeslot's override replaces it: that override could be removed
+        // at any time, which is never checked for overlaps.
+        assert!(overlaps(actuator.time_slot_add_time_override(0, period("18:00-19:00", "wed")),
+                         2));
+
+        // At most one override per day, even at different times.
+        actuator.time_slot_add_time_override(0, period("09:00-10:00", "mon-wed")).unwrap();
+        match actuator.time_slot_add_time_override(0, period("10:00-11:00", "tue")) {
+            Err(TimeOverrideOverlap { id: 0, days, .. }) => assert_eq!(days, WeekdaySet::TUESDAY),
+            result => panic!("{:?}", result),
+        }
+        actuator.time_slot_add_time_override(0, period("10:00-11:00", "thu,fri")).unwrap();
+        drop(actuator);
+
+        // The jitter of the timeslot applies to its overrides as well.
+        let actuator = setup();
+        let mut actuator = actuator.write().unwrap();
+        actuator.time_slot_set_jitter(0, 60).unwrap();
+        assert!(overlaps(actuator.time_slot_add_time_override(0, period("16:30-17:30", "mon")),
+                         2));
+        actuator.time_slot_add_time_override(0, period("16:00-16:30", "mon")).unwrap();
+    }
+
+    #[test]
+    fn wait_until_end() {
+        let at = |dt: &str| dt.parse::<DateTime>().unwrap();
+        let t = |time: &str| time.parse::<Time>().unwrap();
+
+        // (day start, now, end time, second, expected wait). Dates are relative to the day start:
+        // with the default one, 00:10 comes after 23:50 on the same date.
+        let cases = [
+            (DayStart::default(), "01/01/2018 10:00", "10:30", 0, Some(30 * 60)),
+            (DayStart::default(), "01/01/2018 10:00", "10:30", 45, Some(30 * 60 - 45)),
+            // Across midnight, and up to the end of the day (Time::MAX): the wait lasts until the
+            // start of the next day, not its last minute.
+            (DayStart::default(), "01/01/2018 23:50", "00:10", 0, Some(20 * 60)),
+            (DayStart::default(), "01/01/2018 23:50", "03:59", 0, Some(4 * 3600 + 10 * 60)),
+            (DayStart::default(), "01/01/2018 03:58", "03:59", 30, Some(2 * 60 - 30)),
+            (DayStart::MIDNIGHT, "01/01/2018 23:50", "23:59", 0, Some(10 * 60)),
+            (DayStart::MIDNIGHT, "01/01/2018 03:50", "03:59", 0, Some(9 * 60)),
+            // Already reached or passed (e.g. when the clock jumped): no wait.
+            (DayStart::default(), "01/01/2018 10:30", "10:30", 0, None),
+            (DayStart::default(), "01/01/2018 10:29", "10:30", 59, Some(1)),
+            (DayStart::default(), "01/01/2018 00:10", "23:50", 0, None),
+        ];
+        for (day_start, now, end_time, second, expected) in cases.iter() {
+            let now = at(now);
+            let expected_end = end_date_time(&now, t(end_time), *day_start);
+            assert_eq!(seconds_until(&expected_end, &now, *second, *day_start), *expected,
+                       "{} until {} ({:?})", now, end_time, day_start);
+        }
+
+        // The end of the day is the start of the next one, whatever the day start.
+        assert_eq!(end_date_time(&at("01/01/2018 23:50"), Time::MAX, DayStart::default()),
+                   at("02/01/2018 04:00"));
+        assert_eq!(end_date_time(&at("01/01/2018 23:50"), t("23:59"), DayStart::MIDNIGHT),
+                   at("02/01/2018 00:00"));
+    }
+
+    #[test]
+    fn jitter_on_add() {
+        let clock = TestClock::at("01/01/2018 12:00");
+        let (actuator, _) = actuator(&clock);
+        let mut actuator = actuator.write().unwrap();
+        let jittered = |time_interval: &str, jitter_minutes: u32| NewTimeSlot {
+            jitter_minutes,
+            ..new_slot(time_interval, "all", on())
+        };
+        let overlaps = |result: Result<u32>, expected_id: u32| match result {
+            Err(TimeSlotOverlap { id, .. }) => id == expected_id,
+            _ => false,
+        };
+
+        match actuator.add_time_slot(jittered("07:00-08:00", TimeSlot::MAX_JITTER_MINUTES + 1)) {
+            Err(InvalidArgument(IAE::Jitter, _)) => (),
+            result => panic!("{:?}", result),
+        }
+        let id = actuator.add_time_slot(jittered("10:00-11:00", 15)).unwrap();
+        assert_eq!(actuator.timeslots()[&id].jitter_minutes, 15);
+
+        // Overlaps are checked with the jitter of both the new timeslot and the existing ones.
+        assert!(overlaps(actuator.add_time_slot(new_slot("11:00-12:00", "all", on())), id));
+        assert!(overlaps(actuator.add_time_slot(jittered("11:30-12:00", 30)), id));
+        actuator.add_time_slot(jittered("11:30-12:00", 10)).unwrap();
+        match actuator.add_time_slots(vec![jittered("14:00-15:00", 20),
+                                           jittered("15:30-16:00", 20)]) {
+            Err(BatchEntry { index: 1, .. }) => (),
+            result => panic!("{:?}", result),
+        }
+
+        // The free parts around the existing timeslots would overlap them, or each other.
+        assert!(actuator.add_time_slot_fit(jittered("09:00-13:00", 5)).is_err());
+        let added = actuator.add_time_slot_fit(jittered("17:00-18:00", 5)).unwrap();
+        assert_eq!(actuator.timeslots()[&added[0].0].jitter_minutes, 5);
+        let (id, _) = actuator.add_time_slot_replace(jittered("20:00-21:00", 5)).unwrap();
+        assert_eq!(actuator.timeslots()[&id].jitter_minutes, 5);
     }
 }
@@ -1,44 +1,338 @@
 use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
 use std::os::unix::prelude::FileExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 use actuator::*;
 
 pub trait ActuatorController {
-    fn set_state(&mut self, state: &ActuatorState);
+    // Returns once the state has been written (successfully or not).
+    fn set_state(&mut self, state: &ActuatorState) -> io::Result<()>;
+
+    // Read back the current physical state, if the controller supports it (and it can be
+    // interpreted as a state of type actuator_type).
+    fn get_state(&mut self, _actuator_type: &ActuatorType) -> Option<ActuatorState> {
+        None
+    }
 }
 pub type ActuatorControllerHandle = Arc<Mutex<ActuatorController + Send>>;
 
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorFormat {
+    // #RRGGBB
+    Hex,
+    // Decimal components separated by spaces, e.g. "255 128 0".
+    Rgb,
+}
+
+impl Default for ColorFormat {
+    fn default() -> Self {
+        ColorFormat::Hex
+    }
+}
+
+// Appended to every state written. Some targets (e.g. sysfs attributes, named pipes) require one,
+// others reject it.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineTerminator {
+    None,
+    // \n
+    Lf,
+    // \r\n
+    CrLf,
+}
+
+impl LineTerminator {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            LineTerminator::None => "",
+            LineTerminator::Lf => "\n",
+            LineTerminator::CrLf => "\r\n",
+        }
+    }
+}
+
+impl Default for LineTerminator {
+    fn default() -> Self {
+        LineTerminator::None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode {
+    // Write at offset 0, over the previous contents (which are not truncated if longer).
+    Overwrite,
+    // Truncate the file, then write at offset 0.
+    Truncate,
+    // Write sequentially, for targets that cannot seek (pipes, character devices).
+    Append,
+}
+
+impl Default for WriteMode {
+    fn default() -> Self {
+        WriteMode::Overwrite
+    }
+}
+
+// How states are written to the file, when there is a choice.
+#[derive(Clone, Default)]
+pub struct FileFormat {
+    // If set, Enum states are written as their index in this list, instead of their name.
+    pub enum_values: Option<Vec<String>>,
+    pub color: ColorFormat,
+    pub line_terminator: LineTerminator,
+    pub write_mode: WriteMode,
+    // If set, get_state() reads the file back, see FileActuatorController::get_state(). Ignored
+    // in append mode.
+    pub read_back: bool,
+}
+
+// Longer than any state written by FileActuatorController (enum names are expected to be short).
+const MAX_READ_BACK_SIZE: u64 = 256;
+
 pub struct FileActuatorController {
     file: File,
+    // The file is opened write-only, it is opened again to read the state back (which fails if
+    // the file is not readable).
+    path: PathBuf,
+    format: FileFormat,
 }
 
 impl FileActuatorController {
-    pub fn new(path: &Path) -> ::std::io::Result<ActuatorControllerHandle> {
-        let file = OpenOptions::new().write(true).open(path)?;
+    pub fn new(path: &Path, format: FileFormat) -> io::Result<ActuatorControllerHandle> {
+        let file = OpenOptions::new()
+            .write(true)
+            .append(format.write_mode == WriteMode::Append)
+            .open(path)?;
 
         Ok(Arc::new(Mutex::new(FileActuatorController {
-            file
+            file,
+            path: path.to_path_buf(),
+            format,
         })))
     }
 }
 
+// Always with 3 decimals. Anything that rounds to zero (negative zero, subnormals, -0.0001...) is
+// written as 0.000, without a sign that consumers might not expect on zero. Non-finite values are
+// rejected before reaching a controller.
+fn float_str(value: f64) -> String {
+    let s = format!("{:.3}", value);
+    if s == "-0.000" { "0.000".to_string() } else { s }
+}
+
 impl ActuatorController for FileActuatorController {
-    fn set_state(&mut self, state: &ActuatorState) {
+    fn set_state(&mut self, state: &ActuatorState) -> io::Result<()> {
         let data = match state {
             ActuatorState::Toggle(value) => format!("{}", if *value { "1" } else { "0 " }),
-            ActuatorState::FloatValue(value) => format!("{:.3}", value),
-        }.into_bytes();
+            ActuatorState::FloatValue(value) => float_str(*value),
+            ActuatorState::IntValue(value) => format!("{}", value),
+            ActuatorState::Enum(ref name) => match self.format.enum_values {
+                Some(ref values) => match values.iter().position(|v| v == name) {
+                    Some(index) => format!("{}", index),
+                    None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                      format!("unknown enum value {}", name))),
+                },
+                None => name.clone(),
+            },
+            ActuatorState::Color { r, g, b } => match self.format.color {
+                ColorFormat::Hex => state.to_string(),
+                ColorFormat::Rgb => format!("{} {} {}", r, g, b),
+            },
+        };
+        let data = (data + self.format.line_terminator.as_str()).into_bytes();
+
+        match self.format.write_mode {
+            // A single write, so that the value is not split across reads of the target.
+            WriteMode::Append => return self.file.write_all(&data),
+            WriteMode::Truncate => self.file.set_len(0)?,
+            WriteMode::Overwrite => (),
+        }
+
+        let size = self.file.write_at(&data, 0)?;
+        if size != data.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                      format!("short write: {} / {} B", size, data.len())))
+        }
 
-        match self.file.write_at(&data, 0) {
-            Ok(size) if size != data.len() => {
-                eprintln!("Short write: {} / {} B", size, data.len());
+        Ok(())
+    }
+
+    // Parses what set_state() writes. Only regular files are read back: reading e.g. a pipe or a
+    // character device may block or consume data meant for someone else. Anything larger than
+    // what set_state() writes is not a state either, so we don't read any further. Files written
+    // in append mode hold every state written so far, so they are not read back either.
+    fn get_state(&mut self, actuator_type: &ActuatorType) -> Option<ActuatorState> {
+        if !self.format.read_back || self.format.write_mode == WriteMode::Append {
+            return None
+        }
+
+        let file = File::open(&self.path).ok()?;
+        if !file.metadata().ok()?.file_type().is_file() {
+            return None
+        }
+
+        let mut contents = String::new();
+        file.take(MAX_READ_BACK_SIZE + 1).read_to_string(&mut contents).ok()?;
+        if contents.len() as u64 > MAX_READ_BACK_SIZE {
+            return None
+        }
+        let contents = contents.trim();
+
+        match *actuator_type {
+            ActuatorType::Toggle => match contents {
+                "1" => Some(ActuatorState::Toggle(true)),
+                "0" => Some(ActuatorState::Toggle(false)),
+                _ => None,
+            },
+            ActuatorType::FloatValue { .. } => match f64::from_str(contents) {
+                Ok(value) if value.is_finite() => Some(ActuatorState::FloatValue(value)),
+                _ => None,
             },
-            Err(e) => {
-                eprintln!("Write failed: {}", e);
+            ActuatorType::IntValue { .. } =>
+                i64::from_str(contents).ok().map(ActuatorState::IntValue),
+            ActuatorType::Enum { ref values } => {
+                let name = match self.format.enum_values {
+                    Some(ref values) => values.get(usize::from_str(contents).ok()?)?,
+                    None => values.iter().find(|v| *v == contents)?,
+                };
+                Some(ActuatorState::Enum(name.clone()))
             },
-            _ => (),
+            ActuatorType::Color => match self.format.color {
+                ColorFormat::Hex => match ActuatorState::from_str(contents) {
+                    Ok(state @ ActuatorState::Color { .. }) => Some(state),
+                    _ => None,
+                },
+                ColorFormat::Rgb => {
+                    let c: Vec<u8> = contents.split_whitespace()
+                        .map(u8::from_str).collect::<result::Result<_, _>>().ok()?;
+                    if c.len() == 3 {
+                        Some(ActuatorState::Color { r: c[0], g: c[1], b: c[2] })
+                    } else {
+                        None
+                    }
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    // A file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> TempFile {
+            let path = env::temp_dir().join(format!("servoscheduler-{}-{}", process::id(), name));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn controller(path: &Path, read_back: bool) -> ActuatorControllerHandle {
+        let format = FileFormat {
+            write_mode: WriteMode::Truncate,
+            read_back,
+            ..Default::default()
         };
+        FileActuatorController::new(path, format).unwrap()
+    }
+
+    #[test]
+    fn read_back() {
+        let file = TempFile::new("read-back", "");
+        let float_type = ActuatorType::FloatValue { min: 0.0, max: 30.0, step: None };
+
+        // Opt-in only.
+        let controller = self::controller(&file.0, false);
+        controller.lock().unwrap().set_state(&ActuatorState::FloatValue(18.5)).unwrap();
+        assert_eq!(controller.lock().unwrap().get_state(&float_type), None);
+
+        let controller = self::controller(&file.0, true);
+        let mut controller = controller.lock().unwrap();
+        assert_eq!(controller.get_state(&float_type), Some(ActuatorState::FloatValue(18.5)));
+        controller.set_state(&ActuatorState::Toggle(false)).unwrap();
+        assert_eq!(controller.get_state(&ActuatorType::Toggle),
+                   Some(ActuatorState::Toggle(false)));
+        // Not a state of that type.
+        assert_eq!(controller.get_state(&ActuatorType::Color), None);
+
+        // Nothing larger than a state is read.
+        let padded = format!("1{}", " ".repeat(MAX_READ_BACK_SIZE as usize));
+        fs::write(&file.0, &padded[..MAX_READ_BACK_SIZE as usize]).unwrap();
+        assert_eq!(controller.get_state(&ActuatorType::Toggle), Some(ActuatorState::Toggle(true)));
+        fs::write(&file.0, &padded).unwrap();
+        assert_eq!(controller.get_state(&ActuatorType::Toggle), None);
+    }
+
+    #[test]
+    fn no_read_back_from_special_files() {
+        // Reading a character device could block (or consume what it produces).
+        let controller = controller(Path::new("/dev/null"), true);
+        assert_eq!(controller.lock().unwrap().get_state(&ActuatorType::Toggle), None);
+    }
+
+    #[test]
+    fn line_terminators() {
+        let int_type = ActuatorType::IntValue { min: 0, max: 20 };
+        let terminators = [(LineTerminator::None, ""), (LineTerminator::Lf, "\n"),
+                           (LineTerminator::CrLf, "\r\n")];
+
+        for &(line_terminator, terminator) in terminators.iter() {
+            let file = TempFile::new("terminator", "");
+            let format = |write_mode| FileFormat {
+                line_terminator,
+                write_mode,
+                read_back: true,
+                ..Default::default()
+            };
+
+            let controller = FileActuatorController::new(&file.0, format(WriteMode::Truncate))
+                .unwrap();
+            let mut controller = controller.lock().unwrap();
+            controller.set_state(&ActuatorState::IntValue(12)).unwrap();
+            controller.set_state(&ActuatorState::IntValue(7)).unwrap();
+            assert_eq!(fs::read_to_string(&file.0).unwrap(), format!("7{}", terminator));
+            assert_eq!(controller.get_state(&int_type), Some(ActuatorState::IntValue(7)));
+
+            fs::write(&file.0, "").unwrap();
+            let controller = FileActuatorController::new(&file.0, format(WriteMode::Append))
+                .unwrap();
+            let mut controller = controller.lock().unwrap();
+            controller.set_state(&ActuatorState::IntValue(12)).unwrap();
+            controller.set_state(&ActuatorState::IntValue(7)).unwrap();
+            assert_eq!(fs::read_to_string(&file.0).unwrap(),
+                       format!("12{}7{}", terminator, terminator));
+            // Not a single state.
+            assert_eq!(controller.get_state(&int_type), None);
+        }
+
+        // Not even if the file only holds one.
+        let file = TempFile::new("terminator-append", "");
+        let format = FileFormat { write_mode: WriteMode::Append, read_back: true,
+                                  ..Default::default() };
+        let controller = FileActuatorController::new(&file.0, format).unwrap();
+        let mut controller = controller.lock().unwrap();
+        controller.set_state(&ActuatorState::IntValue(7)).unwrap();
+        assert_eq!(controller.get_state(&int_type), None);
     }
 }
@@ -0,0 +1,117 @@
+// Durable record of the state changes applied to the actuators, for later analysis (e.g. of the
+// energy used): unlike the log, one line per change in a format meant to be parsed, and unlike the
+// history, a single file for all the actuators, where only actual changes are recorded. The file is
+// not rotated.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use actuator::ActuatorState;
+use events;
+use events::EventSource;
+use time::DateTime;
+use utils::csv_field;
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditLogFormat {
+    // One JSON object per line, e.g.:
+    // {"timestamp": "01/05/2018 08:00", "actuator_id": 0, "name": "heater", "old_state": 16.0,
+    //  "new_state": 18.5, "source": "timeslot 2", "timeslot_id": 2, "simulated": false}
+    // where old_state is null for the first state applied.
+    Jsonl,
+    // The same fields, with a header line.
+    Csv,
+}
+
+impl Default for AuditLogFormat {
+    fn default() -> Self {
+        AuditLogFormat::Jsonl
+    }
+}
+
+const CSV_HEADER: &str = "timestamp,actuator_id,name,old_state,new_state,source,timeslot_id,\
+                          simulated\n";
+
+// A state change, as recorded.
+pub struct AuditEntry<'a> {
+    pub timestamp: &'a DateTime,
+    pub actuator_id: u32,
+    pub name: &'a str,
+    pub old_state: Option<&'a ActuatorState>,
+    pub new_state: &'a ActuatorState,
+    pub source: &'a EventSource,
+    pub simulated: bool,
+}
+
+impl<'a> AuditEntry<'a> {
+    fn timeslot_id(&self) -> Option<u32> {
+        match *self.source {
+            EventSource::TimeSlot { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    fn to_line(&self, format: AuditLogFormat) -> String {
+        match format {
+            AuditLogFormat::Jsonl => {
+                let mut line = json!({
+                    "timestamp": self.timestamp.to_string(),
+                    "actuator_id": self.actuator_id,
+                    "name": self.name,
+                    "old_state": self.old_state.map(events::state_to_json),
+                    "new_state": events::state_to_json(self.new_state),
+                    "source": self.source.to_string(),
+                    "timeslot_id": self.timeslot_id(),
+                    "simulated": self.simulated,
+                }).to_string();
+                line.push('\n');
+                line
+            },
+            AuditLogFormat::Csv => {
+                let old_state = self.old_state.map(|s| s.to_string()).unwrap_or_default();
+                let timeslot_id = self.timeslot_id().map(|id| id.to_string()).unwrap_or_default();
+                format!("{},{},{},{},{},{},{},{}\n", self.timestamp, self.actuator_id,
+                        csv_field(self.name), csv_field(&old_state),
+                        csv_field(&self.new_state.to_string()), csv_field(&self.source.to_string()),
+                        timeslot_id, self.simulated)
+            },
+        }
+    }
+}
+
+// The file is opened once, and written (unbuffered) for each change, directly from the actuator
+// threads: a change is on disk by the time the next one is applied.
+pub struct AuditLog {
+    format: AuditLogFormat,
+    file: Mutex<File>,
+}
+pub type AuditLogHandle = Arc<AuditLog>;
+
+impl AuditLog {
+    // Append to path, creating it if needed.
+    pub fn open(path: &Path, format: AuditLogFormat) -> io::Result<AuditLogHandle> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        if format == AuditLogFormat::Csv && file.metadata()?.len() == 0 {
+            file.write_all(CSV_HEADER.as_bytes())?;
+        }
+
+        Ok(Arc::new(AuditLog {
+            format,
+            file: Mutex::new(file),
+        }))
+    }
+
+    pub fn record(&self, entry: &AuditEntry) {
+        let line = entry.to_line(self.format);
+        let mut file = self.file.lock().unwrap();
+
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            warn!("Failed to write to the audit log: {}", e);
+        }
+    }
+}
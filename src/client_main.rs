@@ -1,75 +1,114 @@
-#![feature(plugin, use_extern_macros, proc_macro_path_invoc)]
-#![plugin(tarpc_plugins)]
-
-#[macro_use]
-extern crate tarpc;
-
-#[macro_use]
-extern crate serde_derive;
-
-#[macro_use]
-extern crate bitflags;
-extern crate chrono;
-extern crate num;
+extern crate servoscheduler;
 
 #[macro_use]
 extern crate clap;
 #[macro_use]
 extern crate prettytable;
-extern crate regex;
-
-mod actuator;
-mod actuator_controller;
-mod rpc;
-mod schedule;
-mod time;
-mod time_slot;
-mod utils;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_yaml;
+extern crate tarpc;
 
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
 use std::process;
 use std::result;
 use std::str;
 use std::str::FromStr;
+use std::time::{self as std_time, Instant};
 
 use tarpc::sync;
 use tarpc::sync::client::ClientExt;
 
-use actuator::*;
-use time_slot::*;
-use time::*;
-use rpc::{SyncClient};
+use servoscheduler::actuator::*;
+use servoscheduler::follow::Follow;
+use servoscheduler::rpc;
+use servoscheduler::rpc::{SyncClient};
+use servoscheduler::schedule;
+use servoscheduler::schedule::ScheduleEntry;
+use servoscheduler::time_slot::*;
+use servoscheduler::time::*;
+use servoscheduler::unix_transport::{Client, UnixClient};
 
 type RpcResult = result::Result<(), tarpc::Error<rpc::Error>>;
 
-fn parse_colon_specifier(s: &str, expected_num: usize) -> Option<Vec<u32>> {
-    let ids: Vec<&str> = s.split(':').collect();
-    if ids.len() != expected_num {
-        return None
+// Split <actuator ID>:<timeslot>, or <actuator ID>:<timeslot>:<override ID> if with_override is
+// set. The timeslot is returned as is (ID or label, which may itself contain colons).
+fn split_specifier(s: &str, with_override: bool) -> Option<(u32, &str, Option<u32>)> {
+    let colon = s.find(':')?;
+    let actuator_id = u32::from_str(&s[..colon]).ok()?;
+    let rest = &s[colon + 1..];
+
+    if with_override {
+        let colon = rest.rfind(':')?;
+        let override_id = u32::from_str(&rest[colon + 1..]).ok()?;
+        Some((actuator_id, &rest[..colon], Some(override_id)))
+    } else {
+        Some((actuator_id, rest, None))
     }
+}
 
-    let vals: Vec<u32> = ids.iter().filter_map(|s| u32::from_str(s).ok()).collect();
-    if vals.len() == expected_num {
-        Some(vals)
-    } else {
-        None
+// A timeslot ID, or a label identifying a single timeslot of the actuator. A label consisting only
+// of digits is always taken as an ID.
+fn resolve_timeslot(actuator_id: u32, timeslot: &str) -> u32 {
+    if let Ok(id) = u32::from_str(timeslot) {
+        return id
+    }
+
+    require_capability(rpc::capability::TIMESLOT_LABELS);
+
+    let summaries = match get_client().list_timeslot_summaries(actuator_id, None) {
+        Ok(summaries) => summaries.summaries.unwrap_or(Vec::new()),
+        Err(err) => {
+            eprintln!("Failed to list the timeslots: {}", err);
+            process::exit(1)
+        },
+    };
+    let ids: Vec<u32> = summaries.iter()
+        .filter(|s| s.label.as_ref().map(|l| l.as_str()) == Some(timeslot))
+        .map(|s| s.id)
+        .collect();
+
+    match ids.len() {
+        1 => ids[0],
+        0 => {
+            eprintln!("No timeslot labelled \"{}\" on actuator {}", timeslot, actuator_id);
+            process::exit(1)
+        },
+        _ => {
+            let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            eprintln!("Several timeslots labelled \"{}\" on actuator {} (IDs {}), use an ID",
+                      timeslot, actuator_id, ids.join(", "));
+            process::exit(1)
+        },
     }
 }
 
+fn exit_invalid_specifier(value: &str, expected: &str) -> ! {
+    eprintln!("Invalid specifier {}, expected {}", value, expected);
+    process::exit(1)
+}
+
 struct TimeslotSpecifier {
     actuator_id: u32,
     timeslot_id: u32,
 }
 
-impl str::FromStr for TimeslotSpecifier {
-    type Err = ();
-
-    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
-        let vals = parse_colon_specifier(s, 2).ok_or(())?;
+// The "specifier" argument, resolving the timeslot if it is a label.
+fn timeslot_specifier(args: &clap::ArgMatches) -> TimeslotSpecifier {
+    let value = args.value_of("specifier").unwrap();
+    let (actuator_id, timeslot, _) = split_specifier(value, false).unwrap_or_else(
+        || exit_invalid_specifier(value, "<actuator ID>:<timeslot ID or label>"));
 
-        Ok(TimeslotSpecifier {
-            actuator_id: vals[0],
-            timeslot_id: vals[1],
-        })
+    TimeslotSpecifier {
+        actuator_id,
+        timeslot_id: resolve_timeslot(actuator_id, timeslot),
     }
 }
 
@@ -79,30 +118,241 @@ struct TimeslotOverrideSpecifier {
     timeslot_override_id: u32,
 }
 
-impl str::FromStr for TimeslotOverrideSpecifier {
-    type Err = ();
+fn timeslot_override_specifier(args: &clap::ArgMatches) -> TimeslotOverrideSpecifier {
+    let value = args.value_of("specifier").unwrap();
+    let (actuator_id, timeslot, override_id) = split_specifier(value, true).unwrap_or_else(
+        || exit_invalid_specifier(value, "<actuator ID>:<timeslot ID or label>:<override ID>"));
+
+    TimeslotOverrideSpecifier {
+        actuator_id,
+        timeslot_id: resolve_timeslot(actuator_id, timeslot),
+        timeslot_override_id: override_id.unwrap(),
+    }
+}
+
+// The "actuator" argument of the commands that can also be applied to a group, as @<group name>.
+enum Target {
+    Actuator(u32),
+    Group(String),
+}
+
+fn target_arg(args: &clap::ArgMatches) -> Target {
+    let value = args.value_of("actuator").unwrap();
+    if value.starts_with('@') {
+        Target::Group(value[1..].to_string())
+    } else {
+        Target::Actuator(value_t_or_exit!(args, "actuator", u32))
+    }
+}
+
+// The "actuator" argument of the other commands.
+fn actuator_arg(args: &clap::ArgMatches) -> u32 {
+    match target_arg(args) {
+        Target::Actuator(actuator_id) => actuator_id,
+        Target::Group(_) => {
+            eprintln!("This command cannot be applied to a group, specify an actuator ID");
+            process::exit(1)
+        },
+    }
+}
+
+// The actuator IDs of the members of group. Exits if there is no such group.
+fn group_members(group: &str) -> Vec<u32> {
+    require_capability(rpc::capability::ACTUATOR_GROUPS);
+
+    match get_client().list_groups() {
+        Ok(mut groups) => groups.remove(group).unwrap_or_else(|| {
+            eprintln!("No group named {}", group);
+            process::exit(1)
+        }),
+        Err(err) => {
+            eprintln!("Failed to list the groups: {}", err);
+            process::exit(1)
+        },
+    }
+}
+
+// Print the result of a group operation for each member (describe formats a successful result),
+// and exit with an error if it failed for any of them.
+fn report_group_result<T, F>(group: &str, result: rpc::GroupResult<T>, describe: F) -> RpcResult
+where
+    F: Fn(&T) -> String
+{
+    for (actuator_id, member_result) in result.results.iter() {
+        match *member_result {
+            Ok(ref value) => println!("Actuator {}: {}", actuator_id, describe(value)),
+            Err(ref err) => eprintln!("Actuator {}: failed: {}", actuator_id, err),
+        }
+    }
+
+    let failures = result.failures();
+    if !result.applied {
+        eprintln!("Nothing applied to group {}, invalid for actuators {}", group,
+                  failures.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", "));
+        process::exit(1)
+    } else if !failures.is_empty() {
+        process::exit(1)
+    }
+
+    Ok(())
+}
+
+// A date argument, where "-" or "none" means no date restriction (i.e. Date::MIN for a start date
+// and Date::MAX for an end date), as printed in listings.
+struct OptionalDate(Option<Date>);
+
+impl OptionalDate {
+    fn or_start(self) -> Date {
+        self.0.unwrap_or(Date::MIN)
+    }
+
+    fn or_end(self) -> Date {
+        self.0.unwrap_or(Date::MAX)
+    }
+}
+
+impl str::FromStr for OptionalDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "-" | "none" => Ok(OptionalDate(None)),
+            _ => Date::from_str(s).map(|d| OptionalDate(Some(d))),
+        }
+    }
+}
+
+// Either an absolute date, or a number of days relative to today (e.g. -7 for a week ago).
+struct RelativeDate(Date);
+
+impl str::FromStr for RelativeDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        if s.starts_with('+') || s.starts_with('-') {
+            let offset = u32::from_str(&s[1..])
+                .map_err(|_| format!("invalid number of days {}", &s[1..]))? as i64;
+            let offset = if s.starts_with('-') { -offset } else { offset };
+
+            DateTime::now().date.checked_add(offset).map(RelativeDate)
+                .ok_or_else(|| format!("{} days from today is out of range", s))
+        } else {
+            Date::from_str(s).map(|d| RelativeDate(d))
+        }
+    }
+}
+
+// Same as RelativeDate, followed by a time, e.g. +1 08:00 (or a DateTime).
+struct RelativeDateTime(DateTime);
+
+impl str::FromStr for RelativeDateTime {
+    type Err = String;
 
     fn from_str(s: &str) -> result::Result<Self, Self::Err> {
-        let vals = parse_colon_specifier(s, 3).ok_or(())?;
+        if !s.starts_with('+') && !s.starts_with('-') {
+            return DateTime::from_str(s).map(RelativeDateTime)
+        }
 
-        Ok(TimeslotOverrideSpecifier {
-            actuator_id: vals[0],
-            timeslot_id: vals[1],
-            timeslot_override_id: vals[2],
-        })
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 2 {
+            return Err(format!("expected +N hh:mm or -N hh:mm, got \"{}\"", s))
+        }
+        Ok(RelativeDateTime(DateTime {
+            date: RelativeDate::from_str(fields[0])?.0,
+            time: Time::from_str(fields[1])?,
+        }))
     }
 }
 
-fn get_client() -> SyncClient {
-    match SyncClient::connect("localhost:4242", sync::client::Options::default()) {
+thread_local! {
+    // Server address, as passed to --server.
+    static SERVER: RefCell<String> = RefCell::new(String::new());
+}
+
+fn get_client() -> Client {
+    let server = SERVER.with(|s| s.borrow().clone());
+    let client = if server.starts_with("unix:") {
+        UnixClient::connect(Path::new(&server["unix:".len()..])).map(Client::Unix)
+    } else {
+        SyncClient::connect(&server, sync::client::Options::default()).map(Client::Tcp)
+    };
+
+    match client {
         Ok(client) => client,
         Err(err) => {
-            eprintln!("Failed to connect: {}", err);
+            eprintln!("Failed to connect to {}: {}", server, err);
             process::exit(1)
         }
     }
 }
 
+// Same as require_capability(), for optional features: false if unsupported.
+fn server_supports(capability: &str) -> bool {
+    get_client().server_info().map(|info| info.supports(capability)).unwrap_or(false)
+}
+
+// Exit with an error if the server does not support capability (most likely because it is older
+// than this client).
+fn require_capability(capability: &str) {
+    match get_client().server_info() {
+        Ok(ref info) if info.supports(capability) => (),
+        Ok(info) => {
+            eprintln!("The server (version {}) does not support {} (client version {})",
+                      info.version, capability, rpc::VERSION);
+            process::exit(1)
+        },
+        Err(err) => {
+            eprintln!("Failed to query the server capabilities (is it older than the client?): {}",
+                      err);
+            process::exit(1)
+        },
+    }
+}
+
+fn set_log_level(args: &clap::ArgMatches) -> RpcResult {
+    let level = args.value_of("level").unwrap();
+
+    require_capability(rpc::capability::SET_LOG_LEVEL);
+
+    get_client().set_log_level(level.to_string()).and(Ok(()))
+}
+
+fn server_info() -> RpcResult {
+    let info = get_client().server_info()?;
+
+    println!("Server version: {}", info.version);
+    println!("Client version: {}", rpc::VERSION);
+    println!("Capabilities:");
+    for capability in info.capabilities.iter() {
+        println!("  {}", capability);
+    }
+
+    Ok(())
+}
+
+// Above this, warn that the client and server clocks differ.
+const MAX_CLOCK_SKEW_MIN: i64 = 1;
+
+fn ping() -> RpcResult {
+    let client = get_client();
+
+    let start = Instant::now();
+    let reply = client.ping()?;
+    let round_trip = start.elapsed();
+
+    println!("Server alive (uptime {}), round trip {} ms",
+             Duration::minutes((reply.uptime_sec / 60) as i32),
+             round_trip.as_secs() * 1000 + (round_trip.subsec_nanos() / 1_000_000) as u64);
+
+    let skew = reply.now.minutes_since(&DateTime::now());
+    if skew.abs() > MAX_CLOCK_SKEW_MIN {
+        eprintln!("Warning: server time {} differs from the local time by {} min",
+                  reply.now, skew);
+    }
+
+    Ok(())
+}
+
 // TODO: remove, replace with shell script
 fn test() -> RpcResult {
     let client = get_client();
@@ -128,8 +378,9 @@ fn test() -> RpcResult {
         days: WeekdaySet::MONDAY | WeekdaySet::SATURDAY,
     };
 
-    let _time_slot_id = client.add_time_slot(actuator_id, time_period.clone(),
-                                             ActuatorState::Toggle(true), true)?;
+    let _time_slot_id = client.add_time_slot(actuator_id,
+                                             NewTimeSlot::new(time_period.clone(),
+                                                              ActuatorState::Toggle(true), true))?;
 
     time_period.time_interval = TimeInterval {
         start: Time {
@@ -142,7 +393,8 @@ fn test() -> RpcResult {
         },
     };
 
-    client.add_time_slot(actuator_id, time_period, ActuatorState::Toggle(true), true)?;
+    client.add_time_slot(actuator_id,
+                         NewTimeSlot::new(time_period, ActuatorState::Toggle(true), true))?;
 
     // let schedule = client.get_schedule(actuator_id).unwrap();
 
@@ -155,330 +407,1766 @@ fn test() -> RpcResult {
     Ok(())
 }
 
-fn list_actuators() -> RpcResult {
+fn list_actuators(args: &clap::ArgMatches) -> RpcResult {
+    if args.is_present("detailed") {
+        return list_actuators_detailed()
+    }
+
     let actuators = get_client().list_actuators()?;
 
-    println!("{:>5}  {:10} {:5}", "Index", "Name", "Type");
+    println!("{:>5}  {:10} {:7} {:5}", "Index", "Name", "Enabled", "Type");
+    for (id, actuator) in actuators.iter().enumerate() {
+        println!("{:5}  {:10} {:7} {}", id, actuator.name,
+                 if actuator.enabled { "yes" } else { "no" },
+                 simulated_str(actuator.simulate, actuator.actuator_type.to_string()));
+    }
+
+    print_simulation_banner().and(Ok(()))
+}
+
+// Flag value if the actuator it belongs to is simulated.
+fn simulated_str(simulate: bool, value: String) -> String {
+    if simulate { format!("{} [SIMULATED]", value) } else { value }
+}
+
+fn list_groups() -> RpcResult {
+    require_capability(rpc::capability::ACTUATOR_GROUPS);
+
+    let client = get_client();
+    let groups = client.list_groups()?;
+    let actuators = client.list_actuators()?;
+
+    if groups.is_empty() {
+        println!("No group configured");
+        return Ok(())
+    }
+
+    for (group, members) in groups.iter() {
+        let members: Vec<String> = members.iter()
+            .map(|id| format!("{} ({})", id, actuators[*id as usize].name))
+            .collect();
+        println!("@{}: {}", group, members.join(", "));
+    }
+
+    Ok(())
+}
+
+fn list_triggers() -> RpcResult {
+    require_capability(rpc::capability::TRIGGERS);
+
+    let client = get_client();
+    let triggers = client.list_triggers()?;
+    let actuators = client.list_actuators()?;
+
+    if triggers.is_empty() {
+        println!("No trigger configured");
+        return Ok(())
+    }
+
+    for trigger in triggers.iter() {
+        println!("When {} ({}) turns on: turn {} ({}) on for {} min", trigger.source,
+                 actuators[trigger.source as usize].name, trigger.target,
+                 actuators[trigger.target as usize].name, trigger.duration_sec / 60);
+    }
+
+    Ok(())
+}
+
+fn list_actuators_detailed() -> RpcResult {
+    use prettytable::{Table, format};
+
+    require_capability(rpc::capability::ACTUATORS_DETAILED);
+
+    let actuators = get_client().list_actuators_detailed()?;
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row![b => "Index", "Name", "Enabled", "Type", "Default state", "Timeslots",
+                          "Intended state"]);
+    for (id, actuator) in actuators.iter().enumerate() {
+        let enabled = if actuator.info.enabled { "yes" } else { "no" };
+        let timeslots = match actuator.info.max_timeslots {
+            Some(max) => format!("{}/{} enabled (max {})", actuator.enabled_timeslots,
+                                 actuator.total_timeslots, max),
+            None => format!("{}/{} enabled", actuator.enabled_timeslots, actuator.total_timeslots),
+        };
+        let unit = &actuator.info.unit;
+        let intended_state = state_str(&actuator.intended_state, unit);
+        let intended_state = match actuator.active_timeslot {
+            _ if actuator.follow.is_some() => {
+                let source_id = actuator.follow.as_ref().unwrap().actuator_id;
+                format!("{} (following {})", intended_state, source_id)
+            },
+            _ if actuator.vacation.is_some() => format!("{} (vacation)", intended_state),
+            Some(ts_id) => format!("{} (timeslot {})", intended_state, ts_id),
+            None => format!("{} (default)", intended_state),
+        };
+
+        table.add_row(row![id, actuator.info.name, enabled, actuator.info.actuator_type,
+                           state_str(&actuator.default_state, unit), timeslots,
+                           simulated_str(actuator.info.simulate, intended_state)]);
+    }
+
+    table.printstd();
+    print_simulation_banner()?;
+    print_vacation_banner().and(Ok(()))
+}
+
+fn config() -> RpcResult {
+    use prettytable::{Table, format};
+
+    require_capability(rpc::capability::GET_CONFIG);
+
+    let actuators = get_client().get_config()?;
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row![b => "Index", "Name", "Type", "Default state", "Clamp", "Quantize",
+                          "Day start", "Controller", "On failure"]);
     for (id, actuator) in actuators.iter().enumerate() {
-        println!("{:5}  {:10} {:5}", id, actuator.name, actuator.actuator_type);
+        table.add_row(row![id, actuator.name, actuator.actuator_type, actuator.default_state,
+                           if actuator.clamp { "yes" } else { "no" },
+                           if actuator.quantize { "yes" } else { "no" }, actuator.day_start,
+                           actuator.controller, actuator.on_failure]);
     }
+    table.printstd();
 
     Ok(())
 }
 
+fn actuator(args: &clap::ArgMatches) -> RpcResult {
+    require_capability(rpc::capability::ACTUATOR_ENABLE);
+
+    let (enabled, sub) = match args.subcommand() {
+        ("disable", Some(sub)) => (false, sub),
+        ("enable", Some(sub)) => (true, sub),
+        _ => unreachable!(),
+    };
+    match target_arg(sub) {
+        Target::Actuator(actuator_id) => get_client().set_actuator_enabled(actuator_id, enabled),
+        Target::Group(group) => {
+            group_members(&group);
+            let result = get_client().group_set_actuator_enabled(group.clone(), enabled)?;
+            report_group_result(&group, result,
+                                |_| if enabled { "enabled" } else { "disabled" }.to_string())
+        },
+    }
+}
+
+fn time_interval_str(time_period: &TimePeriod) -> String {
+    format!("{} - {}", time_period.time_interval.start, time_period.time_interval.end)
+}
+
+// E.g. 18:00 - 20:00 (MTWTF--, 01/12/2018 to -).
+fn time_period_str(time_period: &TimePeriod) -> String {
+    format!("{} ({}, {} to {})", time_interval_str(time_period), time_period.days,
+            time_period.date_range.start, time_period.date_range.end)
+}
+
+// As far as the server allows: a timeslot may only occur once a year.
+const NEXT_OCCURRENCE_DAYS: u32 = 366;
+
 fn list_time_slots(args: &clap::ArgMatches) -> RpcResult {
     use prettytable::{Table, format};
 
-    fn time_interval_str(time_period: &TimePeriod) -> String {
-        format!("{} - {}", time_period.time_interval.start, time_period.time_interval.end)
-    }
+    let actuator_id = actuator_arg(args);
 
-    let actuator_id = value_t_or_exit!(args, "actuator", u32);
+    // We never have anything cached, so the summaries are always returned.
+    require_capability(rpc::capability::TIMESLOT_SUMMARIES);
 
-    let timeslots = get_client().list_timeslots(actuator_id)?;
+    let summaries = get_client().list_timeslot_summaries(actuator_id, None)?;
+    let shadowed = summaries.shadowed.unwrap_or(Vec::new());
+    let summaries = summaries.summaries.unwrap_or(Vec::new());
 
-    if timeslots.is_empty() {
+    if summaries.is_empty() {
         println!("No timeslot configured");
         return Ok(())
     }
 
+    let next_occurrences = if server_supports(rpc::capability::NEXT_OCCURRENCES) {
+        Some(get_client().next_occurrences(actuator_id, NEXT_OCCURRENCE_DAYS)?)
+    } else {
+        None
+    };
+
+    let unit = actuator_unit(actuator_id);
+    let day_start = actuator_day_start(actuator_id);
+
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_CLEAN);
-    table.set_titles(row![b => "Timeslot ID", "Enabled", "Actuator state", "Time range",
-                          "Start date", "End date", "Days"]);
+    let timestamps = args.is_present("timestamps");
+    let mut titles = row![b => "Timeslot ID", "Label", "Enabled", "Actuator state", "Time range",
+                          "Duration", "Start date", "End date", "Days", "Overrides", "Jitter",
+                          "Next occurrence"];
+    if timestamps {
+        titles.add_cell(cell!(b -> "Created"));
+        titles.add_cell(cell!(b -> "Modified"));
+    }
+    table.set_titles(titles);
 
-    for (slot_id, slot) in timeslots.iter() {
+    let mut weekly_total = Duration::default();
+    for slot in summaries.iter() {
         let time_period = &slot.time_period;
         let enabled = if slot.enabled { "Yes" } else { "No" };
         let time_range = time_interval_str(time_period);
+        let jitter = if slot.jitter_minutes == 0 {
+            "-".to_string()
+        } else {
+            format!("±{}", Duration::minutes(slot.jitter_minutes as i32))
+        };
+
+        let next_occurrence = match next_occurrences {
+            Some(ref next_occurrences) => match next_occurrences.get(&slot.id) {
+                Some(dt) => format!("{} {}", dt.date.chrono_date().format("%a %d/%m/%Y"),
+                                    dt.time),
+                None => "-".to_string(),
+            },
+            // Older server.
+            None => "?".to_string(),
+        };
 
-        table.add_row(row![slot_id, enabled, slot.actuator_state, time_range,
+        if slot.enabled {
+            weekly_total += time_period.weekly_duration(day_start);
+        }
+
+        let mut row = row![slot.id, label_str(&slot.label), enabled,
+                           state_str(&slot.actuator_state, &unit),
+                           time_range, day_start.duration(&time_period.time_interval),
                            time_period.date_range.start, time_period.date_range.end,
-                           time_period.days]);
+                           time_period.days, slot.override_count, jitter, next_occurrence];
+        if timestamps {
+            row.add_cell(cell!(timestamp_str(&slot.created_at)));
+            row.add_cell(cell!(timestamp_str(&slot.modified_at)));
+        }
+        table.add_row(row);
+    }
 
-        for (time_override_id, time_period) in slot.time_override.iter() {
-            let id = format!("{} > {}", slot_id, time_override_id);
-            let time_range = time_interval_str(time_period);
+    table.printstd();
+    // Overrides are not taken into account, and neither are overlapping timeslots.
+    println!("\nScheduled per week: {} (enabled timeslots)", weekly_total);
+
+    // Timeslots that are configured but do nothing are confusing, point them out (older servers
+    // do not check for them).
+    for id in shadowed {
+        eprintln!("Warning: timeslot {} never becomes active in the next {} days, other timeslots \
+                   take precedence", id, schedule::SHADOWED_CHECK_DAYS);
+    }
 
-            table.add_row(row![id, "-", "-", time_range,
-                               time_period.date_range.start, time_period.date_range.end,
-                               time_period.days]);
-        }
+    Ok(())
+}
+
+fn label_str(label: &Option<String>) -> &str {
+    label.as_ref().map(|l| l.as_str()).unwrap_or("-")
+}
+
+fn timestamp_str(timestamp: &Option<DateTime>) -> String {
+    match *timestamp {
+        Some(ref dt) => dt.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+fn show_time_slot(args: &clap::ArgMatches) -> RpcResult {
+    use prettytable::{Table, format};
+
+    let specifier = timeslot_specifier(args);
+
+    let slot = get_client().get_timeslot(specifier.actuator_id, specifier.timeslot_id)?;
+    let day_start = actuator_day_start(specifier.actuator_id);
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row![b => "Timeslot ID", "Label", "Enabled", "Actuator state", "Time range",
+                          "Duration", "Start date", "End date", "Days"]);
+
+    let time_period = &slot.time_period;
+    let enabled = if slot.enabled { "Yes" } else { "No" };
+    let time_range = time_interval_str(time_period);
+
+    table.add_row(row![specifier.timeslot_id, label_str(&slot.label), enabled,
+                       state_str(&slot.actuator_state, &actuator_unit(specifier.actuator_id)),
+                       time_range, day_start.duration(&time_period.time_interval),
+                       time_period.date_range.start, time_period.date_range.end,
+                       time_period.days]);
+
+    for (time_override_id, time_period) in slot.time_override.iter() {
+        let id = format!("{} > {}", specifier.timeslot_id, time_override_id);
+        let time_range = time_interval_str(time_period);
+
+        table.add_row(row![id, "-", "-", "-", time_range,
+                           day_start.duration(&time_period.time_interval),
+                           time_period.date_range.start, time_period.date_range.end,
+                           time_period.days]);
     }
 
     table.printstd();
 
+    println!("\nCreated: {}\nModified: {}", timestamp_str(&slot.created_at),
+             timestamp_str(&slot.modified_at));
+    if let Some(ref condition) = slot.condition {
+        println!("Only applies {} (the default state holds otherwise)",
+                 condition_str(condition));
+    }
+
     Ok(())
 }
 
-fn add_time_slot(args: &clap::ArgMatches) -> RpcResult {
-    let actuator_id = value_t_or_exit!(args, "actuator", u32);
-    let time_interval = value_t_or_exit!(args, "time-interval", TimeInterval);
-    let actuator_state = value_t_or_exit!(args, "state", ActuatorState);
+// Same as value_t_or_exit!(args, name, T), but saying what is wrong with the value.
+fn parse_arg<T: str::FromStr<Err = String>>(args: &clap::ArgMatches, name: &str) -> T {
+    let value = args.value_of(name).unwrap();
+    value.parse().unwrap_or_else(|e| {
+        eprintln!("Invalid value {} for {}: {}", value, name, e);
+        process::exit(1)
+    })
+}
+
+// The "time-interval" argument, restricted by the optional date and weekdays arguments.
+fn time_period_arg(args: &clap::ArgMatches) -> TimePeriod {
+    let time_interval = parse_arg::<TimeInterval>(args, "time-interval");
     // TODO: macro value_t_default_or_exit, or just set value using .default_value()
     let start_date = if args.is_present("start-date") {
-        value_t_or_exit!(args, "start-date", Date)
+        parse_arg::<OptionalDate>(args, "start-date").or_start()
     } else {
         // TODO: maybe actually use today, to make it more consistent with the doc? It might also
         // make it possible to get rid of Date::MIN.
         Date::MIN
     };
     let end_date = if args.is_present("end-date") {
-        value_t_or_exit!(args, "end-date", Date)
+        parse_arg::<OptionalDate>(args, "end-date").or_end()
     } else {
         Date::MAX
     };
     let weekdays = if args.is_present("weekdays") {
-        value_t_or_exit!(args, "weekdays", WeekdaySet)
+        parse_arg::<WeekdaySet>(args, "weekdays")
     } else {
         WeekdaySet::all()
     };
 
-    let time_period = TimePeriod {
+    TimePeriod {
         time_interval: time_interval,
         date_range: DateRange {
             start: start_date,
             end: end_date,
         },
         days: weekdays,
-    };
-
-    get_client().add_time_slot(actuator_id, time_period, actuator_state, true).and(Ok(()))
-}
-
-fn remove_time_slot(args: &clap::ArgMatches) -> RpcResult {
-    let specifier = value_t_or_exit!(args, "specifier", TimeslotSpecifier);
-
-    get_client().remove_time_slot(specifier.actuator_id, specifier.timeslot_id).and(Ok(()))
+    }
 }
 
-fn time_slot_set_time_period(args: &clap::ArgMatches) -> RpcResult {
-    let specifier = value_t_or_exit!(args, "specifier", TimeslotSpecifier);
-    let time_interval = if args.is_present("time-interval") {
-        value_t_or_exit!(args, "time-interval", TimeInterval)
-    } else {
-        TimeInterval { start: Time::EMPTY, end: Time::EMPTY }
-    };
-    let start_date = if args.is_present("start-date") {
-        value_t_or_exit!(args, "start-date", Date)
-    } else {
-        Date::empty_date()
+fn add_time_slot(args: &clap::ArgMatches) -> RpcResult {
+    let target = target_arg(args);
+    let time_period = time_period_arg(args);
+    let actuator_state = match target {
+        Target::Actuator(actuator_id) => actuator_state_arg(args, actuator_id),
+        Target::Group(ref group) => group_state_arg(args, group),
     };
-    let end_date = if args.is_present("end-date") {
-        value_t_or_exit!(args, "end-date", Date)
+
+    let label = args.value_of("label").map(|l| l.to_string());
+    if label.is_some() {
+        require_capability(rpc::capability::TIMESLOT_LABELS);
+    }
+    let jitter_minutes = if args.is_present("jitter") {
+        require_capability(rpc::capability::TIMESLOT_JITTER);
+        value_t_or_exit!(args, "jitter", u32)
     } else {
-        Date::empty_date()
+        0
     };
-    let weekdays = if args.is_present("weekdays") {
-        value_t_or_exit!(args, "weekdays", WeekdaySet)
-    } else {
-        WeekdaySet::empty()
+    let condition = args.value_of("condition").map(|value| {
+        require_capability(rpc::capability::TIMESLOT_CONDITIONS);
+        condition_arg(value)
+    });
+
+    let time_slot = NewTimeSlot {
+        label,
+        jitter_minutes,
+        condition,
+        ..NewTimeSlot::new(time_period, actuator_state, true)
     };
 
-    let time_period = TimePeriod {
-        time_interval: time_interval,
-        date_range: DateRange {
-            start: start_date,
-            end: end_date,
-        },
-        days: weekdays,
-    };
+    let client = get_client();
 
-    get_client().time_slot_set_time_period(specifier.actuator_id, specifier.timeslot_id,
-                                           time_period).and(Ok(()))
+    match target {
+        Target::Actuator(actuator_id) if args.is_present("fit") => {
+            require_capability(rpc::capability::ADD_TIMESLOT_FIT);
+
+            let added = client.add_time_slot_fit(actuator_id, time_slot)?;
+            if added.is_empty() {
+                println!("Nothing added, the time interval is already scheduled");
+            }
+            for (id, time_period) in added {
+                println!("Added timeslot {}: {} ({})", id, time_interval_str(&time_period),
+                         time_period.days);
+            }
+            Ok(())
+        },
+        Target::Actuator(actuator_id) if args.is_present("replace") => {
+            require_capability(rpc::capability::ADD_TIMESLOT_REPLACE);
+
+            let (id, replaced) = client.add_time_slot_replace(actuator_id, time_slot)?;
+            for ts in replaced.iter() {
+                match ts.new_time_period {
+                    Some(ref new_time_period) =>
+                        println!("Narrowed timeslot {}: {} -> {}", ts.id,
+                                 time_period_str(&ts.old_time_period),
+                                 time_period_str(new_time_period)),
+                    None => println!("Removed timeslot {}: {}", ts.id,
+                                     time_period_str(&ts.old_time_period)),
+                }
+            }
+            println!("Added timeslot {}", id);
+            Ok(())
+        },
+        Target::Actuator(actuator_id) => {
+            client.add_time_slot(actuator_id, time_slot).and(Ok(()))
+        },
+        Target::Group(_) if args.is_present("fit") || args.is_present("replace") => {
+            eprintln!("--fit and --replace are not supported for groups");
+            process::exit(1)
+        },
+        Target::Group(group) => {
+            let result = client.group_add_time_slot(group.clone(), time_slot)?;
+            report_group_result(&group, result, |id| format!("added timeslot {}", id))
+        },
+    }
+}
+
+fn add_time_slot_from_template(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    let template = args.value_of("template").unwrap().to_string();
+
+    require_capability(rpc::capability::TIMESLOT_TEMPLATES);
+
+    get_client().apply_template(actuator_id, template).and(Ok(()))
+}
+
+// A file describing a set of timeslots to add at once (see examples/template-*.yaml): classes of
+// days (weekday set, e.g. MTWTF-- or sat,sun), and entries each occurring on the days of one of
+// the classes.
+#[derive(Deserialize)]
+struct TemplateFile {
+    classes: BTreeMap<String, String>,
+    entries: Vec<TemplateEntry>,
+}
+
+#[derive(Deserialize)]
+struct TemplateEntry {
+    // Only used to identify the entry in messages.
+    name: Option<String>,
+    class: String,
+    time: String,
+    // Any scalar, e.g. 19.5 or on.
+    state: serde_yaml::Value,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl TemplateEntry {
+    // E.g. "entry 2 (morning)", numbered from 1.
+    fn describe(&self, index: usize) -> String {
+        match self.name {
+            Some(ref name) => format!("entry {} ({})", index + 1, name),
+            None => format!("entry {}", index + 1),
+        }
+    }
+
+    fn to_time_slot(&self, classes: &BTreeMap<String, WeekdaySet>,
+                    actuator_type: Option<&ActuatorType>) -> result::Result<NewTimeSlot, String> {
+        let days = *classes.get(&self.class)
+            .ok_or_else(|| format!("unknown class \"{}\"", self.class))?;
+        let time_interval = self.time.parse::<TimeInterval>()
+            .map_err(|e| format!("invalid time interval {}: {}", self.time, e))?;
+        let state = match self.state {
+            serde_yaml::Value::String(ref s) => s.clone(),
+            serde_yaml::Value::Number(ref n) => n.to_string(),
+            serde_yaml::Value::Bool(b) => (if b { "on" } else { "off" }).to_string(),
+            _ => return Err("the state must be a string or a number".to_string()),
+        };
+        let actuator_state = parse_actuator_state(&state, actuator_type)
+            .map_err(|e| format!("invalid actuator state {}: {}", state, e))?;
+
+        let time_period = TimePeriod {
+            time_interval,
+            date_range: DateRange { start: Date::MIN, end: Date::MAX },
+            days,
+        };
+        Ok(NewTimeSlot::new(time_period, actuator_state, self.enabled))
+    }
+}
+
+// Read and check the template file at path, exiting if it is invalid. Returns the description of
+// each entry (see TemplateEntry::describe()) and the corresponding timeslot.
+fn read_template_file(path: &str, actuator_type: Option<&ActuatorType>,
+                      day_start: DayStart) -> Vec<(String, NewTimeSlot)> {
+    let exit_invalid = |error: String| -> ! {
+        eprintln!("Invalid template file {}: {}", path, error);
+        process::exit(1)
+    };
+
+    let mut contents = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        eprintln!("Failed to read {}: {}", path, e);
+        process::exit(1)
+    }
+    let template: TemplateFile = serde_yaml::from_str(&contents)
+        .unwrap_or_else(|e| exit_invalid(e.to_string()));
+
+    let mut classes = BTreeMap::new();
+    for (name, days) in template.classes.iter() {
+        let days = days.parse::<WeekdaySet>()
+            .unwrap_or_else(|e| exit_invalid(format!("class {}: {}", name, e)));
+        classes.insert(name.clone(), days);
+    }
+
+    let mut time_slots: Vec<(String, NewTimeSlot)> = Vec::new();
+    for (index, entry) in template.entries.iter().enumerate() {
+        let description = entry.describe(index);
+        let time_slot = entry.to_time_slot(&classes, actuator_type)
+            .unwrap_or_else(|e| exit_invalid(format!("{}: {}", description, e)));
+
+        // The server would reject it as well, but only knows about entry indices.
+        for &(ref other_description, ref other) in time_slots.iter() {
+            if time_slot.time_period.intersection(&other.time_period, day_start).is_some() {
+                exit_invalid(format!("{} overlaps {}", description, other_description));
+            }
+        }
+        time_slots.push((description, time_slot));
+    }
+
+    time_slots
+}
+
+fn add_time_slots_from_file(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    let path = args.value_of("file").unwrap();
+
+    require_capability(rpc::capability::ADD_TIMESLOTS);
+
+    let time_slots = read_template_file(path, actuator_type(actuator_id).as_ref(),
+                                        actuator_day_start(actuator_id));
+    let new_time_slots = time_slots.iter().map(|&(_, ref ts)| ts.clone()).collect();
+
+    let ids = match get_client().add_time_slots(actuator_id, new_time_slots) {
+        Err(tarpc::Error::App(rpc::Error::BatchEntry { index, error })) => {
+            // Name the entry instead of giving its index.
+            eprintln!("Failed to add {}: {}", time_slots[index as usize].0, error);
+            process::exit(1)
+        },
+        result => result?,
+    };
+
+    for (id, &(ref description, ref ts)) in ids.iter().zip(time_slots.iter()) {
+        println!("Added timeslot {} from {}: {} ({})", id, description,
+                 time_interval_str(&ts.time_period), ts.time_period.days);
+    }
+    Ok(())
+}
+
+fn remove_time_slot(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+
+    get_client().remove_time_slot(specifier.actuator_id, specifier.timeslot_id).and(Ok(()))
+}
+
+fn clear_time_slots(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    let disabled_only = args.is_present("disabled-only");
+
+    require_capability(rpc::capability::CLEAR_TIMESLOTS);
+
+    if !args.is_present("yes") {
+        let what = if disabled_only { "the disabled" } else { "all the" };
+        if !confirm(&format!("Remove {} timeslots of actuator {}?", what, actuator_id)) {
+            return Ok(())
+        }
+    }
+
+    let removed = get_client().clear_time_slots(actuator_id, disabled_only)?;
+    println!("Removed {} timeslot(s)", removed);
+    Ok(())
+}
+
+// Ask question on the terminal, true if the answer is yes.
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false
+    }
+    match answer.trim().to_lowercase().as_ref() {
+        "y" | "yes" => true,
+        _ => false,
+    }
+}
+
+fn time_slot_set_time_period(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+    let time_interval = if args.is_present("time-interval") {
+        Some(parse_arg::<TimeInterval>(args, "time-interval"))
+    } else {
+        None
+    };
+    let start_date = if args.is_present("start-date") {
+        Some(parse_arg::<OptionalDate>(args, "start-date").or_start())
+    } else {
+        None
+    };
+    let end_date = if args.is_present("end-date") {
+        Some(parse_arg::<OptionalDate>(args, "end-date").or_end())
+    } else {
+        None
+    };
+    let weekdays = if args.is_present("weekdays") {
+        Some(parse_arg::<WeekdaySet>(args, "weekdays"))
+    } else {
+        None
+    };
+
+    let time_period_update = TimePeriodUpdate {
+        start_time: time_interval.as_ref().map(|ti| ti.start),
+        end_time: time_interval.as_ref().map(|ti| ti.end),
+        start_date,
+        end_date,
+        days: weekdays,
+    };
+
+    get_client().time_slot_set_time_period(specifier.actuator_id, specifier.timeslot_id,
+                                           time_period_update).and(Ok(()))
+}
+
+// Parse the state argument according to the type of the actuator, so that e.g. Enum names can be
+// resolved. Exits if it cannot be parsed.
+fn actuator_state_arg(args: &clap::ArgMatches, actuator_id: u32) -> ActuatorState {
+    let value = args.value_of("state").unwrap();
+
+    parse_actuator_state(value, actuator_type(actuator_id).as_ref())
+        .unwrap_or_else(|e| exit_invalid_state(value, e))
+}
+
+// The info of the actuator, None if it cannot be queried (the server then reports the error).
+fn actuator_info(actuator_id: u32) -> Option<ActuatorInfo> {
+    match get_client().list_actuators() {
+        Ok(ref mut actuators) if (actuator_id as usize) < actuators.len() =>
+            Some(actuators.swap_remove(actuator_id as usize)),
+        _ => None,
+    }
+}
+
+fn actuator_type(actuator_id: u32) -> Option<ActuatorType> {
+    actuator_info(actuator_id).map(|info| info.actuator_type)
+}
+
+// The unit of the actuator's values, None if it has none or if it cannot be queried.
+fn actuator_unit(actuator_id: u32) -> Option<String> {
+    actuator_info(actuator_id).and_then(|info| info.unit)
+}
+
+// All numeric states are displayed with the unit of their actuator (if it has one).
+fn state_str(state: &ActuatorState, unit: &Option<String>) -> String {
+    state.to_string_with_unit(unit.as_ref().map(|unit| unit.as_str()))
+}
+
+// Parse value according to actuator_type if it is known, or as is (letting the server check it).
+fn parse_actuator_state(value: &str, actuator_type: Option<&ActuatorType>)
+                        -> result::Result<ActuatorState, String> {
+    match actuator_type {
+        Some(actuator_type) => ActuatorState::parse_as(value, actuator_type),
+        None => value.parse(),
+    }
+}
+
+// Same as actuator_state_arg(), for a group. The state is parsed according to the type of the
+// first member, the server then checks it against all of them.
+fn group_state_arg(args: &clap::ArgMatches, group: &str) -> ActuatorState {
+    let members = group_members(group);
+    actuator_state_arg(args, members[0])
+}
+
+// The day start of the actuator, which is needed to compute its schedule. Defaults to the default
+// one if it cannot be queried (the server then reports the error on the next call).
+fn actuator_day_start(actuator_id: u32) -> DayStart {
+    actuator_info(actuator_id).map(|info| info.day_start).unwrap_or_default()
+}
+
+// Today, as an actuator with days starting at day_start sees it.
+fn today(day_start: DayStart) -> Date {
+    day_start.date_time(&DateTime::now(), DayStart::default()).date
+}
+
+fn exit_invalid_state(value: &str, error: String) -> ! {
+    eprintln!("Invalid actuator state {}: {}", value, error);
+    process::exit(1)
+}
+
+// The server may clamp states instead of rejecting them, tell the user.
+fn warn_if_clamped(actuator_id: u32, requested: &ActuatorState, effective: &ActuatorState) {
+    if requested != effective {
+        let unit = actuator_unit(actuator_id);
+        eprintln!("Warning: {} is out of range or not on a step, adjusted to {}",
+                  state_str(requested, &unit), state_str(effective, &unit));
+    }
+}
+
+fn time_slot_set_actuator_state(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+    let actuator_state = actuator_state_arg(args, specifier.actuator_id);
+
+    let effective_state = get_client().time_slot_set_actuator_state(
+        specifier.actuator_id, specifier.timeslot_id, actuator_state.clone())?;
+    warn_if_clamped(specifier.actuator_id, &actuator_state, &effective_state);
+    Ok(())
+}
+
+fn time_slot_set_enabled(args: &clap::ArgMatches, enabled: bool) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+
+    get_client().time_slot_set_enabled(specifier.actuator_id, specifier.timeslot_id,
+                                       enabled).and(Ok(()))
+}
+
+fn time_slot_set_label(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+    let label = args.value_of("label").map(|l| l.to_string());
+
+    require_capability(rpc::capability::TIMESLOT_LABELS);
+
+    get_client().time_slot_set_label(specifier.actuator_id, specifier.timeslot_id,
+                                     label).and(Ok(()))
+}
+
+fn time_slot_set_jitter(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+    let jitter_minutes = value_t_or_exit!(args, "minutes", u32);
+
+    require_capability(rpc::capability::TIMESLOT_JITTER);
+
+    get_client().time_slot_set_jitter(specifier.actuator_id, specifier.timeslot_id,
+                                      jitter_minutes).and(Ok(()))
+}
+
+fn time_slot_set_condition(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+    let condition = args.value_of("condition").map(condition_arg);
+
+    require_capability(rpc::capability::TIMESLOT_CONDITIONS);
+
+    get_client().time_slot_set_condition(specifier.actuator_id, specifier.timeslot_id,
+                                         condition).and(Ok(()))
+}
+
+// A condition specified as <actuator ID>=<state>, the state being parsed according to the type of
+// that actuator.
+fn condition_arg(value: &str) -> TimeSlotCondition {
+    let (actuator_id, state) = match value.find('=') {
+        Some(pos) => (u32::from_str(&value[..pos]).ok(), &value[pos + 1..]),
+        None => (None, ""),
+    };
+    let actuator_id = actuator_id.unwrap_or_else(|| {
+        eprintln!("Invalid condition {}, expected <actuator ID>=<state>", value);
+        process::exit(1)
+    });
+    let state = parse_actuator_state(state, actuator_type(actuator_id).as_ref())
+        .unwrap_or_else(|e| exit_invalid_state(state, e));
+
+    TimeSlotCondition { actuator_id, state }
+}
+
+// E.g. "if boiler=On".
+fn condition_str(condition: &TimeSlotCondition) -> String {
+    match actuator_info(condition.actuator_id) {
+        Some(info) => format!("if {}={}", info.name, state_str(&condition.state, &info.unit)),
+        None => format!("if actuator {}={}", condition.actuator_id, condition.state),
+    }
+}
+
+fn time_slot_add_time_override(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_specifier(args);
+    let time_period = time_period_arg(args);
+
+    get_client().time_slot_add_time_override(specifier.actuator_id, specifier.timeslot_id,
+                                             time_period).and(Ok(()))
+}
+
+fn time_slot_remove_time_override(args: &clap::ArgMatches) -> RpcResult {
+    let specifier = timeslot_override_specifier(args);
+
+    get_client().time_slot_remove_time_override(specifier.actuator_id, specifier.timeslot_id,
+                                                specifier.timeslot_override_id).and(Ok(()))
+}
+
+fn shift_time_slots(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    let minutes = if args.is_present("offset") {
+        value_t_or_exit!(args, "offset", i32)
+    } else {
+        value_t_or_exit!(args, "minutes", i32)
+    };
+    let slot_ids = if args.is_present("ids") {
+        Some(values_t_or_exit!(args, "ids", u32))
+    } else {
+        None
+    };
+    let dry_run = args.is_present("dry-run");
+
+    require_capability(rpc::capability::SHIFT_TIMESLOTS);
+
+    let client = get_client();
+    // Fetched first, to show the intervals before shifting.
+    let timeslots = client.list_timeslots(actuator_id)?;
+    let shifted = client.shift_time_slots(actuator_id, slot_ids, minutes, dry_run)?;
+
+    if dry_run {
+        println!("Would shift:");
+    }
+    for (id, time_interval) in shifted.iter() {
+        let before = timeslots.get(id).map_or("?".to_string(),
+                                             |ts| time_interval_str(&ts.time_period));
+        println!("Timeslot {}: {} -> {} - {}", id, before, time_interval.start,
+                 time_interval.end);
+    }
+
+    Ok(())
+}
+
+fn time_slot(args: &clap::ArgMatches) -> RpcResult {
+    match args.subcommand() {
+        ("list", Some(sub)) => list_time_slots(sub),
+        ("show", Some(sub)) => show_time_slot(sub),
+        ("add", Some(sub)) => add_time_slot(sub),
+        ("add-from", Some(sub)) => add_time_slot_from_template(sub),
+        ("add-template", Some(sub)) => add_time_slots_from_file(sub),
+        ("remove", Some(sub)) => remove_time_slot(sub),
+        ("clear", Some(sub)) => clear_time_slots(sub),
+        ("set-time", Some(sub)) => time_slot_set_time_period(sub),
+        ("set-state", Some(sub)) => time_slot_set_actuator_state(sub),
+        ("disable", Some(sub)) => time_slot_set_enabled(sub, false),
+        ("enable", Some(sub)) => time_slot_set_enabled(sub, true),
+        ("set-label", Some(sub)) => time_slot_set_label(sub),
+        ("set-jitter", Some(sub)) => time_slot_set_jitter(sub),
+        ("set-condition", Some(sub)) => time_slot_set_condition(sub),
+        ("add-override", Some(sub)) => time_slot_add_time_override(sub),
+        ("shift", Some(sub)) => shift_time_slots(sub),
+        ("remove-override", Some(sub)) => time_slot_remove_time_override(sub),
+        _ => unreachable!(),
+    }
+}
+
+fn template(args: &clap::ArgMatches) -> RpcResult {
+    use prettytable::{Table, format};
+
+    require_capability(rpc::capability::TIMESLOT_TEMPLATES);
+
+    match args.subcommand() {
+        ("list", Some(_)) => {
+            let templates = get_client().list_templates()?;
+            if templates.is_empty() {
+                println!("No template configured");
+                return Ok(())
+            }
+
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_CLEAN);
+            table.set_titles(row![b => "Name", "Actuator state", "Time range", "Start date",
+                                  "End date", "Days"]);
+            for (name, template) in templates.iter() {
+                let time_period = &template.time_period;
+                table.add_row(row![name, template.actuator_state, time_interval_str(time_period),
+                                   time_period.date_range.start, time_period.date_range.end,
+                                   time_period.days]);
+            }
+            table.printstd();
+            Ok(())
+        },
+        ("save", Some(sub)) => {
+            let name = sub.value_of("name").unwrap().to_string();
+            let time_period = time_period_arg(sub);
+            // Not bound to an actuator, so the state cannot be parsed according to its type.
+            let value = sub.value_of("state").unwrap();
+            let actuator_state = value.parse().unwrap_or_else(|e| exit_invalid_state(value, e));
+            get_client().save_template(name, time_period, actuator_state)
+        },
+        ("remove", Some(sub)) => {
+            let name = sub.value_of("name").unwrap().to_string();
+            get_client().remove_template(name)
+        },
+        _ => unreachable!(),
+    }
+}
+
+// Metadata of the server, or of an actuator if --actuator is given.
+fn metadata(args: &clap::ArgMatches) -> RpcResult {
+    require_capability(rpc::capability::METADATA);
+
+    let (command, sub) = args.subcommand();
+    let sub = sub.unwrap();
+    let actuator_id = if sub.is_present("actuator") { Some(actuator_arg(sub)) } else { None };
+    let key = || sub.value_of("key").unwrap().to_string();
+
+    match command {
+        "show" => {
+            let metadata = get_client().get_metadata(actuator_id)?;
+            if metadata.is_empty() {
+                println!("No metadata");
+            }
+            for (key, value) in metadata.iter() {
+                println!("{}: {}", key, value);
+            }
+            Ok(())
+        },
+        "set" => {
+            let value = sub.value_of("value").unwrap().to_string();
+            get_client().set_metadata(actuator_id, key(), Some(value))
+        },
+        "remove" => get_client().set_metadata(actuator_id, key(), None),
+        _ => unreachable!(),
+    }
+}
+
+fn default_state(args: &clap::ArgMatches) -> RpcResult {
+    if args.subcommand_name() != Some("set") {
+        require_capability(rpc::capability::DEFAULT_SCHEDULE);
+    }
+
+    match args.subcommand() {
+        ("get", Some(sub)) => {
+            let actuator_id = actuator_arg(sub);
+            let default_schedule = get_client().get_default_schedule(actuator_id)?;
+            let day_start = actuator_day_start(actuator_id);
+            let unit = actuator_unit(actuator_id);
+
+            println!("{}", state_str(&default_schedule.base_state, &unit));
+            for (time, state) in default_schedule.changes_in_order(day_start) {
+                println!("From {}: {}", time, state_str(state, &unit));
+            }
+            Ok(())
+        },
+        ("set", Some(sub)) => match target_arg(sub) {
+            Target::Actuator(actuator_id) => {
+                let actuator_state = actuator_state_arg(sub, actuator_id);
+                let effective_state = get_client().set_default_state(actuator_id,
+                                                                     actuator_state.clone())?;
+                warn_if_clamped(actuator_id, &actuator_state, &effective_state);
+                Ok(())
+            },
+            Target::Group(group) => {
+                let actuator_state = group_state_arg(sub, &group);
+                let result = get_client().group_set_default_state(group.clone(),
+                                                                  actuator_state)?;
+                report_group_result(&group, result, |state| format!("default state {}", state))
+            },
+        },
+        ("set-change", Some(sub)) => {
+            let actuator_id = actuator_arg(sub);
+            let time = parse_arg::<Time>(sub, "time");
+            let actuator_state = actuator_state_arg(sub, actuator_id);
+            get_client().set_default_change(actuator_id, time, actuator_state).and(Ok(()))
+        },
+        ("remove-change", Some(sub)) => {
+            let actuator_id = actuator_arg(sub);
+            let time = parse_arg::<Time>(sub, "time");
+            get_client().remove_default_change(actuator_id, time).and(Ok(()))
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn schedule_slot_str(slot: &schedule::ScheduleSlot, day_start: DayStart,
+                     unit: &Option<String>) -> String {
+    let id = match slot.label {
+        Some(ref label) => format!("{} \"{}\"", slot.id, label),
+        None => slot.id.to_string(),
+    };
+
+    let slot_str = match (slot.override_id, &slot.overridden_interval) {
+        (Some(oid), Some(overridden)) => format!(
+            "{} (TS {} override {}, instead of {} - {}, {})",
+            state_str(&slot.actuator_state, unit), id, oid, overridden.start, overridden.end,
+            day_start.duration(&slot.time_interval)
+        ),
+        _ => format!("{} (TS {}, {})", state_str(&slot.actuator_state, unit), id,
+                     day_start.duration(&slot.time_interval)),
+    };
+
+    match slot.condition {
+        Some(ref condition) => format!("{} ({})", slot_str, condition_str(condition)),
+        None => slot_str,
+    }
+}
+
+fn schedule(args: &clap::ArgMatches) -> RpcResult {
+    if let ("copy-day", Some(sub)) = args.subcommand() {
+        return copy_day(sub)
+    }
+
+    let actuator_id = actuator_arg(args);
+    let day_start = actuator_day_start(actuator_id);
+    let start_date = if args.is_present("start-date") {
+        parse_arg::<RelativeDate>(args, "start-date").0
+    } else {
+        today(day_start)
+    };
+    let nb_days = value_t_or_exit!(args, "day-number", i32);
+
+    let timeslots = get_client().list_timeslots(actuator_id)?;
+    require_capability(rpc::capability::DEFAULT_SCHEDULE);
+    let default_schedule = get_client().get_default_schedule(actuator_id)?;
+
+    let schedule = schedule::compute_schedule(&timeslots, &default_schedule, day_start,
+                                              start_date, nb_days);
+    print_schedule(&schedule, day_start, &actuator_unit(actuator_id), args.is_present("group"));
+
+    Ok(())
+}
+
+// One column per day, or per group of consecutive days with the same slots if group is set.
+fn print_schedule(schedule: &schedule::ResolvedSchedule, day_start: DayStart,
+                  unit: &Option<String>, group: bool) {
+    use prettytable::{Table, Row, format};
+
+    // Columns as (first day, last day, entries). Unless grouping, each day has its own column.
+    let mut columns: Vec<(Date, Date, &Vec<ScheduleEntry>)> = Vec::new();
+    for (date, entries) in schedule.iter() {
+        let same_as_last = group &&
+            columns.last().map_or(false, |&(_, _, last_entries)| last_entries == entries);
+
+        if same_as_last {
+            columns.last_mut().unwrap().1 = *date;
+        } else {
+            columns.push((*date, *date, entries));
+        }
+    }
+
+    let mut schedule_table = Table::new();
+    schedule_table.set_titles(Row::new(
+        columns.iter()
+            .map(|&(first, last, _)| if first == last {
+                cell!(b->first.chrono_date().format("%a %d/%m"))
+            } else {
+                cell!(b->format!("{} - {}", first.chrono_date().format("%a %d/%m"),
+                                 last.chrono_date().format("%a %d/%m")))
+            })
+            .collect()
+    ));
+    let mut days_row = Row::empty();
+
+    for &(_, _, entries) in columns.iter() {
+        let mut day_table = Table::new();
+        day_table.set_format(*format::consts::FORMAT_CLEAN);
+
+        // Entries are contiguous, print the time between each of them (and at the end of the last
+        // slot of the day).
+        for (i, entry) in entries.iter().enumerate() {
+            if i > 0 {
+                day_table.add_row(row![entry.time_interval().start, ""]);
+            }
+
+            match *entry {
+                // Slots coming from an override are in italics, to tell them apart at a glance.
+                ScheduleEntry::Slot(ref slot) if slot.override_id.is_some() => {
+                    let slot_str = schedule_slot_str(slot, day_start, unit);
+                    day_table.add_row(row![i->"  :  ", i->slot_str])
+                },
+                ScheduleEntry::Slot(ref slot) =>
+                    day_table.add_row(row!["  |  ", schedule_slot_str(slot, day_start, unit)]),
+                ScheduleEntry::Default { ref actuator_state, .. } =>
+                    day_table.add_row(row!["", state_str(actuator_state, unit)]),
+            };
+        }
+        if let Some(&ScheduleEntry::Slot(ref slot)) = entries.last() {
+            day_table.add_row(row![slot.time_interval.end, ""]);
+        }
+
+        days_row.add_cell(cell!(day_table));
+    }
+
+    schedule_table.add_row(days_row);
+    schedule_table.printstd();
+}
+
+// Per day of the week starting on the start date, how long timeslots apply and their states.
+fn summary(args: &clap::ArgMatches) -> RpcResult {
+    use prettytable::{Table, format};
+
+    let actuator_id = actuator_arg(args);
+    let day_start = actuator_day_start(actuator_id);
+    let start_date = if args.is_present("start-date") {
+        parse_arg::<RelativeDate>(args, "start-date").0
+    } else {
+        today(day_start)
+    };
+
+    let timeslots = get_client().list_timeslots(actuator_id)?;
+    let schedule = schedule::compute_slots(&timeslots, day_start, start_date, 7);
+    let unit = actuator_unit(actuator_id);
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row![b => "Day", "Scheduled", "States"]);
+
+    let mut total = Duration::default();
+    for day in schedule::summarize(&schedule, day_start) {
+        let states: Vec<String> = day.states.iter().map(|s| state_str(s, &unit)).collect();
+        let states = if states.is_empty() { "-".to_string() } else { states.join(", ") };
+
+        table.add_row(row![day.date.chrono_date().format("%a %d/%m"), day.scheduled, states]);
+        total += day.scheduled;
+    }
+    table.add_row(row![b->"Total", b->total, ""]);
+    table.printstd();
+
+    Ok(())
+}
+
+fn copy_day(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    let from = parse_arg::<WeekdaySet>(args, "from");
+    let to = parse_arg::<WeekdaySet>(args, "to");
+    let dry_run = args.is_present("dry-run");
+
+    require_capability(rpc::capability::COPY_DAY);
+
+    let client = get_client();
+    // Even for a dry run, the server checks for conflicts.
+    let copied = client.copy_day(actuator_id, from, to, dry_run)?;
+    if copied.is_empty() {
+        println!("No enabled timeslot to copy");
+        return Ok(())
+    }
+
+    let ids: Vec<String> = copied.iter().map(|id| id.to_string()).collect();
+    if !dry_run {
+        println!("Copied timeslots {}", ids.join(", "));
+        return Ok(())
+    }
+
+    // Show the source and target days of the coming week, as they would be.
+    let mut timeslots = client.list_timeslots(actuator_id)?;
+    for id in copied.iter() {
+        if let Some(ts) = timeslots.get_mut(id) {
+            ts.time_period.days |= to;
+        }
+    }
+    require_capability(rpc::capability::DEFAULT_SCHEDULE);
+    let default_schedule = client.get_default_schedule(actuator_id)?;
+
+    let day_start = actuator_day_start(actuator_id);
+
+    let schedule = schedule::compute_schedule(&timeslots, &default_schedule, day_start,
+                                              today(day_start), 7)
+        .into_iter()
+        .filter(|&(date, _)| (from | to).contains(date.weekday()))
+        .collect();
+    println!("Timeslots {} would be copied, resulting in:", ids.join(", "));
+    print_schedule(&schedule, day_start, &actuator_unit(actuator_id), false);
+
+    Ok(())
+}
+
+// One line per actuator, with its current state.
+fn status() -> RpcResult {
+    use prettytable::{Table, format};
+
+    require_capability(rpc::capability::ALL_CURRENT_STATES);
+
+    let client = get_client();
+    let actuators = client.list_actuators()?;
+    let states = client.get_all_current_states()?;
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row![b => "Index", "Name", "Enabled", "State"]);
+    for (id, actuator) in actuators.iter().enumerate() {
+        let enabled = if actuator.enabled { "yes" } else { "no" };
+        let state = match states.get(&(id as u32)) {
+            Some(state) => state_str(state, &actuator.unit),
+            None => "unknown".to_string(),
+        };
+
+        table.add_row(row![id, actuator.name, enabled, simulated_str(actuator.simulate, state)]);
+    }
+
+    table.printstd();
+    print_simulation_banner()?;
+    print_vacation_banner().and(Ok(()))
+}
+
+fn set_state(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = match target_arg(args) {
+        Target::Actuator(actuator_id) => actuator_id,
+        Target::Group(group) => {
+            let actuator_state = group_state_arg(args, &group);
+            let result = get_client().group_set_state(group.clone(), actuator_state)?;
+            return report_group_result(&group, result, |state| format!("set to {}", state))
+        },
+    };
+    let actuator_state = actuator_state_arg(args, actuator_id);
+
+    // The call only returns once the actuator controller has applied the state (or failed to).
+    let effective_state = get_client().set_state(actuator_id, actuator_state.clone())?;
+    warn_if_clamped(actuator_id, &actuator_state, &effective_state);
+    println!("Actuator {} set to {}", actuator_id,
+             state_str(&effective_state, &actuator_unit(actuator_id)));
+    Ok(())
+}
+
+fn toggle(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+
+    require_capability(rpc::capability::TOGGLE_STATE);
+
+    let state = get_client().toggle_state(actuator_id)?;
+    println!("Actuator {} set to {}", actuator_id, state);
+    Ok(())
+}
+
+// States applied outside of the schedule (see set-state), which hold until its next transition.
+fn manual_override(args: &clap::ArgMatches) -> RpcResult {
+    require_capability(rpc::capability::OVERRIDES);
+
+    let (command, sub) = args.subcommand();
+    let actuator_id = actuator_arg(sub.unwrap());
+
+    match command {
+        "list" => {
+            let overrides = get_client().list_overrides(actuator_id)?;
+            let unit = actuator_unit(actuator_id);
+            if overrides.is_empty() {
+                println!("No override, actuator {} follows its schedule", actuator_id);
+            }
+            for info in overrides.iter() {
+                let until = match info.until {
+                    Some(ref until) => format!("until {}", until),
+                    None => "until the actuator is enabled".to_string(),
+                };
+                println!("{} ({}) since {}, {}", state_str(&info.state, &unit), info.source,
+                         info.since, until);
+            }
+            Ok(())
+        },
+        "cancel" => get_client().cancel_override(actuator_id),
+        _ => unreachable!(),
+    }
+}
+
+fn wait(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    let target_state = actuator_state_arg(args, actuator_id);
+    let deadline = if args.is_present("timeout") {
+        Some(Instant::now() + std_time::Duration::from_secs(value_t_or_exit!(args, "timeout", u64)))
+    } else {
+        None
+    };
+
+    require_capability(rpc::capability::CURRENT_STATE);
+
+    let client = get_client();
+    let mut current = client.get_current_state(actuator_id)?;
+
+    while current.state.as_ref() != Some(&target_state) {
+        // The server caps the wait anyway, just make sure we don't wait past the deadline.
+        let timeout_sec = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    eprintln!("Timed out waiting for actuator {} to be {}", actuator_id,
+                              state_str(&target_state, &actuator_unit(actuator_id)));
+                    process::exit(1)
+                }
+                // Round up, so that we don't spin when less than a second is left.
+                let remaining = deadline - now;
+                (remaining.as_secs() + if remaining.subsec_nanos() > 0 { 1 } else { 0 }) as u32
+            },
+            None => u32::max_value(),
+        };
+
+        current = client.wait_state_change(actuator_id, current.seq, timeout_sec)?;
+    }
+
+    Ok(())
+}
+
+// Print the state changes of all the actuators as they happen, until interrupted.
+fn watch() -> RpcResult {
+    require_capability(rpc::capability::WAIT_ANY_CHANGE);
+
+    let client = get_client();
+    let actuators = client.list_actuators()?;
+    let mut since = u64::max_value();
+
+    loop {
+        // The server caps the timeout, None just means that nothing happened in the meantime.
+        let change = client.wait_for_any_change(since, u32::max_value())?;
+
+        if let Some((seq, actuator_id, state)) = change {
+            if since != u64::max_value() && seq > since + 1 {
+                eprintln!("Warning: missed {} state change(s)", seq - since - 1);
+            }
+            since = seq;
+
+            match actuators.get(actuator_id as usize) {
+                Some(actuator) => println!("{} {} ({}): {}", DateTime::now(), actuator_id,
+                                           actuator.name, state_str(&state, &actuator.unit)),
+                // Should not happen, actuators are never added.
+                None => println!("{} {}: {}", DateTime::now(), actuator_id, state),
+            }
+        }
+    }
+}
+
+fn history(args: &clap::ArgMatches) -> RpcResult {
+    use prettytable::{Table, format};
+
+    let actuator_id = actuator_arg(args);
+    let since = if args.is_present("since") {
+        Some(parse_arg::<RelativeDate>(args, "since").0)
+    } else {
+        None
+    };
+
+    require_capability(rpc::capability::HISTORY);
+
+    let entries = get_client().get_history(actuator_id, since)?;
+
+    if entries.is_empty() {
+        println!("No history");
+        return Ok(())
+    }
+
+    let unit = actuator_unit(actuator_id);
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row!["Date", "Time", "State", "Provenance", "Result"]);
+
+    for entry in entries.iter() {
+        let result = match entry.error {
+            Some(ref e) => format!("failed: {}", e),
+            None if entry.simulated => "simulated".to_string(),
+            None => "ok".to_string(),
+        };
+        table.add_row(row![entry.time.date, entry.time.time, state_str(&entry.state, &unit),
+                           entry.provenance, result]);
+    }
+
+    table.printstd();
+    Ok(())
+}
+
+fn durations(args: &clap::ArgMatches) -> RpcResult {
+    use prettytable::{Table, format};
+
+    let actuator_id = actuator_arg(args);
+    let since = parse_arg::<RelativeDate>(args, "since").0;
+
+    require_capability(rpc::capability::STATE_DURATIONS);
+
+    let durations = get_client().get_state_durations(actuator_id, since)?;
+
+    if durations.is_empty() {
+        println!("No state applied since {}", since);
+        return Ok(())
+    }
+
+    let total: i32 = durations.values().map(|d| d.num_minutes()).sum();
+    let mut durations: Vec<(String, Duration)> = durations.into_iter().collect();
+    durations.sort_by_key(|&(_, duration)| -duration.num_minutes());
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row!["State", "Time", "Share"]);
+
+    for &(ref state, duration) in durations.iter() {
+        // total is 0 if all the states were applied less than a minute ago.
+        let share = if total > 0 {
+            format!("{:.1}%", duration.num_minutes() as f64 * 100.0 / total as f64)
+        } else {
+            "-".to_string()
+        };
+        table.add_row(row![state, duration, share]);
+    }
+
+    table.printstd();
+    Ok(())
+}
+
+fn recompute(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+
+    require_capability(rpc::capability::RECOMPUTE_ACTIVE);
+
+    get_client().recompute_active(actuator_id).and(Ok(()))
+}
+
+// Without argument, show the vacation in progress. A date without year is taken as the next one.
+fn vacation(args: &clap::ArgMatches) -> RpcResult {
+    require_capability(rpc::capability::VACATION);
+
+    let until = match args.value_of("until") {
+        None => {
+            if !print_vacation_banner()? {
+                println!("No vacation in progress");
+            }
+            return Ok(())
+        },
+        Some("off") => return get_client().clear_vacation(),
+        Some(value) => {
+            let mut until = Date::from_str(value).unwrap_or_else(|e| {
+                eprintln!("Invalid date {}: {}", value, e);
+                process::exit(1)
+            });
+            if value.matches('/').count() == 1 && until <= today(DayStart::default()) {
+                until = Date::from_ymd(until.year() + 1, until.month(), until.day())
+                    .unwrap_or(until);
+            }
+            until
+        },
+    };
+
+    let states = args.values_of("state").map(|values| {
+        values.map(|value| {
+            let (actuator_id, state) = match value.find('=') {
+                Some(pos) => (u32::from_str(&value[..pos]).ok(), &value[pos + 1..]),
+                None => (None, ""),
+            };
+            let actuator_id = actuator_id.unwrap_or_else(|| {
+                eprintln!("Invalid state {}, expected <actuator ID>=<state>", value);
+                process::exit(1)
+            });
+            let actuator_state = parse_actuator_state(state, actuator_type(actuator_id).as_ref())
+                .unwrap_or_else(|e| exit_invalid_state(state, e));
+            (actuator_id, actuator_state)
+        }).collect()
+    });
+
+    get_client().set_vacation(until, states)?;
+    print_vacation_banner().and(Ok(()))
 }
 
-fn time_slot_set_actuator_state(args: &clap::ArgMatches) -> RpcResult {
-    let specifier = value_t_or_exit!(args, "specifier", TimeslotSpecifier);
-    let actuator_state = value_t_or_exit!(args, "state", ActuatorState);
+// Print a line about the vacation in progress, if any (and if the server supports it). Returns
+// whether there is one.
+fn print_vacation_banner() -> result::Result<bool, tarpc::Error<rpc::Error>> {
+    if !server_supports(rpc::capability::VACATION) {
+        return Ok(false)
+    }
 
-    get_client().time_slot_set_actuator_state(specifier.actuator_id, specifier.timeslot_id,
-                                              actuator_state).and(Ok(()))
+    match get_client().get_vacation()? {
+        Some(vacation) => {
+            println!("Vacation mode until {}: schedules are suspended (\"vacation off\" to \
+                      resume)", vacation.until);
+            let actuators = get_client().list_actuators()?;
+            for (actuator_id, state) in vacation.states.iter() {
+                let unit = actuators.get(*actuator_id as usize).and_then(|a| a.unit.clone());
+                println!("  Actuator {}: {}", actuator_id, state_str(state, &unit));
+            }
+            Ok(true)
+        },
+        None => Ok(false),
+    }
 }
 
-fn time_slot_set_enabled(args: &clap::ArgMatches, enabled: bool) -> RpcResult {
-    let specifier = value_t_or_exit!(args, "specifier", TimeslotSpecifier);
+// Without source, show what the actuator follows.
+fn follow(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
 
-    get_client().time_slot_set_enabled(specifier.actuator_id, specifier.timeslot_id,
-                                       enabled).and(Ok(()))
-}
+    require_capability(rpc::capability::FOLLOW);
 
-fn time_slot_add_time_override(args: &clap::ArgMatches) -> RpcResult {
-    let specifier = value_t_or_exit!(args, "specifier", TimeslotSpecifier);
-    let time_interval = value_t_or_exit!(args, "time-interval", TimeInterval);
-    let start_date = if args.is_present("start-date") {
-        value_t_or_exit!(args, "start-date", Date)
-    } else {
-        Date::MIN
-    };
-    let end_date = if args.is_present("end-date") {
-        value_t_or_exit!(args, "end-date", Date)
-    } else {
-        Date::MAX
-    };
-    let weekdays = if args.is_present("weekdays") {
-        value_t_or_exit!(args, "weekdays", WeekdaySet)
-    } else {
-        WeekdaySet::all()
+    let client = get_client();
+    let source_id = match args.value_of("source") {
+        None => {
+            require_capability(rpc::capability::ACTUATORS_DETAILED);
+            let actuators = client.list_actuators_detailed()?;
+            let actuator = actuators.get(actuator_id as usize).unwrap_or_else(|| {
+                eprintln!("No actuator with ID {}", actuator_id);
+                process::exit(1)
+            });
+            match actuator.follow {
+                Some(ref follow) => {
+                    let source = &actuators[follow.actuator_id as usize];
+                    print_follow(follow, &source.info.name);
+                },
+                None => println!("Not following any actuator"),
+            }
+            return Ok(())
+        },
+        Some("off") => return client.set_follow(actuator_id, None),
+        Some(_) => value_t_or_exit!(args, "source", u32),
     };
 
-    let time_period = TimePeriod {
-        time_interval: time_interval,
-        date_range: DateRange {
-            start: start_date,
-            end: end_date,
-        },
-        days: weekdays,
+    let source_type = actuator_type(source_id);
+    let follower_type = actuator_type(actuator_id);
+    let map = args.values_of("map").map(|values| {
+        values.map(|value| {
+            let (from, to) = match value.find('=') {
+                Some(pos) => (&value[..pos], &value[pos + 1..]),
+                None => {
+                    eprintln!("Invalid mapping {}, expected <source state>=<state>", value);
+                    process::exit(1)
+                },
+            };
+            (parse_actuator_state(from, source_type.as_ref())
+                 .unwrap_or_else(|e| exit_invalid_state(from, e)),
+             parse_actuator_state(to, follower_type.as_ref())
+                 .unwrap_or_else(|e| exit_invalid_state(to, e)))
+        }).collect()
+    }).unwrap_or_default();
+
+    let follow = Follow {
+        actuator_id: source_id,
+        invert: args.is_present("invert"),
+        map,
     };
+    client.set_follow(actuator_id, Some(follow)).and(Ok(()))
+}
 
-    get_client().time_slot_add_time_override(specifier.actuator_id, specifier.timeslot_id,
-                                             time_period).and(Ok(()))
+fn print_follow(follow: &Follow, source_name: &str) {
+    let how = if follow.invert { " (inverted)" } else { "" };
+    println!("Following actuator {} ({}){}", follow.actuator_id, source_name, how);
+    for entry in follow.map.iter() {
+        println!("  {} -> {}", entry.0, entry.1);
+    }
+    if !follow.map.is_empty() {
+        println!("  (other states -> default state)");
+    }
 }
 
-fn time_slot_remove_time_override(args: &clap::ArgMatches) -> RpcResult {
-    let specifier = value_t_or_exit!(args, "specifier", TimeslotOverrideSpecifier);
+// Without argument, show which actuators are simulated.
+fn simulation(args: &clap::ArgMatches) -> RpcResult {
+    require_capability(rpc::capability::SIMULATION);
 
-    get_client().time_slot_remove_time_override(specifier.actuator_id, specifier.timeslot_id,
-                                                specifier.timeslot_override_id).and(Ok(()))
-}
+    let actuator_id = if args.is_present("actuator") { Some(actuator_arg(args)) } else { None };
 
-fn time_slot(args: &clap::ArgMatches) -> RpcResult {
-    match args.subcommand() {
-        ("list", Some(sub)) => list_time_slots(sub),
-        ("add", Some(sub)) => add_time_slot(sub),
-        ("remove", Some(sub)) => remove_time_slot(sub),
-        ("set-time", Some(sub)) => time_slot_set_time_period(sub),
-        ("set-state", Some(sub)) => time_slot_set_actuator_state(sub),
-        ("disable", Some(sub)) => time_slot_set_enabled(sub, false),
-        ("enable", Some(sub)) => time_slot_set_enabled(sub, true),
-        ("add-override", Some(sub)) => time_slot_add_time_override(sub),
-        ("remove-override", Some(sub)) => time_slot_remove_time_override(sub),
-        _ => unreachable!(),
+    match args.value_of("mode") {
+        Some(mode) => {
+            get_client().set_simulation(actuator_id, mode == "on")?;
+            if !print_simulation_banner()? {
+                println!("Simulation off: all actuators write to their controller");
+            }
+        },
+        None => {
+            if !print_simulation_banner()? {
+                println!("No actuator is simulated");
+            }
+        },
     }
+    Ok(())
 }
 
-fn default_state(args: &clap::ArgMatches) -> RpcResult {
-    let sub = match args.subcommand() {
-        ("get", Some(sub)) => sub,
-        ("set", Some(sub)) => sub,
-        _ => unreachable!(),
-    };
-
-    let actuator_id = value_t_or_exit!(sub, "actuator", u32);
+// Print a (loud) line about the simulated actuators, if any (and if the server supports it).
+// Returns whether there are some.
+fn print_simulation_banner() -> result::Result<bool, tarpc::Error<rpc::Error>> {
+    if !server_supports(rpc::capability::SIMULATION) {
+        return Ok(false)
+    }
 
-    if sub.is_present("state") {
-        let actuator_state = value_t_or_exit!(sub, "state", ActuatorState);
-        get_client().set_default_state(actuator_id, actuator_state).and(Ok(()))
-    } else {
-        println!("{}", get_client().get_default_state(actuator_id)?);
-        Ok(())
+    let simulation = get_client().get_simulation()?;
+    if simulation.server {
+        println!("*** SIMULATION MODE: no actuator writes to its controller (\"simulation off\" \
+                  to resume) ***");
+    } else if !simulation.actuators.is_empty() {
+        let ids: Vec<String> = simulation.actuators.iter().map(|id| id.to_string()).collect();
+        println!("*** SIMULATION MODE: actuator(s) {} do not write to their controller ***",
+                 ids.join(", "));
     }
+    Ok(simulation.server || !simulation.actuators.is_empty())
 }
 
-fn schedule(args: &clap::ArgMatches) -> RpcResult {
-    use prettytable::{Table, Row, format};
+fn next_timeslot(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    let max_days = value_t_or_exit!(args, "day-number", u32);
 
-    let actuator_id = value_t_or_exit!(args, "actuator", u32);
-    let start_date = if args.is_present("start-date") {
-        value_t_or_exit!(args, "start-date", Date)
-    } else {
-        DateTime::now().date
-    };
-    let nb_days = value_t_or_exit!(args, "day-number", u32);
+    require_capability(rpc::capability::NEXT_TIMESLOT);
 
-    let timeslots = get_client().list_timeslots(actuator_id)?;
-    let default_state = get_client().get_default_state(actuator_id)?;
+    match get_client().next_timeslot(actuator_id, max_days)? {
+        Some((date, slot)) => {
+            println!("{} {} - {}: {}", date, slot.time_interval.start, slot.time_interval.end,
+                     schedule_slot_str(&slot, actuator_day_start(actuator_id),
+                                       &actuator_unit(actuator_id)));
+        },
+        None => println!("No timeslot in the next {} days", max_days),
+    }
 
-    let schedule = schedule::compute_schedule(&timeslots, start_date, nb_days);
+    Ok(())
+}
 
-    let mut schedule_table = Table::new();
-    schedule_table.set_titles(Row::new(
-        schedule.keys()
-            .map(|d| cell!(b->d.chrono_date().format("%a %d/%m")))
-            .collect()
-    ));
-    let mut days_row = Row::empty();
+fn state_at(args: &clap::ArgMatches) -> RpcResult {
+    let actuator_id = actuator_arg(args);
+    // Either quoted as a single argument or as two (date and time).
+    let values: Vec<&str> = args.values_of("date-time").unwrap().collect();
+    let value = values.join(" ");
+    let date_time = value.parse::<RelativeDateTime>().unwrap_or_else(|e| {
+        eprintln!("Invalid value {} for date-time: {}", value, e);
+        process::exit(1)
+    }).0;
 
-    for slots in schedule.values() {
-        let mut day_table = Table::new();
-        day_table.set_format(*format::consts::FORMAT_CLEAN);
+    require_capability(rpc::capability::STATE_AT);
 
-        let mut previous_end_time = Time { hour: Time::DAY_START_HOUR, minute: 0 };
+    let resolved = get_client().get_state_at(actuator_id, date_time.clone())?;
+    println!("{}: {} from {} to {} ({})", date_time,
+             state_str(&resolved.actuator_state, &actuator_unit(actuator_id)),
+             resolved.time_interval.start, resolved.time_interval.end,
+             origin_str(&resolved.origin));
 
-        for slot in slots.iter() {
-            let id_string = if let Some(oid) = slot.override_id {
-                format!("{} > {}", slot.id, oid)
-            } else {
-                format!("{}", slot.id)
-            };
+    Ok(())
+}
 
-            if slot.time_interval.start != previous_end_time {
-                day_table.add_row(row!["", default_state]);
-                day_table.add_row(row![slot.time_interval.start, ""]);
-            }
+fn origin_str(origin: &schedule::StateOrigin) -> String {
+    match *origin {
+        schedule::StateOrigin::TimeSlot { id, override_id: Some(override_id) } =>
+            format!("timeslot {}, override {}", id, override_id),
+        schedule::StateOrigin::TimeSlot { id, override_id: None } => format!("timeslot {}", id),
+        schedule::StateOrigin::Default { .. } => "default".to_string(),
+    }
+}
 
-            day_table.add_row(row!["  |  ", format!("{} (TS {})", slot.actuator_state, id_string)]);
-            day_table.add_row(row![slot.time_interval.end, ""]);
+fn simulate_day(args: &clap::ArgMatches) -> RpcResult {
+    use prettytable::{Table, format};
 
-            previous_end_time = slot.time_interval.end;
-        }
+    let actuator_id = actuator_arg(args);
+    let date = parse_arg::<RelativeDate>(args, "date").0;
 
-        day_table.add_row(row!["", default_state]);
+    require_capability(rpc::capability::SIMULATE_DAY);
 
-        days_row.add_cell(cell!(day_table));
-    }
+    let states = get_client().simulate_day(actuator_id, date)?;
+    let unit = actuator_unit(actuator_id);
 
-    schedule_table.add_row(days_row);
-    schedule_table.printstd();
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row!["Time", "State", "Source"]);
+
+    let mut previous_state = None;
+    for resolved in states.iter() {
+        // The actuator is set again at each transition, but show whether that changes anything.
+        let state = if previous_state == Some(&resolved.actuator_state) {
+            format!("{} (unchanged)", state_str(&resolved.actuator_state, &unit))
+        } else {
+            state_str(&resolved.actuator_state, &unit)
+        };
+        let time_interval = format!("{} - {}", resolved.time_interval.start,
+                                    resolved.time_interval.end);
+        table.add_row(row![time_interval, state, origin_str(&resolved.origin)]);
+        previous_state = Some(&resolved.actuator_state);
+    }
 
+    println!("{}:", date);
+    table.printstd();
     Ok(())
 }
 
-fn set_state(args: &clap::ArgMatches) -> RpcResult {
-    let actuator_id = value_t_or_exit!(args, "actuator", u32);
-    let actuator_state = value_t_or_exit!(args, "state", ActuatorState);
-
-    get_client().set_state(actuator_id, actuator_state).and(Ok(()))
-}
-
 fn main() {
     use clap::{Arg, ArgGroup, App, AppSettings, SubCommand};
 
     let actuator_arg = Arg::with_name("actuator")
         .help("Actuator ID");
+    let actuator_or_group_arg = Arg::with_name("actuator")
+        .help("Actuator ID, or @<group name> for all the actuators of a group");
     let actuator_state_arg = Arg::with_name("state")
-        .help("Default actuator state");
+        .help("Actuator state: on/off, a number or a level name, depending on the actuator type");
 
     let timeslot_specifier_arg = Arg::with_name("specifier")
-        .help("Timeslot specifier, specified as <actuator ID>:<timeslot ID>");
+        .help("Timeslot specifier, specified as <actuator ID>:<timeslot ID or label>");
     let timeslot_override_specifier_arg = Arg::with_name("specifier")
-        .help("Timeslot override specifier, specified as <actuator ID>:<timeslot ID>:<override ID>");
+        .help("Timeslot override specifier, specified as \
+               <actuator ID>:<timeslot ID or label>:<override ID> (override IDs are numbered \
+               separately for each timeslot)");
+
+    let label_arg = Arg::with_name("label")
+        .takes_value(true)
+        .help("Timeslot label, not necessarily unique, which can be used instead of its ID");
+
+    let if_arg = Arg::with_name("condition")
+        .takes_value(true)
+        .value_name("ACTUATOR=STATE")
+        .help("Only apply the timeslot while that actuator (by ID) is in that state, e.g. 0=on");
+
+    let template_name_arg = Arg::with_name("template")
+        .help("Timeslot template name (see the template command)");
+
+    let metadata_actuator_arg = actuator_arg.clone()
+        .long("--actuator").short("-a")
+        .takes_value(true)
+        .help("Actuator ID (default: the metadata of the server)");
+    let metadata_key_arg = Arg::with_name("key")
+        .help("Metadata key");
 
     let time_interval_arg = Arg::with_name("time-interval")
         .takes_value(true)
-        .help("Time interval, specified as hh:mm-hh:mm");
+        .help("Time interval, specified as hh:mm-hh:mm or as a start time and a duration, e.g. \
+               18:00+2h30");
     let start_date_arg = Arg::with_name("start-date")
-        .takes_value(true)
-        .help("Start date, specified as DD/MM[/YYYY] (default: today)");
+        .takes_value(true).allow_hyphen_values(true)
+        .help("Start date, specified as DD/MM[/YYYY] or YYYY-MM-DD, or - for none \
+               (default: today)");
     let end_date_arg = Arg::with_name("end-date")
-        .takes_value(true)
-        .help("End date, specified as DD/MM[/YYYY] (default: none)");
+        .takes_value(true).allow_hyphen_values(true)
+        .help("End date, specified as DD/MM[/YYYY] or YYYY-MM-DD, or - for none (default: none)");
+    let change_time_arg = Arg::with_name("time")
+        .help("Time of the daily default state change, specified as hh:mm");
     let weekdays_arg = Arg::with_name("weekdays")
         .takes_value(true).allow_hyphen_values(true)
-        .help("Enable only on certain weekdays, e.g. M----S- for Monday and Saturday (default: all)");
+        .help("Enable only on certain weekdays, e.g. M----S- or mon,sat for Monday and Saturday, \
+               mon-fri, fri-mon, weekdays or weekend, prefixed with ! for all but those, e.g. \
+               !sun (default: all)");
 
     let args = App::new("servoctl")
         .about("CLI for ServoScheduler")
         .setting(AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(SubCommand::with_name("list-actuators")
+        .arg(Arg::with_name("server")
+            .long("--server")
+            .takes_value(true)
+            .default_value("localhost:4242")
+            .help("Server to connect to: host:port, or unix:<path> for a Unix domain socket")
+        ).arg(Arg::with_name("json-errors")
+            .long("--json-errors")
+            .help("Print RPC errors as JSON objects with a stable code, e.g. \
+                   {\"code\": \"overlap\", \"message\": \"...\", \"details\": {...}}")
+        ).subcommand(SubCommand::with_name("ping")
+        ).subcommand(SubCommand::with_name("server-info")
+        ).subcommand(SubCommand::with_name("log-level")
+            .arg(Arg::with_name("level")
+                .required(true)
+                .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+                .help("Server log level")
+            )
+        ).subcommand(SubCommand::with_name("config")
+        ).subcommand(SubCommand::with_name("list-actuators")
+            .arg(Arg::with_name("detailed")
+                .long("--detailed").short("-d")
+                .help("Also show the default state, timeslot counts and intended state (the \
+                       default output is kept stable for scripts)")
+            )
+        ).subcommand(SubCommand::with_name("list-groups")
+        ).subcommand(SubCommand::with_name("list-triggers")
+            .about("List the actuators turned on for some time whenever another one turns on")
+        ).subcommand(SubCommand::with_name("actuator")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("disable")
+                .arg(actuator_or_group_arg.clone()
+                    .required(true)
+                )
+            ).subcommand(SubCommand::with_name("enable")
+                .arg(actuator_or_group_arg.clone()
+                    .required(true)
+                )
+            )
         ).subcommand(SubCommand::with_name("timeslot")
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .subcommand(SubCommand::with_name("list")
                 .arg(actuator_arg.clone()
                     .required(true)
+                ).arg(Arg::with_name("timestamps")
+                    .long("--timestamps").short("-t")
+                    .help("Also show when the timeslots were created and last modified")
+                )
+            ).subcommand(SubCommand::with_name("show")
+                .arg(timeslot_specifier_arg.clone()
+                    .required(true)
                 )
             ).subcommand(SubCommand::with_name("add")
-                .arg(actuator_arg.clone()
+                .arg(actuator_or_group_arg.clone()
                     .required(true)
                 ).arg(time_interval_arg.clone()
                     .required(true)
@@ -490,11 +2178,54 @@ fn main() {
                     .long("--end-date").short("-e")
                 ).arg(weekdays_arg.clone()
                     .long("--weekdays").short("-w")
+                ).arg(label_arg.clone()
+                    .long("--label").short("-l")
+                ).arg(Arg::with_name("jitter")
+                    .takes_value(true)
+                    .long("--jitter").short("-j")
+                    .help("Move the start and end times by up to that many minutes (earlier or \
+                           later), differently every day")
+                ).arg(if_arg.clone()
+                    .long("--if")
+                ).arg(Arg::with_name("fit")
+                    .long("--fit")
+                    .help("Only fill the parts of the time interval where there is no other \
+                           timeslot, instead of failing (this may add several timeslots)")
+                ).arg(Arg::with_name("replace")
+                    .long("--replace")
+                    .conflicts_with("fit")
+                    .help("Narrow or remove the timeslots in the way, instead of failing")
+                )
+            ).subcommand(SubCommand::with_name("add-from")
+                .arg(actuator_arg.clone()
+                    .required(true)
+                ).arg(template_name_arg.clone()
+                    .required(true)
+                )
+            ).subcommand(SubCommand::with_name("add-template")
+                .about("Add the timeslots described in a template file (see \
+                        examples/template-*.yaml), all or nothing")
+                .arg(actuator_arg.clone()
+                    .required(true)
+                ).arg(Arg::with_name("file")
+                    .required(true)
+                    .help("YAML template file")
                 )
             ).subcommand(SubCommand::with_name("remove")
                 .arg(timeslot_specifier_arg.clone()
                     .required(true)
                 )
+            ).subcommand(SubCommand::with_name("clear")
+                .about("Remove all the timeslots of an actuator")
+                .arg(actuator_arg.clone()
+                    .required(true)
+                ).arg(Arg::with_name("disabled-only")
+                    .long("--disabled-only").short("-d")
+                    .help("Only remove the disabled timeslots")
+                ).arg(Arg::with_name("yes")
+                    .long("--yes").short("-y")
+                    .help("Do not ask for confirmation")
+                )
             ).subcommand(SubCommand::with_name("set-time")
                 .arg(timeslot_specifier_arg.clone()
                     .required(true)
@@ -527,7 +2258,29 @@ fn main() {
                 .arg(timeslot_specifier_arg.clone()
                     .required(true)
                 )
+            ).subcommand(SubCommand::with_name("set-label")
+                .arg(timeslot_specifier_arg.clone()
+                    .required(true)
+                ).arg(label_arg.clone()
+                    .help("Timeslot label, not necessarily unique (none to remove it)")
+                )
+            ).subcommand(SubCommand::with_name("set-jitter")
+                .arg(timeslot_specifier_arg.clone()
+                    .required(true)
+                ).arg(Arg::with_name("minutes")
+                    .required(true)
+                    .help("Move the start and end times by up to that many minutes (earlier or \
+                           later), differently every day (0 to disable)")
+                )
+            ).subcommand(SubCommand::with_name("set-condition")
+                .about("Only apply the timeslot while another actuator is in some state")
+                .arg(timeslot_specifier_arg.clone()
+                    .required(true)
+                ).arg(if_arg.clone()
+                    .help("Actuator ID and state, e.g. 0=on (none to remove the condition)")
+                )
             ).subcommand(SubCommand::with_name("add-override")
+                .about("Use another time interval on some of the days the timeslot occurs on")
                 .arg(timeslot_specifier_arg.clone()
                     .required(true)
                 ).arg(time_interval_arg.clone()
@@ -547,11 +2300,80 @@ fn main() {
                     .long("--weekdays").short("-w")
                     .group("date-fields")
                 )
+            ).subcommand(SubCommand::with_name("shift")
+                .about("Move timeslots (and their overrides) earlier or later, e.g. for seasonal \
+                        adjustments")
+                .setting(AppSettings::AllowNegativeNumbers)
+                .arg(actuator_arg.clone()
+                    .required(true)
+                ).group(ArgGroup::with_name("offset-fields")
+                    .required(true)
+                ).arg(Arg::with_name("offset")
+                    .allow_hyphen_values(true)
+                    .group("offset-fields")
+                    .help("Offset in minutes, e.g. +30, or -30 to move earlier")
+                ).arg(Arg::with_name("minutes")
+                    .long("--minutes").short("-m")
+                    .takes_value(true).allow_hyphen_values(true)
+                    .group("offset-fields")
+                    .help("Same as the offset argument")
+                ).arg(Arg::with_name("ids")
+                    .long("--ids")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .help("Comma-separated IDs of the timeslots to shift (default: all)")
+                ).arg(Arg::with_name("dry-run")
+                    .long("--dry-run").short("-n")
+                    .help("Show the resulting time intervals, without changing anything")
+                )
             ).subcommand(SubCommand::with_name("remove-override")
                 .arg(timeslot_override_specifier_arg.clone()
                     .required(true)
                 )
             )
+        ).subcommand(SubCommand::with_name("template")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list")
+            ).subcommand(SubCommand::with_name("save")
+                .arg(Arg::with_name("name")
+                    .required(true)
+                    .help("Template name, replacing any existing template with that name")
+                ).arg(time_interval_arg.clone()
+                    .required(true)
+                ).arg(actuator_state_arg.clone()
+                    .required(true)
+                    .help("Actuator state, integers prefixed with i: and level names with e: \
+                           (e.g. i:3, e:low) as the actuator type is not known")
+                ).arg(start_date_arg.clone()
+                    .long("--start-date").short("-s")
+                ).arg(end_date_arg.clone()
+                    .long("--end-date").short("-e")
+                ).arg(weekdays_arg.clone()
+                    .long("--weekdays").short("-w")
+                )
+            ).subcommand(SubCommand::with_name("remove")
+                .arg(Arg::with_name("name")
+                    .required(true)
+                    .help("Template name")
+                )
+            )
+        ).subcommand(SubCommand::with_name("metadata")
+            .about("Free-form annotations of the server or of an actuator, e.g. for UIs")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("show")
+                .arg(metadata_actuator_arg.clone())
+            ).subcommand(SubCommand::with_name("set")
+                .arg(metadata_key_arg.clone()
+                    .required(true)
+                ).arg(Arg::with_name("value")
+                    .required(true)
+                    .help("Metadata value, replacing the current one if any")
+                ).arg(metadata_actuator_arg.clone())
+            ).subcommand(SubCommand::with_name("remove")
+                .arg(metadata_key_arg.clone()
+                    .required(true)
+                ).arg(metadata_actuator_arg.clone())
+            )
         ).subcommand(SubCommand::with_name("default-state")
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .subcommand(SubCommand::with_name("get")
@@ -559,42 +2381,313 @@ fn main() {
                     .required(true)
                 )
             ).subcommand(SubCommand::with_name("set")
+                .arg(actuator_or_group_arg.clone()
+                    .required(true)
+                ).arg(actuator_state_arg.clone()
+                    .required(true)
+                )
+            ).subcommand(SubCommand::with_name("set-change")
                 .arg(actuator_arg.clone()
                     .required(true)
+                ).arg(change_time_arg.clone()
+                    .required(true)
                 ).arg(actuator_state_arg.clone()
                     .required(true)
+                    .help("Default actuator state from that time on")
+                )
+            ).subcommand(SubCommand::with_name("remove-change")
+                .arg(actuator_arg.clone()
+                    .required(true)
+                ).arg(change_time_arg.clone()
+                    .required(true)
                 )
             )
         ).subcommand(SubCommand::with_name("schedule")
+            .setting(AppSettings::SubcommandsNegateReqs)
             .arg(actuator_arg.clone()
                 .required(true)
             ).arg(start_date_arg.clone()
                 .long("--start-date").short("-s")
+                .allow_hyphen_values(true)
+                .help("Start date, specified as DD/MM[/YYYY] or as a number of days relative to \
+                       today, e.g. -7 (default: today)")
             ).arg(Arg::with_name("day-number")
                 .takes_value(true)
+                .allow_hyphen_values(true)
                 .default_value("7")
-                .help("Number of days to show")
+                .help("Number of days to show (if negative, the days before and including the \
+                       start date)")
                 .long("--day-number").short("-n")
+            ).arg(Arg::with_name("group")
+                .help("Show consecutive days with the same timeslots in a single column")
+                .long("--group").short("-g")
+            ).subcommand(SubCommand::with_name("copy-day")
+                .about("Make the enabled timeslots of a weekday also occur on other weekdays \
+                        (without their overrides)")
+                .arg(actuator_arg.clone()
+                    .required(true)
+                ).arg(Arg::with_name("from")
+                    .long("--from")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Source weekday, e.g. mon or monday")
+                ).arg(Arg::with_name("to")
+                    .long("--to")
+                    .takes_value(true).allow_hyphen_values(true)
+                    .required(true)
+                    .help("Target weekdays, e.g. tue,wed,thu,fri or -TWTF--")
+                ).arg(Arg::with_name("dry-run")
+                    .long("--dry-run").short("-n")
+                    .help("Show the resulting schedule of the source and target days, without \
+                           changing anything")
+                )
             )
-        ).subcommand(SubCommand::with_name("set-state")
+        ).subcommand(SubCommand::with_name("summary")
+            .about("Show how long timeslots apply on each day of a week, and their states")
             .arg(actuator_arg.clone()
                 .required(true)
+            ).arg(Arg::with_name("start-date")
+                .takes_value(true).allow_hyphen_values(true)
+                .long("--start-date").short("-s")
+                .help("First day of the week, specified as DD/MM[/YYYY] or as a number of days \
+                       relative to today, e.g. -7 (default: today)")
+            )
+        ).subcommand(SubCommand::with_name("status")
+        ).subcommand(SubCommand::with_name("set-state")
+            .arg(actuator_or_group_arg.clone()
+                .required(true)
             )
             .arg(&actuator_state_arg)
+        ).subcommand(SubCommand::with_name("toggle")
+            .about("Set the opposite of the current state of a Toggle actuator")
+            .arg(actuator_arg.clone()
+                .required(true)
+            )
+        ).subcommand(SubCommand::with_name("override")
+            .about("States set outside of the schedule, which hold until its next transition")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list")
+                .about("Show the override in effect, if any, and when it ends")
+                .arg(actuator_arg.clone()
+                    .required(true)
+                )
+            ).subcommand(SubCommand::with_name("cancel")
+                .about("Go back to the state the schedule calls for right away")
+                .arg(actuator_arg.clone()
+                    .required(true)
+                )
+            )
+        ).subcommand(SubCommand::with_name("next")
+            .arg(actuator_arg.clone()
+                .required(true)
+            ).arg(Arg::with_name("day-number")
+                .takes_value(true)
+                .default_value("7")
+                .help("Number of days to look ahead (including today)")
+                .long("--day-number").short("-n")
+            )
+        ).subcommand(SubCommand::with_name("at")
+            .about("Show the state scheduled at some date and time")
+            .setting(AppSettings::AllowNegativeNumbers)
+            .arg(actuator_arg.clone()
+                .required(true)
+            ).arg(Arg::with_name("date-time")
+                .required(true).allow_hyphen_values(true)
+                .multiple(true).max_values(2)
+                .help("Date and time, specified as DD/MM[/YYYY] hh:mm, YYYY-MM-DDThh:mm or as a \
+                       number of days relative to today and a time, e.g. +5 19:30")
+            )
+        ).subcommand(SubCommand::with_name("simulate")
+            .about("Show the states the actuator would go through over some day, according to its \
+                    timeslots and default state")
+            .setting(AppSettings::AllowNegativeNumbers)
+            .arg(actuator_arg.clone()
+                .required(true)
+            ).arg(Arg::with_name("date")
+                .required(true).allow_hyphen_values(true)
+                .help("Date, specified as DD/MM[/YYYY] or as a number of days relative to today, \
+                       e.g. +1")
+            )
+        ).subcommand(SubCommand::with_name("wait")
+            .arg(actuator_arg.clone()
+                .required(true)
+            ).arg(actuator_state_arg.clone()
+                .required(true)
+                .help("Actuator state to wait for")
+            ).arg(Arg::with_name("timeout")
+                .takes_value(true)
+                .help("Give up (with a non-zero exit code) after this many seconds")
+                .long("--timeout").short("-t")
+            )
+        ).subcommand(SubCommand::with_name("watch")
+            .about("Print the state changes of all the actuators as they happen")
+        ).subcommand(SubCommand::with_name("history")
+            .arg(actuator_arg.clone()
+                .required(true)
+            ).arg(Arg::with_name("since")
+                .takes_value(true).allow_hyphen_values(true)
+                .long("--since").short("-s")
+                .help("Only show the history from this date on, specified as DD/MM[/YYYY] or as a \
+                       number of days relative to today, e.g. -7 (default: all)")
+            )
+        ).subcommand(SubCommand::with_name("durations")
+            .about("Show how long the actuator spent in each state (since the server started at \
+                    most)")
+            .arg(actuator_arg.clone()
+                .required(true)
+            ).arg(Arg::with_name("since")
+                .takes_value(true).allow_hyphen_values(true)
+                .default_value("-6")
+                .long("--since").short("-s")
+                .help("Count from the start of this date, specified as DD/MM[/YYYY] or as a number \
+                       of days relative to today (default: the last 7 days)")
+            )
+        ).subcommand(SubCommand::with_name("vacation")
+            .about("Suspend the schedules of all actuators until some date, or show the vacation \
+                    in progress")
+            .arg(Arg::with_name("until")
+                .help("Date on which the schedules resume, specified as DD/MM[/YYYY] (the next \
+                       such date if the year is omitted), or \"off\" to end the vacation now")
+            ).arg(Arg::with_name("state")
+                .takes_value(true).multiple(true).number_of_values(1)
+                .long("--state").short("-s")
+                .value_name("ACTUATOR=STATE")
+                .requires("until")
+                .help("State to keep an actuator in, e.g. 1=12.5 (default: its default state)")
+            )
+        ).subcommand(SubCommand::with_name("follow")
+            .about("Drive an actuator with the state of another one instead of its schedule, or \
+                    show the actuator it follows")
+            .arg(actuator_arg.clone()
+                .required(true)
+            ).arg(Arg::with_name("source")
+                .help("ID of the actuator to follow, or \"off\" to go back to the schedule")
+            ).arg(Arg::with_name("invert")
+                .long("--invert").short("-i")
+                .requires("source")
+                .conflicts_with("map")
+                .help("Apply the opposite state of the source (Toggle actuators only)")
+            ).arg(Arg::with_name("map")
+                .takes_value(true).multiple(true).number_of_values(1)
+                .long("--map").short("-m")
+                .value_name("SOURCE_STATE=STATE")
+                .requires("source")
+                .help("Apply STATE when the source is in SOURCE_STATE, e.g. on=high (the other \
+                       states of the source give the default state)")
+            )
+        ).subcommand(SubCommand::with_name("simulation")
+            .about("Stop (or resume) writing to the controllers while still following the \
+                    schedules, or show which actuators are simulated")
+            .arg(Arg::with_name("mode")
+                .possible_values(&["on", "off"])
+                .help("Whether to simulate")
+            ).arg(actuator_arg.clone()
+                .long("--actuator").short("-a")
+                .takes_value(true)
+                .requires("mode")
+                .help("Only change the simulation of this actuator (default: the whole server, \
+                       which simulates all actuators if on)")
+            )
+        ).subcommand(SubCommand::with_name("recompute")
+            .arg(actuator_arg.clone()
+                .required(true)
+            )
         ).subcommand(SubCommand::with_name("test")
         ).get_matches();
 
+    SERVER.with(|s| *s.borrow_mut() = args.value_of("server").unwrap().to_string());
+
     let res = match args.subcommand() {
-        ("list-actuators", Some(_)) => list_actuators(),
+        ("ping", Some(_)) => ping(),
+        ("server-info", Some(_)) => server_info(),
+        ("log-level", Some(sub)) => set_log_level(sub),
+        ("config", Some(_)) => config(),
+        ("list-actuators", Some(sub)) => list_actuators(sub),
+        ("list-groups", Some(_)) => list_groups(),
+        ("list-triggers", Some(_)) => list_triggers(),
+        ("actuator", Some(sub)) => actuator(sub),
         ("timeslot", Some(sub)) => time_slot(sub),
+        ("template", Some(sub)) => template(sub),
+        ("metadata", Some(sub)) => metadata(sub),
         ("default-state", Some(sub)) => default_state(sub),
         ("schedule", Some(sub)) => schedule(sub),
+        ("summary", Some(sub)) => summary(sub),
+        ("status", Some(_)) => status(),
         ("set-state", Some(sub)) => set_state(sub),
+        ("toggle", Some(sub)) => toggle(sub),
+        ("override", Some(sub)) => manual_override(sub),
+        ("next", Some(sub)) => next_timeslot(sub),
+        ("at", Some(sub)) => state_at(sub),
+        ("simulate", Some(sub)) => simulate_day(sub),
+        ("wait", Some(sub)) => wait(sub),
+        ("watch", Some(_)) => watch(),
+        ("history", Some(sub)) => history(sub),
+        ("durations", Some(sub)) => durations(sub),
+        ("vacation", Some(sub)) => vacation(sub),
+        ("follow", Some(sub)) => follow(sub),
+        ("simulation", Some(sub)) => simulation(sub),
+        ("recompute", Some(sub)) => recompute(sub),
         ("test", Some(_)) => test(),
         _ => unreachable!(),
     };
 
     if let Err(error) = res {
-        eprintln!("RPC failed: {}", error);
+        if args.is_present("json-errors") {
+            let code = match error {
+                tarpc::Error::App(ref e) => e.code(),
+                tarpc::Error::Io(_) => "transport",
+                tarpc::Error::RequestDeserialize(_) | tarpc::Error::ResponseDeserialize(_) =>
+                    "protocol",
+            };
+            let mut json_error = json!({ "code": code, "message": error.to_string() });
+            // The structured error (e.g. the conflicting timeslot), for scripts that need more
+            // than the code.
+            if let tarpc::Error::App(ref e) = error {
+                json_error["details"] = serde_json::to_value(e).unwrap_or(serde_json::Value::Null);
+            }
+            eprintln!("{}", json_error);
+        } else {
+            eprintln!("RPC failed: {}", error);
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_date() {
+        let today = DateTime::now().date;
+        assert_eq!(RelativeDate::from_str("-7").unwrap().0, today - 7);
+        assert_eq!(RelativeDate::from_str("+1").unwrap().0, today + 1);
+        assert_eq!(RelativeDate::from_str("01/02/2018").unwrap().0,
+                   Date::from_ymd(2018, 2, 1).unwrap());
+
+        for s in ["+99999999999", "-4294967295", "+4294967295", "--1", "+", "-x"].iter() {
+            assert!(RelativeDate::from_str(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn non_finite_states() {
+        let float_type = ActuatorType::FloatValue { min: 15.0, max: 25.0, step: None };
+
+        // Whether the actuator type is known or not, before anything is sent to the server.
+        for actuator_type in [None, Some(&float_type), Some(&ActuatorType::Color)].iter() {
+            for s in ["NaN", "-nan", "inf", "-Infinity", "1e400"].iter() {
+                assert_eq!(parse_actuator_state(s, *actuator_type),
+                           Err(format!("{} is not a finite number", s)));
+            }
+            assert_eq!(parse_actuator_state("18.5", *actuator_type),
+                       Ok(ActuatorState::FloatValue(18.5)));
+        }
+        // Not even parsed as a number for these.
+        let int_type = ActuatorType::IntValue { min: 0, max: 10 };
+        assert!(parse_actuator_state("inf", Some(&int_type)).is_err());
+        let enum_type = ActuatorType::Enum { values: vec!["low".to_string()] };
+        assert_eq!(parse_actuator_state("nan", Some(&enum_type)),
+                   Err("expected one of: low".to_string()));
     }
 }
@@ -0,0 +1,68 @@
+// Timeslots applying only while another actuator is in some state (see TimeSlotCondition). The
+// actuator threads read the state of the other actuators through their StateNotifier, which never
+// requires locking another actuator. A single thread follows the state changes of the whole server,
+// to have the actuators whose active timeslot depends on the actuator that changed check it again.
+
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::Duration;
+
+use actuator::{Actuator, ActuatorHandle, ActuatorType, StateChangesHandle, StateNotifierHandle};
+
+// The thread checks at least this often whether the actuators still exist, to exit once they have
+// all been dropped.
+const MAX_WAIT_SEC: u64 = 60;
+
+// What a condition can be checked against: an actuator of the server, by ID.
+pub struct ConditionSource {
+    pub actuator_type: ActuatorType,
+    pub notifier: StateNotifierHandle,
+}
+pub type ConditionSourcesHandle = Arc<Vec<ConditionSource>>;
+
+pub fn sources(actuators: &[ActuatorHandle]) -> ConditionSourcesHandle {
+    Arc::new(actuators.iter()
+        .map(|a| {
+            let actuator = a.read().unwrap();
+            ConditionSource {
+                actuator_type: actuator.info.actuator_type.clone(),
+                notifier: actuator.state_notifier(),
+            }
+        })
+        .collect())
+}
+
+// Pass the changes recorded in state_changes on to actuators (by ID, as recorded). The thread does
+// not keep the actuators alive.
+pub fn spawn(actuators: &[ActuatorHandle], state_changes: StateChangesHandle) {
+    let actuators = actuators.iter().map(Arc::downgrade).collect();
+    // Only the changes from now on.
+    let since = state_changes.last_seq();
+
+    thread::spawn(move || condition_thread(actuators, state_changes, since));
+}
+
+fn condition_thread(actuators: Vec<Weak<RwLock<Actuator>>>, state_changes: StateChangesHandle,
+                    mut since: u64) {
+
+    loop {
+        let change = state_changes.wait_after(since, Duration::from_secs(MAX_WAIT_SEC));
+
+        let mut alive = false;
+        for actuator in actuators.iter() {
+            if let Some(actuator) = actuator.upgrade() {
+                alive = true;
+                if let Some((_, actuator_id, _)) = change {
+                    actuator.read().unwrap().condition_source_changed(actuator_id);
+                }
+            }
+        }
+        if !alive {
+            return
+        }
+
+        if let Some((seq, _, _)) = change {
+            since = seq;
+        }
+    }
+}
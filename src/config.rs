@@ -0,0 +1,1256 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_yaml;
+use toml;
+
+use actuator::{ActuatorInfo, ActuatorState, ActuatorType, FailurePolicy};
+use actuator_controller::{ColorFormat, LineTerminator, WriteMode};
+use audit::AuditLogFormat;
+use follow;
+use follow::Follow;
+use log;
+use metadata;
+use metadata::Metadata;
+use time::{Date, DateRange, DayStart, WeekdaySet};
+use time_slot::{TimePeriod, TimeSlotTemplate};
+use utils::ValidCheck;
+
+// Server configuration file. The same structures are used for all the supported formats. A
+// configuration can be split across several files, see load().
+#[derive(Default, Deserialize)]
+pub struct ConfigFile {
+    // Other config files, or directories of config files, to merge into this one. Relative paths
+    // are relative to the directory of this file.
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub log_level: Option<String>,
+    // Address to serve Prometheus metrics on (e.g. 0.0.0.0:9242), disabled if not set.
+    #[serde(default)]
+    pub metrics_address: Option<String>,
+    #[serde(default)]
+    pub history: Option<ConfigHistory>,
+    // File to append each applied state change to, disabled if not set (see audit).
+    #[serde(default)]
+    pub audit_log: Option<ConfigAuditLog>,
+    // Address to serve state events on over WebSocket, disabled if not set.
+    #[serde(default)]
+    pub events_address: Option<String>,
+    // Address to serve RPCs on over TCP. Defaults to localhost:4242, unless unix_socket is set (TCP
+    // is then disabled, unless explicitly set).
+    #[serde(default)]
+    pub rpc_address: Option<String>,
+    #[serde(default)]
+    pub unix_socket: Option<ConfigUnixSocket>,
+    // Address to serve the HTTP/JSON gateway on (see http_gateway), disabled if not set.
+    #[serde(default)]
+    pub http_address: Option<String>,
+    // If set, the actuator threads check the time at least every max_wait_minutes, instead of
+    // only waking up when the active timeslot ends (see Actuator::set_max_wait()).
+    #[serde(default)]
+    pub max_wait_minutes: Option<u32>,
+    // If set, no actuator writes to its controller (see ActuatorInfo::simulate), e.g. to rehearse
+    // a new schedule on the real installation.
+    #[serde(default)]
+    pub simulate: bool,
+    // May be empty in some of the files of a split configuration (e.g. one only setting addresses).
+    #[serde(default)]
+    pub actuators: Vec<ConfigActuator>,
+    // Named sets of actuators (by name), that group-wide operations can be applied to.
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+    // Actuators turned on for some time whenever another one turns on, see ConfigTrigger.
+    #[serde(default)]
+    pub triggers: Vec<ConfigTrigger>,
+    // Timeslot templates by name, see ConfigTemplate.
+    #[serde(default)]
+    pub templates: BTreeMap<String, ConfigTemplate>,
+    // Initial metadata of the server (see metadata).
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+impl ConfigFile {
+    // Check everything that can be checked without creating the actuators, and return all the
+    // problems found. Controller files are not opened, unless check_hardware is set (they are then
+    // opened read-only).
+    pub fn validate(&self, check_hardware: bool) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(ref level) = self.log_level {
+            if log::LevelFilter::from_str(level).is_err() {
+                errors.push(format!("Invalid log level: {}", level));
+            }
+        }
+
+        if let Some(ref unix_socket) = self.unix_socket {
+            if let Err(e) = unix_socket.mode() {
+                errors.push(e);
+            }
+
+            let directory = match Path::new(&unix_socket.path).parent() {
+                Some(directory) if !directory.as_os_str().is_empty() => directory,
+                _ => Path::new("."),
+            };
+            if !directory.is_dir() {
+                errors.push(format!("Unix socket {}: no directory {}", unix_socket.path,
+                                    directory.display()));
+            }
+        }
+
+        // Each server needs its own address.
+        let addresses = [
+            ("rpc_address", &self.rpc_address),
+            ("http_address", &self.http_address),
+            ("metrics_address", &self.metrics_address),
+            ("events_address", &self.events_address),
+        ];
+        let mut used_addresses = BTreeMap::new();
+        for &(name, address) in addresses.iter() {
+            if let Some(ref address) = *address {
+                if let Err(e) = check_address(address, check_hardware) {
+                    errors.push(format!("Invalid {} {}: {}", name, address, e));
+                } else if let Some(other) = used_addresses.insert(address, name) {
+                    errors.push(format!("{} and {} are both {}", other, name, address));
+                }
+            }
+        }
+
+        if let Some(ref history) = self.history {
+            // It is created if it does not exist.
+            let directory = Path::new(&history.directory);
+            if directory.exists() && !directory.is_dir() {
+                errors.push(format!("History directory {} is not a directory",
+                                    history.directory));
+            }
+        }
+
+        if let Some(ref audit_log) = self.audit_log {
+            if let Err(e) = check_log_file(&audit_log.path) {
+                errors.push(format!("Invalid audit log {}: {}", audit_log.path, e));
+            }
+        }
+
+        if self.max_wait_minutes == Some(0) {
+            errors.push("max_wait_minutes must be at least 1".to_string());
+        }
+
+        if let Err(e) = metadata::check(&self.metadata) {
+            errors.push(format!("Invalid metadata: {}", e));
+        }
+
+        let mut names = BTreeSet::new();
+
+        for ca in self.actuators.iter() {
+            if !names.insert(&ca.name) {
+                errors.push(format!("Duplicate actuator name {}", ca.name));
+            }
+
+            let info = ActuatorInfo {
+                name: ca.name.clone(),
+                actuator_type: ca.actuator_type.clone().into(),
+                clamp: ca.clamp,
+                quantize: ca.quantize,
+                enabled: true,
+                day_start: ca.day_start(),
+                simulate: ca.simulate,
+                unit: ca.unit.clone(),
+                max_timeslots: ca.max_timeslots,
+            };
+            let default_state: ActuatorState = ca.default_state.clone().into();
+
+            if !info.valid() {
+                errors.push(format!("Actuator {}: invalid type {}", ca.name, info.actuator_type));
+            } else if !default_state.is_finite() {
+                errors.push(format!("Actuator {}: default state {} is not a finite number",
+                                    ca.name, default_state));
+            } else if !info.actuator_type.accepts(&default_state) {
+                errors.push(format!("Actuator {}: default state {} does not match type {}",
+                                    ca.name, default_state, info.actuator_type));
+            }
+
+            if ca.max_timeslots == Some(0) {
+                errors.push(format!("Actuator {}: max_timeslots must be at least 1", ca.name));
+            }
+
+            if let Some(ref log_file) = ca.log_file {
+                if let Err(e) = check_log_file(log_file) {
+                    errors.push(format!("Actuator {}: invalid log_file {}: {}", ca.name, log_file,
+                                        e));
+                }
+            }
+
+            if let Some(ref unit) = ca.unit {
+                match info.actuator_type {
+                    _ if unit.trim().is_empty() =>
+                        errors.push(format!("Actuator {}: unit must not be empty", ca.name)),
+                    ActuatorType::FloatValue { .. } | ActuatorType::IntValue { .. } => (),
+                    _ => errors.push(format!("Actuator {}: unit requires a FloatValue or \
+                                              IntValue type", ca.name)),
+                }
+            }
+
+            match info.actuator_type {
+                ActuatorType::FloatValue { step: Some(_), .. } => (),
+                _ if ca.quantize =>
+                    errors.push(format!("Actuator {}: quantize requires a FloatValue type with a \
+                                         step", ca.name)),
+                _ => (),
+            }
+
+            if let Err(e) = metadata::check(&ca.metadata) {
+                errors.push(format!("Actuator {}: invalid metadata: {}", ca.name, e));
+            }
+
+            if ca.on_failure.retries > 0 && ca.on_failure.retry_delay_sec == 0 {
+                errors.push(format!("Actuator {}: on_failure.retry_delay_sec must be at least 1",
+                                    ca.name));
+            }
+
+            let controller = match ca.controller() {
+                Ok(controller) => controller,
+                Err(e) => {
+                    errors.push(e);
+                    continue
+                },
+            };
+
+            match controller {
+                ConfigActuatorController::File { ref path, .. } => {
+                    let path = Path::new(path);
+                    let result = if check_hardware {
+                        OpenOptions::new().read(true).open(path).map(|_| ())
+                    } else {
+                        path.metadata().map(|_| ())
+                    };
+
+                    if let Err(e) = result {
+                        errors.push(format!("Actuator {}: cannot access controller file {}: {}",
+                                            ca.name, path.display(), e));
+                    }
+                },
+            }
+        }
+
+        for (group, members) in self.groups.iter() {
+            if group.is_empty() {
+                errors.push("Group names must not be empty".to_string());
+            }
+            if members.is_empty() {
+                errors.push(format!("Group {} has no members", group));
+            }
+
+            let mut group_names = BTreeSet::new();
+            for member in members.iter() {
+                if !names.contains(member) {
+                    errors.push(format!("Group {}: unknown actuator {}", group, member));
+                } else if !group_names.insert(member) {
+                    errors.push(format!("Group {}: duplicate actuator {}", group, member));
+                }
+            }
+        }
+
+        for trigger in self.triggers.iter() {
+            let error = |e: &str| {
+                format!("Trigger {} -> {}: {}", trigger.source, trigger.target, e)
+            };
+
+            if trigger.source == trigger.target {
+                errors.push(error("an actuator cannot trigger itself"));
+                continue
+            }
+            for name in [&trigger.source, &trigger.target].iter() {
+                match self.actuators.iter().find(|ca| ca.name == **name) {
+                    Some(&ConfigActuator { actuator_type: ConfigActuatorType::Toggle, .. }) => (),
+                    Some(_) => errors.push(error(&format!("actuator {} is not a Toggle", name))),
+                    None => errors.push(error(&format!("unknown actuator {}", name))),
+                }
+            }
+            if trigger.duration_minutes == 0 {
+                errors.push(error("duration_minutes must be at least 1"));
+            }
+        }
+
+        for (id, ca) in self.actuators.iter().enumerate() {
+            let cf = match ca.follows {
+                Some(ref cf) => cf,
+                None => continue,
+            };
+            let error = |e: &str| format!("Actuator {}: cannot follow {}: {}", ca.name,
+                                          cf.actuator, e);
+            let source = self.actuators.iter().position(|other| other.name == cf.actuator);
+            let source_id = match source {
+                Some(source_id) => source_id,
+                None => {
+                    errors.push(error("unknown actuator"));
+                    continue
+                },
+            };
+
+            let source_type = self.actuators[source_id].actuator_type.clone().into();
+            if let Err(e) = cf.to_follow(source_id as u32, &source_type,
+                                         &ca.actuator_type.clone().into()) {
+                errors.push(error(&e));
+            }
+
+            let actuators = &self.actuators;
+            let follows = |id: u32| {
+                actuators[id as usize].follows.as_ref()
+                    .and_then(|cf| actuators.iter().position(|other| other.name == cf.actuator))
+                    .map(|id| id as u32)
+            };
+            if follow::creates_cycle(id as u32, source_id as u32, actuators.len(), follows) {
+                errors.push(error("an actuator cannot follow itself, even indirectly"));
+            }
+        }
+
+        for (name, template) in self.templates.iter() {
+            if name.is_empty() {
+                errors.push("Template names must not be empty".to_string());
+            }
+            if let Err(e) = template.to_template() {
+                errors.push(format!("Template {}: {}", name, e));
+            }
+        }
+
+        errors
+    }
+
+    // Add everything defined in other, loaded from path, to self. Actuators, groups, templates and
+    // metadata keys must not be defined in several files, nor any of the settings (except simulate,
+    // set if it is in any file).
+    fn merge(&mut self, other: ConfigFile, path: &Path, origins: &mut Origins) {
+        {
+            let mut merge_setting = |setting: &mut Option<String>, value: Option<String>,
+                                     name: &str| {
+                if value.is_some() && origins.claim(format!("Setting {}", name), path) {
+                    *setting = value;
+                }
+            };
+            merge_setting(&mut self.log_level, other.log_level, "log_level");
+            merge_setting(&mut self.metrics_address, other.metrics_address, "metrics_address");
+            merge_setting(&mut self.events_address, other.events_address, "events_address");
+            merge_setting(&mut self.rpc_address, other.rpc_address, "rpc_address");
+            merge_setting(&mut self.http_address, other.http_address, "http_address");
+        }
+        if other.history.is_some() && origins.claim("Setting history".to_string(), path) {
+            self.history = other.history;
+        }
+        if other.audit_log.is_some() && origins.claim("Setting audit_log".to_string(), path) {
+            self.audit_log = other.audit_log;
+        }
+        if other.unix_socket.is_some() && origins.claim("Setting unix_socket".to_string(), path) {
+            self.unix_socket = other.unix_socket;
+        }
+        if other.max_wait_minutes.is_some() &&
+            origins.claim("Setting max_wait_minutes".to_string(), path) {
+            self.max_wait_minutes = other.max_wait_minutes;
+        }
+        self.simulate |= other.simulate;
+
+        for ca in other.actuators.into_iter() {
+            // Duplicates in the same file are reported by validate().
+            origins.claim(format!("Actuator {}", ca.name), path);
+            self.actuators.push(ca);
+        }
+        for (name, members) in other.groups.into_iter() {
+            if origins.claim(format!("Group {}", name), path) {
+                self.groups.insert(name, members);
+            }
+        }
+        self.triggers.extend(other.triggers);
+        for (name, template) in other.templates.into_iter() {
+            if origins.claim(format!("Template {}", name), path) {
+                self.templates.insert(name, template);
+            }
+        }
+        for (key, value) in other.metadata.into_iter() {
+            if origins.claim(format!("Metadata key {}", key), path) {
+                self.metadata.insert(key, value);
+            }
+        }
+    }
+}
+
+// The file each item of a merged configuration comes from, see ConfigFile::merge().
+#[derive(Default)]
+struct Origins {
+    paths: BTreeMap<String, PathBuf>,
+    errors: Vec<String>,
+}
+
+impl Origins {
+    // Record that item is defined in path, and return whether it was the first file to define it
+    // (an error is recorded otherwise).
+    fn claim(&mut self, item: String, path: &Path) -> bool {
+        if let Some(other) = self.paths.get(&item) {
+            if other != path {
+                self.errors.push(format!("{} is defined in both {} and {}", item, other.display(),
+                                         path.display()));
+            }
+            return false
+        }
+        self.paths.insert(item, path.to_path_buf());
+        true
+    }
+}
+
+// See Follow, with the actuator followed by name, e.g.:
+//   follows: { actuator: heating, invert: false }
+//   follows:
+//     actuator: heating
+//     map:
+//       - from: { type: Toggle, value: true }
+//         to: { type: Enum, value: high }
+#[derive(Clone, Deserialize)]
+pub struct ConfigFollow {
+    pub actuator: String,
+    #[serde(default)]
+    pub invert: bool,
+    #[serde(default)]
+    pub map: Vec<ConfigFollowMapping>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ConfigFollowMapping {
+    pub from: ConfigActuatorState,
+    pub to: ConfigActuatorState,
+}
+
+impl ConfigFollow {
+    // The Follow of source_id, checked against the types of the source and the follower.
+    pub fn to_follow(&self, source_id: u32, source_type: &ActuatorType,
+                     follower_type: &ActuatorType) -> result::Result<Follow, String> {
+        let follow = Follow {
+            actuator_id: source_id,
+            invert: self.invert,
+            map: self.map.iter()
+                .map(|mapping| (mapping.from.clone().into(), mapping.to.clone().into()))
+                .collect(),
+        };
+        follow.check(source_type, follower_type)?;
+        Ok(follow)
+    }
+}
+
+// See Trigger, e.g. {source: shower, target: exhaust_fan, duration_minutes: 20}.
+#[derive(Deserialize)]
+pub struct ConfigTrigger {
+    pub source: String,
+    pub target: String,
+    pub duration_minutes: u32,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigActuator {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_actuator_type")]
+    pub actuator_type: ConfigActuatorType,
+    #[serde(deserialize_with = "deserialize_default_state")]
+    pub default_state: ConfigActuatorState,
+    #[serde(default)]
+    pub clamp: bool,
+    // If set, FloatValue states not on the step of the type are snapped to the nearest step
+    // instead of rejected (see ActuatorInfo::quantize).
+    #[serde(default)]
+    pub quantize: bool,
+    // If set, the days of the actuator start at midnight instead of Time::DAY_START_HOUR: 02:00
+    // is then early on a day rather than late on the previous one, and a timeslot can no longer
+    // cross midnight (see DayStart).
+    #[serde(default)]
+    pub midnight_days: bool,
+    // Kept as is when parsing the config file, and checked by controller(), so that errors can
+    // name the actuator and the faulty field.
+    pub controller: serde_json::Value,
+    // Initial metadata of the actuator (see metadata).
+    #[serde(default)]
+    pub metadata: Metadata,
+    // What to do when the controller fails to apply a scheduled state (see FailurePolicy), e.g.
+    // {retries: 3, retry_delay_sec: 30, fallback: revert_to_default}. By default, the actuator is
+    // left as is until the next transition.
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+    // Same as ConfigFile::simulate, for this actuator only.
+    #[serde(default)]
+    pub simulate: bool,
+    // See ActuatorInfo::unit, e.g. "°C" or "%".
+    #[serde(default)]
+    pub unit: Option<String>,
+    // File to append the transitions of the actuator to, as CSV (see TransitionLog).
+    #[serde(default)]
+    pub log_file: Option<String>,
+    // See ActuatorInfo::max_timeslots, unlimited by default.
+    #[serde(default)]
+    pub max_timeslots: Option<u32>,
+    // Another actuator to follow instead of a schedule, see ConfigFollow.
+    #[serde(default)]
+    pub follows: Option<ConfigFollow>,
+}
+
+impl ConfigActuator {
+    pub fn day_start(&self) -> DayStart {
+        if self.midnight_days { DayStart::MIDNIGHT } else { DayStart::default() }
+    }
+
+    pub fn controller(&self) -> result::Result<ConfigActuatorController, String> {
+        let error = |e: String| format!("Actuator {}: {}", self.name, e);
+
+        let entry = self.controller.as_object()
+            .ok_or_else(|| error("controller must be a map with a type".to_string()))?;
+        let controller_type = match entry.get("type") {
+            Some(&serde_json::Value::String(ref controller_type)) => controller_type,
+            Some(other) => return Err(error(format!("invalid controller type {}", other))),
+            None => return Err(error(format!("controller has no type (expected one of {})",
+                                             ConfigActuatorController::TYPES.join(", ")))),
+        };
+        let fields = ConfigActuatorController::fields(controller_type)
+            .ok_or_else(|| error(format!("unknown controller type {} (expected one of {})",
+                                         controller_type,
+                                         ConfigActuatorController::TYPES.join(", "))))?;
+
+        for (name, value) in entry.iter().filter(|&(name, _)| name != "type") {
+            let field = fields.iter().find(|field| field.name == name);
+            match field {
+                Some(field) => (field.check)(value).map_err(|e| {
+                    error(format!("invalid {} in {} controller: {}", name, controller_type, e))
+                })?,
+                None => {
+                    let names: Vec<&str> = fields.iter().map(|field| field.name).collect();
+                    let hint = match closest_name(name, &names) {
+                        Some(closest) => format!("did you mean {}?", closest),
+                        None => format!("expected one of {}", names.join(", ")),
+                    };
+                    return Err(error(format!("unknown field {} in {} controller ({})",
+                                             name, controller_type, hint)))
+                },
+            }
+        }
+
+        if let Some(field) = fields.iter().find(|f| f.required && !entry.contains_key(f.name)) {
+            return Err(error(format!("{} controller has no {}", controller_type, field.name)))
+        }
+
+        // Everything has been checked, this is not expected to fail.
+        serde_json::from_value(self.controller.clone())
+            .map_err(|e| error(format!("invalid {} controller: {}", controller_type, e)))
+    }
+}
+
+// A field of a controller entry, and how to check its value.
+struct ControllerField {
+    name: &'static str,
+    required: bool,
+    check: fn(&serde_json::Value) -> result::Result<(), String>,
+}
+
+impl ControllerField {
+    fn new<T: DeserializeOwned>(name: &'static str, required: bool) -> ControllerField {
+        fn check<T: DeserializeOwned>(value: &serde_json::Value) -> result::Result<(), String> {
+            serde_json::from_value::<T>(value.clone()).map(|_| ()).map_err(|e| e.to_string())
+        }
+
+        ControllerField { name, required, check: check::<T> }
+    }
+}
+
+// Check that address is host:port, and also that host resolves if resolve is set.
+fn check_address(address: &str, resolve: bool) -> result::Result<(), String> {
+    let mut fields = address.rsplitn(2, ':');
+    let port = fields.next().unwrap();
+    let host = fields.next().unwrap_or("");
+
+    if host.is_empty() {
+        return Err("expected host:port".to_string())
+    }
+    port.parse::<u16>().map_err(|_| format!("invalid port {}", port))?;
+    if resolve {
+        address.to_socket_addrs().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Check that a log file can be created at path (if it does not exist yet): unlike the history
+// directory, its directory is not created.
+fn check_log_file(path: &str) -> result::Result<(), String> {
+    let path = Path::new(path);
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+
+    if path.is_dir() {
+        Err("is a directory".to_string())
+    } else if !directory.is_dir() {
+        Err(format!("directory {} does not exist", directory.display()))
+    } else {
+        Ok(())
+    }
+}
+
+// The candidate closest to name, if close enough to be a typo of it.
+fn closest_name<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    // Levenshtein distance.
+    let distance = |a: &str, b: &str| {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..b.len() + 1).collect();
+
+        for (i, ca) in a.chars().enumerate() {
+            let mut previous = row[0];
+            row[0] = i + 1;
+            for j in 0..b.len() {
+                let substitution = previous + if ca == b[j] { 0 } else { 1 };
+                previous = row[j + 1];
+                row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+            }
+        }
+        row[b.len()]
+    };
+
+    candidates.iter()
+        .map(|candidate| (distance(name, candidate), *candidate))
+        .filter(|&(d, _)| d <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum ConfigActuatorController {
+    // If enum_as_index is set, Enum states are written as their index instead of their name.
+    // color_format is either hex (default) or rgb.
+    // line_terminator is none (default), lf or crlf.
+    // write_mode is overwrite (default), truncate or append (for pipes and character devices).
+    // If read_back is set, the file is read when the server starts, so as not to write the state
+    // if it is already the right one (except in append mode).
+    File {
+        path: String,
+        #[serde(default)]
+        enum_as_index: bool,
+        #[serde(default)]
+        color_format: ColorFormat,
+        #[serde(default)]
+        line_terminator: LineTerminator,
+        #[serde(default)]
+        write_mode: WriteMode,
+        #[serde(default)]
+        read_back: bool,
+    },
+}
+
+impl ConfigActuatorController {
+    const TYPES: &'static [&'static str] = &["File"];
+
+    // The fields of each type of controller (besides type). Must be kept in sync with the enum.
+    fn fields(controller_type: &str) -> Option<Vec<ControllerField>> {
+        match controller_type {
+            "File" => Some(vec![
+                ControllerField::new::<String>("path", true),
+                ControllerField::new::<bool>("enum_as_index", false),
+                ControllerField::new::<ColorFormat>("color_format", false),
+                ControllerField::new::<LineTerminator>("line_terminator", false),
+                ControllerField::new::<WriteMode>("write_mode", false),
+                ControllerField::new::<bool>("read_back", false),
+            ]),
+            _ => None,
+        }
+    }
+
+    // Must not include any secret (credentials etc.), it is sent to clients.
+    pub fn summary(&self) -> String {
+        match *self {
+            ConfigActuatorController::File {
+                ref path, enum_as_index, color_format, line_terminator, write_mode, read_back
+            } => {
+                let mut summary = format!("File {}", path);
+                if enum_as_index {
+                    summary += " (enum as index)";
+                }
+                if color_format == ColorFormat::Rgb {
+                    summary += " (color as rgb)";
+                }
+                match line_terminator {
+                    LineTerminator::None => (),
+                    LineTerminator::Lf => summary += " (lf terminated)",
+                    LineTerminator::CrLf => summary += " (crlf terminated)",
+                }
+                match write_mode {
+                    WriteMode::Overwrite => (),
+                    WriteMode::Truncate => summary += " (truncate)",
+                    WriteMode::Append => summary += " (append)",
+                }
+                if read_back {
+                    summary += " (read back)";
+                }
+                summary
+            },
+        }
+    }
+}
+
+fn default_history_max_file_size() -> u64 { 1024 * 1024 }
+fn default_history_kept_files() -> u32 { 5 }
+
+#[derive(Deserialize)]
+pub struct ConfigHistory {
+    pub directory: String,
+    #[serde(default = "default_history_max_file_size")]
+    pub max_file_size: u64,
+    #[serde(default = "default_history_kept_files")]
+    pub kept_files: u32,
+}
+
+// E.g. in YAML:
+//   audit_log:
+//     path: /var/log/servoscheduler/audit.csv
+//     format: csv
+#[derive(Deserialize)]
+pub struct ConfigAuditLog {
+    pub path: String,
+    #[serde(default)]
+    pub format: AuditLogFormat,
+}
+
+// The dates and weekdays are optional and specified the same way as with servoctl, e.g. in YAML:
+//   templates:
+//     weekday_morning:
+//       time_interval: "07:00-09:00"
+//       weekdays: MTWTF--
+//       state: { type: Toggle, value: true }
+#[derive(Deserialize)]
+pub struct ConfigTemplate {
+    pub time_interval: String,
+    #[serde(default)]
+    pub start_date: Option<String>,
+    #[serde(default)]
+    pub end_date: Option<String>,
+    #[serde(default)]
+    pub weekdays: Option<String>,
+    pub state: ConfigActuatorState,
+}
+
+impl ConfigTemplate {
+    pub fn to_template(&self) -> result::Result<TimeSlotTemplate, String> {
+        let time_interval = self.time_interval.parse()
+            .map_err(|e| format!("invalid time interval {}: {}", self.time_interval, e))?;
+        let start = match self.start_date {
+            Some(ref date) =>
+                date.parse().map_err(|e| format!("invalid start date {}: {}", date, e))?,
+            None => Date::MIN,
+        };
+        let end = match self.end_date {
+            Some(ref date) =>
+                date.parse().map_err(|e| format!("invalid end date {}: {}", date, e))?,
+            None => Date::MAX,
+        };
+        let days = match self.weekdays {
+            Some(ref days) =>
+                days.parse().map_err(|e| format!("invalid weekdays {}: {}", days, e))?,
+            None => WeekdaySet::all(),
+        };
+
+        let time_period = TimePeriod {
+            time_interval,
+            date_range: DateRange { start, end },
+            days,
+        };
+        // Not bound to an actuator, check it against the default days.
+        time_period.check(DayStart::default())?;
+        // The state is checked against each actuator the template is used for, but no actuator
+        // accepts this one.
+        let actuator_state: ActuatorState = self.state.clone().into();
+        if !actuator_state.is_finite() {
+            return Err(format!("state {} is not a finite number", actuator_state))
+        }
+
+        Ok(TimeSlotTemplate {
+            time_period,
+            actuator_state,
+        })
+    }
+}
+
+fn default_unix_socket_mode() -> String { "660".to_string() }
+
+// Serve RPCs on a Unix domain socket (e.g. /run/servoscheduler.sock), in addition to or instead of
+// TCP. mode is the permissions of the socket, which control who may connect, as an octal string
+// (e.g. "660", quoted so that it is not parsed as a number).
+#[derive(Deserialize)]
+pub struct ConfigUnixSocket {
+    pub path: String,
+    #[serde(default = "default_unix_socket_mode")]
+    pub mode: String,
+}
+
+impl ConfigUnixSocket {
+    pub fn mode(&self) -> result::Result<u32, String> {
+        match u32::from_str_radix(&self.mode, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(mode),
+            _ => Err(format!("Invalid Unix socket mode {} (expected octal, e.g. 660)", self.mode)),
+        }
+    }
+}
+
+// Config file representation of actuator types and states. It is explicitly tagged and converted
+// to/from the RPC types, so that the config format does not change along with the wire format.
+// In YAML:
+//   actuator_type: { type: Toggle }
+//   actuator_type: { type: FloatValue, min: 15, max: 25 }
+//   actuator_type: { type: FloatValue, min: 15, max: 25, step: 0.5 }
+//   actuator_type: { type: IntValue, min: 0, max: 3 }
+//   actuator_type: { type: Enum, values: [off, low, high] }
+//   actuator_type: { type: Color }
+//   default_state: { type: Toggle, value: false }
+//   default_state: { type: FloatValue, value: 18.5 }
+//   default_state: { type: IntValue, value: 2 }
+//   default_state: { type: Enum, value: low }
+//   default_state: { type: Color, value: "#FFA040" }
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum ConfigActuatorType {
+    Toggle,
+    FloatValue {
+        min: f64,
+        max: f64,
+        #[serde(default)]
+        step: Option<f64>,
+    },
+    IntValue { min: i64, max: i64 },
+    Enum { values: Vec<String> },
+    Color,
+}
+
+impl From<ConfigActuatorType> for ActuatorType {
+    fn from(config: ConfigActuatorType) -> Self {
+        match config {
+            ConfigActuatorType::Toggle => ActuatorType::Toggle,
+            ConfigActuatorType::FloatValue { min, max, step } =>
+                ActuatorType::FloatValue { min, max, step },
+            ConfigActuatorType::IntValue { min, max } => ActuatorType::IntValue { min, max },
+            ConfigActuatorType::Enum { values } => ActuatorType::Enum { values },
+            ConfigActuatorType::Color => ActuatorType::Color,
+        }
+    }
+}
+
+// The forms used before the config representation was tagged are still accepted for actuators
+// (they only existed for toggles and floats):
+//   actuator_type: Toggle
+//   actuator_type: { FloatValue: { min: 15, max: 25 } }
+//   default_state: false
+//   default_state: 18.5
+// The value is kept as is first, to tell the forms apart without losing the details of the errors
+// in the tagged form. Unlike serde_json::Value (see ConfigActuator::controller), serde_yaml::Value
+// can hold non-finite floats, which are reported by validate().
+#[derive(Deserialize)]
+enum LegacyConfigActuatorType {
+    Toggle,
+    FloatValue { min: f64, max: f64 },
+}
+
+fn deserialize_actuator_type<'de, D: Deserializer<'de>>(deserializer: D)
+    -> result::Result<ConfigActuatorType, D::Error>
+{
+    let value = serde_yaml::Value::deserialize(deserializer)?;
+
+    if value.get("type").is_some() {
+        return serde_yaml::from_value(value).map_err(de::Error::custom)
+    }
+
+    match serde_yaml::from_value(value) {
+        Ok(LegacyConfigActuatorType::Toggle) => Ok(ConfigActuatorType::Toggle),
+        Ok(LegacyConfigActuatorType::FloatValue { min, max }) =>
+            Ok(ConfigActuatorType::FloatValue { min, max, step: None }),
+        Err(_) => Err(de::Error::custom("actuator type must be a map with a type, e.g. \
+                                         { type: FloatValue, min: 15, max: 25 }")),
+    }
+}
+
+fn deserialize_default_state<'de, D: Deserializer<'de>>(deserializer: D)
+    -> result::Result<ConfigActuatorState, D::Error>
+{
+    let value = serde_yaml::Value::deserialize(deserializer)?;
+
+    match value {
+        serde_yaml::Value::Bool(value) => Ok(ConfigActuatorState::Toggle(value)),
+        serde_yaml::Value::Number(ref value) =>
+            Ok(ConfigActuatorState::FloatValue(value.as_f64().unwrap())),
+        _ => serde_yaml::from_value(value).map_err(de::Error::custom),
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum ConfigActuatorState {
+    Toggle(bool),
+    FloatValue(f64),
+    IntValue(i64),
+    Enum(String),
+    Color(ConfigColor),
+}
+
+// Specified as "#RRGGBB".
+#[derive(Clone)]
+pub struct ConfigColor(ActuatorState);
+
+impl<'de> Deserialize<'de> for ConfigColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match ActuatorState::from_str(&s) {
+            Ok(state @ ActuatorState::Color { .. }) => Ok(ConfigColor(state)),
+            _ => Err(de::Error::custom(format!("invalid color {}, expected #RRGGBB", s))),
+        }
+    }
+}
+
+impl From<ConfigActuatorState> for ActuatorState {
+    fn from(config: ConfigActuatorState) -> Self {
+        match config {
+            ConfigActuatorState::Toggle(value) => ActuatorState::Toggle(value),
+            ConfigActuatorState::FloatValue(value) => ActuatorState::FloatValue(value),
+            ConfigActuatorState::IntValue(value) => ActuatorState::IntValue(value),
+            ConfigActuatorState::Enum(value) => ActuatorState::Enum(value),
+            ConfigActuatorState::Color(ConfigColor(state)) => state,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    // Guess the format from the extension of path, if it is a known one.
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ConfigFormat::Yaml => "YAML",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Json => "JSON",
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!("unknown config format {} (expected yaml, toml or json)", s)),
+        }
+    }
+}
+
+// The errors of all the parsers include the location (line and column) of the problem.
+pub fn parse(mut reader: impl Read, format: ConfigFormat) -> result::Result<ConfigFile, String> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)
+        .map_err(|e| format!("Reading config file failed: {}", e))?;
+
+    match format {
+        ConfigFormat::Yaml => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+        ConfigFormat::Toml => toml::from_str(&contents).map_err(|e| e.to_string()),
+        ConfigFormat::Json => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+    }.map_err(|e| format!("Parsing config file as {} failed: {}", format.name(), e))
+}
+
+// Load the configuration at path, either a single file or a directory, merging all the files it
+// consists of (see ConfigFile::merge()). The files of a directory that have the extension of a
+// known format (see ConfigFormat::from_path()) are loaded in name order, the others and hidden
+// files are ignored. Files listed in include are loaded after the including file. format only
+// applies to path itself, the format of the other files is guessed from their extension.
+pub fn load(path: &Path, format: Option<ConfigFormat>) -> result::Result<ConfigFile, String> {
+    let mut files = Vec::new();
+    load_files(path, format, &mut BTreeSet::new(), &mut files)?;
+    if files.is_empty() {
+        return Err(format!("No config file found in {}", path.display()))
+    }
+
+    let mut config = ConfigFile::default();
+    let mut origins = Origins::default();
+    for (path, file) in files.into_iter() {
+        config.merge(file, &path, &mut origins);
+    }
+
+    if origins.errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(origins.errors.join("; "))
+    }
+}
+
+fn load_files(path: &Path, format: Option<ConfigFormat>, loaded: &mut BTreeSet<PathBuf>,
+              files: &mut Vec<(PathBuf, ConfigFile)>) -> result::Result<(), String> {
+    // Also catches include cycles.
+    let canonical = path.canonicalize()
+        .map_err(|e| format!("Failed to open config file {}: {}", path.display(), e))?;
+    if !loaded.insert(canonical) {
+        return Err(format!("{} is loaded more than once", path.display()))
+    }
+
+    if path.is_dir() {
+        let read_error = |e| format!("Failed to read config directory {}: {}", path.display(), e);
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path).map_err(&read_error)? {
+            let entry = entry.map_err(&read_error)?.path();
+            // Names that are not valid Unicode are ignored as well.
+            let hidden = match entry.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.starts_with('.'),
+                None => true,
+            };
+            if !hidden && entry.is_file() && ConfigFormat::from_path(&entry).is_some() {
+                entries.push(entry);
+            }
+        }
+        entries.sort();
+
+        for entry in entries.iter() {
+            load_files(entry, None, loaded, files)?;
+        }
+        return Ok(())
+    }
+
+    let format = format.or_else(|| ConfigFormat::from_path(path)).unwrap_or(ConfigFormat::Yaml);
+    let file = File::open(path)
+        .map_err(|e| format!("Failed to open config file {}: {}", path.display(), e))?;
+    let config = parse(file, format).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    let includes: Vec<PathBuf> = config.include.iter().map(|i| directory.join(i)).collect();
+    files.push((path.to_path_buf(), config));
+    for include in includes.iter() {
+        load_files(include, None, loaded, files)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actuator(actuator_type: &str, default_state: &str)
+        -> result::Result<ConfigActuator, String>
+    {
+        let yaml = format!("actuators:\n\
+                            - name: heater\n  \
+                              actuator_type: {}\n  \
+                              default_state: {}\n  \
+                              controller: {{ type: File, path: /dev/null }}\n",
+                           actuator_type, default_state);
+        parse(yaml.as_bytes(), ConfigFormat::Yaml).map(|mut config| config.actuators.remove(0))
+    }
+
+    // ActuatorType has no PartialEq, compare how it is displayed.
+    fn info(actuator: ConfigActuator) -> (String, ActuatorState) {
+        (ActuatorType::from(actuator.actuator_type).to_string(), actuator.default_state.into())
+    }
+
+    #[test]
+    fn legacy_actuator_types_and_states() {
+        let float_type = ActuatorType::FloatValue { min: 15.0, max: 25.0, step: None }.to_string();
+
+        assert_eq!(info(actuator("Toggle", "true").unwrap()),
+                   (ActuatorType::Toggle.to_string(), ActuatorState::Toggle(true)));
+        assert_eq!(info(actuator("{ FloatValue: { min: 15, max: 25 } }", "18.5").unwrap()),
+                   (float_type.clone(), ActuatorState::FloatValue(18.5)));
+        assert_eq!(info(actuator("{ FloatValue: { min: 15, max: 25 } }", "18").unwrap()),
+                   (float_type.clone(), ActuatorState::FloatValue(18.0)));
+        // Both forms can be mixed.
+        assert_eq!(info(actuator("{ type: FloatValue, min: 15, max: 25 }", "18.5").unwrap()),
+                   (float_type, ActuatorState::FloatValue(18.5)));
+        assert_eq!(info(actuator("Toggle", "{ type: Toggle, value: false }").unwrap()),
+                   (ActuatorType::Toggle.to_string(), ActuatorState::Toggle(false)));
+
+        // Types and states that never had another form.
+        let enum_type = ActuatorType::Enum { values: vec!["low".to_string(), "high".to_string()] };
+        assert_eq!(info(actuator("{ type: Enum, values: [low, high] }",
+                                 "{ type: Enum, value: low }").unwrap()),
+                   (enum_type.to_string(), ActuatorState::Enum("low".to_string())));
+        assert!(actuator("Enum", "{ type: Toggle, value: false }").is_err());
+        assert!(actuator("{ type: Toggle }", "low").is_err());
+    }
+
+    #[test]
+    fn legacy_forms_in_toml_and_json() {
+        let toml = "[[actuators]]\n\
+                    name = \"heater\"\n\
+                    actuator_type = { FloatValue = { min = 15.0, max = 25.0 } }\n\
+                    default_state = 18.5\n\
+                    controller = { type = \"File\", path = \"/dev/null\" }\n";
+        let mut config = parse(toml.as_bytes(), ConfigFormat::Toml).unwrap();
+        assert_eq!(info(config.actuators.remove(0)),
+                   (ActuatorType::FloatValue { min: 15.0, max: 25.0, step: None }.to_string(),
+                    ActuatorState::FloatValue(18.5)));
+
+        let json = r#"{"actuators": [{"name": "heater", "actuator_type": "Toggle",
+                       "default_state": false,
+                       "controller": {"type": "File", "path": "/dev/null"}}]}"#;
+        let mut config = parse(json.as_bytes(), ConfigFormat::Json).unwrap();
+        assert_eq!(info(config.actuators.remove(0)),
+                   (ActuatorType::Toggle.to_string(), ActuatorState::Toggle(false)));
+    }
+
+    #[test]
+    fn invalid_actuator_types_and_states() {
+        let error = actuator("Float", "18.5").err().unwrap();
+        assert!(error.contains("actuator type must be a map with a type"), "{}", error);
+        // The errors of the tagged form are kept.
+        let error = actuator("{ type: Float, min: 15, max: 25 }", "18.5").err().unwrap();
+        assert!(error.contains("unknown variant `Float`"), "{}", error);
+        let error = actuator("{ type: FloatValue, min: 15 }", "18.5").err().unwrap();
+        assert!(error.contains("missing field `max`"), "{}", error);
+        let error = actuator("Toggle", "{ type: Toggle, value: 2 }").err().unwrap();
+        assert!(error.contains("invalid type: integer `2`, expected a boolean"), "{}", error);
+        let error = actuator("Toggle", "on").err().unwrap();
+        assert!(error.contains("invalid type: string \"on\""), "{}", error);
+    }
+
+    // What the same configuration in each format must agree on.
+    fn summary(config: &ConfigFile) -> Vec<String> {
+        let mut summary = vec![format!("{:?} {:?}", config.log_level, config.groups)];
+        for actuator in config.actuators.iter() {
+            summary.push(format!("{} {} {} {} {}", actuator.name,
+                                 ActuatorType::from(actuator.actuator_type.clone()),
+                                 ActuatorState::from(actuator.default_state.clone()),
+                                 actuator.midnight_days, actuator.controller().unwrap().summary()));
+        }
+        summary
+    }
+
+    #[test]
+    fn formats() {
+        let yaml = "log_level: debug\n\
+                    actuators:\n\
+                    - name: heater\n  \
+                      actuator_type: { type: FloatValue, min: 15, max: 25 }\n  \
+                      default_state: { type: FloatValue, value: 18.5 }\n  \
+                      controller: { type: File, path: /dev/null, line_terminator: lf }\n\
+                    - name: lights\n  \
+                      actuator_type: { type: Toggle }\n  \
+                      default_state: { type: Toggle, value: false }\n  \
+                      midnight_days: true\n  \
+                      controller: { type: File, path: /dev/null }\n\
+                    groups:\n  \
+                      all: [heater, lights]\n";
+        let toml = "log_level = \"debug\"\n\
+                    [[actuators]]\n\
+                    name = \"heater\"\n\
+                    actuator_type = { type = \"FloatValue\", min = 15.0, max = 25.0 }\n\
+                    default_state = { type = \"FloatValue\", value = 18.5 }\n\
+                    controller = { type = \"File\", path = \"/dev/null\", \
+                                   line_terminator = \"lf\" }\n\
+                    [[actuators]]\n\
+                    name = \"lights\"\n\
+                    actuator_type = { type = \"Toggle\" }\n\
+                    default_state = { type = \"Toggle\", value = false }\n\
+                    midnight_days = true\n\
+                    controller = { type = \"File\", path = \"/dev/null\" }\n\
+                    [groups]\n\
+                    all = [\"heater\", \"lights\"]\n";
+        let json = r#"{
+            "log_level": "debug",
+            "actuators": [
+                {"name": "heater",
+                 "actuator_type": {"type": "FloatValue", "min": 15, "max": 25},
+                 "default_state": {"type": "FloatValue", "value": 18.5},
+                 "controller": {"type": "File", "path": "/dev/null", "line_terminator": "lf"}},
+                {"name": "lights",
+                 "actuator_type": {"type": "Toggle"},
+                 "default_state": {"type": "Toggle", "value": false},
+                 "midnight_days": true,
+                 "controller": {"type": "File", "path": "/dev/null"}}
+            ],
+            "groups": {"all": ["heater", "lights"]}
+        }"#;
+
+        let expected = summary(&parse(yaml.as_bytes(), ConfigFormat::Yaml).unwrap());
+        assert_eq!(expected, vec![
+            "Some(\"debug\") {\"all\": [\"heater\", \"lights\"]}".to_string(),
+            format!("heater {} 18.5 false File /dev/null (lf terminated)",
+                    ActuatorType::FloatValue { min: 15.0, max: 25.0, step: None }),
+            format!("lights {} Off true File /dev/null", ActuatorType::Toggle),
+        ]);
+        assert_eq!(summary(&parse(toml.as_bytes(), ConfigFormat::Toml).unwrap()), expected);
+        assert_eq!(summary(&parse(json.as_bytes(), ConfigFormat::Json).unwrap()), expected);
+    }
+
+    #[test]
+    fn format_errors() {
+        // The format attempted and the location are reported.
+        let error = |contents: &str, format: ConfigFormat| {
+            parse(contents.as_bytes(), format).err().unwrap()
+        };
+
+        let yaml = error("log_level: debug\nactuators: 3\n", ConfigFormat::Yaml);
+        assert!(yaml.starts_with("Parsing config file as YAML failed: ") &&
+                yaml.contains("line 2"), "{}", yaml);
+        let toml = error("log_level = \"debug\"\n[[actuators]]\nname = 3\n", ConfigFormat::Toml);
+        assert!(toml.starts_with("Parsing config file as TOML failed: ") &&
+                toml.contains("line 3"), "{}", toml);
+        let json = error("{\"log_level\": \"debug\",\n \"actuators\": [}", ConfigFormat::Json);
+        assert!(json.starts_with("Parsing config file as JSON failed: ") &&
+                json.contains("line 2"), "{}", json);
+
+        // Each format only parses as itself.
+        assert!(error("log_level = \"debug\"\n", ConfigFormat::Json).contains("as JSON"));
+        assert!(error("{\"log_level\": \"debug\"}", ConfigFormat::Toml).contains("as TOML"));
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!("yml".parse(), Ok(ConfigFormat::Yaml));
+        assert_eq!("TOML".parse(), Ok(ConfigFormat::Toml));
+        assert!("ini".parse::<ConfigFormat>().is_err());
+        assert_eq!(ConfigFormat::from_path(Path::new("/etc/svsc/server.json")),
+                   Some(ConfigFormat::Json));
+        assert_eq!(ConfigFormat::from_path(Path::new("server")), None);
+        assert_eq!(ConfigFormat::from_path(Path::new("server.conf")), None);
+    }
+
+    #[test]
+    fn non_finite_states() {
+        // Parsed (YAML has NaN and infinities), but rejected when validating.
+        for &(yaml, value) in [(".nan", "NaN"), (".inf", "inf"), ("-.inf", "-inf")].iter() {
+            let tagged = format!("{{ type: FloatValue, value: {} }}", yaml);
+            // Only actuators accept the legacy form.
+            for default_state in [yaml, &tagged].iter() {
+                let config = format!("actuators:\n\
+                                      - name: heater\n  \
+                                        actuator_type: {{ type: FloatValue, min: 15, max: 25 }}\n  \
+                                        default_state: {}\n  \
+                                        controller: {{ type: File, path: /dev/null }}\n\
+                                      templates:\n  \
+                                        morning:\n    \
+                                          time_interval: \"07:00-09:00\"\n    \
+                                          state: {}\n",
+                                     default_state, tagged);
+                let config = parse(config.as_bytes(), ConfigFormat::Yaml).unwrap();
+
+                let error = format!("Actuator heater: default state {} is not a finite number",
+                                    value);
+                let errors = config.validate(false);
+                assert!(errors.contains(&error), "{:?}", errors);
+                assert_eq!(config.templates["morning"].to_template().err(),
+                           Some(format!("state {} is not a finite number", value)));
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+use serde_json;
+use tungstenite;
+
+use actuator::ActuatorState;
+use time::DateTime;
+
+// Number of events buffered for each subscriber. A subscriber that falls further behind is
+// dropped, so that publishing never blocks the actuator threads.
+const SUBSCRIBER_QUEUE_SIZE: usize = 64;
+
+// What caused a state change.
+#[derive(Clone, Debug)]
+pub enum EventSource {
+    TimeSlot { id: u32, override_id: Option<u32> },
+    Default,
+    Manual,
+    // The default state, applied because the scheduled one could not be (see FailurePolicy).
+    Fallback,
+    // See Vacation.
+    Vacation,
+    // The state change of another actuator (see Trigger).
+    Trigger { actuator_id: u32 },
+    // The state of the actuator followed (see Follow).
+    Follow { actuator_id: u32 },
+}
+
+// Also used as the provenance in the history.
+impl fmt::Display for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EventSource::TimeSlot { id, override_id: Some(override_id) } =>
+                write!(f, "timeslot {} override {}", id, override_id),
+            EventSource::TimeSlot { id, override_id: None } => write!(f, "timeslot {}", id),
+            EventSource::Default => write!(f, "default"),
+            EventSource::Manual => write!(f, "manual"),
+            EventSource::Fallback => write!(f, "fallback"),
+            EventSource::Vacation => write!(f, "vacation"),
+            EventSource::Trigger { actuator_id } => write!(f, "trigger {}", actuator_id),
+            EventSource::Follow { actuator_id } => write!(f, "follow {}", actuator_id),
+        }
+    }
+}
+
+// Published every time the state applied to an actuator changes.
+#[derive(Clone, Debug)]
+pub struct StateEvent {
+    pub actuator_id: u32,
+    pub name: String,
+    pub state: ActuatorState,
+    pub source: EventSource,
+    pub timestamp: DateTime,
+    // Set if the state was not actually written (see ActuatorInfo::simulate).
+    pub simulated: bool,
+}
+
+impl StateEvent {
+    // Events are sent as JSON objects, e.g.:
+    // {"actuator_id": 0, "name": "heater", "state": 18.5,
+    //  "source": {"slot": 2, "override": null}, "timestamp": "01/05/2018 08:00",
+    //  "simulated": false}
+    // where source is either as above, {"trigger": <source actuator ID>},
+    // {"follow": <followed actuator ID>}, "default", "manual", "fallback" or "vacation".
+    pub fn to_json(&self) -> String {
+        let state = state_to_json(&self.state);
+        let source = match self.source {
+            EventSource::TimeSlot { id, override_id } => json!({
+                "slot": id,
+                "override": override_id,
+            }),
+            EventSource::Default => json!("default"),
+            EventSource::Manual => json!("manual"),
+            EventSource::Fallback => json!("fallback"),
+            EventSource::Vacation => json!("vacation"),
+            EventSource::Trigger { actuator_id } => json!({ "trigger": actuator_id }),
+            EventSource::Follow { actuator_id } => json!({ "follow": actuator_id }),
+        };
+
+        json!({
+            "actuator_id": self.actuator_id,
+            "name": self.name,
+            "state": state,
+            "source": source,
+            "timestamp": self.timestamp.to_string(),
+            "simulated": self.simulated,
+        }).to_string()
+    }
+}
+
+// A state as a JSON value: a boolean, a number, the name of the level, or #RRGGBB for a color.
+pub fn state_to_json(state: &ActuatorState) -> serde_json::Value {
+    match *state {
+        ActuatorState::Toggle(value) => json!(value),
+        ActuatorState::FloatValue(value) => json!(value),
+        ActuatorState::IntValue(value) => json!(value),
+        ActuatorState::Enum(ref name) => json!(name),
+        ActuatorState::Color { .. } => json!(state.to_string()),
+    }
+}
+
+// Broadcasts the state events (as JSON) to all the subscribers.
+pub struct EventBroadcaster {
+    subscribers: Mutex<Vec<SyncSender<String>>>,
+    // Last event of each actuator, sent to new subscribers first.
+    latest: Mutex<BTreeMap<u32, String>>,
+}
+pub type EventBroadcasterHandle = Arc<EventBroadcaster>;
+
+impl EventBroadcaster {
+    pub fn new() -> EventBroadcasterHandle {
+        Arc::new(EventBroadcaster {
+            subscribers: Mutex::new(Vec::new()),
+            latest: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn publish(&self, event: &StateEvent) {
+        let json = event.to_json();
+
+        self.latest.lock().unwrap().insert(event.actuator_id, json.clone());
+
+        // Drop the subscribers that are gone or too slow.
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            match subscriber.try_send(json.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropping slow event subscriber");
+                    false
+                },
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    // The receiver first gets the latest event of each actuator, then all new events.
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = sync_channel(SUBSCRIBER_QUEUE_SIZE);
+
+        // Keep latest locked until the subscriber is registered, so that no event is missed.
+        let latest = self.latest.lock().unwrap();
+        for json in latest.values() {
+            // Cannot fail, the queue is large enough for a snapshot.
+            let _ = sender.try_send(json.clone());
+        }
+        self.subscribers.lock().unwrap().push(sender);
+
+        receiver
+    }
+}
+
+// Serve the events over WebSocket on address, from a dedicated thread (plus one per client).
+pub fn spawn_server(address: &str, broadcaster: EventBroadcasterHandle) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let broadcaster = broadcaster.clone();
+                    thread::spawn(move || handle_client(stream, &broadcaster));
+                },
+                Err(e) => warn!("Failed to accept event client: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, broadcaster: &EventBroadcaster) {
+    let mut websocket = match tungstenite::accept(stream) {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            warn!("WebSocket handshake failed: {}", e);
+            return
+        },
+    };
+
+    // Events are only sent one way, anything the client sends is ignored. The loop ends when the
+    // client disconnects (write error), or when the subscriber gets dropped.
+    for json in broadcaster.subscribe() {
+        if websocket.write_message(tungstenite::Message::Text(json)).is_err() {
+            break
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// Actuators mirroring the state of another one instead of following a schedule of their own, e.g.
+// an indicator LED showing whether the heating is on. The source is watched through its
+// StateNotifier, from a thread per follower, which passes each state change on to the follower
+// (see Actuator::set_follow()).
+
+use std::result;
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::Duration;
+
+use actuator::{Actuator, ActuatorHandle, ActuatorState, ActuatorType, StateNotifierHandle};
+
+// The threads check at least this often whether the follower still follows the same source, to
+// exit once it no longer does (or has been dropped).
+const MAX_WAIT_SEC: u64 = 60;
+
+// The follower is driven with the state of the actuator actuator_id: inverted if invert is set
+// (both must be Toggle actuators), or translated through map if it is not empty (the states of the
+// source that are not in it give the default state of the follower). Otherwise the follower's type
+// must accept all the states of the source's, see check().
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Follow {
+    pub actuator_id: u32,
+    #[serde(default)]
+    pub invert: bool,
+    // Pairs of (source state, follower state).
+    #[serde(default)]
+    pub map: Vec<(ActuatorState, ActuatorState)>,
+}
+
+impl Follow {
+    // The state of the follower for state of the source, None if the map has no entry for it.
+    pub fn apply(&self, state: &ActuatorState) -> Option<ActuatorState> {
+        if !self.map.is_empty() {
+            return self.map.iter().find(|entry| entry.0 == *state).map(|entry| entry.1.clone())
+        }
+
+        match *state {
+            ActuatorState::Toggle(value) if self.invert => Some(ActuatorState::Toggle(!value)),
+            ref state => Some(state.clone()),
+        }
+    }
+
+    // Check that the follower can be driven from the source, given their types.
+    pub fn check(&self, source_type: &ActuatorType,
+                 follower_type: &ActuatorType) -> result::Result<(), String> {
+        if self.invert {
+            if !self.map.is_empty() {
+                return Err("invert and map cannot be combined".to_string())
+            }
+            return match (source_type, follower_type) {
+                (&ActuatorType::Toggle, &ActuatorType::Toggle) => Ok(()),
+                _ => Err("invert requires two Toggle actuators".to_string()),
+            }
+        }
+
+        if self.map.is_empty() {
+            return if accepts_all(source_type, follower_type) {
+                Ok(())
+            } else {
+                Err(format!("type {} does not accept all the states of type {} (a map is needed)",
+                            follower_type, source_type))
+            }
+        }
+
+        for entry in self.map.iter() {
+            if !source_type.accepts(&entry.0) {
+                return Err(format!("{} does not match the source's type {}", entry.0, source_type))
+            }
+            if !follower_type.accepts(&entry.1) {
+                return Err(format!("{} does not match the follower's type {}", entry.1,
+                                   follower_type))
+            }
+        }
+        Ok(())
+    }
+}
+
+// Whether all the states of type source are accepted by type follower.
+fn accepts_all(source: &ActuatorType, follower: &ActuatorType) -> bool {
+    match (source, follower) {
+        (&ActuatorType::Toggle, &ActuatorType::Toggle) |
+        (&ActuatorType::Color, &ActuatorType::Color) => true,
+        (&ActuatorType::IntValue { min, max },
+         &ActuatorType::IntValue { min: follower_min, max: follower_max }) =>
+            follower_min <= min && max <= follower_max,
+        // With a step, only the same steps are sure to be accepted.
+        (&ActuatorType::FloatValue { min, max, step },
+         &ActuatorType::FloatValue { min: follower_min, max: follower_max, step: follower_step }) =>
+            follower_min <= min && max <= follower_max && match follower_step {
+                Some(follower_step) => follower_min == min && step == Some(follower_step),
+                None => true,
+            },
+        (&ActuatorType::Enum { ref values }, &ActuatorType::Enum { values: ref follower_values }) =>
+            values.iter().all(|value| follower_values.contains(value)),
+        _ => false,
+    }
+}
+
+// Whether making follower follow source would create a cycle, given what each actuator follows
+// (among actuator_count actuators).
+pub fn creates_cycle<F>(follower: u32, source: u32, actuator_count: usize, follows: F) -> bool
+where
+    F: Fn(u32) -> Option<u32>
+{
+    let mut current = source;
+
+    // A longer chain would have to go through an actuator twice, i.e. already be a cycle.
+    for _ in 0..actuator_count {
+        if current == follower {
+            return true
+        }
+        current = match follows(current) {
+            Some(next) => next,
+            None => return false,
+        };
+    }
+    true
+}
+
+// Pass the state changes of source on to follower, as long as it follows source with the given
+// generation (see Actuator::set_follow()). The thread does not keep the follower alive.
+pub fn spawn(generation: u64, source: &ActuatorHandle, follower: &ActuatorHandle) {
+    let notifier = source.read().unwrap().state_notifier();
+    let name = format!("{} -> {}", source.read().unwrap().info.name,
+                       follower.read().unwrap().info.name);
+    let follower = Arc::downgrade(follower);
+
+    thread::spawn(move || follow_thread(generation, name, notifier, follower));
+}
+
+fn follow_thread(generation: u64, name: String, notifier: StateNotifierHandle,
+                 follower: Weak<RwLock<Actuator>>) {
+    let mut current = notifier.current();
+
+    loop {
+        let follower_handle = match follower.upgrade() {
+            Some(follower_handle) => follower_handle,
+            None => return,
+        };
+        if !follower_handle.read().unwrap().source_state_changed(generation, current.state) {
+            debug!("[FO {}] no longer following, exiting", name);
+            return
+        }
+        drop(follower_handle);
+
+        current = notifier.wait_change(current.seq, Duration::from_secs(MAX_WAIT_SEC));
+    }
+}
@@ -0,0 +1,261 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use actuator::ActuatorState;
+use time::*;
+
+// One state applied to an actuator.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HistoryEntry {
+    pub time: DateTime,
+    pub state: ActuatorState,
+    // What caused the state to be applied, e.g. "timeslot 2" or "manual".
+    pub provenance: String,
+    // Set if the actuator controller failed to apply the state.
+    pub error: Option<String>,
+    // Set if the state was not actually written, the actuator being simulated.
+    #[serde(default)]
+    pub simulated: bool,
+}
+
+// Entries are stored one per line, as tab-separated fields.
+impl HistoryEntry {
+    fn to_line(&self) -> String {
+        let result = match self.error {
+            // Make sure the message cannot break the format.
+            Some(ref e) => format!("error: {}", e.replace(|c| c == '\t' || c == '\n', " ")),
+            None if self.simulated => "simulated".to_string(),
+            None => "ok".to_string(),
+        };
+
+        // Integers and names need their prefix to be parsed back as IntValue/Enum.
+        let state = match self.state {
+            ActuatorState::IntValue(value) => format!("i:{}", value),
+            ActuatorState::Enum(ref name) => format!("e:{}", name),
+            ref state => state.to_string(),
+        };
+
+        format!("{}\t{}\t{}\t{}\n", self.time, state, self.provenance, result)
+    }
+
+    fn from_line(line: &str) -> Option<HistoryEntry> {
+        let fields: Vec<&str> = line.trim_right_matches('\n').split('\t').collect();
+        if fields.len() != 4 {
+            return None
+        }
+
+        let error = if fields[3] == "ok" || fields[3] == "simulated" {
+            None
+        } else {
+            Some(fields[3].trim_left_matches("error: ").to_string())
+        };
+
+        Some(HistoryEntry {
+            time: DateTime::from_str(fields[0]).ok()?,
+            state: ActuatorState::from_str(fields[1]).ok()?,
+            provenance: fields[2].to_string(),
+            error,
+            simulated: fields[3] == "simulated",
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct HistoryConfig {
+    pub directory: PathBuf,
+    // When the log file of an actuator reaches this size, it is rotated.
+    pub max_file_size: u64,
+    // Number of rotated files kept for each actuator, in addition to the current one.
+    pub kept_files: u32,
+}
+
+// Per-actuator append-only history files, written by a dedicated thread so that slow storage does
+// not delay the actuator threads.
+pub struct History {
+    config: HistoryConfig,
+    // Sender is not Sync, hence the Mutex.
+    sender: Mutex<Sender<(String, HistoryEntry)>>,
+}
+pub type HistoryHandle = Arc<History>;
+
+impl History {
+    pub fn new(config: HistoryConfig) -> io::Result<HistoryHandle> {
+        fs::create_dir_all(&config.directory)?;
+
+        let (sender, receiver) = channel::<(String, HistoryEntry)>();
+        let writer_config = config.clone();
+
+        thread::spawn(move || {
+            for (actuator_name, entry) in receiver {
+                if let Err(e) = append(&writer_config, &actuator_name, &entry) {
+                    warn!("Failed to write the history of actuator {}: {}", actuator_name, e);
+                }
+            }
+        });
+
+        Ok(Arc::new(History {
+            config,
+            sender: Mutex::new(sender),
+        }))
+    }
+
+    // Queue entry to be written.
+    pub fn record(&self, actuator_name: &str, entry: HistoryEntry) {
+        // The writer thread never exits, sending cannot fail.
+        let _ = self.sender.lock().unwrap().send((actuator_name.to_string(), entry));
+    }
+
+    // Read back the entries of an actuator, starting on since (if specified), oldest first.
+    pub fn read(&self, actuator_name: &str, since: Option<Date>) -> io::Result<Vec<HistoryEntry>> {
+        let mut entries = Vec::new();
+
+        // Oldest file first.
+        for index in (0..self.config.kept_files + 1).rev() {
+            let file = match File::open(file_path(&self.config, actuator_name, index)) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            for line in BufReader::new(file).lines() {
+                // Ignore lines we cannot parse (e.g. partially written), instead of failing.
+                if let Some(entry) = HistoryEntry::from_line(&line?) {
+                    if since.map_or(true, |since| entry.time.date >= since) {
+                        entries.push(entry);
+                    }
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+// The current file has index 0, rotated files 1 (most recent) to kept_files.
+fn file_path(config: &HistoryConfig, actuator_name: &str, index: u32) -> PathBuf {
+    // Actuator names are free-form, only keep characters that are safe in a file name and
+    // percent-encode the others (including '%' itself), so that distinct names never end up in
+    // the same file.
+    let mut stem = String::new();
+    for c in actuator_name.chars() {
+        if c.is_alphanumeric() || c == '-' || c == '_' {
+            stem.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                stem.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+
+    if index == 0 {
+        config.directory.join(format!("{}.log", stem))
+    } else {
+        config.directory.join(format!("{}.log.{}", stem, index))
+    }
+}
+
+fn append(config: &HistoryConfig, actuator_name: &str, entry: &HistoryEntry) -> io::Result<()> {
+    let path = file_path(config, actuator_name, 0);
+
+    let size = match fs::metadata(&path) {
+        Ok(metadata) => metadata.len(),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+
+    if size >= config.max_file_size {
+        rotate(config, actuator_name)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(entry.to_line().as_bytes())
+}
+
+fn rotate(config: &HistoryConfig, actuator_name: &str) -> io::Result<()> {
+    if config.kept_files == 0 {
+        return fs::remove_file(file_path(config, actuator_name, 0))
+    }
+
+    // Shift all the files by one, the oldest one gets overwritten.
+    for index in (0..config.kept_files).rev() {
+        let from = file_path(config, actuator_name, index);
+        if from.exists() {
+            fs::rename(from, file_path(config, actuator_name, index + 1))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // A directory in the temporary directory, removed (with its contents) when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("servoscheduler-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn distinct_file_names() {
+        let dir = TempDir::new("history");
+        let config = HistoryConfig {
+            directory: dir.0.clone(),
+            max_file_size: 1 << 20,
+            kept_files: 1,
+        };
+        let file_name = |actuator_name: &str| {
+            file_path(&config, actuator_name, 0).file_name().unwrap().to_str().unwrap()
+                .to_string()
+        };
+
+        assert_eq!(file_name("heater-2_b"), "heater-2_b.log");
+        assert_eq!(file_name("a b"), "a%20b.log");
+        assert_eq!(file_name("a/b"), "a%2Fb.log");
+        assert_eq!(file_name("a%20b"), "a%2520b.log");
+        assert_eq!(file_name("chauffé ☀"), "chauffé%20%E2%98%80.log");
+
+        // Names that used to collide each get their own history.
+        let names = ["a b", "a/b", "a_b", "a%20b"];
+        for (i, name) in names.iter().enumerate() {
+            let entry = HistoryEntry {
+                time: "01/05/2018 08:00".parse().unwrap(),
+                state: ActuatorState::Toggle(true),
+                provenance: format!("timeslot {}", i),
+                error: None,
+                simulated: false,
+            };
+            append(&config, name, &entry).unwrap();
+        }
+
+        let history = History::new(config.clone()).unwrap();
+        for (i, name) in names.iter().enumerate() {
+            let entries = history.read(name, None).unwrap();
+            assert_eq!(entries.len(), 1, "{}", name);
+            assert_eq!(entries[0].provenance, format!("timeslot {}", i));
+        }
+    }
+}
@@ -0,0 +1,417 @@
+// HTTP/JSON gateway to the Server API, for clients that cannot speak tarpc (browsers, curl...).
+// Request and response bodies are the JSON serialization of the same types as the RPCs use:
+//
+//   GET    /info                               server_info
+//   GET    /actuators                          list_actuators
+//   PUT    /actuators/<id>/enabled             set_actuator_enabled (body: bool)
+//   GET    /actuators/<id>/state               get_current_state
+//   PUT    /actuators/<id>/state               set_state (body: ActuatorState)
+//   PUT    /actuators/<id>/default_state       set_default_state (body: ActuatorState)
+//   GET    /actuators/<id>/default_schedule    get_default_schedule
+//   GET    /actuators/<id>/history             get_history
+//   GET    /actuators/<id>/timeslots           list_timeslots
+//   POST   /actuators/<id>/timeslots           add_time_slot (body: NewTimeSlot)
+//   GET    /actuators/<id>/timeslots/<ts_id>   get_timeslot
+//   DELETE /actuators/<id>/timeslots/<ts_id>   remove_time_slot
+//   GET    /groups                             list_groups
+//   GET    /metadata                           get_metadata (of the server)
+//   PUT    /metadata/<key>                     set_metadata (body: string, or null to remove)
+//   GET    /actuators/<id>/metadata            get_metadata
+//   PUT    /actuators/<id>/metadata/<key>      set_metadata (body: string, or null to remove)
+//   GET    /vacation                           get_vacation
+//   GET    /simulation                         get_simulation
+//
+// Errors are reported with a 4xx or 5xx status and a {"code": ..., "message": ...} body, where
+// code is the same as rpc::Error::code() for server errors.
+//
+// Requests other than GET must have a Content-Type: application/json header (even without a
+// body): browsers send cross-site requests with other content types without asking first (no
+// CORS preflight, which the gateway never answers), so a web page could otherwise make them.
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use rpc;
+use server::Server;
+use time_slot::NewTimeSlot;
+
+// Way more than any legitimate request, to avoid allocating arbitrary amounts of memory.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+const MAX_LINE_SIZE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+// Each connection has its own thread, the ones beyond that many are turned away.
+const MAX_CONNECTIONS: usize = 32;
+
+// A client that stops sending or reading is dropped after that long, so that it does not hold one
+// of the connections.
+const REQUEST_TIMEOUT_SEC: u64 = 5;
+
+enum HttpError {
+    BadRequest(String),
+    NotFound,
+    UnsupportedMediaType,
+    TooManyConnections,
+    Server(rpc::Error),
+    Internal(String),
+}
+
+impl From<rpc::Error> for HttpError {
+    fn from(error: rpc::Error) -> Self {
+        HttpError::Server(error)
+    }
+}
+
+type Result<T> = result::Result<T, HttpError>;
+
+// Releases its connection slot when the connection is done with.
+struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Serve the gateway on address, from a dedicated thread (plus one per connection). Returns the
+// address actually bound (address may use port 0).
+pub fn spawn_server(address: &str, server: Arc<Server>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept HTTP client: {}", e);
+                    continue
+                },
+            };
+
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                warn!("Too many HTTP connections, refusing one");
+                // Short enough to fit in the socket buffer, this does not block.
+                let _ = write_response(stream, error_response(HttpError::TooManyConnections));
+                continue
+            }
+
+            let connection = Connection(connections.clone());
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_request(stream, &server) {
+                    warn!("HTTP request failed: {}", e);
+                }
+                drop(connection);
+            });
+        }
+    });
+
+    Ok(local_address)
+}
+
+// Read a line, failing if it is longer than MAX_LINE_SIZE (including the line terminator).
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_SIZE).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE_SIZE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"))
+    }
+    Ok(line)
+}
+
+fn handle_request(stream: TcpStream, server: &Server) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SEC)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SEC)))?;
+
+    let mut reader = BufReader::new(stream);
+
+    let request_line = read_line(&mut reader)?;
+
+    // Content-Length and Content-Type are the only headers we need.
+    let mut content_length = 0;
+    let mut content_type = String::new();
+    for count in 0.. {
+        let header = read_line(&mut reader)?;
+        if header.trim().is_empty() {
+            break
+        }
+        if count == MAX_HEADERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many headers"))
+        }
+
+        let mut fields = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")
+                })?;
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                // Without parameters (e.g. charset).
+                content_type = value.split(';').next().unwrap().trim().to_ascii_lowercase();
+            }
+        }
+    }
+
+    let mut fields = request_line.split_whitespace();
+    let method = fields.next().unwrap_or("");
+
+    let result = if content_length > MAX_BODY_SIZE {
+        Err(HttpError::BadRequest(format!("request body too large ({} B)", content_length)))
+    } else if method != "GET" && content_type != "application/json" {
+        Err(HttpError::UnsupportedMediaType)
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        // No endpoint takes a query string, ignore it.
+        let path = fields.next().unwrap_or("").split('?').next().unwrap();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        if method != "GET" {
+            info!("HTTP {} {}", method, path);
+        }
+        handle(server, method, &segments, &body)
+    };
+
+    let response = match result {
+        Ok(json) => ("200 OK", json),
+        Err(error) => error_response(error),
+    };
+    write_response(reader.into_inner(), response)
+}
+
+fn write_response(mut stream: TcpStream, (status, body): (&str, String)) -> io::Result<()> {
+    write!(stream, "HTTP/1.0 {}\r\n\
+                    Content-Type: application/json\r\n\
+                    Content-Length: {}\r\n\
+                    \r\n\
+                    {}", status, body.len(), body)
+}
+
+fn handle(server: &Server, method: &str, path: &[&str], body: &[u8]) -> Result<String> {
+    match (method, path) {
+        ("GET", &["info"]) => to_json(&server.server_info()),
+        ("GET", &["actuators"]) => to_json(&server.list_actuators()),
+        ("PUT", &["actuators", id, "enabled"]) =>
+            to_json(&server.set_actuator_enabled(parse_id(id)?, parse_body(body)?)?),
+        ("GET", &["actuators", id, "state"]) =>
+            to_json(&server.get_current_state(parse_id(id)?)?),
+        ("PUT", &["actuators", id, "state"]) =>
+            to_json(&server.set_state(parse_id(id)?, parse_body(body)?)?),
+        ("PUT", &["actuators", id, "default_state"]) =>
+            to_json(&server.set_default_state(parse_id(id)?, parse_body(body)?)?),
+        ("GET", &["actuators", id, "default_schedule"]) =>
+            to_json(&server.get_default_schedule(parse_id(id)?)?),
+        ("GET", &["actuators", id, "history"]) =>
+            to_json(&server.get_history(parse_id(id)?, None)?),
+        ("GET", &["actuators", id, "timeslots"]) =>
+            to_json(&server.list_timeslots(parse_id(id)?)?),
+        ("POST", &["actuators", id, "timeslots"]) => {
+            let timeslot: NewTimeSlot = parse_body(body)?;
+            to_json(&server.add_time_slot(parse_id(id)?, timeslot)?)
+        },
+        ("GET", &["actuators", id, "timeslots", ts_id]) =>
+            to_json(&server.get_timeslot(parse_id(id)?, parse_id(ts_id)?)?),
+        ("DELETE", &["actuators", id, "timeslots", ts_id]) =>
+            to_json(&server.remove_time_slot(parse_id(id)?, parse_id(ts_id)?)?),
+        ("GET", &["groups"]) => to_json(&server.list_groups()),
+        ("GET", &["metadata"]) => to_json(&server.get_metadata(None)?),
+        ("PUT", &["metadata", key]) =>
+            to_json(&server.set_metadata(None, key.to_string(), parse_body(body)?)?),
+        ("GET", &["actuators", id, "metadata"]) =>
+            to_json(&server.get_metadata(Some(parse_id(id)?))?),
+        ("PUT", &["actuators", id, "metadata", key]) =>
+            to_json(&server.set_metadata(Some(parse_id(id)?), key.to_string(),
+                                         parse_body(body)?)?),
+        ("GET", &["vacation"]) => to_json(&server.get_vacation()),
+        ("GET", &["simulation"]) => to_json(&server.get_simulation()),
+        _ => Err(HttpError::NotFound),
+    }
+}
+
+fn parse_id(id: &str) -> Result<u32> {
+    id.parse().map_err(|_| HttpError::BadRequest(format!("invalid ID {}", id)))
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body)
+        .map_err(|e| HttpError::BadRequest(format!("invalid request body: {}", e)))
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String> {
+    serde_json::to_string(value).map_err(|e| HttpError::Internal(e.to_string()))
+}
+
+fn error_response(error: HttpError) -> (&'static str, String) {
+    let (status, code, message) = match error {
+        HttpError::BadRequest(message) => ("400 Bad Request", "bad_request", message),
+        HttpError::NotFound =>
+            ("404 Not Found", "not_found", "no such endpoint".to_string()),
+        HttpError::UnsupportedMediaType =>
+            ("415 Unsupported Media Type", "unsupported_media_type",
+             "the Content-Type must be application/json".to_string()),
+        HttpError::TooManyConnections =>
+            ("503 Service Unavailable", "too_many_connections",
+             "too many connections, try again later".to_string()),
+        HttpError::Server(error) => (server_error_status(&error), error.code(), error.to_string()),
+        HttpError::Internal(message) =>
+            ("500 Internal Server Error", "internal", message),
+    };
+
+    (status, json!({ "code": code, "message": message }).to_string())
+}
+
+fn server_error_status(error: &rpc::Error) -> &'static str {
+    use rpc::Error::*;
+
+    match *error {
+        InvalidArgument(..) => "400 Bad Request",
+        ActuatorNotFound(_) | TimeSlotNotFound { .. } | TimeOverrideNotFound { .. } |
+        GroupNotFound(_) | TemplateNotFound(_) => "404 Not Found",
+        TimeSlotOverlap { .. } | TimeOverrideOverlap { .. } | ActuatorDisabled |
+        CopyDayConflict { .. } | ShiftConflict { .. } | LimitExceeded { .. } => "409 Conflict",
+        HistoryUnavailable(_) => "503 Service Unavailable",
+        ControllerFailure(_) => "500 Internal Server Error",
+        BatchEntry { ref error, .. } => server_error_status(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+
+    use actuator::ActuatorState;
+    use config;
+    use time::{Date, DateRange};
+    use time_slot::TimePeriod;
+
+    fn gateway() -> SocketAddr {
+        let yaml = "actuators:\n\
+                    - name: heater\n  \
+                      actuator_type: { type: Toggle }\n  \
+                      default_state: { type: Toggle, value: false }\n  \
+                      controller: { type: File, path: /dev/null }\n";
+        let config = config::parse(yaml.as_bytes(), config::ConfigFormat::Yaml).unwrap();
+        spawn_server("127.0.0.1:0", Arc::new(Server::new(config).unwrap())).unwrap()
+    }
+
+    // Send request as is, returning the whole response (empty if the connection was dropped).
+    fn send(address: &SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        // The gateway may close the connection before reading everything.
+        let _ = stream.write_all(request);
+        let _ = stream.shutdown(Shutdown::Write);
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    }
+
+    fn request(address: &SocketAddr, method: &str, path: &str, content_type: Option<&str>,
+               body: &str) -> String {
+        let content_type = content_type.map(|t| format!("Content-Type: {}\r\n", t))
+            .unwrap_or_default();
+        let request = format!("{} {} HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+                              method, path, content_type, body.len(), body);
+        send(address, request.as_bytes())
+    }
+
+    fn status(response: &str) -> &str {
+        response.lines().next().unwrap_or("")
+    }
+
+    fn body(response: &str) -> &str {
+        &response[response.find("\r\n\r\n").unwrap() + 4..]
+    }
+
+    #[test]
+    fn content_type_required() {
+        let address = gateway();
+        let time_period = TimePeriod {
+            time_interval: "07:00-08:00".parse().unwrap(),
+            date_range: DateRange { start: Date::MIN, end: Date::MAX },
+            days: "all".parse().unwrap(),
+        };
+        let timeslot = NewTimeSlot::new(time_period, ActuatorState::Toggle(true), true);
+        let timeslot = serde_json::to_string(&timeslot).unwrap();
+        let list = || request(&address, "GET", "/actuators/0/timeslots", None, "");
+
+        for content_type in [None, Some("text/plain"), Some("application/x-www-form-urlencoded"),
+                             Some("multipart/form-data")].iter() {
+            let response = request(&address, "POST", "/actuators/0/timeslots", *content_type,
+                                   &timeslot);
+            assert_eq!(status(&response), "HTTP/1.0 415 Unsupported Media Type");
+            assert_eq!(body(&list()), "{}");
+        }
+        let response = request(&address, "DELETE", "/actuators/0/timeslots/0", None, "");
+        assert_eq!(status(&response), "HTTP/1.0 415 Unsupported Media Type");
+
+        let response = request(&address, "POST", "/actuators/0/timeslots",
+                               Some("Application/JSON; charset=utf-8"), &timeslot);
+        assert_eq!(status(&response), "HTTP/1.0 200 OK", "{}", response);
+        assert_eq!(body(&response), "0");
+        // GET requests need none.
+        assert!(body(&list()).starts_with("{\"0\":"));
+    }
+
+    #[test]
+    fn size_limits() {
+        let address = gateway();
+        let ok = |response: String| status(&response) == "HTTP/1.0 200 OK";
+
+        let path = format!("/info?{}", "x".repeat(MAX_LINE_SIZE as usize - 30));
+        assert!(ok(request(&address, "GET", &path, None, "")));
+        let path = format!("/info?{}", "x".repeat(MAX_LINE_SIZE as usize));
+        assert_eq!(request(&address, "GET", &path, None, ""), "");
+
+        let header = format!("X-Padding: {}\r\n", "x".repeat(MAX_LINE_SIZE as usize - 20));
+        let headers = |count: usize| format!("GET /info HTTP/1.1\r\n{}\r\n", header.repeat(count));
+        assert!(ok(send(&address, headers(MAX_HEADERS).as_bytes())));
+        assert_eq!(send(&address, headers(MAX_HEADERS + 1).as_bytes()), "");
+        let header = format!("X-Padding: {}\r\n", "x".repeat(MAX_LINE_SIZE as usize));
+        assert_eq!(send(&address, format!("GET /info HTTP/1.1\r\n{}\r\n", header).as_bytes()), "");
+
+        // Rejected before reading the body.
+        let response = send(&address, format!("PUT /metadata/x HTTP/1.1\r\n\
+                                                Content-Type: application/json\r\n\
+                                                Content-Length: {}\r\n\r\n",
+                                               MAX_BODY_SIZE + 1).as_bytes());
+        assert_eq!(status(&response), "HTTP/1.0 400 Bad Request");
+    }
+
+    #[test]
+    fn connection_limit() {
+        let address = gateway();
+
+        // Never send their request.
+        let stalled: Vec<TcpStream> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        let response = request(&address, "GET", "/info", None, "");
+        assert_eq!(status(&response), "HTTP/1.0 503 Service Unavailable");
+
+        // Their connections are released once they are gone.
+        drop(stalled);
+        for _ in 0..500 {
+            let response = request(&address, "GET", "/info", None, "");
+            if status(&response) == "HTTP/1.0 200 OK" {
+                return
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("connections not released");
+    }
+}
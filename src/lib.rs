@@ -0,0 +1,56 @@
+#![feature(plugin, use_extern_macros, proc_macro_path_invoc)]
+#![plugin(tarpc_plugins)]
+
+// The scheduling engine, usable in-process without going through RPC: create actuators with
+// Actuator::new() (or all of them from a config file with Server::new()) and call their methods
+// directly. The RPC layer (rpc, rpc_server) is a thin wrapper around Server.
+
+#[macro_use]
+extern crate tarpc;
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_yaml;
+extern crate toml;
+
+#[macro_use]
+extern crate bitflags;
+extern crate chrono;
+extern crate num;
+
+extern crate regex;
+#[macro_use]
+extern crate serde_json;
+extern crate tungstenite;
+extern crate bincode;
+
+#[macro_use]
+extern crate log;
+
+pub mod actuator;
+pub mod actuator_controller;
+pub mod audit;
+pub mod condition;
+pub mod config;
+pub mod events;
+pub mod follow;
+pub mod history;
+pub mod http_gateway;
+pub mod metadata;
+pub mod metrics;
+#[macro_use]
+pub mod rpc;
+pub mod rpc_server;
+pub mod schedule;
+pub mod server;
+pub mod time;
+pub mod time_slot;
+pub mod transition_log;
+pub mod trigger;
+pub mod unix_transport;
+pub mod utils;
+
+pub use actuator::{Actuator, ActuatorHandle, ActuatorInfo, ActuatorState, ActuatorType};
+pub use server::Server;
+pub use time_slot::TimeSlot;
@@ -0,0 +1,53 @@
+// Free-form key-value annotations attached to the server and to each actuator, e.g. a room or an
+// icon for a UI. They are only stored, and have no effect on the schedule.
+
+use std::collections::BTreeMap;
+use std::result;
+
+use rpc::Error::InvalidArgument;
+use rpc::InvalArgError as IAE;
+
+pub type Metadata = BTreeMap<String, String>;
+
+pub const MAX_KEY_LEN: usize = 64;
+pub const MAX_VALUE_LEN: usize = 1024;
+pub const MAX_ENTRIES: usize = 64;
+
+pub fn check_entry(key: &str, value: &str) -> result::Result<(), String> {
+    if key.is_empty() || key.chars().count() > MAX_KEY_LEN {
+        Err(format!("key \"{}\" must be 1 to {} characters long", key, MAX_KEY_LEN))
+    } else if value.chars().count() > MAX_VALUE_LEN {
+        Err(format!("value of {} longer than {} characters", key, MAX_VALUE_LEN))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn check(metadata: &Metadata) -> result::Result<(), String> {
+    if metadata.len() > MAX_ENTRIES {
+        return Err(format!("more than {} metadata entries", MAX_ENTRIES))
+    }
+    for (key, value) in metadata.iter() {
+        check_entry(key, value)?;
+    }
+    Ok(())
+}
+
+// Set key to value, or remove it if value is None (removing a missing key is not an error).
+pub fn set(metadata: &mut Metadata, key: String,
+           value: Option<String>) -> result::Result<(), ::rpc::Error> {
+    match value {
+        Some(value) => {
+            check_entry(&key, &value).map_err(|e| InvalidArgument(IAE::Metadata, e))?;
+            if metadata.len() >= MAX_ENTRIES && !metadata.contains_key(&key) {
+                return Err(InvalidArgument(IAE::Metadata,
+                                           format!("at most {} entries", MAX_ENTRIES)))
+            }
+            metadata.insert(key, value);
+        },
+        None => {
+            metadata.remove(&key);
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use actuator::ActuatorState;
+use time::{Clock, ClockHandle, DateTime};
+
+// Metrics of one actuator, updated by its thread whenever it applies a state.
+#[derive(Default)]
+pub struct ActuatorMetrics {
+    state: Option<ActuatorState>,
+    transitions: u64,
+    controller_failures: u64,
+    // Set when the actuator thread gave up applying a state (see FailurePolicy), until a state is
+    // applied successfully.
+    failing: bool,
+    next_transition: Option<DateTime>,
+}
+pub type MetricsHandle = Arc<Mutex<ActuatorMetrics>>;
+
+impl ActuatorMetrics {
+    pub fn new() -> MetricsHandle {
+        Arc::new(Mutex::new(ActuatorMetrics::default()))
+    }
+
+    // Record that state was applied (successfully or not), and will remain until next_transition.
+    pub fn record_state(&mut self, state: &ActuatorState, success: bool,
+                        next_transition: DateTime) {
+        if success {
+            if self.state.as_ref() != Some(state) {
+                self.transitions += 1;
+            }
+            self.state = Some(state.clone());
+            self.failing = false;
+        } else {
+            self.controller_failures += 1;
+        }
+
+        self.next_transition = Some(next_transition);
+    }
+
+    pub fn gave_up(&mut self) {
+        self.failing = true;
+    }
+}
+
+// Toggles are exposed as 0/1. Enum and Color states have no (single) numeric value, they are not
+// exposed.
+fn state_value(state: &ActuatorState) -> Option<f64> {
+    match *state {
+        ActuatorState::Toggle(value) => Some(if value { 1.0 } else { 0.0 }),
+        ActuatorState::FloatValue(value) => Some(value),
+        ActuatorState::IntValue(value) => Some(value as f64),
+        ActuatorState::Enum(_) | ActuatorState::Color { .. } => None,
+    }
+}
+
+// Escape a label value as required by the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Render the metrics of all actuators in the Prometheus text exposition format.
+pub fn render(actuators: &[(String, MetricsHandle)], clock: &Clock) -> String {
+    let now = clock.now();
+    let mut out = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str,
+                      value: &Fn(&ActuatorMetrics) -> Option<f64>| {
+        writeln!(out, "# HELP servoscheduler_{} {}", name, help).unwrap();
+        writeln!(out, "# TYPE servoscheduler_{} {}", name, kind).unwrap();
+
+        for &(ref actuator_name, ref metrics) in actuators.iter() {
+            if let Some(value) = value(&metrics.lock().unwrap()) {
+                writeln!(out, "servoscheduler_{}{{actuator=\"{}\"}} {}",
+                         name, escape_label(actuator_name), value).unwrap();
+            }
+        }
+    };
+
+    metric("actuator_state", "gauge",
+           "Current state of the actuator (0/1 for toggles).",
+           &|m| m.state.as_ref().and_then(state_value));
+    metric("state_transitions_total", "counter",
+           "Number of times the state of the actuator changed.",
+           &|m| Some(m.transitions as f64));
+    metric("controller_failures_total", "counter",
+           "Number of times the actuator controller failed to apply a state.",
+           &|m| Some(m.controller_failures as f64));
+    metric("controller_failing", "gauge",
+           "1 if the actuator thread gave up applying the scheduled state, after retrying.",
+           &|m| Some(if m.failing { 1.0 } else { 0.0 }));
+    metric("seconds_until_next_transition", "gauge",
+           "Time until the actuator thread next changes the state.",
+           &|m| m.next_transition.as_ref().map(|next| (next.minutes_since(&now) * 60) as f64));
+
+    out
+}
+
+// Requests are served one at a time, a client that stops sending or reading is dropped after that
+// long so that it does not block the others.
+const REQUEST_TIMEOUT_SEC: u64 = 5;
+
+// Serve /metrics over HTTP on address, from a dedicated thread. Returns the address actually bound
+// (address may use port 0).
+pub fn spawn_server(address: &str, actuators: Vec<(String, MetricsHandle)>, clock: ClockHandle)
+    -> io::Result<SocketAddr>
+{
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| handle_request(stream, &actuators, &*clock));
+
+            if let Err(e) = result {
+                warn!("Metrics request failed: {}", e);
+            }
+        }
+    });
+
+    Ok(local_address)
+}
+
+fn handle_request(stream: TcpStream, actuators: &[(String, MetricsHandle)],
+                  clock: &Clock) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SEC)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SEC)))?;
+
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Skip the headers, we don't need any of them.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break
+        }
+    }
+
+    let mut fields = request_line.split_whitespace();
+    let (status, body) = match (fields.next(), fields.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(actuators, clock)),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    let mut stream = reader.into_inner();
+    write!(stream, "HTTP/1.0 {}\r\n\
+                    Content-Type: text/plain; version=0.0.4\r\n\
+                    Content-Length: {}\r\n\
+                    \r\n\
+                    {}", status, body.len(), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+    use time::SystemClock;
+
+    struct FixedClock(DateTime);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime {
+            self.0.clone()
+        }
+    }
+
+    fn scrape(address: &SocketAddr) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    // Parse the samples out of a body in the text exposition format, checking that each sample
+    // belongs to a metric with HELP and TYPE lines.
+    fn parse_exposition(body: &str) -> Vec<(String, String, f64)> {
+        let mut typed = Vec::new();
+        let mut samples = Vec::new();
+
+        for line in body.lines() {
+            let fields: Vec<&str> = line.splitn(4, ' ').collect();
+            match fields[0] {
+                "#" => {
+                    assert!(fields.len() == 4 && fields[2].starts_with("servoscheduler_"),
+                            "{}", line);
+                    match fields[1] {
+                        "HELP" => {},
+                        "TYPE" => {
+                            assert!(["gauge", "counter"].contains(&fields[3]), "{}", line);
+                            typed.push(fields[2].to_string());
+                        },
+                        _ => panic!("{}", line),
+                    }
+                },
+                _ => {
+                    // Label values may contain spaces, the value is after the last one.
+                    let (sample, value) = line.split_at(line.rfind(' ').unwrap());
+                    let (name, labels) = sample.split_at(sample.find('{').unwrap());
+                    assert!(labels.starts_with("{actuator=\"") && labels.ends_with("\"}"),
+                            "{}", line);
+                    assert!(typed.last().map(|t| t.as_str()) == Some(name), "{}", line);
+                    let value = value[1..].parse().unwrap();
+                    samples.push((name.to_string(), labels.to_string(), value));
+                },
+            }
+        }
+
+        samples
+    }
+
+    #[test]
+    fn serve_metrics() {
+        let now: DateTime = "01/05/2018 23:30".parse().unwrap();
+        let heater = ActuatorMetrics::new();
+        let next = now.add_minutes(90);
+        heater.lock().unwrap().record_state(&ActuatorState::Toggle(true), true, next.clone());
+        heater.lock().unwrap().record_state(&ActuatorState::Toggle(true), false, next.clone());
+        let colors = ActuatorMetrics::new();
+        colors.lock().unwrap().record_state(&ActuatorState::Enum("red".to_string()), true, next);
+
+        let address = spawn_server("127.0.0.1:0", vec![("heater".to_string(), heater),
+                                                       ("a \"b\"".to_string(), colors)],
+                                   Arc::new(FixedClock(now)))
+            .unwrap();
+        let response = scrape(&address);
+
+        let body_start = response.find("\r\n\r\n").unwrap() + 4;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response[..body_start].contains("Content-Type: text/plain; version=0.0.4\r\n"));
+
+        let samples = parse_exposition(&response[body_start..]);
+        let value = |name: &str, labels: &str| {
+            samples.iter().find(|s| s.0 == name && s.1 == labels).map(|s| s.2)
+        };
+        let heater = "{actuator=\"heater\"}";
+        let colors = "{actuator=\"a \\\"b\\\"\"}";
+        assert_eq!(value("servoscheduler_actuator_state", heater), Some(1.0));
+        assert_eq!(value("servoscheduler_state_transitions_total", heater), Some(1.0));
+        assert_eq!(value("servoscheduler_controller_failures_total", heater), Some(1.0));
+        assert_eq!(value("servoscheduler_seconds_until_next_transition", heater), Some(5400.0));
+        // Enums have no numeric value.
+        assert_eq!(value("servoscheduler_actuator_state", colors), None);
+        assert_eq!(value("servoscheduler_state_transitions_total", colors), Some(1.0));
+    }
+
+    #[test]
+    fn stalled_client() {
+        let address = spawn_server("127.0.0.1:0", vec![("heater".to_string(),
+                                                        ActuatorMetrics::new())],
+                                   SystemClock::new()).unwrap();
+
+        // Never sends its request, the next client is served once it times out.
+        let _stalled = TcpStream::connect(address).unwrap();
+        let start = Instant::now();
+        assert!(scrape(&address).starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(start.elapsed() < Duration::from_secs(REQUEST_TIMEOUT_SEC * 2));
+    }
+}
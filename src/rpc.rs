@@ -1,9 +1,17 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error;
 use std::fmt;
+use std::result;
 
-use actuator::{ActuatorInfo, ActuatorState};
+use actuator::{ActiveOverrideInfo, ActuatorInfo, ActuatorState, ActuatorType, CurrentState,
+               FailurePolicy, Vacation};
+use follow::Follow;
+use history::HistoryEntry;
+use metadata::Metadata;
+use schedule::{DefaultSchedule, ResolvedState, ScheduleSlot};
+use time::{Date, DateRange, DateTime, DayStart, Duration, Time, TimeInterval, WeekdaySet};
 use time_slot::*;
+use trigger::Trigger;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum InvalArgError {
@@ -12,6 +20,41 @@ pub enum InvalArgError {
     TimeOverrideId,
     TimePeriod,
     ActuatorState,
+    DefaultChangeTime,
+    LogLevel,
+    Jitter,
+    Label,
+    TemplateName,
+    Weekdays,
+    DateTime,
+    Metadata,
+    Date,
+    Follow,
+    Condition,
+}
+
+impl InvalArgError {
+    // Stable identifier, unlike the Display representation.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            InvalArgError::ActuatorId => "invalid_arg:actuator_id",
+            InvalArgError::TimeSlotId => "invalid_arg:time_slot_id",
+            InvalArgError::TimeOverrideId => "invalid_arg:time_override_id",
+            InvalArgError::TimePeriod => "invalid_arg:time_period",
+            InvalArgError::ActuatorState => "invalid_arg:actuator_state",
+            InvalArgError::DefaultChangeTime => "invalid_arg:default_change_time",
+            InvalArgError::LogLevel => "invalid_arg:log_level",
+            InvalArgError::Jitter => "invalid_arg:jitter",
+            InvalArgError::Label => "invalid_arg:label",
+            InvalArgError::TemplateName => "invalid_arg:template_name",
+            InvalArgError::Weekdays => "invalid_arg:weekdays",
+            InvalArgError::DateTime => "invalid_arg:date_time",
+            InvalArgError::Metadata => "invalid_arg:metadata",
+            InvalArgError::Date => "invalid_arg:date",
+            InvalArgError::Follow => "invalid_arg:follow",
+            InvalArgError::Condition => "invalid_arg:condition",
+        }
+    }
 }
 
 impl fmt::Display for InvalArgError {
@@ -22,6 +65,17 @@ impl fmt::Display for InvalArgError {
             InvalArgError::TimeOverrideId => "time override ID",
             InvalArgError::TimePeriod => "time period",
             InvalArgError::ActuatorState => "actuator state",
+            InvalArgError::DefaultChangeTime => "default state change time",
+            InvalArgError::LogLevel => "log level",
+            InvalArgError::Jitter => "jitter",
+            InvalArgError::Label => "label",
+            InvalArgError::TemplateName => "template name",
+            InvalArgError::Weekdays => "weekdays",
+            InvalArgError::DateTime => "date and time",
+            InvalArgError::Metadata => "metadata",
+            InvalArgError::Date => "date",
+            InvalArgError::Follow => "actuator to follow",
+            InvalArgError::Condition => "timeslot condition",
         };
         f.write_str(desc)
     }
@@ -29,22 +83,121 @@ impl fmt::Display for InvalArgError {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Error {
-    InvalidArgument(InvalArgError),
-    TimeSlotOverlap(u32),
-    TimeOverrideOverlap(u32),
+    // The detail is a human-readable explanation (e.g. "7.2 not accepted by type Float [0, 1]"),
+    // possibly empty.
+    InvalidArgument(InvalArgError, String),
+    ActuatorNotFound(u32),
+    // The name of the actuator is included, as the IDs are only unique within an actuator.
+    TimeSlotNotFound { actuator: String, id: u32 },
+    TimeOverrideNotFound { actuator: String, time_slot_id: u32, id: u32 },
+    // The conflicting timeslot and its time interval (its time override's, if that is what
+    // overlaps), and the time interval, date range and weekdays over which it overlaps.
+    TimeSlotOverlap {
+        id: u32,
+        slot_interval: TimeInterval,
+        time_interval: TimeInterval,
+        date_range: DateRange,
+        days: WeekdaySet,
+    },
+    // The conflicting override of the same timeslot, and the days on which both would apply.
+    TimeOverrideOverlap { id: u32, date_range: DateRange, days: WeekdaySet },
+    // The actuator controller failed to apply the state.
+    ControllerFailure(String),
+    // The history is not enabled, or could not be read.
+    HistoryUnavailable(String),
+    // The operation requires the actuator to be enabled.
+    ActuatorDisabled,
+    GroupNotFound(String),
+    TemplateNotFound(String),
+    // Copying a day would make timeslot id overlap with timeslot conflicting_id.
+    CopyDayConflict { id: u32, conflicting_id: u32 },
+    // Shifting timeslots would make timeslot id overlap with timeslot conflicting_id.
+    ShiftConflict { id: u32, conflicting_id: u32 },
+    // Adding timeslots in bulk failed because of the timeslot at index (in the batch), with that
+    // error. Nothing was added.
+    BatchEntry { index: u32, error: Box<Error> },
+    // The actuator already has as many timeslots as allowed (see ActuatorInfo::max_timeslots).
+    LimitExceeded { max_timeslots: u32 },
+}
+
+impl Error {
+    // Stable identifier for scripts to branch on, unlike the Display representation which may
+    // change. Codes must never be changed or reused.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Error::InvalidArgument(ref arg, _) => arg.code(),
+            // Reported as invalid arguments before the not-found variants existed.
+            Error::ActuatorNotFound(_) => InvalArgError::ActuatorId.code(),
+            Error::TimeSlotNotFound { .. } => InvalArgError::TimeSlotId.code(),
+            Error::TimeOverrideNotFound { .. } => InvalArgError::TimeOverrideId.code(),
+            Error::TimeSlotOverlap { .. } => "overlap",
+            Error::TimeOverrideOverlap { .. } => "override_overlap",
+            Error::ControllerFailure(_) => "controller_failure",
+            Error::HistoryUnavailable(_) => "history_unavailable",
+            Error::ActuatorDisabled => "actuator_disabled",
+            Error::GroupNotFound(_) => "group_not_found",
+            Error::TemplateNotFound(_) => "template_not_found",
+            Error::CopyDayConflict { .. } => "copy_day_conflict",
+            Error::ShiftConflict { .. } => "shift_conflict",
+            // What matters is why the entry was rejected.
+            Error::BatchEntry { ref error, .. } => error.code(),
+            Error::LimitExceeded { .. } => "limit_exceeded",
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::InvalidArgument(ref arg) => write!(f, "invalid argument: {}", arg),
-            Error::TimeSlotOverlap(id) => write!(f, "overlap with time slot (ID {})", id),
-            Error::TimeOverrideOverlap(id) =>
-                write!(f, "overlap with another time override in this slot (ID {})", id),
+            Error::InvalidArgument(ref arg, ref detail) if detail.is_empty() =>
+                write!(f, "invalid argument: {}", arg),
+            Error::InvalidArgument(ref arg, ref detail) =>
+                write!(f, "invalid argument: {} ({})", arg, detail),
+            Error::ActuatorNotFound(id) => write!(f, "no actuator with ID {}", id),
+            Error::TimeSlotNotFound { ref actuator, id } =>
+                write!(f, "no time slot with ID {} in actuator {}", id, actuator),
+            Error::TimeOverrideNotFound { ref actuator, time_slot_id, id } =>
+                write!(f, "no time override with ID {} in time slot {} of actuator {}",
+                       id, time_slot_id, actuator),
+            Error::TimeSlotOverlap { id, ref slot_interval, ref time_interval, ref date_range,
+                                     days } =>
+                write!(f, "conflicts with time slot {} ({} - {}) at {} - {} {}", id,
+                       slot_interval.start, slot_interval.end, time_interval.start,
+                       time_interval.end, days_str(date_range, days)),
+            Error::TimeOverrideOverlap { id, ref date_range, days } =>
+                write!(f, "conflicts with time override {} of this slot {} (only one override \
+                           can apply per day)", id, days_str(date_range, days)),
+            Error::ControllerFailure(ref msg) => write!(f, "actuator controller failure: {}", msg),
+            Error::HistoryUnavailable(ref msg) => write!(f, "history unavailable: {}", msg),
+            Error::ActuatorDisabled => write!(f, "actuator disabled"),
+            Error::GroupNotFound(ref name) => write!(f, "no group named {}", name),
+            Error::TemplateNotFound(ref name) => write!(f, "no template named {}", name),
+            Error::CopyDayConflict { id, conflicting_id } =>
+                write!(f, "time slot {} would overlap with time slot {} on the target days", id,
+                       conflicting_id),
+            Error::ShiftConflict { id, conflicting_id } =>
+                write!(f, "time slot {} would overlap with time slot {} once shifted", id,
+                       conflicting_id),
+            Error::BatchEntry { index, ref error } => write!(f, "entry {}: {}", index, error),
+            Error::LimitExceeded { max_timeslots } =>
+                write!(f, "too many time slots (at most {} for this actuator)", max_timeslots),
         }
     }
 }
 
+// E.g. "on mon,tue between 01/12/2018 and 15/12/2018", or "every day".
+fn days_str(date_range: &DateRange, days: WeekdaySet) -> String {
+    let days = if days.is_all() { "every day".to_string() } else { format!("on {}", days.names()) };
+
+    match (date_range.start, date_range.end) {
+        (Date::MIN, Date::MAX) => days,
+        (start, Date::MAX) => format!("{} from {}", days, start),
+        (Date::MIN, end) => format!("{} until {}", days, end),
+        (start, end) if start == end => format!("on {}", start),
+        (start, end) => format!("{} between {} and {}", days, start, end),
+    }
+}
+
 impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
         None
@@ -53,27 +206,357 @@ impl error::Error for Error {
 
 impl From<InvalArgError> for Error {
     fn from(error: InvalArgError) -> Self {
-        Error::InvalidArgument(error)
+        Error::InvalidArgument(error, String::new())
     }
 }
 
-service! {
-    // Specifying | Error anyway, because tarpc::util::Never is a pain to handle.
-    rpc list_actuators() -> Vec<ActuatorInfo> | Error;
-    rpc list_timeslots(actuator_id: u32) -> BTreeMap<u32, TimeSlot> | Error;
+// Optional features a server may support, so that clients can check for them before using them
+// (independently of the server version).
+pub mod capability {
+    pub const TIMESLOT_SUMMARIES: &str = "timeslot_summaries";
+    pub const DEFAULT_SCHEDULE: &str = "default_schedule";
+    pub const RECOMPUTE_ACTIVE: &str = "recompute_active";
+    pub const NEXT_TIMESLOT: &str = "next_timeslot";
+    pub const SET_LOG_LEVEL: &str = "set_log_level";
+    pub const CURRENT_STATE: &str = "current_state";
+    pub const HISTORY: &str = "history";
+    pub const ACTUATOR_ENABLE: &str = "actuator_enable";
+    pub const GET_CONFIG: &str = "get_config";
+    pub const TIMESLOT_JITTER: &str = "timeslot_jitter";
+    pub const ACTUATORS_DETAILED: &str = "actuators_detailed";
+    pub const ALL_CURRENT_STATES: &str = "all_current_states";
+    pub const TIMESLOT_LABELS: &str = "timeslot_labels";
+    pub const ACTUATOR_GROUPS: &str = "actuator_groups";
+    pub const TIMESLOT_TEMPLATES: &str = "timeslot_templates";
+    pub const COPY_DAY: &str = "copy_day";
+    pub const SHIFT_TIMESLOTS: &str = "shift_timeslots";
+    pub const CLEAR_TIMESLOTS: &str = "clear_timeslots";
+    pub const NEXT_OCCURRENCES: &str = "next_occurrences";
+    pub const STATE_AT: &str = "state_at";
+    pub const TOGGLE_STATE: &str = "toggle_state";
+    pub const ADD_TIMESLOT_FIT: &str = "add_timeslot_fit";
+    pub const METADATA: &str = "metadata";
+    pub const ADD_TIMESLOT_REPLACE: &str = "add_timeslot_replace";
+    pub const ADD_TIMESLOTS: &str = "add_timeslots";
+    pub const VACATION: &str = "vacation";
+    pub const SIMULATION: &str = "simulation";
+    pub const TRIGGERS: &str = "triggers";
+    pub const OVERRIDES: &str = "overrides";
+    pub const STATE_DURATIONS: &str = "state_durations";
+    pub const WAIT_ANY_CHANGE: &str = "wait_any_change";
+    pub const SIMULATE_DAY: &str = "simulate_day";
+    pub const MAX_TIMESLOTS: &str = "max_timeslots";
+    pub const FOLLOW: &str = "follow";
+    pub const TIMESLOT_CONDITIONS: &str = "timeslot_conditions";
+    pub const TIMESLOT_SHADOWED: &str = "timeslot_shadowed";
+}
+
+// Capabilities of this version of the server.
+pub const CAPABILITIES: &[&str] = &[
+    capability::TIMESLOT_SUMMARIES,
+    capability::DEFAULT_SCHEDULE,
+    capability::RECOMPUTE_ACTIVE,
+    capability::NEXT_TIMESLOT,
+    capability::SET_LOG_LEVEL,
+    capability::CURRENT_STATE,
+    capability::HISTORY,
+    capability::ACTUATOR_ENABLE,
+    capability::GET_CONFIG,
+    capability::TIMESLOT_JITTER,
+    capability::ACTUATORS_DETAILED,
+    capability::ALL_CURRENT_STATES,
+    capability::TIMESLOT_LABELS,
+    capability::ACTUATOR_GROUPS,
+    capability::TIMESLOT_TEMPLATES,
+    capability::COPY_DAY,
+    capability::SHIFT_TIMESLOTS,
+    capability::CLEAR_TIMESLOTS,
+    capability::NEXT_OCCURRENCES,
+    capability::STATE_AT,
+    capability::TOGGLE_STATE,
+    capability::ADD_TIMESLOT_FIT,
+    capability::METADATA,
+    capability::ADD_TIMESLOT_REPLACE,
+    capability::ADD_TIMESLOTS,
+    capability::VACATION,
+    capability::SIMULATION,
+    capability::TRIGGERS,
+    capability::OVERRIDES,
+    capability::STATE_DURATIONS,
+    capability::WAIT_ANY_CHANGE,
+    capability::SIMULATE_DAY,
+    capability::MAX_TIMESLOTS,
+    capability::FOLLOW,
+    capability::TIMESLOT_CONDITIONS,
+    capability::TIMESLOT_SHADOWED,
+];
 
-    rpc get_default_state(actuator_id: u32) -> ActuatorState | Error;
-    rpc set_default_state(actuator_id: u32, default_state: ActuatorState) -> () | Error;
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-    rpc add_time_slot(actuator_id: u32, time_period: TimePeriod, actuator_state: ActuatorState, enabled: bool) -> u32 | Error;
-    // TODO: choose one spelling: time_slot or timeslot
-    rpc remove_time_slot(actuator_id: u32, time_slot_id: u32) -> () | Error;
-    // Allows time_period's fields to be empty.
-    rpc time_slot_set_time_period(actuator_id: u32, time_slot_id: u32, time_period: TimePeriod) -> () | Error;
-    rpc time_slot_set_enabled(actuator_id: u32, time_slot_id: u32, enabled: bool) -> () | Error;
-    rpc time_slot_set_actuator_state(actuator_id: u32, time_slot_id: u32, actuator_state: ActuatorState) -> () | Error;
-    rpc time_slot_add_time_override(actuator_id: u32, time_slot_id: u32, time_period: TimePeriod) -> u32 | Error;
-    rpc time_slot_remove_time_override(actuator_id: u32, time_slot_id: u32, time_override_id: u32) -> () | Error;
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ServerInfo {
+    pub version: String,
+    pub capabilities: BTreeSet<String>,
+}
+
+impl ServerInfo {
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PingReply {
+    pub uptime_sec: u64,
+    // The server's current time, to detect clock skew between client and server.
+    pub now: DateTime,
+}
+
+// Configuration of an actuator, as loaded from the config file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ActuatorConfigSummary {
+    pub name: String,
+    pub actuator_type: ActuatorType,
+    pub default_state: ActuatorState,
+    pub clamp: bool,
+    #[serde(default)]
+    pub quantize: bool,
+    #[serde(default)]
+    pub day_start: DayStart,
+    // Human-readable description of the controller, without any secret it may be configured with.
+    pub controller: String,
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+
+// Overview of an actuator, all fields taken at the same time.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ActuatorDetails {
+    pub info: ActuatorInfo,
+    pub default_state: ActuatorState,
+    pub enabled_timeslots: u32,
+    pub total_timeslots: u32,
+    // The state the schedule currently calls for (see Actuator::intended_state()).
+    pub intended_state: ActuatorState,
+    // The timeslot intended_state comes from, None for the default state.
+    pub active_timeslot: Option<u32>,
+    // If set, intended_state is the vacation state (and active_timeslot None).
+    #[serde(default)]
+    pub vacation: Option<Vacation>,
+    // If set, intended_state comes from the actuator followed (and active_timeslot is None).
+    #[serde(default)]
+    pub follow: Option<Follow>,
+}
 
-    rpc set_state(actuator_id: u32, state: ActuatorState) -> () | Error;
+// A vacation in progress (see Server::set_vacation()), with the state each actuator is kept in.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VacationStatus {
+    pub until: Date,
+    pub states: BTreeMap<u32, ActuatorState>,
 }
+
+// Which actuators are simulated (see ActuatorInfo::simulate): all of them if server is set, and
+// otherwise the ones in actuators.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SimulationStatus {
+    pub server: bool,
+    pub actuators: BTreeSet<u32>,
+}
+
+impl SimulationStatus {
+    pub fn simulates(&self, actuator_id: u32) -> bool {
+        self.server || self.actuators.contains(&actuator_id)
+    }
+}
+
+// Result of an operation applied to all the members of a group. The operation is first validated
+// on every member, and only applied if it is valid for all of them.
+#[derive(Serialize, Deserialize)]
+pub struct GroupResult<T> {
+    // False if the validation failed for at least one member, in which case nothing was applied.
+    pub applied: bool,
+    // By actuator ID. If applied, the result for every member (applying may still fail, e.g.
+    // ControllerFailure). Otherwise, only the members that failed the validation.
+    pub results: BTreeMap<u32, result::Result<T, Error>>,
+}
+
+impl<T> GroupResult<T> {
+    pub fn failures(&self) -> Vec<u32> {
+        self.results.iter().filter(|&(_, r)| r.is_err()).map(|(id, _)| *id).collect()
+    }
+}
+
+// The RPC methods, passed to the macro callback. This allows generating other transports than
+// tarpc's (see unix_transport) from the same definitions.
+macro_rules! rpc_methods {
+    ($callback:ident) => {
+        $callback! {
+            // Specifying | Error anyway, because tarpc::util::Never is a pain to handle.
+            // Should never change, so that any client can query any server.
+            rpc server_info() -> ServerInfo | Error;
+            // Cheap call to check that the server is alive.
+            rpc ping() -> PingReply | Error;
+            // Level as accepted by log::LevelFilter (off, error, warn, info, debug, trace).
+            rpc set_log_level(level: String) -> () | Error;
+            // The configuration the server loaded (not reflecting later changes, e.g. of the
+            // default state).
+            rpc get_config() -> Vec<ActuatorConfigSummary> | Error;
+
+            rpc list_actuators() -> Vec<ActuatorInfo> | Error;
+            // Same as list_actuators(), with more information on each actuator.
+            rpc list_actuators_detailed() -> Vec<ActuatorDetails> | Error;
+            // A disabled actuator keeps its configuration and schedule, but its state is not
+            // applied anymore (including by set_state()). When enabled again, the active timeslot
+            // is recomputed.
+            rpc set_actuator_enabled(actuator_id: u32, enabled: bool) -> () | Error;
+            rpc list_timeslots(actuator_id: u32) -> BTreeMap<u32, TimeSlot> | Error;
+            // Cheaper than list_timeslots(). If if_changed is the current generation, no summary is
+            // returned.
+            rpc list_timeslot_summaries(actuator_id: u32, if_changed: Option<u64>) -> TimeSlotSummaries | Error;
+            rpc get_timeslot(actuator_id: u32, time_slot_id: u32) -> TimeSlot | Error;
+
+            rpc get_default_state(actuator_id: u32) -> ActuatorState | Error;
+            // The state setters return the state actually set, which differs from the requested one
+            // if it was clamped or quantized (see ActuatorInfo::clamp and ActuatorInfo::quantize).
+            rpc set_default_state(actuator_id: u32, default_state: ActuatorState) -> ActuatorState | Error;
+            rpc get_default_schedule(actuator_id: u32) -> DefaultSchedule | Error;
+            // Add (or replace) a daily change of the default state at the given time.
+            rpc set_default_change(actuator_id: u32, time: Time, state: ActuatorState) -> () | Error;
+            rpc remove_default_change(actuator_id: u32, time: Time) -> () | Error;
+
+            rpc add_time_slot(actuator_id: u32, time_slot: NewTimeSlot) -> u32 | Error;
+            // Same as add_time_slot(), but only filling the parts of the time period where there is
+            // no other timeslot (possibly adding several timeslots, or none). Returns the ID and
+            // time period of each added timeslot.
+            rpc add_time_slot_fit(actuator_id: u32, time_slot: NewTimeSlot) -> Vec<(u32, TimePeriod)> | Error;
+            // Same as add_time_slot(), but narrowing or removing the timeslots in the way, at
+            // once. Returns the ID of the new timeslot and the narrowed or removed timeslots.
+            rpc add_time_slot_replace(actuator_id: u32, time_slot: NewTimeSlot) -> (u32, Vec<ReplacedTimeSlot>) | Error;
+            // Add several timeslots at once, all or nothing (see Error::BatchEntry). Returns their
+            // IDs, in order.
+            rpc add_time_slots(actuator_id: u32, time_slots: Vec<NewTimeSlot>) -> Vec<u32> | Error;
+            // TODO: choose one spelling: time_slot or timeslot
+            rpc remove_time_slot(actuator_id: u32, time_slot_id: u32) -> () | Error;
+            // Remove all the timeslots (or only the disabled ones) at once, returning how many
+            // were removed.
+            rpc clear_time_slots(actuator_id: u32, disabled_only: bool) -> u32 | Error;
+            rpc time_slot_set_time_period(actuator_id: u32, time_slot_id: u32, time_period_update: TimePeriodUpdate) -> () | Error;
+            rpc time_slot_set_enabled(actuator_id: u32, time_slot_id: u32, enabled: bool) -> () | Error;
+            // None to remove the label.
+            rpc time_slot_set_label(actuator_id: u32, time_slot_id: u32, label: Option<String>) -> () | Error;
+            rpc time_slot_set_actuator_state(actuator_id: u32, time_slot_id: u32, actuator_state: ActuatorState) -> ActuatorState | Error;
+            // Move the start and end times of the timeslot by a random amount of at most
+            // jitter_minutes every day (0 to disable).
+            rpc time_slot_set_jitter(actuator_id: u32, time_slot_id: u32, jitter_minutes: u32) -> () | Error;
+            // Only apply the timeslot while another actuator is in some state (see
+            // TimeSlotCondition), None to remove the condition.
+            rpc time_slot_set_condition(actuator_id: u32, time_slot_id: u32, condition: Option<TimeSlotCondition>) -> () | Error;
+            rpc time_slot_add_time_override(actuator_id: u32, time_slot_id: u32, time_period: TimePeriod) -> u32 | Error;
+            rpc time_slot_remove_time_override(actuator_id: u32, time_slot_id: u32, time_override_id: u32) -> () | Error;
+            // Move the selected timeslots (all if None), including their overrides, by minutes
+            // (earlier if negative), all or nothing. Returns the new time interval of each shifted
+            // timeslot (without modifying them if dry_run is set).
+            rpc shift_time_slots(actuator_id: u32, slot_ids: Option<Vec<u32>>, minutes: i32, dry_run: bool) -> BTreeMap<u32, TimeInterval> | Error;
+
+            rpc set_state(actuator_id: u32, state: ActuatorState) -> ActuatorState | Error;
+            // Same as set_state() with the opposite of the current state, for Toggle actuators
+            // only. Returns the state set.
+            rpc toggle_state(actuator_id: u32) -> ActuatorState | Error;
+            rpc get_current_state(actuator_id: u32) -> CurrentState | Error;
+            // The states applied outside of the schedule (manually or by a trigger) that still
+            // hold, see ActiveOverrideInfo.
+            rpc list_overrides(actuator_id: u32) -> Vec<ActiveOverrideInfo> | Error;
+            // Drop the override (if any), applying the state the schedule calls for right away.
+            rpc cancel_override(actuator_id: u32) -> () | Error;
+            // The current state of all the actuators, by actuator ID. Actuators whose state has not
+            // been applied yet are omitted.
+            rpc get_all_current_states() -> BTreeMap<u32, ActuatorState> | Error;
+            // Wait until the current state's sequence number differs from seq, for at most
+            // timeout_sec (capped by the server). Returns the current state, changed or not.
+            rpc wait_state_change(actuator_id: u32, seq: u64, timeout_sec: u32) -> CurrentState | Error;
+            // Same as wait_state_change() for all the actuators at once: the first state change of
+            // any actuator after the one numbered since, as (sequence number, actuator ID, state),
+            // None if there was none within timeout_sec. Passing the returned sequence number to
+            // the next call misses no change, unless more than StateChanges keeps happened in the
+            // meantime (the sequence numbers then skip some). u64::max_value() waits for the next.
+            rpc wait_for_any_change(since: u64, timeout_sec: u32) -> Option<(u64, u32, ActuatorState)> | Error;
+            // Recompute the active timeslot and apply it again (recovery).
+            rpc recompute_active(actuator_id: u32) -> () | Error;
+            // The states applied to the actuator (as persisted), starting on since if specified.
+            rpc get_history(actuator_id: u32, since: Option<Date>) -> Vec<HistoryEntry> | Error;
+            // The time spent in each state (formatted as ActuatorState's Display does) from the
+            // start of since until now, as far as the server knows: not before it started, nor
+            // more than a year back.
+            rpc get_state_durations(actuator_id: u32, since: Date) -> BTreeMap<String, Duration> | Error;
+            // The next timeslot starting now or later, and the date it occurs on, looking at most
+            // max_days days ahead (including today).
+            rpc next_timeslot(actuator_id: u32, max_days: u32) -> Option<(Date, ScheduleSlot)> | Error;
+            // When each timeslot next starts (now or later, jitter included), looking at most
+            // max_days days ahead. Disabled timeslots, and the ones not starting in that time, are
+            // omitted. Not part of the summaries, as it changes without the timeslots changing.
+            rpc next_occurrences(actuator_id: u32, max_days: u32) -> BTreeMap<u32, DateTime> | Error;
+            // The state scheduled at date_time (relative to the actuator's days), where it comes
+            // from and over which time interval it applies. Manual state changes are not taken into
+            // account.
+            rpc get_state_at(actuator_id: u32, date_time: DateTime) -> ResolvedState | Error;
+            // The states scheduled on date, in the order the actuator would apply them over the
+            // day: the states of the timeslots (or their overrides) and of the default state in
+            // between. Manual state changes and the vacation are not taken into account.
+            rpc simulate_day(actuator_id: u32, date: Date) -> Vec<ResolvedState> | Error;
+            // Make the enabled timeslots occurring on the from weekday also occur on the to
+            // weekdays, unless that creates overlaps. Returns the IDs of the timeslots modified (or
+            // that would be, if dry_run is set).
+            rpc copy_day(actuator_id: u32, from: WeekdaySet, to: WeekdaySet, dry_run: bool) -> Vec<u32> | Error;
+
+            // The groups from the configuration, with their members' actuator IDs.
+            rpc list_groups() -> BTreeMap<String, Vec<u32>> | Error;
+            // The triggers from the configuration (see Trigger).
+            rpc list_triggers() -> Vec<Trigger> | Error;
+            // Same as the corresponding methods, applied to all the members of the group (see
+            // GroupResult).
+            rpc group_set_actuator_enabled(group: String, enabled: bool) -> GroupResult<()> | Error;
+            rpc group_set_default_state(group: String, default_state: ActuatorState) -> GroupResult<ActuatorState> | Error;
+            rpc group_add_time_slot(group: String, time_slot: NewTimeSlot) -> GroupResult<u32> | Error;
+            rpc group_set_state(group: String, state: ActuatorState) -> GroupResult<ActuatorState> | Error;
+
+            // The templates from the configuration, and the ones saved since the server started.
+            rpc list_templates() -> BTreeMap<String, TimeSlotTemplate> | Error;
+            // Add (or replace) a template. Saved templates are not persisted: after a restart,
+            // only the ones from the configuration are available.
+            rpc save_template(name: String, time_period: TimePeriod, actuator_state: ActuatorState) -> () | Error;
+            rpc remove_template(name: String) -> () | Error;
+            // Add a timeslot to the actuator from the template, the same way as add_time_slot()
+            // (the timeslot is enabled). Returns the ID of the new timeslot.
+            rpc apply_template(actuator_id: u32, name: String) -> u32 | Error;
+
+            // Free-form annotations (see metadata), of the actuator if actuator_id is set, of the
+            // server otherwise. Like templates, the ones set since the server started are not
+            // persisted: after a restart, only the ones from the configuration remain.
+            rpc get_metadata(actuator_id: Option<u32>) -> Metadata | Error;
+            // Set (or remove, if value is None) a metadata entry.
+            rpc set_metadata(actuator_id: Option<u32>, key: String, value: Option<String>) -> () | Error;
+
+            // Suspend the schedule of all the actuators until the start of the day until, keeping
+            // them in the given states (by default, their default state) in the meantime. Like the
+            // timeslots, it is not persisted.
+            rpc set_vacation(until: Date, states: Option<Vec<(u32, ActuatorState)>>) -> () | Error;
+            // Resume the schedule right away.
+            rpc clear_vacation() -> () | Error;
+            // The vacation in progress, None if there is none (or it is over).
+            rpc get_vacation() -> Option<VacationStatus> | Error;
+
+            // Drive the actuator with the state of another one instead of its schedule (see
+            // Follow), or go back to its schedule if follow is None. Like the timeslots, it is not
+            // persisted: after a restart, only the follows from the configuration remain.
+            rpc set_follow(actuator_id: u32, follow: Option<Follow>) -> () | Error;
+
+            // Stop (or resume) writing to the controllers, of the given actuator or of all of them,
+            // while still going through the schedule. An actuator is simulated if either it or the
+            // whole server is.
+            rpc set_simulation(actuator_id: Option<u32>, enabled: bool) -> () | Error;
+            rpc get_simulation() -> SimulationStatus | Error;
+        }
+    }
+}
+
+rpc_methods!(service);
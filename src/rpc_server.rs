@@ -1,9 +1,16 @@
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
-use actuator::{ActuatorInfo, ActuatorState};
-use rpc::SyncService;
+use actuator::{ActiveOverrideInfo, ActuatorInfo, ActuatorState, CurrentState};
+use follow::Follow;
+use history::HistoryEntry;
+use metadata::Metadata;
+use rpc::{ActuatorConfigSummary, ActuatorDetails, GroupResult, PingReply, ServerInfo,
+          SimulationStatus, SyncService, VacationStatus};
+use schedule::{DefaultSchedule, ResolvedState, ScheduleSlot};
+use time::{Date, DateTime, Duration, Time, TimeInterval, WeekdaySet};
 use time_slot::*;
+use trigger::Trigger;
 use server::*;
 
 pub struct RpcServer {
@@ -30,53 +37,306 @@ impl Clone for RpcServer {
 }
 
 impl SyncService for RpcServer {
+    fn server_info(&self) -> Result<ServerInfo> {
+        Ok(self.server.server_info())
+    }
+
+    fn set_log_level(&self, level: String) -> Result<()> {
+        info!("RPC set_log_level({})", level);
+        self.server.set_log_level(level)
+    }
+
+    fn ping(&self) -> Result<PingReply> {
+        Ok(self.server.ping())
+    }
+
+    fn get_config(&self) -> Result<Vec<ActuatorConfigSummary>> {
+        Ok(self.server.get_config())
+    }
+
     fn list_actuators(&self) -> Result<Vec<ActuatorInfo>> {
         Ok(self.server.list_actuators())
     }
 
+    fn list_actuators_detailed(&self) -> Result<Vec<ActuatorDetails>> {
+        Ok(self.server.list_actuators_detailed())
+    }
+
+    fn set_actuator_enabled(&self, actuator_id: u32, enabled: bool) -> Result<()> {
+        info!("RPC set_actuator_enabled({}, {})", actuator_id, enabled);
+        self.server.set_actuator_enabled(actuator_id, enabled)
+    }
+
     fn list_timeslots(&self, actuator_id: u32) -> Result<BTreeMap<u32, TimeSlot>> {
         self.server.list_timeslots(actuator_id)
     }
 
+    fn list_timeslot_summaries(&self, actuator_id: u32, if_changed: Option<u64>) -> Result<TimeSlotSummaries> {
+        self.server.list_timeslot_summaries(actuator_id, if_changed)
+    }
+
+    fn get_timeslot(&self, actuator_id: u32, time_slot_id: u32) -> Result<TimeSlot> {
+        self.server.get_timeslot(actuator_id, time_slot_id)
+    }
+
     fn get_default_state(&self, actuator_id: u32) -> Result<ActuatorState> {
         self.server.get_default_state(actuator_id)
     }
 
-    fn set_default_state(&self, actuator_id: u32, default_state: ActuatorState) -> Result<()> {
+    fn set_default_state(&self, actuator_id: u32, default_state: ActuatorState) -> Result<ActuatorState> {
+        info!("RPC set_default_state({}, {:?})", actuator_id, default_state);
         self.server.set_default_state(actuator_id, default_state)
     }
 
-    fn add_time_slot(&self, actuator_id: u32, time_period: TimePeriod, actuator_state: ActuatorState, enabled: bool) -> Result<u32> {
-        self.server.add_time_slot(actuator_id, time_period, actuator_state, enabled)
+    fn get_default_schedule(&self, actuator_id: u32) -> Result<DefaultSchedule> {
+        self.server.get_default_schedule(actuator_id)
+    }
+
+    fn set_default_change(&self, actuator_id: u32, time: Time, state: ActuatorState) -> Result<()> {
+        info!("RPC set_default_change({}, {}, {:?})", actuator_id, time, state);
+        self.server.set_default_change(actuator_id, time, state)
+    }
+
+    fn remove_default_change(&self, actuator_id: u32, time: Time) -> Result<()> {
+        info!("RPC remove_default_change({}, {})", actuator_id, time);
+        self.server.remove_default_change(actuator_id, time)
+    }
+
+    fn add_time_slot(&self, actuator_id: u32, time_slot: NewTimeSlot) -> Result<u32> {
+        info!("RPC add_time_slot({}, {:?})", actuator_id, time_slot);
+        self.server.add_time_slot(actuator_id, time_slot)
+    }
+
+    fn add_time_slot_fit(&self, actuator_id: u32, time_slot: NewTimeSlot) -> Result<Vec<(u32, TimePeriod)>> {
+        info!("RPC add_time_slot_fit({}, {:?})", actuator_id, time_slot);
+        self.server.add_time_slot_fit(actuator_id, time_slot)
+    }
+
+    fn add_time_slot_replace(&self, actuator_id: u32, time_slot: NewTimeSlot) -> Result<(u32, Vec<ReplacedTimeSlot>)> {
+        info!("RPC add_time_slot_replace({}, {:?})", actuator_id, time_slot);
+        self.server.add_time_slot_replace(actuator_id, time_slot)
+    }
+
+    fn add_time_slots(&self, actuator_id: u32, time_slots: Vec<NewTimeSlot>) -> Result<Vec<u32>> {
+        info!("RPC add_time_slots({}, {:?})", actuator_id, time_slots);
+        self.server.add_time_slots(actuator_id, time_slots)
     }
 
     fn remove_time_slot(&self, actuator_id: u32, time_slot_id: u32) -> Result<()> {
+        info!("RPC remove_time_slot({}, {})", actuator_id, time_slot_id);
         self.server.remove_time_slot(actuator_id, time_slot_id)
     }
 
-    fn time_slot_set_time_period(&self, actuator_id: u32, time_slot_id: u32, time_period: TimePeriod) -> Result<()> {
-        self.server.time_slot_set_time_period(actuator_id, time_slot_id, time_period)
+    fn clear_time_slots(&self, actuator_id: u32, disabled_only: bool) -> Result<u32> {
+        info!("RPC clear_time_slots({}, {})", actuator_id, disabled_only);
+        self.server.clear_time_slots(actuator_id, disabled_only)
+    }
+
+    fn time_slot_set_time_period(&self, actuator_id: u32, time_slot_id: u32, time_period_update: TimePeriodUpdate) -> Result<()> {
+        info!("RPC time_slot_set_time_period({}, {}, {:?})",
+              actuator_id, time_slot_id, time_period_update);
+        self.server.time_slot_set_time_period(actuator_id, time_slot_id, time_period_update)
     }
 
     fn time_slot_set_enabled(&self, actuator_id: u32, time_slot_id: u32, enabled: bool) -> Result<()> {
+        info!("RPC time_slot_set_enabled({}, {}, {})", actuator_id, time_slot_id, enabled);
         self.server.time_slot_set_enabled(actuator_id, time_slot_id, enabled)
     }
 
-    fn time_slot_set_actuator_state(&self, actuator_id: u32, time_slot_id: u32, actuator_state: ActuatorState) -> Result<()> {
+    fn time_slot_set_actuator_state(&self, actuator_id: u32, time_slot_id: u32, actuator_state: ActuatorState) -> Result<ActuatorState> {
+        info!("RPC time_slot_set_actuator_state({}, {}, {:?})",
+              actuator_id, time_slot_id, actuator_state);
         self.server.time_slot_set_actuator_state(actuator_id, time_slot_id, actuator_state)
     }
 
+    fn time_slot_set_label(&self, actuator_id: u32, time_slot_id: u32, label: Option<String>) -> Result<()> {
+        info!("RPC time_slot_set_label({}, {}, {:?})", actuator_id, time_slot_id, label);
+        self.server.time_slot_set_label(actuator_id, time_slot_id, label)
+    }
+
+    fn time_slot_set_jitter(&self, actuator_id: u32, time_slot_id: u32, jitter_minutes: u32) -> Result<()> {
+        info!("RPC time_slot_set_jitter({}, {}, {})", actuator_id, time_slot_id, jitter_minutes);
+        self.server.time_slot_set_jitter(actuator_id, time_slot_id, jitter_minutes)
+    }
+
+    fn time_slot_set_condition(&self, actuator_id: u32, time_slot_id: u32, condition: Option<TimeSlotCondition>) -> Result<()> {
+        info!("RPC time_slot_set_condition({}, {}, {:?})", actuator_id, time_slot_id, condition);
+        self.server.time_slot_set_condition(actuator_id, time_slot_id, condition)
+    }
+
     fn time_slot_add_time_override(&self, actuator_id: u32, time_slot_id: u32, time_period: TimePeriod) -> Result<u32> {
+        info!("RPC time_slot_add_time_override({}, {}, {:?})",
+              actuator_id, time_slot_id, time_period);
         self.server.time_slot_add_time_override(actuator_id, time_slot_id, time_period)
     }
 
     fn time_slot_remove_time_override(&self, actuator_id: u32, time_slot_id: u32, time_override_id: u32) -> Result<()> {
+        info!("RPC time_slot_remove_time_override({}, {}, {})",
+              actuator_id, time_slot_id, time_override_id);
         self.server.time_slot_remove_time_override(actuator_id, time_slot_id, time_override_id)
     }
 
-    fn set_state(&self, actuator_id: u32, state: ActuatorState) -> Result<()> {
+    fn shift_time_slots(&self, actuator_id: u32, slot_ids: Option<Vec<u32>>, minutes: i32, dry_run: bool) -> Result<BTreeMap<u32, TimeInterval>> {
+        if !dry_run {
+            info!("RPC shift_time_slots({}, {:?}, {})", actuator_id, slot_ids, minutes);
+        }
+        self.server.shift_time_slots(actuator_id, slot_ids, minutes, dry_run)
+    }
+
+    fn set_state(&self, actuator_id: u32, state: ActuatorState) -> Result<ActuatorState> {
+        info!("RPC set_state({}, {:?})", actuator_id, state);
         self.server.set_state(actuator_id, state)
     }
+
+    fn toggle_state(&self, actuator_id: u32) -> Result<ActuatorState> {
+        info!("RPC toggle_state({})", actuator_id);
+        self.server.toggle_state(actuator_id)
+    }
+
+    fn list_overrides(&self, actuator_id: u32) -> Result<Vec<ActiveOverrideInfo>> {
+        self.server.list_overrides(actuator_id)
+    }
+
+    fn cancel_override(&self, actuator_id: u32) -> Result<()> {
+        info!("RPC cancel_override({})", actuator_id);
+        self.server.cancel_override(actuator_id)
+    }
+
+    fn get_current_state(&self, actuator_id: u32) -> Result<CurrentState> {
+        self.server.get_current_state(actuator_id)
+    }
+
+    fn get_all_current_states(&self) -> Result<BTreeMap<u32, ActuatorState>> {
+        Ok(self.server.get_all_current_states())
+    }
+
+    fn wait_state_change(&self, actuator_id: u32, seq: u64, timeout_sec: u32) -> Result<CurrentState> {
+        self.server.wait_state_change(actuator_id, seq, timeout_sec)
+    }
+
+    fn wait_for_any_change(&self, since: u64, timeout_sec: u32) -> Result<Option<(u64, u32, ActuatorState)>> {
+        Ok(self.server.wait_for_any_change(since, timeout_sec))
+    }
+
+    fn get_history(&self, actuator_id: u32, since: Option<Date>) -> Result<Vec<HistoryEntry>> {
+        self.server.get_history(actuator_id, since)
+    }
+
+    fn get_state_durations(&self, actuator_id: u32, since: Date) -> Result<BTreeMap<String, Duration>> {
+        self.server.get_state_durations(actuator_id, since)
+    }
+
+    fn recompute_active(&self, actuator_id: u32) -> Result<()> {
+        info!("RPC recompute_active({})", actuator_id);
+        self.server.recompute_active(actuator_id)
+    }
+
+    fn next_timeslot(&self, actuator_id: u32, max_days: u32) -> Result<Option<(Date, ScheduleSlot)>> {
+        self.server.next_timeslot(actuator_id, max_days)
+    }
+
+    fn next_occurrences(&self, actuator_id: u32, max_days: u32) -> Result<BTreeMap<u32, DateTime>> {
+        self.server.next_occurrences(actuator_id, max_days)
+    }
+
+    fn get_state_at(&self, actuator_id: u32, date_time: DateTime) -> Result<ResolvedState> {
+        self.server.get_state_at(actuator_id, date_time)
+    }
+
+    fn simulate_day(&self, actuator_id: u32, date: Date) -> Result<Vec<ResolvedState>> {
+        self.server.simulate_day(actuator_id, date)
+    }
+
+    fn copy_day(&self, actuator_id: u32, from: WeekdaySet, to: WeekdaySet, dry_run: bool) -> Result<Vec<u32>> {
+        if !dry_run {
+            info!("RPC copy_day({}, {}, {})", actuator_id, from, to);
+        }
+        self.server.copy_day(actuator_id, from, to, dry_run)
+    }
+
+    fn list_groups(&self) -> Result<BTreeMap<String, Vec<u32>>> {
+        Ok(self.server.list_groups())
+    }
+
+    fn list_triggers(&self) -> Result<Vec<Trigger>> {
+        Ok(self.server.list_triggers())
+    }
+
+    fn group_set_actuator_enabled(&self, group: String, enabled: bool) -> Result<GroupResult<()>> {
+        info!("RPC group_set_actuator_enabled({}, {})", group, enabled);
+        self.server.group_set_actuator_enabled(&group, enabled)
+    }
+
+    fn group_set_default_state(&self, group: String, default_state: ActuatorState) -> Result<GroupResult<ActuatorState>> {
+        info!("RPC group_set_default_state({}, {:?})", group, default_state);
+        self.server.group_set_default_state(&group, default_state)
+    }
+
+    fn group_add_time_slot(&self, group: String, time_slot: NewTimeSlot) -> Result<GroupResult<u32>> {
+        info!("RPC group_add_time_slot({}, {:?})", group, time_slot);
+        self.server.group_add_time_slot(&group, time_slot)
+    }
+
+    fn group_set_state(&self, group: String, state: ActuatorState) -> Result<GroupResult<ActuatorState>> {
+        info!("RPC group_set_state({}, {:?})", group, state);
+        self.server.group_set_state(&group, state)
+    }
+
+    fn list_templates(&self) -> Result<BTreeMap<String, TimeSlotTemplate>> {
+        Ok(self.server.list_templates())
+    }
+
+    fn save_template(&self, name: String, time_period: TimePeriod, actuator_state: ActuatorState) -> Result<()> {
+        info!("RPC save_template({}, {:?}, {:?})", name, time_period, actuator_state);
+        self.server.save_template(name, time_period, actuator_state)
+    }
+
+    fn remove_template(&self, name: String) -> Result<()> {
+        info!("RPC remove_template({})", name);
+        self.server.remove_template(&name)
+    }
+
+    fn apply_template(&self, actuator_id: u32, name: String) -> Result<u32> {
+        info!("RPC apply_template({}, {})", actuator_id, name);
+        self.server.apply_template(actuator_id, &name)
+    }
+
+    fn get_metadata(&self, actuator_id: Option<u32>) -> Result<Metadata> {
+        self.server.get_metadata(actuator_id)
+    }
+
+    fn set_metadata(&self, actuator_id: Option<u32>, key: String, value: Option<String>) -> Result<()> {
+        info!("RPC set_metadata({:?}, {}, {:?})", actuator_id, key, value);
+        self.server.set_metadata(actuator_id, key, value)
+    }
+
+    fn set_vacation(&self, until: Date, states: Option<Vec<(u32, ActuatorState)>>) -> Result<()> {
+        info!("RPC set_vacation({}, {:?})", until, states);
+        self.server.set_vacation(until, states)
+    }
+
+    fn clear_vacation(&self) -> Result<()> {
+        info!("RPC clear_vacation()");
+        self.server.clear_vacation()
+    }
+
+    fn get_vacation(&self) -> Result<Option<VacationStatus>> {
+        Ok(self.server.get_vacation())
+    }
+
+    fn set_follow(&self, actuator_id: u32, follow: Option<Follow>) -> Result<()> {
+        info!("RPC set_follow({}, {:?})", actuator_id, follow);
+        self.server.set_follow(actuator_id, follow)
+    }
+
+    fn set_simulation(&self, actuator_id: Option<u32>, enabled: bool) -> Result<()> {
+        info!("RPC set_simulation({:?}, {})", actuator_id, enabled);
+        self.server.set_simulation(actuator_id, enabled)
+    }
+
+    fn get_simulation(&self) -> Result<SimulationStatus> {
+        Ok(self.server.get_simulation())
+    }
 }
 
 /* impl FutureService for RpcServer {
@@ -1,76 +1,777 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use actuator::ActuatorState;
 use time::*;
 use time_slot::*;
 
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct ScheduleSlot {
     pub time_interval: TimeInterval,
     pub actuator_state: ActuatorState,
     pub id: u32,
+    pub label: Option<String>,
     pub override_id: Option<u32>,
+    // If an override applies, the time interval of the timeslot itself (which is replaced by the
+    // override's).
+    pub overridden_interval: Option<TimeInterval>,
+    // The condition of the timeslot, if any: the schedule assumes it holds.
+    #[serde(default)]
+    pub condition: Option<TimeSlotCondition>,
+}
+
+impl ScheduleSlot {
+    fn new(timeslot: &TimeSlot, id: u32, time_interval: TimeInterval,
+           override_id: Option<u32>) -> ScheduleSlot {
+        ScheduleSlot {
+            time_interval,
+            actuator_state: timeslot.actuator_state.clone(),
+            id,
+            label: timeslot.label.clone(),
+            override_id,
+            overridden_interval: override_id.map(|_| timeslot.time_period.time_interval.clone()),
+            condition: timeslot.condition.clone(),
+        }
+    }
 }
 
 pub type Schedule = BTreeMap<Date, Vec<ScheduleSlot>>;
 
-pub fn compute_schedule(timeslots: &BTreeMap<u32, TimeSlot>,
-                        start_date: Date, nb_days: u32) -> Schedule {
-    let mut day = start_date.clone();
-    let mut schedule = Schedule::new();
+// A part of a day in a ResolvedSchedule: either (part of) a slot, or the default state.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub enum ScheduleEntry {
+    Slot(ScheduleSlot),
+    Default {
+        time_interval: TimeInterval,
+        actuator_state: ActuatorState,
+    },
+}
+
+impl ScheduleEntry {
+    pub fn time_interval(&self) -> &TimeInterval {
+        match *self {
+            ScheduleEntry::Slot(ref slot) => &slot.time_interval,
+            ScheduleEntry::Default { ref time_interval, .. } => time_interval,
+        }
+    }
+
+    pub fn actuator_state(&self) -> &ActuatorState {
+        match *self {
+            ScheduleEntry::Slot(ref slot) => &slot.actuator_state,
+            ScheduleEntry::Default { ref actuator_state, .. } => actuator_state,
+        }
+    }
+}
+
+// Same as Schedule, but each day is entirely covered: its entries are contiguous, the first one
+// starts at the start of the day and the last one ends at the end of the day (DayStart::last()).
+pub type ResolvedSchedule = BTreeMap<Date, Vec<ScheduleEntry>>;
+
+// The state an actuator is in when no timeslot is active. It is base_state from the start of the
+// day, until the first change (if any); each change then sets the default state from its time on,
+// until the next change. The same changes apply every day.
+// changes is ordered according to the default day start: the actuator's day start must be passed
+// to get them in order.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DefaultSchedule {
+    pub base_state: ActuatorState,
+    pub changes: BTreeMap<Time, ActuatorState>,
+}
+
+impl DefaultSchedule {
+    pub fn new(base_state: ActuatorState) -> DefaultSchedule {
+        DefaultSchedule {
+            base_state,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    // The default state at time.
+    pub fn state_at(&self, time: Time, day_start: DayStart) -> &ActuatorState {
+        self.changes_in_order(day_start).into_iter()
+            .take_while(|&(change_time, _)| !day_start.is_before(time, change_time))
+            .last()
+            .map(|(_, state)| state)
+            .unwrap_or(&self.base_state)
+    }
+
+    // The time of the first change strictly after time (on the same day), if any.
+    pub fn next_change_after(&self, time: Time, day_start: DayStart) -> Option<Time> {
+        self.changes_in_order(day_start).into_iter()
+            .map(|(change_time, _)| change_time)
+            .find(|change_time| day_start.is_before(time, *change_time))
+    }
+
+    // The changes, in the order in which they apply over a day starting at day_start.
+    pub fn changes_in_order(&self, day_start: DayStart) -> Vec<(Time, &ActuatorState)> {
+        let mut changes: Vec<(Time, &ActuatorState)> = self.changes.iter()
+            .map(|(time, state)| (*time, state))
+            .collect();
+        changes.sort_by_key(|&(time, _)| day_start.minute_of_day(time));
+        changes
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = &ActuatorState> {
+        Some(&self.base_state).into_iter().chain(self.changes.values())
+    }
+}
+
+// Compute the schedule over nb_days days, starting on start_date, with the default state in
+// between the slots (see compute_slots()).
+pub fn compute_schedule(timeslots: &BTreeMap<u32, TimeSlot>, default_schedule: &DefaultSchedule,
+                        day_start: DayStart, start_date: Date, nb_days: i32) -> ResolvedSchedule {
+    compute_slots(timeslots, day_start, start_date, nb_days).into_iter()
+        .map(|(date, slots)| (date, resolve_day(slots, default_schedule, day_start)))
+        .collect()
+}
+
+// Compute the slots over nb_days days, starting on start_date. If nb_days is negative, the
+// schedule covers -nb_days days ending on start_date instead.
+// As everywhere below, day_start is the one of the actuator the timeslots belong to, and dates are
+// relative to it.
+pub fn compute_slots(timeslots: &BTreeMap<u32, TimeSlot>, day_start: DayStart,
+                     start_date: Date, nb_days: i32) -> Schedule {
+    // Clamped to the range of dates, rather than overflowing near Date::MIN or Date::MAX.
+    let dates = if nb_days < 0 {
+        DateRange {
+            start: start_date.checked_add(nb_days as i64 + 1).unwrap_or(Date::MIN),
+            end: start_date,
+        }
+    } else if nb_days > 0 {
+        DateRange {
+            start: start_date,
+            end: start_date.checked_add(nb_days as i64 - 1).unwrap_or(Date::MAX),
+        }
+    } else {
+        return Schedule::new()
+    };
+    let index = TimeSlotIndex::new(timeslots, day_start);
+
+    dates.iter_days().map(|date| (date, index.slots_on(date))).collect()
+}
+
+// Turn the slots of a day (sorted by time) into entries covering the whole day. Should slots
+// overlap, the first one takes precedence (as in resolve_state()): the next one only starts once
+// it ends, or is dropped if it ends first.
+fn resolve_day(slots: Vec<ScheduleSlot>, default_schedule: &DefaultSchedule,
+               day_start: DayStart) -> Vec<ScheduleEntry> {
+    let mut entries = Vec::new();
+    let mut covered_until = day_start.first();
+
+    for mut slot in slots {
+        if !day_start.is_before(covered_until, slot.time_interval.end) {
+            continue
+        }
+
+        if day_start.is_before(covered_until, slot.time_interval.start) {
+            add_default_entries(&mut entries, default_schedule, day_start,
+                                covered_until, slot.time_interval.start);
+        } else {
+            slot.time_interval.start = covered_until;
+        }
+
+        covered_until = slot.time_interval.end;
+        entries.push(ScheduleEntry::Slot(slot));
+    }
+
+    if day_start.is_before(covered_until, day_start.last()) {
+        add_default_entries(&mut entries, default_schedule, day_start,
+                            covered_until, day_start.last());
+    }
+
+    entries
+}
+
+// Add the default state entries from start to end, one per change of the default state.
+fn add_default_entries(entries: &mut Vec<ScheduleEntry>, default_schedule: &DefaultSchedule,
+                       day_start: DayStart, start: Time, end: Time) {
+    let changes: Vec<Time> = default_schedule.changes_in_order(day_start).into_iter()
+        .map(|(time, _)| time)
+        .filter(|time| day_start.is_before(start, *time) && day_start.is_before(*time, end))
+        .collect();
+
+    let mut start = start;
+    for time in changes.into_iter().chain(Some(end)) {
+        entries.push(ScheduleEntry::Default {
+            time_interval: TimeInterval { start, end: time },
+            actuator_state: default_schedule.state_at(start, day_start).clone(),
+        });
+        start = time;
+    }
+}
 
-    for _ in 0..nb_days {
+// How much of a day is scheduled away from the default state, i.e. covered by timeslots.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DaySummary {
+    pub date: Date,
+    pub scheduled: Duration,
+    // The distinct states of the timeslots, in order of first occurrence.
+    pub states: Vec<ActuatorState>,
+}
+
+// Summarize each day of schedule (as computed by compute_slots()). Overlapping slots are only
+// counted once, and a slot ending at the end of the day lasts until the start of the next day.
+pub fn summarize(schedule: &Schedule, day_start: DayStart) -> Vec<DaySummary> {
+    schedule.iter()
+        .map(|(date, slots)| {
+            // Slots as minutes from the start of the day, sorted by start.
+            let mut intervals: Vec<(i32, i32)> = slots.iter()
+                .map(|slot| {
+                    let start = day_start.minute_of_day(slot.time_interval.start);
+                    (start, start + day_start.duration(&slot.time_interval).num_minutes())
+                })
+                .collect();
+            intervals.sort();
+
+            let mut scheduled = 0;
+            let mut covered_until = 0;
+            for (start, end) in intervals {
+                let start = start.max(covered_until);
+                if end > start {
+                    scheduled += end - start;
+                    covered_until = end;
+                }
+            }
+
+            let mut states = Vec::new();
+            for slot in slots.iter() {
+                if !states.contains(&slot.actuator_state) {
+                    states.push(slot.actuator_state.clone());
+                }
+            }
+
+            DaySummary { date: *date, scheduled: Duration::minutes(scheduled), states }
+        })
+        .collect()
+}
+
+// Index of the enabled timeslots by weekday, so that computing the slots of a given day only
+// requires going through the timeslots that may occur on that day.
+pub struct TimeSlotIndex<'a> {
+    // One entry per weekday, starting on Monday. Each entry is sorted by (non-overridden) start
+    // time.
+    by_weekday: Vec<Vec<(u32, &'a TimeSlot)>>,
+    day_start: DayStart,
+}
+
+impl<'a> TimeSlotIndex<'a> {
+    pub fn new(timeslots: &'a BTreeMap<u32, TimeSlot>, day_start: DayStart) -> TimeSlotIndex<'a> {
+        let mut by_weekday = vec![Vec::new(); 7];
+
+        for (id, ts) in timeslots.iter().filter(|&(_, ts)| ts.enabled) {
+            for (day_idx, slots) in by_weekday.iter_mut().enumerate() {
+                if ts.time_period.days.bits() & (1 << day_idx) != 0 {
+                    slots.push((*id, ts));
+                }
+            }
+        }
+
+        for slots in by_weekday.iter_mut() {
+            slots.sort_by_key(|&(_, ts)| {
+                day_start.minute_of_day(ts.time_period.time_interval.start)
+            });
+        }
+
+        TimeSlotIndex { by_weekday, day_start }
+    }
+
+    // The slots occurring on date, sorted by time.
+    pub fn slots_on(&self, date: Date) -> Vec<ScheduleSlot> {
         let mut slots = Vec::<ScheduleSlot>::new();
 
-        for (id, ts) in timeslots.iter() {
-            if !ts.enabled {
-                continue;
+        for &(id, ts) in self.by_weekday[date.weekday_index()].iter() {
+            if let Some((time_interval, override_id))
+                = ts.time_interval_on(id, date, self.day_start)
+            {
+                slots.push(ScheduleSlot::new(ts, id, time_interval, override_id));
+            }
+        }
+
+        // Overrides may have changed the order, sort slots by time again (this is cheap if they
+        // did not).
+        let day_start = self.day_start;
+        slots.sort_by_key(|s| day_start.minute_of_day(s.time_interval.start));
+        slots
+    }
+}
+
+// Where the state scheduled at some point comes from.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub enum StateOrigin {
+    TimeSlot {
+        id: u32,
+        override_id: Option<u32>,
+    },
+    // If the default state applies until a timeslot starts (and not until it changes or the end of
+    // the day), next_id identifies that timeslot.
+    Default {
+        next_id: Option<u32>,
+        next_override_id: Option<u32>,
+    },
+}
+
+// The state scheduled at some point (see resolve_state()), and the time interval over which it
+// applies on that day.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ResolvedState {
+    pub actuator_state: ActuatorState,
+    pub origin: StateOrigin,
+    pub time_interval: TimeInterval,
+}
+
+// Resolve the state scheduled at dt: either the state of the timeslot active at dt, or the default
+// state. Timeslots take precedence over the default state, and the default state applies until it
+// changes, the next timeslot starts or the day ends, whichever comes first. This is how the
+// actuator computes its active timeslot.
+pub fn resolve_state(timeslots: &BTreeMap<u32, TimeSlot>, default_schedule: &DefaultSchedule,
+                     day_start: DayStart, dt: &DateTime) -> ResolvedState {
+    let slots = TimeSlotIndex::new(timeslots, day_start).slots_on(dt.date);
+    // Slots are sorted by time, so this is either the slot containing dt, or the next one. All the
+    // slots before it have ended.
+    let next_pos = slots.iter()
+        .position(|slot| day_start.is_before(dt.time, slot.time_interval.end));
+
+    if let Some(pos) = next_pos {
+        let slot = &slots[pos];
+        if day_start.contains(&slot.time_interval, dt.time) {
+            return ResolvedState {
+                actuator_state: slot.actuator_state.clone(),
+                origin: StateOrigin::TimeSlot { id: slot.id, override_id: slot.override_id },
+                time_interval: slot.time_interval.clone(),
             }
+        }
+    }
+
+    // The default state applies from the last of the end of the previous slot, the last change
+    // and the start of the day.
+    let previous_end = slots[..next_pos.unwrap_or(slots.len())].last()
+        .map(|slot| slot.time_interval.end);
+    let last_change = default_schedule.changes_in_order(day_start).into_iter()
+        .map(|(change_time, _)| change_time)
+        .take_while(|change_time| !day_start.is_before(dt.time, *change_time))
+        .last();
+    let start = previous_end.into_iter().chain(last_change)
+        .max_by_key(|time| day_start.minute_of_day(*time))
+        .unwrap_or(day_start.first());
+
+    let change_end = default_schedule.next_change_after(dt.time, day_start);
+    let (end, next_id, next_override_id) = match (next_pos.map(|pos| &slots[pos]), change_end) {
+        (Some(slot), Some(change_time))
+            if day_start.is_before(change_time, slot.time_interval.start) =>
+            (change_time, None, None),
+        (Some(slot), _) => (slot.time_interval.start, Some(slot.id), slot.override_id),
+        (None, change_time) => (change_time.unwrap_or(day_start.last()), None, None),
+    };
+
+    ResolvedState {
+        actuator_state: default_schedule.state_at(dt.time, day_start).clone(),
+        origin: StateOrigin::Default { next_id, next_override_id },
+        time_interval: TimeInterval { start, end },
+    }
+}
+
+// The states scheduled on date in chronological order, from the start to the end of the day, as
+// the actuator goes through them: each one applies until its time interval ends, at which point
+// the state is resolved again.
+pub fn resolve_transitions(timeslots: &BTreeMap<u32, TimeSlot>,
+                           default_schedule: &DefaultSchedule, day_start: DayStart,
+                           date: Date) -> Vec<ResolvedState> {
+    let mut states = Vec::new();
+    let mut dt = DateTime { date, time: day_start.first() };
+
+    loop {
+        let resolved = resolve_state(timeslots, default_schedule, day_start, &dt);
+        let end = resolved.time_interval.end;
+        states.push(resolved);
 
-            if let Some((time_interval, override_id)) = ts.time_interval_on(day) {
-                slots.push(ScheduleSlot {
-                    time_interval,
-                    actuator_state: ts.actuator_state.clone(),
-                    id: *id,
-                    override_id,
-                });
+        // The actuator moves to the next day once the last minute is reached. Also stop if the
+        // state does not end after dt, rather than resolving it forever.
+        if end == day_start.last() || !day_start.is_before(dt.time, end) {
+            break
+        }
+        dt.time = end;
+    }
+
+    states
+}
+
+// Long enough to cover all weekdays several times.
+pub const SHADOWED_CHECK_DAYS: i32 = 28;
+
+// Find the enabled timeslots that occur at least once over the nb_days days starting on
+// start_date, but never actually appear in the schedule, because other timeslots take precedence
+// (see resolve_day()) on all the days they occur.
+pub fn find_shadowed(timeslots: &BTreeMap<u32, TimeSlot>, default_schedule: &DefaultSchedule,
+                     day_start: DayStart, start_date: Date, nb_days: i32) -> Vec<u32>
+{
+    let mut occurring = BTreeSet::new();
+    let mut scheduled = BTreeSet::new();
+
+    for (_, slots) in compute_slots(timeslots, day_start, start_date, nb_days) {
+        occurring.extend(slots.iter().map(|slot| slot.id));
+
+        for entry in resolve_day(slots, default_schedule, day_start) {
+            if let ScheduleEntry::Slot(slot) = entry {
+                scheduled.insert(slot.id);
             }
         }
+    }
 
-        // Sort slots by time.
-        slots.sort_unstable_by_key(|s| s.time_interval.start);
+    occurring.difference(&scheduled).cloned().collect()
+}
+
+// Find the next timeslot starting at dt or later, looking at most max_days days ahead (including
+// dt.date). Returns the date on which it occurs along with the slot.
+pub fn find_next_timeslot_from(timeslots: &BTreeMap<u32, TimeSlot>, day_start: DayStart,
+                               dt: &DateTime, max_days: u32) -> Option<(Date, ScheduleSlot)>
+{
+    let index = TimeSlotIndex::new(timeslots, day_start);
 
-        schedule.insert(day, slots);
-        day += 1;
+    for date in DateRange::starting_on(dt.date, max_days as i64).iter_days() {
+        // Slots are sorted by time, and on the following days any slot will do.
+        let next_slot = index.slots_on(date).into_iter()
+            .find(|slot| date > dt.date || !day_start.is_before(slot.time_interval.start, dt.time));
+
+        if let Some(slot) = next_slot {
+            return Some((date, slot))
+        }
     }
 
-    schedule
+    None
+}
+
+// The functions below are straightforward (but slower) equivalents to going through
+// TimeSlotIndex::slots_on().
+
+// Find the timeslot active at dt, if any.
+pub fn find_active_timeslot(timeslots: &BTreeMap<u32, TimeSlot>, day_start: DayStart,
+                            dt: &DateTime) -> Option<ScheduleSlot>
+{
+    for (id, ts) in timeslots.iter() {
+        if !ts.enabled {
+            continue;
+        }
+
+        if let Some((time_interval, override_id)) = ts.time_interval_on(*id, dt.date, day_start) {
+            if day_start.contains(&time_interval, dt.time) {
+                return Some(ScheduleSlot::new(ts, *id, time_interval, override_id))
+            }
+        }
+    }
+
+    None
 }
 
 // Find the next active timeslot in timeslots scheduled on dt.date, starting on dt.time or later.
-pub fn find_next_timeslot(timeslots: &BTreeMap<u32, TimeSlot>, dt: &DateTime)
-    -> Option<ScheduleSlot>
+pub fn find_next_timeslot(timeslots: &BTreeMap<u32, TimeSlot>, day_start: DayStart,
+                          dt: &DateTime) -> Option<ScheduleSlot>
 {
     let mut next_ts: Option<ScheduleSlot> = None;
     for (id, ts) in timeslots.iter() {
-        if let Some((time_interval, override_id)) = ts.time_interval_on(dt.date) {
-            if !ts.enabled || time_interval.start < dt.time {
+        if let Some((time_interval, override_id)) = ts.time_interval_on(*id, dt.date, day_start) {
+            if !ts.enabled || day_start.is_before(time_interval.start, dt.time) {
                 continue;
             }
 
             if let Some(ref slot) = next_ts {
-                if time_interval.start > slot.time_interval.start {
+                if day_start.is_before(slot.time_interval.start, time_interval.start) {
                     continue;
                 }
             }
 
-            next_ts = Some(ScheduleSlot {
-                time_interval,
-                actuator_state: ts.actuator_state.clone(),
-                id: *id,
-                override_id,
-            });
+            next_ts = Some(ScheduleSlot::new(ts, *id, time_interval, override_id));
         }
     }
 
     next_ts
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date::from_ymd(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn compute_slots_dates() {
+        let timeslots = BTreeMap::new();
+        let ds = DayStart::default();
+        let dates = |start: Date, nb_days: i32| -> Vec<Date> {
+            compute_slots(&timeslots, ds, start, nb_days).keys().cloned().collect()
+        };
+        let start = date(2018, 1, 10);
+
+        assert_eq!(dates(start, 2), vec![start, start + 1]);
+        assert_eq!(dates(start, -2), vec![start - 1, start]);
+        assert!(dates(start, 0).is_empty());
+        // Clamped to the range of dates.
+        assert_eq!(dates(Date::MAX, 3), vec![Date::MAX]);
+        assert_eq!(dates(Date::MIN, -3), vec![Date::MIN]);
+        assert_eq!(dates(Date::MIN + 1, i32::min_value()), vec![Date::MIN, Date::MIN + 1]);
+    }
+
+    // The slots occurring on date, as computed before TimeSlotIndex: going through all the
+    // timeslots.
+    fn reference_slots_on(timeslots: &BTreeMap<u32, TimeSlot>, day_start: DayStart,
+                          date: Date) -> Vec<ScheduleSlot> {
+        let mut slots = Vec::new();
+
+        for (id, ts) in timeslots.iter() {
+            if !ts.enabled {
+                continue;
+            }
+            if let Some((time_interval, override_id)) = ts.time_interval_on(*id, date, day_start) {
+                slots.push(ScheduleSlot::new(ts, *id, time_interval, override_id));
+            }
+        }
+
+        slots.sort_by_key(|s| day_start.minute_of_day(s.time_interval.start));
+        slots
+    }
+
+    // Deterministic pseudo-random numbers.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: u32) -> u32 {
+            self.0 += 1;
+            (::utils::hash_u64(self.0) % n as u64) as u32
+        }
+    }
+
+    fn random_time_period(random: &mut Random, day_start: DayStart) -> TimePeriod {
+        let start = day_start.add_minutes(day_start.first(), random.below(24 * 4) as i32 * 15).0;
+        let (end, day_carry) = day_start.add_minutes(start, 15 + random.below(16) as i32 * 15);
+        let first_date = date(2018, 1, 1) + random.below(60) as i64;
+
+        TimePeriod {
+            time_interval: TimeInterval {
+                start,
+                end: if day_carry > 0 { day_start.last() } else { end },
+            },
+            date_range: match random.below(3) {
+                0 => DateRange { start: Date::MIN, end: Date::MAX },
+                1 => DateRange { start: first_date, end: Date::MAX },
+                _ => DateRange::starting_on(first_date, 1 + random.below(30) as i64),
+            },
+            days: WeekdaySet::from_bits(1 + random.below(127) as u8).unwrap(),
+        }
+    }
+
+    // Timeslots that do not overlap, as the actuator would accept them, with overrides, jitter
+    // and disabled ones.
+    fn random_timeslots(random: &mut Random, day_start: DayStart) -> BTreeMap<u32, TimeSlot> {
+        let mut timeslots: BTreeMap<u32, TimeSlot> = BTreeMap::new();
+
+        for id in 0..30 {
+            let mut ts = TimeSlot::new(random.below(5) != 0, ActuatorState::Toggle(true),
+                                       random_time_period(random, day_start));
+            for _ in 0..random.below(3) {
+                ts.add_time_override(random_time_period(random, day_start));
+            }
+            if random.below(4) == 0 {
+                ts.jitter_minutes = random.below(30);
+            }
+
+            if ts.time_period.check(day_start).is_ok() &&
+                timeslots.values().all(|other| !ts.overlaps_time_slot(other, day_start))
+            {
+                timeslots.insert(id, ts);
+            }
+        }
+
+        timeslots
+    }
+
+    #[test]
+    fn index_matches_reference() {
+        for seed in 0..20 {
+            for &day_start in [DayStart::default(), DayStart::MIDNIGHT].iter() {
+                let mut random = Random(seed * 1000);
+                let timeslots = random_timeslots(&mut random, day_start);
+                let index = TimeSlotIndex::new(&timeslots, day_start);
+
+                for date in DateRange::starting_on(date(2018, 1, 1), 90).iter_days() {
+                    assert_eq!(index.slots_on(date),
+                               reference_slots_on(&timeslots, day_start, date),
+                               "seed {}, {}", seed, date);
+                }
+
+                let schedule = compute_slots(&timeslots, day_start, date(2018, 1, 1), 90);
+                for (date, slots) in schedule.iter() {
+                    assert_eq!(*slots, reference_slots_on(&timeslots, day_start, *date));
+                }
+
+                // Same for the active and next timeslots, at random times.
+                for _ in 0..200 {
+                    let dt = DateTime {
+                        date: date(2018, 1, 1) + random.below(90) as i64,
+                        time: day_start.add_minutes(day_start.first(),
+                                                    random.below(24 * 60) as i32).0,
+                    };
+                    let resolved = resolve_state(&timeslots, &DefaultSchedule::new(
+                        ActuatorState::Toggle(false)), day_start, &dt);
+                    let active = find_active_timeslot(&timeslots, day_start, &dt);
+
+                    match resolved.origin {
+                        StateOrigin::TimeSlot { id, override_id } => {
+                            let active = active.unwrap();
+                            assert_eq!((active.id, active.override_id), (id, override_id));
+                            assert_eq!(active.time_interval, resolved.time_interval);
+                        },
+                        StateOrigin::Default { .. } => assert!(active.is_none(), "{}", dt),
+                    }
+                    assert_eq!(find_next_timeslot_from(&timeslots, day_start, &dt, 1)
+                                   .map(|(_, slot)| slot),
+                               find_next_timeslot(&timeslots, day_start, &dt));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn next_timeslot_on_later_days() {
+        let ds = DayStart::default();
+        let at = |dt: &str| dt.parse::<DateTime>().unwrap();
+        let mut timeslots = BTreeMap::new();
+
+        // Nothing to find: stops at the bound.
+        assert!(find_next_timeslot_from(&timeslots, ds, &at("01/01/2018 10:00"), 1000).is_none());
+
+        // 2018-01-01 is a Monday.
+        let period = TimePeriod {
+            time_interval: "06:00-07:00".parse().unwrap(),
+            date_range: DateRange { start: Date::MIN, end: Date::MAX },
+            days: WeekdaySet::WEDNESDAY,
+        };
+        timeslots.insert(0, TimeSlot::new(true, ActuatorState::Toggle(true), period));
+
+        let next = |dt: &str, max_days: u32| {
+            find_next_timeslot_from(&timeslots, ds, &at(dt), max_days).map(|(date, slot)| {
+                assert_eq!(slot.id, 0);
+                date
+            })
+        };
+        assert_eq!(next("01/01/2018 10:00", 7), Some(date(2018, 1, 3)));
+        assert_eq!(next("01/01/2018 10:00", 2), None);
+        assert_eq!(next("03/01/2018 06:00", 1), Some(date(2018, 1, 3)));
+        assert_eq!(next("03/01/2018 06:01", 1), None);
+        assert_eq!(next("03/01/2018 06:01", 8), Some(date(2018, 1, 10)));
+    }
+
+    // Check that entries partition the day: contiguous, non-empty, from the first to the last
+    // minute of the day.
+    fn assert_covers_day(entries: &[ScheduleEntry], day_start: DayStart) {
+        let mut covered_until = day_start.first();
+
+        for entry in entries {
+            let time_interval = entry.time_interval();
+            assert_eq!(time_interval.start, covered_until, "gap or overlap in {:?}", entries);
+            assert!(day_start.is_before(time_interval.start, time_interval.end),
+                    "empty entry in {:?}", entries);
+            covered_until = time_interval.end;
+        }
+        assert_eq!(covered_until, day_start.last(), "day not fully covered: {:?}", entries);
+    }
+
+    #[test]
+    fn schedule_covers_days() {
+        let every_day = |time_interval: &str| TimeSlot::new(true, ActuatorState::Toggle(true),
+            TimePeriod {
+                time_interval: time_interval.parse().unwrap(),
+                date_range: DateRange { start: Date::MIN, end: Date::MAX },
+                days: WeekdaySet::all(),
+            });
+        let mut default_schedule = DefaultSchedule::new(ActuatorState::Toggle(false));
+        let start = date(2018, 1, 1);
+
+        for &day_start in [DayStart::default(), DayStart::MIDNIGHT].iter() {
+            let last = day_start.last();
+            let cases: Vec<Vec<TimeSlot>> = vec![
+                // Empty day.
+                vec![],
+                // Back-to-back slots.
+                vec![every_day("09:00-10:00"), every_day("10:00-11:00")],
+                // From the first to the last minute of the day.
+                vec![every_day(&format!("{}-{}", day_start.first(), last))],
+                vec![every_day(&format!("{}-12:00", day_start.first())),
+                     every_day(&format!("12:00-{}", last))],
+                // Ending at the last minute of the day, i.e. Time::MAX with the default day start.
+                vec![every_day(&format!("20:00-{}", last))],
+            ];
+
+            for slots in cases.into_iter() {
+                let timeslots: BTreeMap<u32, TimeSlot> =
+                    slots.into_iter().enumerate().map(|(id, ts)| (id as u32, ts)).collect();
+                let schedule = compute_schedule(&timeslots, &default_schedule, day_start,
+                                                start, 2);
+                assert_eq!(schedule.len(), 2);
+                for entries in schedule.values() {
+                    assert_covers_day(entries, day_start);
+                    // As many slot entries as timeslots: none is merged or dropped.
+                    assert_eq!(entries.iter().filter(|e| match **e {
+                        ScheduleEntry::Slot(_) => true,
+                        ScheduleEntry::Default { .. } => false,
+                    }).count(), timeslots.len());
+                }
+            }
+
+            // An empty day is split at the changes of the default state only.
+            default_schedule.changes.insert("08:00".parse().unwrap(), ActuatorState::Toggle(true));
+            default_schedule.changes.insert("20:00".parse().unwrap(),
+                                            ActuatorState::Toggle(false));
+            let schedule = compute_schedule(&BTreeMap::new(), &default_schedule, day_start,
+                                            start, 1);
+            let entries = &schedule[&start];
+            assert_covers_day(entries, day_start);
+            assert_eq!(entries.iter().map(|e| e.actuator_state().clone()).collect::<Vec<_>>(),
+                       vec![ActuatorState::Toggle(false), ActuatorState::Toggle(true),
+                            ActuatorState::Toggle(false)]);
+
+            // Random timeslots, whose overrides may overlap other timeslots.
+            for seed in 0..20 {
+                let mut random = Random(seed * 1000);
+                let timeslots = random_timeslots(&mut random, day_start);
+                for entries in compute_schedule(&timeslots, &default_schedule, day_start,
+                                                start, 60).values() {
+                    assert_covers_day(entries, day_start);
+                }
+            }
+            default_schedule.changes.clear();
+        }
+    }
+
+    #[test]
+    fn shadowed_timeslots() {
+        let ds = DayStart::default();
+        // 2018-01-01 is a Monday.
+        let slot = |enabled: bool, time_interval: &str, days: WeekdaySet| TimeSlot::new(
+            enabled, ActuatorState::Toggle(true), TimePeriod {
+                time_interval: time_interval.parse().unwrap(),
+                date_range: DateRange { start: Date::MIN, end: Date::MAX },
+                days,
+            });
+        let default_schedule = DefaultSchedule::new(ActuatorState::Toggle(false));
+        let mut timeslots = BTreeMap::new();
+        timeslots.insert(0, slot(true, "09:00-12:00", WeekdaySet::all()));
+        // Fully covered by timeslot 0.
+        timeslots.insert(1, slot(true, "10:00-11:00", WeekdaySet::all()));
+        // Only partly covered: starts once timeslot 0 ends.
+        timeslots.insert(2, slot(true, "11:00-13:00", WeekdaySet::all()));
+        // Fully covered on Mondays only, by timeslot 4.
+        timeslots.insert(3, slot(true, "14:00-15:00", WeekdaySet::all()));
+        timeslots.insert(4, slot(true, "13:30-16:00", WeekdaySet::MONDAY));
+        // Disabled, so never in the schedule in the first place.
+        timeslots.insert(5, slot(false, "10:00-11:00", WeekdaySet::all()));
+        let shadowed = |start: Date, nb_days: i32| {
+            find_shadowed(&timeslots, &default_schedule, ds, start, nb_days)
+        };
+
+        assert_eq!(shadowed(date(2018, 1, 1), 7), vec![1]);
+        assert_eq!(shadowed(date(2018, 1, 1), 1), vec![1, 3]);
+        assert_eq!(shadowed(date(2018, 1, 2), 1), vec![1]);
+        // Timeslots that do not occur at all are not shadowed.
+        assert!(shadowed(date(2018, 1, 1), 0).is_empty());
+    }
+}
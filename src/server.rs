@@ -1,102 +1,372 @@
 use std::collections::BTreeMap;
-use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use serde_yaml;
+use audit::AuditLog;
+use condition;
+use events;
+use events::EventBroadcaster;
+use follow;
+use follow::Follow;
+use history::{History, HistoryConfig, HistoryEntry};
+use log;
+use metadata;
+use metadata::Metadata;
+use metrics;
 
 use actuator::*;
 use actuator_controller::*;
+use config::*;
+use schedule::{DefaultSchedule, ResolvedState, ScheduleSlot};
+use time;
+use time::{Date, DateTime, DayStart, SystemClock, Time, TimeInterval, WeekdaySet};
 use time_slot::*;
-use utils::*;
+use trigger;
+use trigger::Trigger;
 
+use rpc;
+use rpc::{ActuatorConfigSummary, ActuatorDetails, GroupResult, PingReply, ServerInfo,
+          SimulationStatus, VacationStatus};
 use rpc::InvalArgError as IAE;
 use rpc::Error::*;
 pub type Result<T> = result::Result<T, ::rpc::Error>;
 
+const MAX_NEXT_TIMESLOT_DAYS: u32 = 366;
+// Clients waiting for longer should call wait_state_change() again.
+const MAX_WAIT_STATE_CHANGE_SEC: u32 = 60;
+
 // TODO: merge with RpcServer?
 pub struct Server {
     actuators: Vec<ActuatorHandle>,
+    // As loaded, for get_config().
+    config: Vec<ActuatorConfigSummary>,
+    // Group name -> member actuator IDs, sorted.
+    groups: BTreeMap<String, Vec<u32>>,
+    triggers: Vec<Trigger>,
+    templates: RwLock<BTreeMap<String, TimeSlotTemplate>>,
+    metadata: RwLock<Metadata>,
+    // The simulate flag of each actuator is derived from it, see set_simulation().
+    simulation: RwLock<SimulationStatus>,
+    // Held while checking and modifying what the actuators follow, so that concurrent changes
+    // cannot create a cycle.
+    follow_lock: Mutex<()>,
+    state_changes: StateChangesHandle,
+    start_instant: Instant,
 }
 
-impl Server {
-    pub fn new(config_file: impl Read) -> result::Result<Server, String> {
-        #[derive(Deserialize)]
-        #[serde(tag = "type")]
-        enum ConfigActuatorController {
-            File { path: String },
-        };
-        // We can't modify ActuatorState's serde attributes directly, as otherwise tarpc would
-        // complain, so as a workaround we create a mirror struct.
-        #[derive(Deserialize)]
-        #[serde(untagged)]
-        pub enum ConfigActuatorState {
-            Toggle(bool),
-            FloatValue(f64),
+// Stop the actuator threads, so that embedders can drop a Server without leaking them.
+impl Drop for Server {
+    fn drop(&mut self) {
+        for actuator in self.actuators.iter() {
+            actuator.write().unwrap().stop();
         }
-        #[derive(Deserialize)]
-        struct ConfigActuator {
-            name: String,
-            actuator_type: ActuatorType,
-            default_state: ConfigActuatorState,
-            controller: ConfigActuatorController,
+    }
+}
+
+impl Server {
+    pub fn new(config: ConfigFile) -> result::Result<Server, String> {
+        let errors = config.validate(false);
+        if !errors.is_empty() {
+            return Err(errors.join("; "))
         }
-        #[derive(Deserialize)]
-        struct ConfigFile {
-            actuators: Vec<ConfigActuator>,
+
+        if let Some(ref level) = config.log_level {
+            log::set_max_level(log::LevelFilter::from_str(level).unwrap());
         }
 
-        let config: ConfigFile = serde_yaml::from_reader(config_file)
-            .map_err(|e| format!("Reading config file failed: {}", e))?;
+        // The members are known to exist, config has been validated.
+        let groups = config.groups.iter()
+            .map(|(group, members)| {
+                let mut ids: Vec<u32> = members.iter()
+                    .map(|m| config.actuators.iter().position(|ca| ca.name == *m).unwrap() as u32)
+                    .collect();
+                ids.sort();
+                (group.clone(), ids)
+            })
+            .collect();
+
+        // Valid as well.
+        let templates = config.templates.iter()
+            .map(|(name, template)| (name.clone(), template.to_template().unwrap()))
+            .collect();
 
+        let mut simulation = SimulationStatus { server: config.simulate, ..Default::default() };
+        let state_changes = StateChanges::new();
         let mut actuators = Vec::<ActuatorHandle>::new();
+        let mut actuator_configs = Vec::new();
+        // Actuators targeting the same file share the same controller, so that their writes do not
+        // race. The key is the canonical path, to catch different spellings of the same path.
+        let mut file_controllers = BTreeMap::<PathBuf, (String, ActuatorControllerHandle)>::new();
+        // Set up once all the actuators exist.
+        let mut config_follows = Vec::new();
 
         for ca in config.actuators {
-            let controller = match ca.controller {
-                ConfigActuatorController::File { ref path } => {
-                    FileActuatorController::new(Path::new(&path))
+            // Valid as well.
+            let controller_config = ca.controller().unwrap();
+            let controller = match controller_config {
+                ConfigActuatorController::File {
+                    ref path, enum_as_index, color_format, line_terminator, write_mode, read_back
+                } => {
+                    let path = Path::new(&path);
+                    let key = path.canonicalize().unwrap_or(path.to_path_buf());
+                    let existing = file_controllers.get(&key).cloned();
+
+                    match existing {
+                        Some((other_name, controller)) => {
+                            warn!("Actuators {} and {} both write to {}, sharing their controller",
+                                  other_name, ca.name, path.display());
+                            Ok(controller)
+                        },
+                        None => {
+                            if read_back && write_mode == WriteMode::Append {
+                                warn!("Actuator {}: read_back is ignored in append mode",
+                                      ca.name);
+                            }
+                            let format = FileFormat {
+                                enum_values: match ca.actuator_type {
+                                    ConfigActuatorType::Enum { ref values } if enum_as_index =>
+                                        Some(values.clone()),
+                                    _ => None,
+                                },
+                                color: color_format,
+                                line_terminator,
+                                write_mode,
+                                read_back,
+                            };
+
+                            FileActuatorController::new(path, format).map(|controller| {
+                                file_controllers.insert(key, (ca.name.clone(), controller.clone()));
+                                controller
+                            })
+                        },
+                    }
                 },
             }.map_err(|e| format!("Failed to create controller for actuator {}: {}", ca.name, e))?;
 
-            let default_state = match ca.default_state {
-                ConfigActuatorState::Toggle(b) => ActuatorState::Toggle(b),
-                ConfigActuatorState::FloatValue(f) => ActuatorState::FloatValue(f),
+            let day_start = ca.day_start();
+            let info = ActuatorInfo {
+                name: ca.name.clone(),
+                actuator_type: ca.actuator_type.into(),
+                clamp: ca.clamp,
+                quantize: ca.quantize,
+                enabled: true,
+                day_start,
+                // Set right away, so that the initial state is not written.
+                simulate: config.simulate || ca.simulate,
+                unit: ca.unit.clone(),
+                max_timeslots: ca.max_timeslots,
             };
+            let default_state: ActuatorState = ca.default_state.into();
 
-            let actuator = Actuator::new(
-                ActuatorInfo {
-                    name: ca.name.clone(),
-                    actuator_type: ca.actuator_type,
-                },
-                default_state,
-                controller,
-            );
+            actuator_configs.push(ActuatorConfigSummary {
+                name: info.name.clone(),
+                actuator_type: info.actuator_type.clone(),
+                default_state: default_state.clone(),
+                clamp: info.clamp,
+                quantize: info.quantize,
+                day_start: info.day_start,
+                controller: controller_config.summary(),
+                on_failure: ca.on_failure,
+            });
 
-            if !actuator.read().unwrap().valid() {
-                return Err(format!("Invalid configuration for actuator {}", ca.name))
+            if ca.simulate {
+                simulation.actuators.insert(actuators.len() as u32);
+            }
+            if let Some(ref follows) = ca.follows {
+                config_follows.push((actuators.len() as u32, follows.clone()));
             }
 
+            let actuator = Actuator::new(info, default_state, controller);
+            actuator.write().unwrap().enable_state_changes(actuators.len() as u32,
+                                                           state_changes.clone());
+            if let Some(ref log_file) = ca.log_file {
+                actuator.write().unwrap().enable_transition_log(PathBuf::from(log_file));
+            }
+            // Valid as well.
+            for (key, value) in ca.metadata {
+                actuator.write().unwrap().set_metadata(key, Some(value)).unwrap();
+            }
+            actuator.write().unwrap().set_failure_policy(ca.on_failure);
+            if let Some(minutes) = config.max_wait_minutes {
+                actuator.read().unwrap()
+                    .set_max_wait(Some(Duration::from_secs(minutes as u64 * 60)));
+            }
             actuators.push(actuator);
         }
 
-        Ok(Server {
+        let condition_sources = condition::sources(&actuators);
+        for (id, actuator) in actuators.iter().enumerate() {
+            actuator.write().unwrap().enable_conditions(id as u32, condition_sources.clone());
+        }
+        condition::spawn(&actuators, state_changes.clone());
+
+        if let Some(ref ch) = config.history {
+            let history = History::new(HistoryConfig {
+                directory: PathBuf::from(&ch.directory),
+                max_file_size: ch.max_file_size,
+                kept_files: ch.kept_files,
+            }).map_err(|e| format!("Failed to set up the history in {}: {}", ch.directory, e))?;
+
+            for actuator in actuators.iter() {
+                actuator.write().unwrap().enable_history(history.clone());
+            }
+        }
+
+        if let Some(ref ca) = config.audit_log {
+            let audit_log = AuditLog::open(Path::new(&ca.path), ca.format)
+                .map_err(|e| format!("Failed to open the audit log {}: {}", ca.path, e))?;
+
+            for (id, actuator) in actuators.iter().enumerate() {
+                actuator.write().unwrap().enable_audit_log(id as u32, audit_log.clone());
+            }
+        }
+
+        if let Some(ref address) = config.events_address {
+            let broadcaster = EventBroadcaster::new();
+
+            for (id, actuator) in actuators.iter().enumerate() {
+                actuator.write().unwrap().enable_events(id as u32, broadcaster.clone());
+            }
+
+            events::spawn_server(address, broadcaster)
+                .map_err(|e| format!("Failed to serve events on {}: {}", address, e))?;
+        }
+
+        if let Some(ref address) = config.metrics_address {
+            let metrics = actuators.iter()
+                .map(|a| {
+                    let mut actuator = a.write().unwrap();
+                    (actuator.info.name.clone(), actuator.enable_metrics())
+                })
+                .collect();
+
+            metrics::spawn_server(address, metrics, SystemClock::new())
+                .map_err(|e| format!("Failed to serve metrics on {}: {}", address, e))?;
+        }
+
+        // The actuators are known to exist, config has been validated.
+        let actuator_id = |name: &str| {
+            actuator_configs.iter().position(|ac| ac.name == name).unwrap() as u32
+        };
+        let triggers: Vec<Trigger> = config.triggers.iter()
+            .map(|ct| Trigger {
+                source: actuator_id(&ct.source),
+                target: actuator_id(&ct.target),
+                duration_sec: ct.duration_minutes * 60,
+            })
+            .collect();
+        for t in triggers.iter() {
+            trigger::spawn(t.clone(), &actuators[t.source as usize],
+                           &actuators[t.target as usize]);
+        }
+
+        // Valid as well.
+        let follows: Vec<(u32, Follow)> = config_follows.iter()
+            .map(|&(id, ref cf)| {
+                let source_id = actuator_id(&cf.actuator);
+                let source_type = &actuator_configs[source_id as usize].actuator_type;
+                let follower_type = &actuator_configs[id as usize].actuator_type;
+                (id, cf.to_follow(source_id, source_type, follower_type).unwrap())
+            })
+            .collect();
+
+        let server = Server {
             actuators,
-        })
+            config: actuator_configs,
+            groups,
+            triggers,
+            templates: RwLock::new(templates),
+            metadata: RwLock::new(config.metadata),
+            simulation: RwLock::new(simulation),
+            follow_lock: Mutex::new(()),
+            state_changes,
+            start_instant: Instant::now(),
+        };
+
+        for (actuator_id, follow) in follows {
+            server.set_follow(actuator_id, Some(follow)).map_err(|e| e.to_string())?;
+        }
+        Ok(server)
     }
 
     // Public API (exposed via RPC)
 
+    pub fn server_info(&self) -> ServerInfo {
+        ServerInfo {
+            version: rpc::VERSION.to_string(),
+            capabilities: rpc::CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    pub fn set_log_level(&self, level: String) -> Result<()> {
+        let level = log::LevelFilter::from_str(&level)
+            .map_err(|_| InvalidArgument(IAE::LogLevel, format!("unknown level {}", level)))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    pub fn ping(&self) -> PingReply {
+        PingReply {
+            uptime_sec: self.start_instant.elapsed().as_secs(),
+            now: DateTime::now(),
+        }
+    }
+
+    pub fn get_config(&self) -> Vec<ActuatorConfigSummary> {
+        self.config.clone()
+    }
+
     pub fn list_actuators(&self) -> Vec<ActuatorInfo> {
         self.actuators.iter()
             .map(|a| a.read().unwrap().info.clone())
             .collect()
     }
 
+    pub fn list_actuators_detailed(&self) -> Vec<ActuatorDetails> {
+        self.actuators.iter()
+            .map(|a| {
+                let actuator = a.read().unwrap();
+                let (intended_state, active_timeslot) = actuator.intended_state();
+
+                ActuatorDetails {
+                    info: actuator.info.clone(),
+                    default_state: actuator.default_state().clone(),
+                    enabled_timeslots: actuator.timeslots().values()
+                        .filter(|ts| ts.enabled).count() as u32,
+                    total_timeslots: actuator.timeslots().len() as u32,
+                    intended_state,
+                    active_timeslot,
+                    vacation: actuator.vacation(),
+                    follow: actuator.follow().cloned(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn set_actuator_enabled(&self, actuator_id: u32, enabled: bool) -> Result<()> {
+        self.write_actuator(actuator_id,
+                            |a| Ok(a.set_enabled(enabled)))
+    }
+
     pub fn list_timeslots(&self, actuator_id: u32) -> Result<BTreeMap<u32, TimeSlot>> {
         self.read_actuator(actuator_id,
                            |a| Ok(a.timeslots().clone()))
     }
 
+    pub fn list_timeslot_summaries(&self, actuator_id: u32,
+                                   if_changed: Option<u64>) -> Result<TimeSlotSummaries> {
+        self.read_actuator(actuator_id,
+                           |a| Ok(a.timeslot_summaries(if_changed)))
+    }
+
+    pub fn get_timeslot(&self, actuator_id: u32, time_slot_id: u32) -> Result<TimeSlot> {
+        self.read_actuator(actuator_id,
+                           |a| a.timeslot(time_slot_id).map(|ts| ts.clone()))
+    }
+
     pub fn get_default_state(&self, actuator_id: u32) -> Result<ActuatorState> {
         self.read_actuator(actuator_id,
                            |a| Ok(a.default_state().clone()))
@@ -104,18 +374,51 @@ impl Server {
 
     pub fn set_default_state(&self,
                              actuator_id: u32,
-                             default_state: ActuatorState) -> Result<()> {
+                             default_state: ActuatorState) -> Result<ActuatorState> {
         self.write_actuator(actuator_id,
                             |a| a.set_default_state(default_state))
     }
 
+    pub fn get_default_schedule(&self, actuator_id: u32) -> Result<DefaultSchedule> {
+        self.read_actuator(actuator_id,
+                           |a| Ok(a.default_schedule().clone()))
+    }
+
+    pub fn set_default_change(&self,
+                              actuator_id: u32,
+                              time: Time,
+                              state: ActuatorState) -> Result<()> {
+        self.write_actuator(actuator_id,
+                            |a| a.set_default_change(time, state))
+    }
+
+    pub fn remove_default_change(&self, actuator_id: u32, time: Time) -> Result<()> {
+        self.write_actuator(actuator_id,
+                            |a| a.remove_default_change(time))
+    }
+
     pub fn add_time_slot(&self,
                          actuator_id: u32,
-                         time_period: TimePeriod,
-                         actuator_state: ActuatorState,
-                         enabled: bool) -> Result<u32> {
-        self.write_actuator(actuator_id,
-                            |a| a.add_time_slot(time_period, actuator_state, enabled))
+                         time_slot: NewTimeSlot) -> Result<u32> {
+        self.write_actuator(actuator_id, |a| a.add_time_slot(time_slot))
+    }
+
+    pub fn add_time_slot_fit(&self,
+                             actuator_id: u32,
+                             time_slot: NewTimeSlot) -> Result<Vec<(u32, TimePeriod)>> {
+        self.write_actuator(actuator_id, |a| a.add_time_slot_fit(time_slot))
+    }
+
+    pub fn add_time_slot_replace(&self,
+                                 actuator_id: u32,
+                                 time_slot: NewTimeSlot) -> Result<(u32, Vec<ReplacedTimeSlot>)> {
+        self.write_actuator(actuator_id, |a| a.add_time_slot_replace(time_slot))
+    }
+
+    pub fn add_time_slots(&self,
+                          actuator_id: u32,
+                          time_slots: Vec<NewTimeSlot>) -> Result<Vec<u32>> {
+        self.write_actuator(actuator_id, |a| a.add_time_slots(time_slots))
     }
 
     pub fn remove_time_slot(&self, actuator_id: u32, time_slot_id: u32) -> Result<()> {
@@ -123,12 +426,16 @@ impl Server {
                             |a| a.remove_time_slot(time_slot_id))
     }
 
+    pub fn clear_time_slots(&self, actuator_id: u32, disabled_only: bool) -> Result<u32> {
+        self.write_actuator(actuator_id, |a| Ok(a.clear_time_slots(disabled_only)))
+    }
+
     pub fn time_slot_set_time_period(&self,
                                  actuator_id: u32,
                                  time_slot_id: u32,
-                                 time_period: TimePeriod) -> Result<()> {
+                                 time_period_update: TimePeriodUpdate) -> Result<()> {
         self.write_actuator(actuator_id,
-            |a| a.time_slot_set_time_period(time_slot_id, time_period))
+            |a| a.time_slot_set_time_period(time_slot_id, time_period_update))
     }
 
     pub fn time_slot_set_enabled(&self,
@@ -142,11 +449,35 @@ impl Server {
     pub fn time_slot_set_actuator_state(&self,
                                         actuator_id: u32,
                                         time_slot_id: u32,
-                                        actuator_state: ActuatorState) -> Result<()> {
+                                        actuator_state: ActuatorState) -> Result<ActuatorState> {
         self.write_actuator(actuator_id,
             |a| a.time_slot_set_actuator_state(time_slot_id, actuator_state))
     }
 
+    pub fn time_slot_set_label(&self,
+                               actuator_id: u32,
+                               time_slot_id: u32,
+                               label: Option<String>) -> Result<()> {
+        self.write_actuator(actuator_id,
+            |a| a.time_slot_set_label(time_slot_id, label))
+    }
+
+    pub fn time_slot_set_jitter(&self,
+                                actuator_id: u32,
+                                time_slot_id: u32,
+                                jitter_minutes: u32) -> Result<()> {
+        self.write_actuator(actuator_id,
+            |a| a.time_slot_set_jitter(time_slot_id, jitter_minutes))
+    }
+
+    pub fn time_slot_set_condition(&self,
+                                   actuator_id: u32,
+                                   time_slot_id: u32,
+                                   condition: Option<TimeSlotCondition>) -> Result<()> {
+        self.write_actuator(actuator_id,
+            |a| a.time_slot_set_condition(time_slot_id, condition))
+    }
+
     pub fn time_slot_add_time_override(&self,
                                        actuator_id: u32,
                                        time_slot_id: u32,
@@ -163,17 +494,254 @@ impl Server {
             |a| a.time_slot_remove_time_override(time_slot_id, time_override_id))
     }
 
-    pub fn set_state(&self, actuator_id: u32, state: ActuatorState) -> Result<()> {
+    pub fn shift_time_slots(&self, actuator_id: u32, slot_ids: Option<Vec<u32>>, minutes: i32,
+                            dry_run: bool) -> Result<BTreeMap<u32, TimeInterval>> {
+        self.write_actuator(actuator_id, |a| a.shift_time_slots(slot_ids, minutes, dry_run))
+    }
+
+    pub fn set_state(&self, actuator_id: u32, state: ActuatorState) -> Result<ActuatorState> {
         self.read_actuator(actuator_id, |a| a.set_state(state))
     }
 
+    pub fn toggle_state(&self, actuator_id: u32) -> Result<ActuatorState> {
+        self.write_actuator(actuator_id, |a| a.toggle_state())
+    }
+
+    pub fn list_overrides(&self, actuator_id: u32) -> Result<Vec<ActiveOverrideInfo>> {
+        self.read_actuator(actuator_id, |a| Ok(a.overrides()))
+    }
+
+    pub fn cancel_override(&self, actuator_id: u32) -> Result<()> {
+        self.read_actuator(actuator_id, |a| {
+            a.cancel_override();
+            Ok(())
+        })
+    }
+
+    pub fn get_current_state(&self, actuator_id: u32) -> Result<CurrentState> {
+        self.read_actuator(actuator_id, |a| Ok(a.state_notifier().current()))
+    }
+
+    pub fn get_all_current_states(&self) -> BTreeMap<u32, ActuatorState> {
+        self.actuators.iter().enumerate()
+            .filter_map(|(id, a)| {
+                a.read().unwrap().state_notifier().current().state.map(|state| (id as u32, state))
+            })
+            .collect()
+    }
+
+    pub fn wait_state_change(&self, actuator_id: u32, seq: u64,
+                             timeout_sec: u32) -> Result<CurrentState> {
+        // Do not keep the actuator locked while waiting.
+        let notifier = self.read_actuator(actuator_id, |a| Ok(a.state_notifier()))?;
+        let timeout_sec = timeout_sec.min(MAX_WAIT_STATE_CHANGE_SEC);
+
+        Ok(notifier.wait_change(seq, Duration::from_secs(timeout_sec as u64)))
+    }
+
+    pub fn wait_for_any_change(&self, since: u64, timeout_sec: u32) -> Option<StateChange> {
+        let timeout_sec = timeout_sec.min(MAX_WAIT_STATE_CHANGE_SEC);
+        self.state_changes.wait_after(since, Duration::from_secs(timeout_sec as u64))
+    }
+
+    pub fn get_history(&self, actuator_id: u32, since: Option<Date>) -> Result<Vec<HistoryEntry>> {
+        // Do not keep the actuator locked while reading the files.
+        let (name, history) = self.read_actuator(actuator_id,
+                                                 |a| Ok((a.info.name.clone(), a.history())))?;
+        let history = history.ok_or(HistoryUnavailable("history is not enabled".to_string()))?;
+
+        history.read(&name, since).map_err(|e| HistoryUnavailable(e.to_string()))
+    }
+
+    pub fn get_state_durations(&self, actuator_id: u32,
+                               since: Date) -> Result<BTreeMap<String, time::Duration>> {
+        self.read_actuator(actuator_id, |a| Ok(a.state_durations(since)))
+    }
+
+    pub fn recompute_active(&self, actuator_id: u32) -> Result<()> {
+        self.read_actuator(actuator_id, |a| Ok(a.recompute_active_timeslot()))
+    }
+
+    pub fn next_timeslot(&self, actuator_id: u32,
+                         max_days: u32) -> Result<Option<(Date, ScheduleSlot)>> {
+        // Looking further than a year ahead is never useful, don't let clients make us do that.
+        let max_days = max_days.min(MAX_NEXT_TIMESLOT_DAYS);
+        self.read_actuator(actuator_id, |a| Ok(a.next_timeslot(max_days)))
+    }
+
+    pub fn next_occurrences(&self, actuator_id: u32,
+                            max_days: u32) -> Result<BTreeMap<u32, DateTime>> {
+        // Same as next_timeslot().
+        let max_days = max_days.min(MAX_NEXT_TIMESLOT_DAYS);
+        self.read_actuator(actuator_id, |a| Ok(a.next_occurrences(max_days)))
+    }
+
+    pub fn get_state_at(&self, actuator_id: u32, date_time: DateTime) -> Result<ResolvedState> {
+        self.read_actuator(actuator_id, |a| a.state_at(&date_time))
+    }
+
+    pub fn simulate_day(&self, actuator_id: u32, date: Date) -> Result<Vec<ResolvedState>> {
+        self.read_actuator(actuator_id, |a| Ok(a.states_on(date)))
+    }
+
+    pub fn copy_day(&self, actuator_id: u32, from: WeekdaySet, to: WeekdaySet,
+                    dry_run: bool) -> Result<Vec<u32>> {
+        self.write_actuator(actuator_id, |a| a.copy_day(from, to, dry_run))
+    }
+
+    pub fn list_groups(&self) -> BTreeMap<String, Vec<u32>> {
+        self.groups.clone()
+    }
+
+    pub fn list_triggers(&self) -> Vec<Trigger> {
+        self.triggers.clone()
+    }
+
+    pub fn group_set_actuator_enabled(&self, group: &str,
+                                      enabled: bool) -> Result<GroupResult<()>> {
+        self.write_group(group,
+                         |_| Ok(()),
+                         |a| Ok(a.set_enabled(enabled)))
+    }
+
+    pub fn group_set_default_state(&self,
+                                   group: &str,
+                                   default_state: ActuatorState)
+                                   -> Result<GroupResult<ActuatorState>> {
+        self.write_group(group,
+                         |a| a.accept_state(default_state.clone()).map(|_| ()),
+                         |a| a.set_default_state(default_state.clone()))
+    }
+
+    pub fn group_add_time_slot(&self,
+                               group: &str,
+                               time_slot: NewTimeSlot) -> Result<GroupResult<u32>> {
+        self.write_group(group,
+                         |a| a.check_new_time_slot(time_slot.clone()).map(|_| ()),
+                         |a| a.add_time_slot(time_slot.clone()))
+    }
+
+    pub fn group_set_state(&self, group: &str,
+                           state: ActuatorState) -> Result<GroupResult<ActuatorState>> {
+        self.write_group(group,
+                         |a| a.check_set_state(state.clone()).map(|_| ()),
+                         |a| a.set_state(state.clone()))
+    }
+
+    // See Vacation. All or nothing: the states are checked first.
+    pub fn set_vacation(&self, until: Date,
+                        states: Option<Vec<(u32, ActuatorState)>>) -> Result<()> {
+        let today = DateTime::now().date;
+        if until <= today {
+            return Err(InvalidArgument(IAE::Date,
+                                       format!("vacation must end after today ({})", today)))
+        }
+
+        let mut vacation_states = BTreeMap::new();
+        for (actuator_id, state) in states.unwrap_or(Vec::new()) {
+            let state = self.read_actuator(actuator_id, |a| a.accept_state(state))?;
+            if vacation_states.insert(actuator_id, state).is_some() {
+                return Err(InvalidArgument(IAE::ActuatorId,
+                                           format!("several states for actuator {}", actuator_id)))
+            }
+        }
+
+        for (actuator_id, actuator) in self.actuators.iter().enumerate() {
+            let state = vacation_states.remove(&(actuator_id as u32));
+            actuator.write().unwrap().set_vacation(Some(Vacation { until, state }))?;
+        }
+        Ok(())
+    }
+
+    pub fn clear_vacation(&self) -> Result<()> {
+        for actuator in self.actuators.iter() {
+            actuator.write().unwrap().set_vacation(None)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_vacation(&self) -> Option<VacationStatus> {
+        let mut status: Option<VacationStatus> = None;
+
+        for (actuator_id, actuator) in self.actuators.iter().enumerate() {
+            let actuator = actuator.read().unwrap();
+            if let Some(Vacation { until, state }) = actuator.vacation() {
+                let state = state.unwrap_or_else(|| actuator.default_state().clone());
+                status.get_or_insert_with(|| VacationStatus { until, states: BTreeMap::new() })
+                    .states.insert(actuator_id as u32, state);
+            }
+        }
+
+        status
+    }
+
+    // See Follow. The actuator starts following the current state of the source right away.
+    pub fn set_follow(&self, actuator_id: u32, follow: Option<Follow>) -> Result<()> {
+        let _follow_guard = self.follow_lock.lock().unwrap();
+        let follower_type = self.read_actuator(actuator_id,
+                                               |a| Ok(a.info.actuator_type.clone()))?;
+
+        let follow = match follow {
+            Some(follow) => follow,
+            None => return self.write_actuator(actuator_id, |a| {
+                a.set_follow(None, None);
+                Ok(())
+            }),
+        };
+
+        let source_id = follow.actuator_id;
+        let (source_type, source_state) = self.read_actuator(source_id, |a| {
+            Ok((a.info.actuator_type.clone(), a.state_notifier().current().state))
+        })?;
+        follow.check(&source_type, &follower_type).map_err(|e| InvalidArgument(IAE::Follow, e))?;
+
+        let actuators = &self.actuators;
+        if follow::creates_cycle(actuator_id, source_id, actuators.len(), |id| {
+            actuators[id as usize].read().unwrap().follow().map(|f| f.actuator_id)
+        }) {
+            return Err(InvalidArgument(IAE::Follow,
+                                       "an actuator cannot follow itself, even indirectly"
+                                           .to_string()))
+        }
+
+        let generation = self.write_actuator(actuator_id,
+                                             |a| Ok(a.set_follow(Some(follow), source_state)))?;
+        follow::spawn(generation, &actuators[source_id as usize],
+                      &actuators[actuator_id as usize]);
+        Ok(())
+    }
+
+    pub fn set_simulation(&self, actuator_id: Option<u32>, enabled: bool) -> Result<()> {
+        let mut simulation = self.simulation.write().unwrap();
+
+        match actuator_id {
+            Some(actuator_id) => {
+                self.read_actuator(actuator_id, |_| Ok(()))?;
+                if enabled {
+                    simulation.actuators.insert(actuator_id);
+                } else {
+                    simulation.actuators.remove(&actuator_id);
+                }
+            },
+            None => simulation.server = enabled,
+        }
+
+        for (actuator_id, actuator) in self.actuators.iter().enumerate() {
+            actuator.write().unwrap().set_simulate(simulation.simulates(actuator_id as u32));
+        }
+        Ok(())
+    }
+
+    pub fn get_simulation(&self) -> SimulationStatus {
+        self.simulation.read().unwrap().clone()
+    }
 
     fn read_actuator<F, T>(&self, actuator_id: u32, func: F) -> Result<T>
     where
         F: FnOnce(&Actuator) -> Result<T>
     {
         let actuator_handle =
-            self.actuators.get(actuator_id as usize).ok_or(InvalidArgument(IAE::ActuatorId))?;
+            self.actuators.get(actuator_id as usize).ok_or(ActuatorNotFound(actuator_id))?;
         func(&actuator_handle.read().unwrap())
     }
 
@@ -182,7 +750,91 @@ impl Server {
         F: FnOnce(&mut Actuator) -> Result<T>
     {
         let actuator_handle =
-            self.actuators.get(actuator_id as usize).ok_or(InvalidArgument(IAE::ActuatorId))?;
+            self.actuators.get(actuator_id as usize).ok_or(ActuatorNotFound(actuator_id))?;
         func(&mut *actuator_handle.write().unwrap())
     }
+
+    pub fn list_templates(&self) -> BTreeMap<String, TimeSlotTemplate> {
+        self.templates.read().unwrap().clone()
+    }
+
+    pub fn save_template(&self,
+                         name: String,
+                         time_period: TimePeriod,
+                         actuator_state: ActuatorState) -> Result<()> {
+        if name.is_empty() {
+            return Err(InvalidArgument(IAE::TemplateName, "empty name".to_string()))
+        }
+        // The state can only be checked when applying the template, against the actuator's type
+        // (but no actuator accepts a non-finite one, as in ConfigTemplate::to_template()). Same for
+        // the time interval, which is only checked against the default days for now.
+        if !actuator_state.is_finite() {
+            let detail = format!("{} is not a finite number", actuator_state);
+            return Err(InvalidArgument(IAE::ActuatorState, detail))
+        }
+        time_period.check(DayStart::default()).map_err(|e| InvalidArgument(IAE::TimePeriod, e))?;
+
+        self.templates.write().unwrap().insert(name, TimeSlotTemplate {
+            time_period,
+            actuator_state,
+        });
+        Ok(())
+    }
+
+    pub fn remove_template(&self, name: &str) -> Result<()> {
+        self.templates.write().unwrap().remove(name)
+            .map(|_| ())
+            .ok_or_else(|| TemplateNotFound(name.to_string()))
+    }
+
+    pub fn apply_template(&self, actuator_id: u32, name: &str) -> Result<u32> {
+        let template = self.templates.read().unwrap().get(name).cloned()
+            .ok_or_else(|| TemplateNotFound(name.to_string()))?;
+
+        self.write_actuator(actuator_id,
+                            |a| a.add_time_slot(NewTimeSlot::new(template.time_period,
+                                                                 template.actuator_state, true)))
+    }
+
+    pub fn get_metadata(&self, actuator_id: Option<u32>) -> Result<Metadata> {
+        match actuator_id {
+            Some(actuator_id) => self.read_actuator(actuator_id, |a| Ok(a.metadata().clone())),
+            None => Ok(self.metadata.read().unwrap().clone()),
+        }
+    }
+
+    pub fn set_metadata(&self, actuator_id: Option<u32>, key: String,
+                        value: Option<String>) -> Result<()> {
+        match actuator_id {
+            Some(actuator_id) => self.write_actuator(actuator_id, |a| a.set_metadata(key, value)),
+            None => metadata::set(&mut self.metadata.write().unwrap(), key, value),
+        }
+    }
+
+    // Apply func to all the members of group, if validate succeeds for all of them (see
+    // GroupResult). The members stay locked from the validation to the application, so that the
+    // validation still holds when applying.
+    fn write_group<V, F, T>(&self, group: &str, validate: V, func: F) -> Result<GroupResult<T>>
+    where
+        V: Fn(&Actuator) -> Result<()>,
+        F: Fn(&mut Actuator) -> Result<T>
+    {
+        let ids = self.groups.get(group).ok_or_else(|| GroupNotFound(group.to_string()))?;
+        // Locked in ID order, so that concurrent group operations cannot deadlock.
+        let mut members: Vec<_> = ids.iter()
+            .map(|id| (*id, self.actuators[*id as usize].write().unwrap()))
+            .collect();
+
+        let failures: BTreeMap<u32, Result<T>> = members.iter()
+            .filter_map(|&(id, ref actuator)| validate(&**actuator).err().map(|e| (id, Err(e))))
+            .collect();
+        if !failures.is_empty() {
+            return Ok(GroupResult { applied: false, results: failures })
+        }
+
+        let results = members.iter_mut()
+            .map(|&mut (id, ref mut actuator)| (id, func(&mut **actuator)))
+            .collect();
+        Ok(GroupResult { applied: true, results })
+    }
 }
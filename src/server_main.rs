@@ -1,64 +1,117 @@
-#![feature(plugin, use_extern_macros, proc_macro_path_invoc)]
-#![plugin(tarpc_plugins)]
+extern crate servoscheduler;
 
-#[macro_use]
+extern crate clap;
+extern crate env_logger;
+extern crate log;
 extern crate tarpc;
 
-#[macro_use]
-extern crate serde_derive;
-extern crate serde_yaml;
-
-// Only for FutureService
-// extern crate futures;
-// extern crate tokio_core;
-
-#[macro_use]
-extern crate bitflags;
-extern crate chrono;
-extern crate num;
-
-extern crate regex;
-
-// #[macro_use]
-// extern crate log;
-// extern crate env_logger;
-
-mod actuator;
-mod actuator_controller;
-mod rpc;
-mod rpc_server;
-mod schedule;
-mod server;
-mod time;
-mod time_slot;
-mod utils;
-
-use std::fs::File;
 use std::path::Path;
+use std::process;
 use std::result;
+use std::thread;
 
+use clap::{App, Arg};
 use tarpc::sync;
 
-use rpc::SyncServiceExt;
-use rpc_server::RpcServer;
-use server::Server;
+use servoscheduler::config;
+use servoscheduler::config::ConfigFormat;
+use servoscheduler::http_gateway;
+use servoscheduler::rpc::SyncServiceExt;
+use servoscheduler::rpc_server::RpcServer;
+use servoscheduler::server::Server;
+use servoscheduler::unix_transport;
 
 fn main() -> result::Result<(), String> {
-    let args: Vec<String> = std::env::args().collect();
+    let args = App::new("svsc_server")
+        .about("ServoScheduler server")
+        .arg(Arg::with_name("config-file")
+            .required(true)
+            .help("Config file, or directory of config files to merge")
+        ).arg(Arg::with_name("config-format")
+            .takes_value(true)
+            .possible_values(&["yaml", "toml", "json"])
+            .long("--config-format")
+            .help("Format of the config file (default: guessed from the extension, or YAML). \
+                   Included files and files in a directory are always guessed.")
+        ).arg(Arg::with_name("check")
+            .long("--check")
+            .help("Only validate the config file, and exit")
+        ).arg(Arg::with_name("check-hardware")
+            .long("--check-hardware")
+            .requires("check")
+            .help("Also check that the controller files can be opened (read-only) and that the \
+                   addresses to listen on resolve")
+        ).get_matches();
+
+    let config_path = Path::new(args.value_of("config-file").unwrap());
+    let config_format = match args.value_of("config-format") {
+        Some(format) => Some(format.parse::<ConfigFormat>()?),
+        None => None,
+    };
+
+    // The logger itself lets everything through, the level is only controlled via
+    // log::set_max_level(), so that it can be changed at runtime (see Server::set_log_level()).
+    env_logger::Builder::new()
+        .filter(None, log::LevelFilter::Trace)
+        .init();
+    log::set_max_level(log::LevelFilter::Info);
 
-    if args.len() != 2 {
-        return Err(format!("Usage: {} config_file.yaml", args[0]))
+    let config = config::load(config_path, config_format)?;
+
+    if args.is_present("check") {
+        // Nothing is started: no thread, no listening socket, and controllers are at most opened
+        // read-only.
+        let errors = config.validate(args.is_present("check-hardware"));
+        if errors.is_empty() {
+            println!("{}: OK ({} actuators, {} groups, {} templates)", config_path.display(),
+                     config.actuators.len(), config.groups.len(), config.templates.len());
+            return Ok(())
+        }
+
+        for error in errors.iter() {
+            eprintln!("{}", error);
+        }
+        eprintln!("{}: {} problem(s) found", config_path.display(), errors.len());
+        process::exit(1);
     }
 
-    let config_file = File::open(Path::new(&args[1]))
-        .map_err(|e| format!("Failed to open config file: {}", e))?;
-    let server = Server::new(config_file)
+    // Both are consumed by Server::new().
+    let unix_socket = match config.unix_socket {
+        Some(ref unix_socket) => Some((unix_socket.path.clone(), unix_socket.mode()?)),
+        None => None,
+    };
+    let rpc_address = match (config.rpc_address.clone(), &unix_socket) {
+        (Some(address), _) => Some(address),
+        (None, &None) => Some("localhost:4242".to_string()),
+        (None, &Some(_)) => None,
+    };
+    let http_address = config.http_address.clone();
+
+    let server = Server::new(config)
         .map_err(|e| format!("Failed to create server: {}", e))?;
 
     let rpc_server = RpcServer::new(server);
 
-    let handle = rpc_server.listen("localhost:4242", sync::server::Options::default())
-        .unwrap();
-    handle.run();
+    if let Some((path, mode)) = unix_socket {
+        unix_transport::listen(rpc_server.clone(), Path::new(&path), mode)
+            .map_err(|e| format!("Failed to listen on {}: {}", path, e))?;
+    }
+
+    if let Some(address) = http_address {
+        http_gateway::spawn_server(&address, rpc_server.server.clone())
+            .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    }
+
+    match rpc_address {
+        Some(address) => {
+            let handle = rpc_server.listen(address.as_str(), sync::server::Options::default())
+                .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+            handle.run();
+        },
+        // Everything happens in the Unix socket threads.
+        None => loop {
+            thread::park();
+        },
+    }
     Ok(())
 }
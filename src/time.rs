@@ -1,16 +1,18 @@
 use std::cmp::Ordering;
 use std::fmt;
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
 use std::result;
 use std::str;
+use std::sync::Arc;
 
 use chrono;
 use chrono::{Datelike, Timelike};
 use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use utils::*;
 
-#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Date {
     // Use chrono's representation, because it makes it much easier to manipulate the date and
     // provides fast access to metadata (like weekday).
@@ -22,12 +24,6 @@ impl Date {
     // Use valid values because it's much easier to handle (no need to special-case).
     pub const MIN: Date = Date { chrono_date: chrono::naive::MIN_DATE };
     pub const MAX: Date = Date { chrono_date: chrono::naive::MAX_DATE };
-    // This is rather arbitrary. Ideally it would be an invalid value, but chrono does not allow
-    // this. It also cannot be implemented as a const member, because ::from_yo() is not a constant
-    // function.
-    pub fn empty_date() -> Date {
-        Date::from(chrono::NaiveDate::from_yo(1, 1))
-    }
 
     pub fn from_ymd(year: i32, month: u32, day: u32) -> Option<Date> {
         chrono::NaiveDate::from_ymd_opt(year, month, day).map(|cd| Date::from(cd))
@@ -46,14 +42,33 @@ impl Date {
     }
 
     pub fn weekday(&self) -> WeekdaySet {
-        let idx = self.chrono_date.weekday().num_days_from_monday();
-        WeekdaySet::from_bits(1 << idx).unwrap()
+        WeekdaySet::from_bits(1 << self.weekday_index()).unwrap()
+    }
+
+    // Day number, 1 for 01/01/0001 (and consecutive for consecutive days).
+    pub fn num_days_from_ce(&self) -> i32 {
+        self.chrono_date.num_days_from_ce()
+    }
+
+    // 0 for Monday, 6 for Sunday.
+    pub fn weekday_index(&self) -> usize {
+        self.chrono_date.weekday().num_days_from_monday() as usize
     }
 
     pub fn chrono_date(&self) -> chrono::NaiveDate {
         self.chrono_date
     }
 
+    // Same as self + days, None if the result is out of range instead of panicking.
+    pub fn checked_add(&self, days: i64) -> Option<Date> {
+        // Far beyond the range of dates, and within that of chrono::Duration::days().
+        const MAX_DAYS: i64 = 1 << 32;
+        if days < -MAX_DAYS || days > MAX_DAYS {
+            return None
+        }
+        self.chrono_date.checked_add_signed(chrono::Duration::days(days)).map(Date::from)
+    }
+
     // Private to avoid misuses: this is the "real" today, not taking into account the hour shift
     // of Time.
     fn today_raw() -> Date {
@@ -67,9 +82,25 @@ impl From<chrono::NaiveDate> for Date {
     }
 }
 
+// Serialized as chrono's representation (YYYY-MM-DD), so that dates can be map keys in formats
+// where keys must be strings, like JSON (e.g. Schedule). The binary representation is the same as
+// if it were derived.
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        self.chrono_date.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        chrono::NaiveDate::deserialize(deserializer).map(Date::from)
+    }
+}
+
 impl ValidCheck for Date {
     fn valid(&self) -> bool {
-        *self != Date::empty_date()
+        // chrono guarantees that the date exists.
+        true
     }
 }
 
@@ -110,40 +141,85 @@ impl fmt::Display for Date {
     }
 }
 
+// Parse a number captured by one of the regexes of the FromStr implementations. They only match a
+// few ASCII digits (\d would match any Unicode digit, which Rust does not parse), so that this
+// should not fail, but an error is better than a panic if it does.
+fn parse_number<T: str::FromStr>(s: &str) -> result::Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number \"{}\"", s))
+}
+
 impl str::FromStr for Date {
-    type Err = ();
+    type Err = String;
 
+    // Accepts DD/MM[/YYYY] and ISO 8601 (YYYY-MM-DD). Years must be written in full.
     fn from_str(s: &str) -> result::Result<Self, Self::Err> {
-        let re = Regex::new(r"^(\d+)/(\d+)(?:/(\d+))?$").unwrap();
-        match re.captures(s) {
-            Some(caps) => Date::from_ymd(
-                {
-                    if let Some(year) = caps.get(3) {
-                        // We need to handle the error case, because although the regex validates that
-                        // the capture is an integer, it may not be representable as u8.
-                        i32::from_str(year.as_str()).or(Err(()))?
-                    } else {
-                        // Using the real date arguably makes more sense here.
-                        Date::today_raw().year()
-                    }
-                },
-                u32::from_str(&caps[2]).or(Err(()))?,
-                u32::from_str(&caps[1]).or(Err(()))?,
-            ).ok_or(()),
-            None => Err(())
+        let re = Regex::new(r"^([0-9]{1,2})/([0-9]{1,2})(?:/([0-9]{4}))?$").unwrap();
+        let iso_re = Regex::new(r"^([0-9]{4})-([0-9]{1,2})-([0-9]{1,2})$").unwrap();
+
+        let (year, month, day) = if let Some(caps) = re.captures(s) {
+            let year = if let Some(year) = caps.get(3) {
+                parse_number(year.as_str())?
+            } else {
+                // Using the real date arguably makes more sense here.
+                Date::today_raw().year()
+            };
+
+            (year, parse_number(&caps[2])?, parse_number(&caps[1])?)
+        } else if let Some(caps) = iso_re.captures(s) {
+            (parse_number(&caps[1])?, parse_number(&caps[2])?, parse_number(&caps[3])?)
+        } else {
+            return Err("expected DD/MM[/YYYY] or YYYY-MM-DD".to_string())
+        };
+
+        if month < 1 || month > 12 {
+            return Err(format!("invalid month {} (expected 1-12)", month))
         }
+        Date::from_ymd(year, month, day)
+            .ok_or_else(|| format!("invalid day {} (not in month {} of {})", day, month, year))
     }
 }
 
+// As everywhere else, DateRange methods expect a range of valid dates. An inverted range (end
+// before start) contains no day at all.
 impl DateRange {
-    // Must be a range of valid dates.
+    // The len_days days starting on start (none if len_days is not positive).
+    pub fn starting_on(start: Date, len_days: i64) -> DateRange {
+        DateRange { start, end: start + (len_days - 1) }
+    }
+
+    pub fn len_days(&self) -> i64 {
+        let num_day_diff = self.end.chrono_date.signed_duration_since(self.start.chrono_date)
+            .num_days();
+        if num_day_diff < 0 { 0 } else { num_day_diff + 1 }
+    }
+
+    // The dates of the range, in order.
+    pub fn iter_days(&self) -> DateRangeDays {
+        DateRangeDays {
+            next: if self.start <= self.end { Some(self.start) } else { None },
+            end: self.end,
+        }
+    }
+
+    // Whether any date of the range falls on one of days.
+    pub fn contains_weekday(&self, days: WeekdaySet) -> bool {
+        !(self.weekday_set() & days).is_empty()
+    }
+
     pub fn weekday_set(&self) -> WeekdaySet {
         let start_day = self.start.chrono_date.weekday().num_days_from_monday();
-        let num_day_diff = self.end.chrono_date.signed_duration_since(self.start.chrono_date).num_days() as u32;
+        let len_days = self.len_days();
+
+        if len_days == 0 {
+            return WeekdaySet::empty()
+        } else if len_days >= 7 {
+            return WeekdaySet::all()
+        }
 
-        if num_day_diff >= 6 {
-            WeekdaySet::all()
-        } else if start_day + num_day_diff <= 6 {
+        // Known to be in [0, 5], casting a huge difference would have wrapped around.
+        let num_day_diff = (len_days - 1) as u32;
+
+        if start_day + num_day_diff <= 6 {
             // No wrapping around, the end weekday index is greater than the start.
             WeekdaySet::from_bits(bit_range::<u8>(start_day, start_day + num_day_diff)).unwrap()
         } else {
@@ -156,6 +232,22 @@ impl DateRange {
     }
 }
 
+// See DateRange::iter_days(). Never goes past the end date, which may be Date::MAX.
+pub struct DateRangeDays {
+    next: Option<Date>,
+    end: Date,
+}
+
+impl Iterator for DateRangeDays {
+    type Item = Date;
+
+    fn next(&mut self) -> Option<Date> {
+        let date = self.next?;
+        self.next = if date < self.end { Some(date + 1) } else { None };
+        Some(date)
+    }
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct Time {
     pub hour: u8,
@@ -168,23 +260,23 @@ impl Time {
     pub const DAY_START_HOUR: u8 = 4;
     pub const MIN: Time = Time { hour: Self::DAY_START_HOUR, minute: 0 };
     pub const MAX: Time = Time { hour: (Self::DAY_START_HOUR - 1) % 24, minute: 59 };
-    pub const EMPTY: Time = Time { hour: 25, minute: 0 };
 
     pub fn now() -> Time {
         Time::from(chrono::offset::Local::now().time())
     }
 
-    pub fn sub_minute(&self, rhs: Time) -> i32 {
-        // TODO: do something about DST...
-        self.minute_since_start() - rhs.minute_since_start()
-    }
+    pub const MINUTES_PER_DAY: i32 = 24 * 60;
 
-    fn shifted_hour(&self) -> u8 {
-        (self.hour + 24 - Self::DAY_START_HOUR) % 24
+    // Number of minutes from self to other, within the same (shifted) day: negative if other is
+    // before self.
+    pub fn minutes_until(&self, other: Time) -> i32 {
+        DayStart::default().minutes_until(*self, other)
     }
 
-    fn minute_since_start(&self) -> i32 {
-        (self.shifted_hour() as i32) * 60 + (self.minute as i32)
+    // Add (or subtract if negative) minutes, wrapping around at the start of the (shifted) day.
+    // Also returns by how many days the result was carried over (e.g. 1 if it is on the next day).
+    pub fn add_minutes(self, minutes: i32) -> (Time, i32) {
+        DayStart::default().add_minutes(self, minutes)
     }
 }
 
@@ -205,19 +297,13 @@ impl ValidCheck for Time {
 
 impl PartialOrd for Time {
     fn partial_cmp(&self, other: &Time) -> Option<Ordering> {
-        match self.shifted_hour().partial_cmp(&other.shifted_hour()) {
-            Some(Ordering::Equal) => self.minute.partial_cmp(&other.minute),
-            r => r
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Time {
     fn cmp(&self, other: &Time) -> Ordering {
-        match self.shifted_hour().cmp(&other.shifted_hour()) {
-            Ordering::Equal => self.minute.cmp(&other.minute),
-            r => r
-        }
+        DayStart::default().cmp(*self, *other)
     }
 }
 
@@ -227,28 +313,328 @@ impl fmt::Display for Time {
     }
 }
 
+impl TimeInterval {
+    // An interval ending at Time::MAX lasts until the end of the day, i.e. the start of the next
+    // day, and not until Time::MAX itself (consistently with the actuator thread).
+    pub fn duration(&self) -> Duration {
+        DayStart::default().duration(self)
+    }
+
+    // Move the interval by minutes (earlier if negative), or None if it would then cross the start
+    // of the day. The end of the day (Time::MAX) is moved the same way as any other end time.
+    pub fn shift(&self, minutes: i32) -> Option<TimeInterval> {
+        DayStart::default().shift(self, minutes)
+    }
+}
+
+// The hour at which the days of an actuator start. It defines how times are ordered, and so which
+// time intervals are valid: an interval cannot cross the start of the day. With the default
+// (Time::DAY_START_HOUR), 22:00-02:00 is a valid interval, late on a given day; with days starting
+// at midnight, it is not and must be split into 22:00-23:59 (which lasts until the end of the day)
+// and 00:00-02:00 on the following days.
+// Times between midnight and the start of the day belong to the previous date. Dates and times
+// are always given with the default days (e.g. by DateTime::now() or a Clock), see date_time() to
+// convert them.
+// Time's own order, Time::MIN/MAX and the methods of Time and TimeInterval correspond to the
+// default.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct DayStart {
+    pub hour: u8,
+}
+
+impl Default for DayStart {
+    fn default() -> Self {
+        DayStart { hour: Time::DAY_START_HOUR }
+    }
+}
+
+// The time at which the day starts, e.g. 04:00.
+impl fmt::Display for DayStart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.first())
+    }
+}
+
+impl DayStart {
+    pub const MIDNIGHT: DayStart = DayStart { hour: 0 };
+
+    // The first and last minute of the day (equivalent to Time::MIN and Time::MAX).
+    pub fn first(&self) -> Time {
+        Time { hour: self.hour, minute: 0 }
+    }
+
+    pub fn last(&self) -> Time {
+        Time { hour: (self.hour + 23) % 24, minute: 59 }
+    }
+
+    // Number of minutes from the start of the day to time.
+    pub fn minute_of_day(&self, time: Time) -> i32 {
+        ((time.hour + 24 - self.hour) % 24) as i32 * 60 + time.minute as i32
+    }
+
+    pub fn cmp(&self, a: Time, b: Time) -> Ordering {
+        self.minute_of_day(a).cmp(&self.minute_of_day(b))
+    }
+
+    // Whether a is strictly before b.
+    pub fn is_before(&self, a: Time, b: Time) -> bool {
+        self.minute_of_day(a) < self.minute_of_day(b)
+    }
+
+    // Same as Time::minutes_until().
+    pub fn minutes_until(&self, from: Time, to: Time) -> i32 {
+        // TODO: do something about DST...
+        self.minute_of_day(to) - self.minute_of_day(from)
+    }
+
+    // Same as Time::add_minutes().
+    pub fn add_minutes(&self, time: Time, minutes: i32) -> (Time, i32) {
+        let total = self.minute_of_day(time) + minutes;
+        // Floor division, so that the remainder is always positive.
+        let mut day_carry = total / Time::MINUTES_PER_DAY;
+        if total < day_carry * Time::MINUTES_PER_DAY {
+            day_carry -= 1;
+        }
+        let minute_of_day = total - day_carry * Time::MINUTES_PER_DAY;
+
+        let time = Time {
+            hour: ((minute_of_day / 60) as u8 + self.hour) % 24,
+            minute: (minute_of_day % 60) as u8,
+        };
+        (time, day_carry)
+    }
+
+    // The interval in minutes from the start of the day, so that ExclusiveRange's operations
+    // follow this order.
+    fn minutes(&self, time_interval: &TimeInterval) -> ExclusiveRange<i32> {
+        ExclusiveRange {
+            start: self.minute_of_day(time_interval.start),
+            end: self.minute_of_day(time_interval.end),
+        }
+    }
+
+    // Whether time_interval is valid, i.e. does not cross the start of the day.
+    pub fn valid(&self, time_interval: &TimeInterval) -> bool {
+        let minutes = self.minutes(time_interval);
+        time_interval.start.valid() && time_interval.end.valid() && minutes.start < minutes.end
+    }
+
+    pub fn contains(&self, time_interval: &TimeInterval, time: Time) -> bool {
+        self.minutes(time_interval).contains(&self.minute_of_day(time))
+    }
+
+    pub fn overlaps(&self, a: &TimeInterval, b: &TimeInterval) -> bool {
+        self.minutes(a).overlaps(&self.minutes(b))
+    }
+
+    pub fn intersection(&self, a: &TimeInterval, b: &TimeInterval) -> Option<TimeInterval> {
+        let intersection = self.minutes(a).intersection(&self.minutes(b))?;
+        Some(TimeInterval {
+            start: self.add_minutes(self.first(), intersection.start).0,
+            end: self.add_minutes(self.first(), intersection.end).0,
+        })
+    }
+
+    // Same as TimeInterval::duration().
+    pub fn duration(&self, time_interval: &TimeInterval) -> Duration {
+        let end_adjust = if time_interval.end == self.last() { 1 } else { 0 };
+        Duration::minutes(self.minutes_until(time_interval.start, time_interval.end) + end_adjust)
+    }
+
+    // The parts of time_interval not covered by any of the intervals in busy, in order.
+    pub fn subtract(&self, time_interval: &TimeInterval,
+                    busy: &[TimeInterval]) -> Vec<TimeInterval> {
+        let mut busy: Vec<ExclusiveRange<i32>> = busy.iter().map(|ti| self.minutes(ti)).collect();
+        busy.sort_by_key(|range| range.start);
+
+        let range = self.minutes(time_interval);
+        let mut free = Vec::new();
+        let mut start = range.start;
+        for busy_range in busy {
+            if busy_range.start > start {
+                free.push((start, busy_range.start.min(range.end)));
+            }
+            start = start.max(busy_range.end);
+        }
+        free.push((start, range.end));
+
+        free.into_iter()
+            .filter(|&(start, end)| start < end)
+            .map(|(start, end)| TimeInterval {
+                start: self.add_minutes(self.first(), start).0,
+                end: self.add_minutes(self.first(), end).0,
+            })
+            .collect()
+    }
+
+    // Same as TimeInterval::shift().
+    pub fn shift(&self, time_interval: &TimeInterval, minutes: i32) -> Option<TimeInterval> {
+        let end_adjust = if time_interval.end == self.last() { 1 } else { 0 };
+        let (start, start_carry) = self.add_minutes(time_interval.start, minutes);
+        let (end, end_carry) = self.add_minutes(time_interval.end, minutes + end_adjust);
+
+        match (start_carry, end_carry) {
+            (0, 0) => Some(TimeInterval { start, end }),
+            // Now ending exactly at the end of the day.
+            (0, 1) if end == self.first() => Some(TimeInterval { start, end: self.last() }),
+            _ => None,
+        }
+    }
+
+    // Convert dt, with days starting at from, to days starting at self: only the date may change.
+    pub fn date_time(&self, dt: &DateTime, from: DayStart) -> DateTime {
+        let after = |day_start: DayStart| if dt.time.hour < day_start.hour { 0 } else { 1 };
+        DateTime {
+            date: dt.date + (after(*self) - after(from)) as i64,
+            time: dt.time,
+        }
+    }
+
+    // Same as DateTime::minutes_since(), with both dates and times relative to these days.
+    pub fn minutes_since(&self, dt: &DateTime, other: &DateTime) -> i64 {
+        let day_diff = dt.date.chrono_date.signed_duration_since(other.date.chrono_date)
+            .num_days();
+        day_diff * Time::MINUTES_PER_DAY as i64 + self.minutes_until(other.time, dt.time) as i64
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Duration {
+    minutes: i32,
+}
+
+impl Duration {
+    pub fn minutes(minutes: i32) -> Duration {
+        Duration { minutes }
+    }
+
+    pub fn num_minutes(&self) -> i32 {
+        self.minutes
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::minutes(self.minutes + rhs.minutes)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.minutes += rhs.minutes;
+    }
+}
+
+impl Mul<i32> for Duration {
+    type Output = Duration;
+
+    fn mul(self, rhs: i32) -> Duration {
+        Duration::minutes(self.minutes * rhs)
+    }
+}
+
+// Formatted as e.g. 2h30, 2h or 45min.
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.minutes < 0 { "-" } else { "" };
+        let (hours, minutes) = (self.minutes.abs() / 60, self.minutes.abs() % 60);
+
+        match (hours, minutes) {
+            (0, m) => write!(f, "{}{}min", sign, m),
+            (h, 0) => write!(f, "{}{}h", sign, h),
+            (h, m) => write!(f, "{}{}h{:02}", sign, h, m),
+        }
+    }
+}
+
+impl str::FromStr for Time {
+    type Err = String;
+
+    // Only valid times are accepted.
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let re = Regex::new(r"^([0-9]{1,2}):([0-9]{2})$").unwrap();
+        let caps = re.captures(s).ok_or_else(|| format!("expected hh:mm, got \"{}\"", s))?;
+        let time = Time {
+            hour: parse_number(&caps[1])?,
+            minute: parse_number(&caps[2])?,
+        };
+
+        if time.hour >= 24 {
+            Err(format!("invalid hour {} in {} (expected 0-23)", time.hour, s))
+        } else if time.minute >= 60 {
+            Err(format!("invalid minute {} in {} (expected 0-59)", time.minute, s))
+        } else {
+            Ok(time)
+        }
+    }
+}
+
+impl str::FromStr for Duration {
+    type Err = String;
+
+    // As displayed (e.g. 2h30, 2h or 45min), also accepting 2h30m and 45m. Minutes only follow
+    // hours if they are less than 60.
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let error = || format!("expected e.g. 2h30, 2h or 45min, got \"{}\"", s);
+        let re = Regex::new(r"^(?:([0-9]{1,3})h)?(?:([0-9]{1,4})(m|min)?)?$").unwrap();
+        let caps = re.captures(s).ok_or_else(&error)?;
+        // Minutes need a unit, unless they follow hours (which also rules out an empty string).
+        if caps.get(1).is_none() && caps.get(3).is_none() {
+            return Err(error())
+        }
+        let hours: i32 = caps.get(1).map_or(Ok(0), |h| parse_number(h.as_str()))?;
+        let minutes: i32 = caps.get(2).map_or(Ok(0), |m| parse_number(m.as_str()))?;
+
+        if caps.get(1).is_some() && minutes >= 60 {
+            return Err(format!("invalid minutes {} in {} (expected 0-59)", minutes, s))
+        }
+        Ok(Duration::minutes(hours * 60 + minutes))
+    }
+}
+
 impl str::FromStr for TimeInterval {
-    type Err = ();
+    type Err = String;
 
+    // Either start-end (hh:mm-hh:mm) or start+duration (e.g. 18:00+2h30, see Duration), the end
+    // then wrapping around midnight if needed. Both times must be valid, and different: the
+    // duration must be less than a day. Whether the interval crosses the start of the day depends
+    // on the actuator, it is not checked here (see DayStart::valid()).
     fn from_str(s: &str) -> result::Result<Self, Self::Err> {
-        let re = Regex::new(r"^(\d+):(\d+)-(\d+):(\d+)$").unwrap();
-        match re.captures(s) {
-            Some(caps) => Ok(TimeInterval {
-                start: Time {
-                    hour: u8::from_str(&caps[1]).unwrap(),
-                    minute: u8::from_str(&caps[2]).unwrap(),
-                },
-                end: Time {
-                    hour: u8::from_str(&caps[3]).unwrap(),
-                    minute: u8::from_str(&caps[4]).unwrap(),
-                }
-            }),
-            None => Err(())
+        if let Some(pos) = s.find('+') {
+            let start = Time::from_str(&s[..pos]).map_err(|e| format!("start: {}", e))?;
+            let duration = Duration::from_str(&s[pos + 1..])
+                .map_err(|e| format!("duration: {}", e))?;
+            let minutes = duration.num_minutes();
+            if minutes <= 0 || minutes >= Time::MINUTES_PER_DAY {
+                return Err(format!("invalid duration {} (expected more than 0 and less than \
+                                    24h)", duration))
+            }
+
+            return Ok(TimeInterval { start, end: start.add_minutes(minutes).0 })
+        }
+
+        let times: Vec<&str> = s.split('-').collect();
+        if times.len() != 2 {
+            return Err(format!("expected hh:mm-hh:mm or hh:mm+duration, got \"{}\"", s))
+        }
+
+        let start = Time::from_str(times[0]).map_err(|e| format!("start: {}", e))?;
+        let end = Time::from_str(times[1]).map_err(|e| format!("end: {}", e))?;
+        if start == end {
+            return Err(format!("empty time interval (starts and ends at {})", start))
         }
+
+        Ok(TimeInterval { start, end })
     }
 }
 
-#[derive(Clone, Debug)]
+// A point in time, where date is the day as shifted by Time::DAY_START_HOUR (for actuators with
+// another DayStart, relative to it, see DayStart::date_time()): 01:00 belongs to the previous day,
+// i.e. 15/10/2018 01:00 is during the night from the 15th to the 16th. This is also how it is
+// printed and parsed, as DD/MM/YYYY hh:mm (or YYYY-MM-DDThh:mm).
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct DateTime {
     pub date: Date,
     pub time: Time,
@@ -256,6 +642,11 @@ pub struct DateTime {
 
 impl DateTime {
     pub fn now() -> DateTime {
+        DateTime::now_precise().0
+    }
+
+    // Same as now(), also returning the seconds elapsed within the current minute.
+    pub fn now_precise() -> (DateTime, u32) {
         let chrono_now = chrono::offset::Local::now();
         let time = Time::from(chrono_now.time());
 
@@ -265,10 +656,95 @@ impl DateTime {
         let date = Date::from(chrono_now.date().naive_local()
                               + chrono::Duration::days(day_offset));
 
-        DateTime {
-            date,
-            time,
+        (DateTime { date, time }, chrono_now.time().second())
+    }
+}
+
+impl DateTime {
+    // Number of minutes elapsed from other to self (negative if self is before other).
+    pub fn minutes_since(&self, other: &DateTime) -> i64 {
+        DayStart::default().minutes_since(self, other)
+    }
+
+    // Add (or subtract if negative) minutes, moving to another day if needed.
+    pub fn add_minutes(&self, minutes: i32) -> DateTime {
+        let (time, day_carry) = self.time.add_minutes(minutes);
+        DateTime { date: self.date + day_carry as i64, time }
+    }
+}
+
+impl Add<Duration> for DateTime {
+    type Output = DateTime;
+
+    fn add(self, rhs: Duration) -> DateTime {
+        self.add_minutes(rhs.num_minutes())
+    }
+}
+
+impl PartialOrd for DateTime {
+    fn partial_cmp(&self, other: &DateTime) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Consistent with Time's order (and so only meaningful with the default DayStart).
+impl Ord for DateTime {
+    fn cmp(&self, other: &DateTime) -> Ordering {
+        (self.date, self.time).cmp(&(other.date, other.time))
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.date, self.time)
+    }
+}
+
+impl str::FromStr for DateTime {
+    type Err = String;
+
+    // A date and a time separated by a space or T, each as accepted by their own from_str().
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.splitn(2, |c| c == ' ' || c == 'T').collect();
+        if fields.len() != 2 {
+            return Err(format!("expected DD/MM/YYYY hh:mm or YYYY-MM-DDThh:mm, got \"{}\"", s))
         }
+
+        Ok(DateTime {
+            date: Date::from_str(fields[0])?,
+            time: Time::from_str(fields[1].trim_left())?,
+        })
+    }
+}
+
+// Source of the current date and time. The actuator logic only gets the time from a Clock, so
+// that it can be driven by something else than the system clock.
+pub trait Clock {
+    fn now(&self) -> DateTime;
+
+    // Same as DateTime::now_precise(). Clocks that only have a minute resolution can rely on the
+    // default, as if it was always the start of the minute.
+    fn now_precise(&self) -> (DateTime, u32) {
+        (self.now(), 0)
+    }
+}
+pub type ClockHandle = Arc<Clock + Send + Sync>;
+
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn new() -> ClockHandle {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime {
+        DateTime::now()
+    }
+
+    fn now_precise(&self) -> (DateTime, u32) {
+        DateTime::now_precise()
     }
 }
 
@@ -287,6 +763,100 @@ bitflags! {
 
 impl WeekdaySet {
     const TEXT_REPR: [char; 7] = ['M', 'T', 'W', 'T', 'F', 'S' ,'S'];
+    const NAMES: [&'static str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+    const FULL_NAMES: [&'static str; 7] =
+        ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+    // Number of days in the set.
+    pub fn len(&self) -> i32 {
+        self.bits().count_ones() as i32
+    }
+
+    // Comma-separated list of the days, e.g. mon,sat (as accepted by from_str()).
+    pub fn names(&self) -> String {
+        let names: Vec<&str> = (0..7)
+            .filter(|i| self.bits() & (1 << i) != 0)
+            .map(|i| Self::NAMES[i])
+            .collect();
+        names.join(",")
+    }
+
+    // Positional mask, e.g. M----S-.
+    fn from_mask(s: &str) -> Option<WeekdaySet> {
+        if s.len() != 7 {
+            return None
+        }
+
+        let mut day_bits = 0;
+        for (i, c) in s.char_indices() {
+            if c == Self::TEXT_REPR[i] {
+                day_bits |= 1 << i;
+            } else if c != '-' {
+                return None
+            }
+        }
+
+        WeekdaySet::from_bits(day_bits)
+    }
+
+    // Comma-separated list of days (see day_index()), ranges of days (e.g. mon-fri, or fri-mon
+    // for Friday to Monday) and shorthands: weekdays (mon-fri), weekend (sat-sun) and all. Case
+    // does not matter. The error names the invalid element.
+    fn from_list(s: &str) -> result::Result<WeekdaySet, String> {
+        let mut set = WeekdaySet::empty();
+
+        for element in s.split(',') {
+            let element = element.trim();
+            set |= match element.to_lowercase().as_str() {
+                "all" => WeekdaySet::all(),
+                "weekdays" => WeekdaySet::all() - WeekdaySet::SATURDAY - WeekdaySet::SUNDAY,
+                "weekend" => WeekdaySet::SATURDAY | WeekdaySet::SUNDAY,
+                _ => Self::from_range(element)?,
+            };
+        }
+
+        Ok(set)
+    }
+
+    // A single day, or a range of days (wrapping around after Sunday).
+    fn from_range(s: &str) -> result::Result<WeekdaySet, String> {
+        let bounds: Vec<&str> = s.split('-').collect();
+        let (first, last) = match bounds.len() {
+            1 => (Self::day_index(s)?, Self::day_index(s)?),
+            2 => (Self::day_index(bounds[0])?, Self::day_index(bounds[1])?),
+            _ => return Err(format!("invalid weekday range \"{}\"", s)),
+        };
+
+        let mut set = WeekdaySet::empty();
+        let mut i = first;
+        loop {
+            set |= WeekdaySet::from_bits(1 << i).unwrap();
+            if i == last {
+                break
+            }
+            i = (i + 1) % 7;
+        }
+
+        Ok(set)
+    }
+
+    // Index (from Monday) of a day name, full or abbreviated to any unambiguous prefix, e.g. mon,
+    // tues or thu.
+    fn day_index(name: &str) -> result::Result<usize, String> {
+        let lower_name = name.to_lowercase();
+        let matches: Vec<usize> = (0..7)
+            .filter(|&i| !lower_name.is_empty() && Self::FULL_NAMES[i].starts_with(&lower_name))
+            .collect();
+
+        match matches.len() {
+            0 => Err(format!("unknown weekday \"{}\"", name)),
+            1 => Ok(matches[0]),
+            _ => {
+                let candidates: Vec<&str> = matches.iter().map(|&i| Self::NAMES[i]).collect();
+                Err(format!("ambiguous weekday \"{}\" ({}?)", name, candidates.join(" or ")))
+            },
+        }
+    }
 }
 
 impl fmt::Display for WeekdaySet {
@@ -305,24 +875,320 @@ impl fmt::Display for WeekdaySet {
     }
 }
 
+// Either a mask (M----S-) or a list of days (mon,sat or mon-fri, see from_list()). A "!" or "not "
+// prefix selects all the other days, e.g. "!sun" is every day except Sunday.
 impl str::FromStr for WeekdaySet {
-    type Err = ();
+    type Err = String;
 
     fn from_str(s: &str) -> result::Result<Self, Self::Err> {
-        if s.len() != 7 {
-            return Err(())
+        let (inverted, s) = if s.starts_with('!') {
+            (true, &s[1..])
+        } else if s.to_lowercase().starts_with("not ") {
+            (true, s[4..].trim_left())
+        } else {
+            (false, s)
+        };
+
+        let set = match Self::from_mask(s) {
+            Some(set) => set,
+            None => Self::from_list(s)?,
+        };
+
+        Ok(if inverted { WeekdaySet::all() - set } else { set })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date::from_ymd(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn checked_add() {
+        assert_eq!(date(2018, 1, 1).checked_add(-1), Some(date(2017, 12, 31)));
+        assert_eq!(date(2018, 1, 1).checked_add(365), Some(date(2019, 1, 1)));
+        assert_eq!(Date::MAX.checked_add(1), None);
+        assert_eq!(Date::MIN.checked_add(-1), None);
+        assert_eq!(date(2018, 1, 1).checked_add(99999999999), None);
+        assert_eq!(date(2018, 1, 1).checked_add(i64::min_value()), None);
+    }
+
+    #[test]
+    fn parse_date() {
+        let valid = [
+            ("01/02/2018", (2018, 2, 1)),
+            ("1/2/2018", (2018, 2, 1)),
+            ("29/02/2020", (2020, 2, 29)),
+            ("2018-02-01", (2018, 2, 1)),
+            ("2018-2-1", (2018, 2, 1)),
+            ("2020-02-29", (2020, 2, 29)),
+        ];
+        for &(s, (year, month, day)) in valid.iter() {
+            assert_eq!(s.parse(), Ok(date(year, month, day)), "{}", s);
         }
+        // Without a year, the current one.
+        assert_eq!("01/02".parse::<Date>().map(|d| (d.month(), d.day())), Ok((2, 1)));
 
-        let mut day_bits = 0;
-        for (i, c) in s.char_indices() {
-            if c == Self::TEXT_REPR[i] {
-                day_bits |= 1 << i;
-            } else if c != '-' {
-                return Err(())
+        let invalid = [
+            "31/02/2018", "29/02/2019", "2019-02-29", "00/01/2018", "01/13/2018", "2018-13-01",
+            // Two-digit years are not guessed.
+            "01/02/18", "18-02-01",
+            "", "-", "none", "1/2/3/4", "123/1", "01-02-2018", "2018/02/01", " 01/02/2018",
+        ];
+        for s in invalid.iter() {
+            assert!(s.parse::<Date>().is_err(), "{}", s);
+        }
+
+        // What is displayed can be parsed back.
+        let d = date(2018, 3, 4);
+        assert_eq!(d.to_string().parse(), Ok(d));
+    }
+
+    #[test]
+    fn weekday_set() {
+        // 01/01/2018 is a Monday.
+        for start_day in 0..7 {
+            let start = date(2018, 1, 1) + start_day;
+
+            for len_days in 0..9 {
+                let range = DateRange::starting_on(start, len_days);
+                // The weekdays the range actually covers.
+                let expected = range.iter_days()
+                    .fold(WeekdaySet::empty(), |set, date| set | date.weekday());
+
+                assert_eq!(range.weekday_set(), expected, "{} days from {}", len_days, start);
+                assert_eq!(range.weekday_set().len(), ::std::cmp::min(len_days, 7) as i32);
             }
         }
 
-        Ok(WeekdaySet::from_bits(day_bits).unwrap())
+        // Exactly 6 days starting mid-week: all days but the day before the start.
+        let range = DateRange { start: date(2018, 1, 3), end: date(2018, 1, 8) };
+        assert_eq!(range.weekday_set(), WeekdaySet::all() - WeekdaySet::TUESDAY);
+
+        let inverted = DateRange { start: date(2018, 1, 8), end: date(2018, 1, 3) };
+        assert_eq!(inverted.weekday_set(), WeekdaySet::empty());
+        assert_eq!(DateRange { start: Date::MIN, end: Date::MAX }.weekday_set(), WeekdaySet::all());
     }
-}
 
+    fn time(s: &str) -> Time {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn time_arithmetic() {
+        // Within the shifted day (04:00 to 03:59).
+        assert_eq!(time("08:00").minutes_until(time("09:30")), 90);
+        assert_eq!(time("09:30").minutes_until(time("08:00")), -90);
+        assert_eq!(time("23:00").minutes_until(time("01:00")), 120);
+        assert_eq!(time("01:00").minutes_until(time("23:00")), -120);
+        assert_eq!(Time::MIN.minutes_until(Time::MAX), Time::MINUTES_PER_DAY - 1);
+        assert_eq!(time("12:00").minutes_until(time("12:00")), 0);
+
+        assert_eq!(time("08:00").add_minutes(90), (time("09:30"), 0));
+        assert_eq!(time("23:30").add_minutes(60), (time("00:30"), 0));
+        assert_eq!(time("03:30").add_minutes(60), (time("04:30"), 1));
+        assert_eq!(time("04:30").add_minutes(-60), (time("03:30"), -1));
+        assert_eq!(time("12:00").add_minutes(-Time::MINUTES_PER_DAY), (time("12:00"), -1));
+        assert_eq!(time("12:00").add_minutes(3 * Time::MINUTES_PER_DAY + 1), (time("12:01"), 3));
+        assert_eq!(Time::MAX.add_minutes(1), (Time::MIN, 1));
+
+        // Adding back the minutes until a time gives that time.
+        for &(from, to) in [("04:00", "03:59"), ("13:27", "05:12"), ("22:00", "21:59")].iter() {
+            let minutes = time(from).minutes_until(time(to));
+            assert_eq!(time(from).add_minutes(minutes), (time(to), 0));
+        }
+
+        let midnight = DayStart::MIDNIGHT;
+        assert_eq!(midnight.minutes_until(time("23:00"), time("01:00")), -22 * 60);
+        assert_eq!(midnight.add_minutes(time("23:30"), 60), (time("00:30"), 1));
+    }
+
+    #[test]
+    fn duration_display() {
+        let cases = [
+            (0, "0min"), (45, "45min"), (60, "1h"), (150, "2h30"), (61, "1h01"), (24 * 60, "24h"),
+            (-45, "-45min"), (-150, "-2h30"),
+        ];
+        for &(minutes, s) in cases.iter() {
+            assert_eq!(Duration::minutes(minutes).to_string(), s);
+            if minutes >= 0 {
+                assert_eq!(s.parse(), Ok(Duration::minutes(minutes)));
+            }
+        }
+
+        assert_eq!(Duration::minutes(90) + Duration::minutes(45), Duration::minutes(135));
+        assert_eq!(Duration::minutes(90) * 5, Duration::minutes(450));
+    }
+
+    #[test]
+    fn weekday_lists() {
+        let days = |mask: &str| WeekdaySet::from_mask(mask).unwrap();
+        let cases = [
+            ("mon", "M------"), ("Sunday", "------S"), ("TUES", "-T-----"), ("th", "---T---"),
+            ("mon-fri", "MTWTF--"), ("fri-mon", "M---FSS"), ("sun-mon", "M-----S"),
+            ("sat-sun", "-----SS"), ("wed-wed", "--W----"), ("tue-mon", "MTWTFSS"),
+            ("mon,sat", "M----S-"), (" mon , wed ", "M-W----"), ("fri-mon,wed", "M-W-FSS"),
+            ("weekdays", "MTWTF--"), ("Weekend", "-----SS"), ("ALL", "MTWTFSS"),
+            ("weekend,mon", "M----SS"), ("mon,mon-tue", "MT-----"),
+        ];
+        for &(list, mask) in cases.iter() {
+            assert_eq!(WeekdaySet::from_list(list), Ok(days(mask)), "{}", list);
+            assert_eq!(list.parse(), Ok(days(mask)), "{}", list);
+        }
+
+        // The error names the invalid element.
+        let errors = [
+            ("mon,xyz", "unknown weekday \"xyz\""),
+            ("", "unknown weekday \"\""),
+            ("mon,", "unknown weekday \"\""),
+            ("t", "ambiguous weekday \"t\" (tue or thu?)"),
+            ("mon-s", "ambiguous weekday \"s\" (sat or sun?)"),
+            ("mon-tue-wed", "invalid weekday range \"mon-tue-wed\""),
+            ("mon-", "unknown weekday \"\""),
+            ("MTWTFSS,mon", "unknown weekday \"MTWTFSS\""),
+        ];
+        for &(list, error) in errors.iter() {
+            assert_eq!(WeekdaySet::from_list(list), Err(error.to_string()), "{}", list);
+        }
+
+        // The positional form is still accepted, and is what is displayed.
+        assert_eq!("M----S-".parse(), Ok(days("M----S-")));
+        assert_eq!(WeekdaySet::from_range("fri-mon").unwrap().to_string(), "M---FSS");
+        assert_eq!("!fri-mon".parse(), Ok(days("-TWT---")));
+        assert_eq!("not weekend".parse(), Ok(days("MTWTF--")));
+    }
+
+    #[test]
+    fn garbage_not_parsed() {
+        // Other scripts' digits (Arabic-Indic, fullwidth) are not accepted as digits.
+        for s in ["١٢:٠٠", "１２:００", "12:٠٠", "٠١/٠٢", "01/02/٢٠١٨", "٢٠١٨-٠١-٠١", "١h", "٤٥min",
+                  "12:00-١٣:٠٠", "12:00+١h"].iter() {
+            assert!(s.parse::<Time>().is_err(), "{}", s);
+            assert!(s.parse::<Date>().is_err(), "{}", s);
+            assert!(s.parse::<Duration>().is_err(), "{}", s);
+            assert!(s.parse::<TimeInterval>().is_err(), "{}", s);
+            assert!(s.parse::<WeekdaySet>().is_err(), "{}", s);
+        }
+
+        // Random strings made of the characters of valid ones: whatever they give, nothing panics.
+        let alphabet: Vec<char> = "0123456789:/-+hmin,!MTWFS٠١٢٣٤٥٦٧٨٩ é".chars().collect();
+        let mut state = 0;
+        for _ in 0..2000 {
+            state = ::utils::hash_u64(state);
+            let len = (state % 16) as usize;
+            let s: String = (0..len).map(|i| {
+                alphabet[(::utils::hash_u64(state + i as u64) % alphabet.len() as u64) as usize]
+            }).collect();
+            let _ = s.parse::<Time>();
+            let _ = s.parse::<Date>();
+            let _ = s.parse::<Duration>();
+            let _ = s.parse::<TimeInterval>();
+            let _ = s.parse::<WeekdaySet>();
+            let _ = s.parse::<DateTime>();
+        }
+    }
+
+    #[test]
+    fn date_range_against_chrono() {
+        // Going through chrono only, one day at a time.
+        let chrono_days = |range: &DateRange| {
+            let mut days = Vec::new();
+            let mut day = range.start.chrono_date;
+            while day <= range.end.chrono_date {
+                days.push(day);
+                match day.succ_opt() {
+                    Some(next) => day = next,
+                    None => break,
+                }
+            }
+            days
+        };
+        let chrono_weekdays = |days: &[chrono::NaiveDate]| days.iter()
+            .map(|day| WeekdaySet::from_bits(1 << day.weekday().num_days_from_monday()).unwrap())
+            .fold(WeekdaySet::empty(), |set, weekday| set | weekday);
+
+        // Short ranges (including empty, inverted and within a weekend), around an arbitrary
+        // date and up to the last one.
+        let mut seed = 0;
+        let mut random = |n: u64| {
+            seed += 1;
+            hash_u64(seed) % n
+        };
+        let mut random_range = || {
+            let base = if random(4) == 0 { Date::MAX - 20 } else { date(2018, 1, 1) };
+            let start = base + random(21) as i64;
+            let end = start.checked_add(random(12) as i64 - 2).unwrap_or(Date::MAX);
+            DateRange { start, end }
+        };
+
+        for _ in 0..2000 {
+            let (range, other) = (random_range(), random_range());
+            let days = chrono_days(&range);
+
+            assert_eq!(range.iter_days().map(|date| date.chrono_date).collect::<Vec<_>>(), days,
+                       "{:?}", range);
+            assert_eq!(range.len_days(), days.len() as i64, "{:?}", range);
+            assert_eq!(range.weekday_set(), chrono_weekdays(&days), "{:?}", range);
+            for bits in 0..128 {
+                let weekdays = WeekdaySet::from_bits(bits).unwrap();
+                assert_eq!(range.contains_weekday(weekdays),
+                           !(chrono_weekdays(&days) & weekdays).is_empty(),
+                           "{:?} {}", range, weekdays);
+            }
+
+            let other_days = chrono_days(&other);
+            let common: Vec<chrono::NaiveDate> = days.iter()
+                .filter(|day| other_days.contains(day))
+                .cloned()
+                .collect();
+            let intersection = range.intersection(&other)
+                .map(|intersection| chrono_days(&intersection))
+                .unwrap_or_default();
+            assert_eq!(intersection, common, "{:?} {:?}", range, other);
+        }
+
+        // 05/01/2018 is a Friday: a range shorter than a week, across a weekend.
+        let range = DateRange { start: date(2018, 1, 5), end: date(2018, 1, 8) };
+        assert_eq!(range.len_days(), 4);
+        assert!(range.contains_weekday(WeekdaySet::SATURDAY | WeekdaySet::WEDNESDAY));
+        assert!(range.contains_weekday(WeekdaySet::MONDAY));
+        assert!(!range.contains_weekday(WeekdaySet::TUESDAY | WeekdaySet::THURSDAY));
+    }
+
+    #[test]
+    fn interval_durations() {
+        let interval = |s: &str| s.parse::<TimeInterval>().unwrap();
+
+        // (day start, interval, duration as displayed in the listings).
+        let cases = [
+            (DayStart::default(), "07:00-09:30", "2h30"),
+            (DayStart::default(), "07:00-07:45", "45min"),
+            (DayStart::default(), "22:00-02:00", "4h"),
+            // Up to the end of the day (Time::MAX), which is included.
+            (DayStart::default(), "22:00-03:59", "6h"),
+            (DayStart::default(), "04:00-03:59", "24h"),
+            (DayStart::MIDNIGHT, "22:00-23:59", "2h"),
+            (DayStart::MIDNIGHT, "00:00-23:59", "24h"),
+            // Not the end of the day with these day starts: 03:59 is just another minute.
+            (DayStart::MIDNIGHT, "02:00-03:59", "1h59"),
+            (DayStart::default(), "22:00-23:59", "1h59"),
+        ];
+        for (day_start, time_interval, expected) in cases.iter() {
+            let time_interval = interval(time_interval);
+            assert_eq!(day_start.duration(&time_interval).to_string(), *expected,
+                       "{:?} ({:?})", time_interval, day_start);
+            if *day_start == DayStart::default() {
+                assert_eq!(time_interval.duration(), day_start.duration(&time_interval));
+            }
+        }
+
+        assert_eq!(WeekdaySet::empty().len(), 0);
+        assert_eq!("mon-fri".parse::<WeekdaySet>().unwrap().len(), 5);
+        assert_eq!(WeekdaySet::all().len(), 7);
+        assert_eq!((Duration::minutes(150) * 5).to_string(), "12h30");
+    }
+}
@@ -16,25 +16,164 @@ impl TimePeriod {
         self.date_range.contains(&date) && !(date.weekday() & self.days).is_empty()
     }
 
-    pub fn overlaps_dates(&self, other: &TimePeriod) -> bool {
-        if let Some(intersection) = self.date_range.intersection(&other.date_range) {
-            if self.days.is_all() && other.days.is_all() {
-                // Fast path: both repeat every day, no need to check weekdays.
-                true
-            } else {
-                // There must be at least one day included in the intersection and both of the time
-                // periods.
-                let intersect_weekdays = intersection.weekday_set();
+    // Restrict self to the days on which both self and other occur (keeping self's time interval).
+    // Returns None if there is no such day.
+    pub fn restrict_dates(&self, other: &TimePeriod) -> Option<TimePeriod> {
+        let intersection = self.date_range.intersection(&other.date_range)?;
+
+        let days = if self.days.is_all() && other.days.is_all() {
+            // Fast path: both repeat every day, no need to check weekdays.
+            WeekdaySet::all()
+        } else {
+            // There must be at least one day included in the intersection and both of the time
+            // periods.
+            intersection.weekday_set() & self.days & other.days
+        };
+
+        if days.is_empty() {
+            None
+        } else {
+            Some(TimePeriod {
+                time_interval: self.time_interval.clone(),
+                date_range: intersection,
+                days,
+            })
+        }
+    }
+
+    // How long the time period lasts over a week, e.g. 5h for 1h on weekdays. Only the weekdays
+    // that actually occur within the date range are counted.
+    pub fn weekly_duration(&self, day_start: DayStart) -> Duration {
+        let days = self.date_range.weekday_set() & self.days;
+        day_start.duration(&self.time_interval) * days.len()
+    }
 
-                !(intersect_weekdays & self.days & other.days).is_empty()
+    // The time interval, dates and weekdays shared by self and other, if any.
+    pub fn intersection(&self, other: &TimePeriod, day_start: DayStart) -> Option<TimePeriod> {
+        let time_interval = day_start.intersection(&self.time_interval, &other.time_interval)?;
+        let mut intersection = self.restrict_dates(other)?;
+        intersection.time_interval = time_interval;
+        Some(intersection)
+    }
+
+    // Narrow self so that it no longer overlaps other, if the result can be expressed as a single
+    // time period: either by shortening the time interval (if other applies on all the days self
+    // does), or by removing weekdays or dates (if other covers the whole time interval). None if
+    // that is not possible (including if nothing would remain).
+    pub fn narrow_around(&self, other: &TimePeriod, day_start: DayStart) -> Option<TimePeriod> {
+        let mut narrowed = self.clone();
+        let own_days = self.date_range.weekday_set() & self.days;
+        let covers_dates = other.date_range.start <= self.date_range.start &&
+            self.date_range.end <= other.date_range.end;
+        let covers_days = (own_days - other.days).is_empty();
+
+        if covers_dates && covers_days {
+            let remaining = day_start.subtract(&self.time_interval,
+                                               &[other.time_interval.clone()]);
+            if remaining.len() != 1 {
+                return None
             }
+            narrowed.time_interval = remaining[0].clone();
+            return Some(narrowed)
+        }
+
+        let covers_time = day_start.intersection(&self.time_interval, &other.time_interval)
+            .map_or(false, |intersection| intersection == self.time_interval);
+        if !covers_time {
+            return None
+        }
+
+        if covers_dates {
+            narrowed.days = self.days - other.days;
+        } else if covers_days && other.date_range.start <= self.date_range.start {
+            narrowed.date_range.start = other.date_range.end + 1;
+        } else if covers_days && self.date_range.end <= other.date_range.end {
+            narrowed.date_range.end = other.date_range.start - 1;
         } else {
-            false
+            return None
+        }
+
+        // Dates may have been removed, leaving none of the weekdays.
+        if narrowed.date_range.contains_weekday(narrowed.days) { Some(narrowed) } else { None }
+    }
+
+    pub fn overlaps_dates(&self, other: &TimePeriod) -> bool {
+        self.restrict_dates(other).is_some()
+    }
+
+    pub fn overlaps(&self, other: &TimePeriod, day_start: DayStart) -> bool {
+        day_start.overlaps(&self.time_interval, &other.time_interval) && self.overlaps_dates(other)
+    }
+
+    // The time period covering all the possible start and end times with jitter_minutes of jitter
+    // (see TimeSlot::jitter_minutes): both are extended by jitter_minutes, within the day.
+    pub fn with_jitter(&self, jitter_minutes: u32, day_start: DayStart) -> TimePeriod {
+        let mut time_period = self.clone();
+        let (start, day_carry) = day_start.add_minutes(self.time_interval.start,
+                                                       -(jitter_minutes as i32));
+        time_period.time_interval.start = if day_carry < 0 { day_start.first() } else { start };
+        let (end, day_carry) = day_start.add_minutes(self.time_interval.end,
+                                                     jitter_minutes as i32);
+        time_period.time_interval.end = if day_carry > 0 { day_start.last() } else { end };
+        time_period
+    }
+}
+
+// Partial update of a TimePeriod: only the fields that are set are modified.
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+pub struct TimePeriodUpdate {
+    pub start_time: Option<Time>,
+    pub end_time: Option<Time>,
+    pub start_date: Option<Date>,
+    pub end_date: Option<Date>,
+    pub days: Option<WeekdaySet>,
+}
+
+impl TimePeriodUpdate {
+    // Note: the result is not checked for validity.
+    pub fn apply(&self, time_period: &TimePeriod) -> TimePeriod {
+        let mut new_time_period = time_period.clone();
+
+        if let Some(start_time) = self.start_time {
+            new_time_period.time_interval.start = start_time;
+        }
+        if let Some(end_time) = self.end_time {
+            new_time_period.time_interval.end = end_time;
+        }
+        if let Some(start_date) = self.start_date {
+            new_time_period.date_range.start = start_date;
         }
+        if let Some(end_date) = self.end_date {
+            new_time_period.date_range.end = end_date;
+        }
+        if let Some(days) = self.days {
+            new_time_period.days = days;
+        }
+
+        new_time_period
     }
+}
 
-    pub fn overlaps(&self, other: &TimePeriod) -> bool {
-        self.overlaps_dates(other) && self.time_interval.overlaps(&other.time_interval)
+impl TimePeriod {
+    // Same as valid(), but explaining what is invalid. The time interval must not cross the start
+    // of the day as defined by day_start.
+    pub fn check(&self, day_start: DayStart) -> Result<(), String> {
+        let ti = &self.time_interval;
+        if !ti.start.valid() || !ti.end.valid() {
+            Err(format!("invalid time in {} - {}", ti.start, ti.end))
+        } else if ti.start == ti.end {
+            Err(format!("empty time interval {} - {}", ti.start, ti.end))
+        } else if !day_start.valid(ti) {
+            Err(format!("time interval {} - {} crosses the start of the day ({}), split it",
+                        ti.start, ti.end, day_start.first()))
+        } else if !self.date_range.valid() {
+            Err(format!("start date {} after end date {}",
+                        self.date_range.start, self.date_range.end))
+        } else if self.days.is_empty() {
+            Err("no weekday selected".to_string())
+        } else {
+            Ok(())
+        }
     }
 }
 
@@ -46,49 +185,528 @@ impl ValidCheck for TimePeriod {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct TimeSlot {
+    // Free-form name, not necessarily unique.
+    #[serde(default)]
+    pub label: Option<String>,
     pub enabled: bool,
     pub actuator_state: ActuatorState,
     pub time_period: TimePeriod,
     pub time_override: BTreeMap<u32, TimePeriod>,
+    // If not zero, the start and end times are moved by a pseudo-random amount of at most that
+    // many minutes (earlier or later), different every day (e.g. to simulate presence).
+    #[serde(default)]
+    pub jitter_minutes: u32,
+    // Set by the server when the timeslot is added, and every time it is modified. None if unknown
+    // (timeslots saved before they existed).
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+    #[serde(default)]
+    pub modified_at: Option<DateTime>,
+    // If set, the timeslot only applies while the condition holds, see TimeSlotCondition.
+    #[serde(default)]
+    pub condition: Option<TimeSlotCondition>,
+
+    // Override IDs are allocated per timeslot. This is internal state, not exposed via RPC.
+    #[serde(skip)]
+    next_override_id: u32,
 }
 
 impl TimeSlot {
+    // Half a day, so that the start time can always be moved in both directions.
+    pub const MAX_JITTER_MINUTES: u32 = 12 * 60;
+    // In characters.
+    pub const MAX_LABEL_LEN: usize = 64;
+
     pub fn new(enabled: bool, actuator_state: ActuatorState, time_period: TimePeriod) -> TimeSlot {
         TimeSlot {
+            label: None,
             enabled,
             actuator_state,
             time_period,
             time_override: BTreeMap::new(),
+            jitter_minutes: 0,
+            created_at: None,
+            modified_at: None,
+            condition: None,
+            next_override_id: 0,
+        }
+    }
+
+    pub fn summary(&self, id: u32) -> TimeSlotSummary {
+        TimeSlotSummary {
+            id,
+            label: self.label.clone(),
+            enabled: self.enabled,
+            actuator_state: self.actuator_state.clone(),
+            time_period: self.time_period.clone(),
+            override_count: self.time_override.len() as u32,
+            jitter_minutes: self.jitter_minutes,
+            created_at: self.created_at.clone(),
+            modified_at: self.modified_at.clone(),
+            condition: self.condition.clone(),
+        }
+    }
+
+    // Record that the timeslot was modified at now.
+    pub fn touch(&mut self, now: DateTime) {
+        self.modified_at = Some(now);
+    }
+
+    // Note: no validation is performed, see Actuator::time_slot_add_time_override().
+    pub fn add_time_override(&mut self, time_period: TimePeriod) -> u32 {
+        let id = self.next_override_id;
+        self.time_override.insert(id, time_period);
+        self.next_override_id += 1;
+        id
+    }
+
+    // When the timeslot (whose ID is id) next starts at from or later, looking at most max_days
+    // days ahead (including from.date). None if it is disabled.
+    pub fn next_occurrence(&self, id: u32, from: &DateTime, max_days: u32,
+                           day_start: DayStart) -> Option<DateTime> {
+        if !self.enabled {
+            return None
+        }
+
+        for date in DateRange::starting_on(from.date, max_days as i64).iter_days() {
+            if let Some((time_interval, _)) = self.time_interval_on(id, date, day_start) {
+                if date > from.date || !day_start.is_before(time_interval.start, from.time) {
+                    return Some(DateTime { date, time: time_interval.start })
+                }
+            }
         }
+
+        None
     }
 
-    pub fn time_interval_on(&self, date: Date) -> Option<(TimeInterval, Option<u32>)> {
+    // Jitter (if any) is applied to the returned time interval, including an override's. id is the
+    // ID of the timeslot, which the jitter depends on.
+    pub fn time_interval_on(&self, id: u32, date: Date,
+                            day_start: DayStart) -> Option<(TimeInterval, Option<u32>)> {
         if self.time_period.occurs_on(date) {
             for (oid, or) in self.time_override.iter() {
                 if or.occurs_on(date) {
-                    return Some((or.time_interval.clone(), Some(*oid)))
+                    return Some((self.apply_jitter(&or.time_interval, id, date, day_start),
+                                 Some(*oid)))
                 }
             }
 
-            return Some((self.time_period.time_interval.clone(), None))
+            return Some((self.apply_jitter(&self.time_period.time_interval, id, date,
+                                           day_start),
+                         None))
         }
 
         None
     }
 
-    pub fn overlaps(&self, time_period: &TimePeriod) -> bool {
-        if self.time_period.overlaps_dates(&time_period) {
-            if self.time_period.time_interval.overlaps(&time_period.time_interval) {
-                return true
-            }
+    // Move the start and end of time_interval by pseudo-random offsets within [-jitter, +jitter].
+    // The offsets only depend on the date and the ID of the timeslot, so that they are stable
+    // within a day (the schedule, the next timeslot and the actuator thread must all agree, every
+    // time they are computed), and differ between timeslots at the same times. Both are kept within
+    // the day, and the end after the start. An interval lasting until the end of the day keeps
+    // doing so.
+    // The result stays within with_jitter(), which is what overlaps are checked against: jitter
+    // never makes timeslots overlap.
+    fn apply_jitter(&self, time_interval: &TimeInterval, id: u32, date: Date,
+                    day_start: DayStart) -> TimeInterval {
+        if self.jitter_minutes == 0 {
+            return time_interval.clone()
+        }
 
-            for or in self.time_override.values() {
-                if or.overlaps(&time_period) {
-                    return true
-                }
+        let offset = |is_end: bool| {
+            let seed = (date.num_days_from_ce() as u64) << 33 | (id as u64) << 1 | is_end as u64;
+            let range = 2 * self.jitter_minutes as u64 + 1;
+            (hash_u64(seed) % range) as i32 - self.jitter_minutes as i32
+        };
+
+        let (start, day_carry) = day_start.add_minutes(time_interval.start, offset(false));
+        let start = if day_carry < 0 {
+            day_start.first()
+        } else if day_carry > 0 || !day_start.is_before(start, time_interval.end) {
+            day_start.add_minutes(time_interval.end, -1).0
+        } else {
+            start
+        };
+
+        let end = if time_interval.end == day_start.last() {
+            time_interval.end
+        } else {
+            let (end, day_carry) = day_start.add_minutes(time_interval.end, offset(true));
+            if day_carry > 0 {
+                day_start.last()
+            } else if day_carry < 0 || !day_start.is_before(start, end) {
+                day_start.add_minutes(start, 1).0
+            } else {
+                end
             }
+        };
+
+        TimeInterval { start, end }
+    }
+
+    // The time periods during which the timeslot may be active: its own time period, and its time
+    // overrides restricted to the days on which the timeslot occurs (an override never applies on
+    // other days).
+    // Jitter is taken into account, so that a timeslot cannot overlap another on some days only.
+    pub fn effective_time_periods(&self, day_start: DayStart) -> Vec<TimePeriod> {
+        let mut periods = vec![self.time_period.clone()];
+
+        periods.extend(self.time_override.values()
+                       .filter_map(|or| or.restrict_dates(&self.time_period)));
+
+        if self.jitter_minutes != 0 {
+            periods = periods.iter().map(|p| p.with_jitter(self.jitter_minutes, day_start))
+                .collect();
+        }
+        periods
+    }
+
+    // Where the first of the effective time periods of self overlapping time_period intersects it,
+    // if any.
+    pub fn find_overlap(&self, time_period: &TimePeriod,
+                        day_start: DayStart) -> Option<Overlap> {
+        self.effective_time_periods(day_start).into_iter()
+            .filter_map(|p| {
+                Some(Overlap {
+                    intersection: p.intersection(time_period, day_start)?,
+                    time_interval: p.time_interval,
+                })
+            })
+            .next()
+    }
+
+    // Same as find_overlap(), with the first of the effective time periods of other overlapping
+    // self's.
+    pub fn find_overlap_time_slot(&self, other: &TimeSlot,
+                                  day_start: DayStart) -> Option<Overlap> {
+        other.effective_time_periods(day_start).iter()
+            .filter_map(|p| self.find_overlap(p, day_start))
+            .next()
+    }
+
+    pub fn overlaps(&self, time_period: &TimePeriod, day_start: DayStart) -> bool {
+        self.find_overlap(time_period, day_start).is_some()
+    }
+
+    pub fn overlaps_time_slot(&self, other: &TimeSlot, day_start: DayStart) -> bool {
+        self.find_overlap_time_slot(other, day_start).is_some()
+    }
+}
+
+// A timeslot to add, as passed to Actuator::add_time_slot() (or add_time_slots() in bulk).
+// Everything is checked before the timeslot is added, so that adding it is all or nothing.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct NewTimeSlot {
+    pub time_period: TimePeriod,
+    pub actuator_state: ActuatorState,
+    pub enabled: bool,
+    #[serde(default)]
+    pub label: Option<String>,
+    // See TimeSlot::jitter_minutes.
+    #[serde(default)]
+    pub jitter_minutes: u32,
+    // See TimeSlot::condition.
+    #[serde(default)]
+    pub condition: Option<TimeSlotCondition>,
+}
+
+impl NewTimeSlot {
+    pub fn new(time_period: TimePeriod,
+               actuator_state: ActuatorState,
+               enabled: bool) -> NewTimeSlot {
+        NewTimeSlot {
+            time_period,
+            actuator_state,
+            enabled,
+            label: None,
+            jitter_minutes: 0,
+            condition: None,
+        }
+    }
+}
+
+// A timeslot that conflicted with a timeslot added in replace mode (see
+// Actuator::add_time_slot_replace()), with its new time period if it could be narrowed. It was
+// removed otherwise.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ReplacedTimeSlot {
+    pub id: u32,
+    pub old_time_period: TimePeriod,
+    pub new_time_period: Option<TimePeriod>,
+}
+
+// See TimeSlot::find_overlap().
+#[derive(Clone, Debug)]
+pub struct Overlap {
+    // The time interval of the overlapping timeslot (or of its override), including jitter.
+    pub time_interval: TimeInterval,
+    pub intersection: TimePeriod,
+}
+
+// The timeslot only applies if the state last applied to the actuator actuator_id (of the same
+// server) is state, e.g. running a pump only while the boiler is on. Otherwise the default state
+// holds. It is checked when the timeslot starts, and again whenever that actuator's state changes
+// while the timeslot is active (see Actuator::time_slot_set_condition()).
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct TimeSlotCondition {
+    pub actuator_id: u32,
+    pub state: ActuatorState,
+}
+
+// Lightweight version of TimeSlot, without the overrides.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TimeSlotSummary {
+    pub id: u32,
+    pub label: Option<String>,
+    pub enabled: bool,
+    pub actuator_state: ActuatorState,
+    pub time_period: TimePeriod,
+    pub override_count: u32,
+    pub jitter_minutes: u32,
+    pub created_at: Option<DateTime>,
+    pub modified_at: Option<DateTime>,
+    #[serde(default)]
+    pub condition: Option<TimeSlotCondition>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TimeSlotSummaries {
+    // Changes every time the timeslots are modified.
+    pub generation: u64,
+    // None if the timeslots have not been modified since the requested generation.
+    pub summaries: Option<Vec<TimeSlotSummary>>,
+    // The enabled timeslots that never become active over the next SHADOWED_CHECK_DAYS days (see
+    // schedule::find_shadowed()), along with the summaries.
+    #[serde(default)]
+    pub shadowed: Option<Vec<u32>>,
+}
+
+// A timeslot that can be added to any actuator of a matching type, by name (see
+// Server::apply_template()).
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TimeSlotTemplate {
+    pub time_period: TimePeriod,
+    pub actuator_state: ActuatorState,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Dates are DD/MM/YYYY, "-" for unbounded.
+    fn period(time_interval: &str, start: &str, end: &str, days: &str) -> TimePeriod {
+        let date = |s: &str, unbounded: Date| if s == "-" { unbounded } else { s.parse().unwrap() };
+        TimePeriod {
+            time_interval: time_interval.parse().unwrap(),
+            date_range: DateRange { start: date(start, Date::MIN), end: date(end, Date::MAX) },
+            days: days.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn overlaps() {
+        let ds = DayStart::default();
+        let weekdays = period("07:00-08:00", "-", "-", "mon-fri");
+
+        // (other period, expected overlap), each checked both ways.
+        let cases = [
+            // Disjoint weekdays.
+            (period("07:00-08:00", "-", "-", "sat,sun"), false),
+            (period("07:30-09:00", "-", "-", "fri-sun"), true),
+            (period("08:00-09:00", "-", "-", "mon-fri"), false),
+            // Short ranges: 06/01/2018 and 07/01/2018 are a Saturday and a Sunday.
+            (period("07:00-08:00", "06/01/2018", "07/01/2018", "all"), false),
+            (period("07:00-08:00", "06/01/2018", "08/01/2018", "all"), true),
+            (period("07:00-08:00", "01/01/2018", "05/01/2018", "sat"), false),
+        ];
+        for &(ref other, expected) in cases.iter() {
+            assert_eq!(weekdays.overlaps(other, ds), expected, "{:?}", other);
+            assert_eq!(other.overlaps(&weekdays, ds), expected, "{:?}", other);
+        }
+
+        // Adjacent date ranges.
+        let first = period("07:00-08:00", "01/01/2018", "10/01/2018", "all");
+        assert!(!first.overlaps(&period("07:00-08:00", "11/01/2018", "-", "all"), ds));
+        assert!(first.overlaps(&period("07:00-08:00", "10/01/2018", "-", "all"), ds));
+        // A single shared day, which is not one of the weekdays of the other period.
+        assert!(!first.overlaps(&period("07:00-08:00", "10/01/2018", "-", "mon,tue,thu-sun"), ds));
+    }
+
+    #[test]
+    fn restrict_dates() {
+        let weekdays = period("07:00-08:00", "-", "-", "mon-fri");
+
+        let restricted = weekdays.restrict_dates(&period("12:00-13:00", "05/01/2018", "08/01/2018",
+                                                         "all")).unwrap();
+        assert_eq!(restricted.time_interval, weekdays.time_interval);
+        assert_eq!(restricted.date_range,
+                   DateRange { start: "05/01/2018".parse().unwrap(),
+                               end: "08/01/2018".parse().unwrap() });
+        assert_eq!(restricted.days, "mon,fri".parse().unwrap());
+
+        assert!(weekdays.restrict_dates(&period("07:00-08:00", "06/01/2018", "07/01/2018", "all"))
+                .is_none());
+    }
+
+    #[test]
+    fn time_period_update() {
+        let original = period("07:00-08:00", "01/01/2018", "31/12/2018", "mon-fri");
+        let start_time: Time = "06:30".parse().unwrap();
+        let end_time: Time = "09:00".parse().unwrap();
+        let start_date: Date = "01/03/2018".parse().unwrap();
+        let end_date: Date = "30/06/2018".parse().unwrap();
+        let days: WeekdaySet = "sat,sun".parse().unwrap();
+
+        let updated = TimePeriodUpdate::default().apply(&original);
+        assert_eq!(updated.time_interval, original.time_interval);
+        assert_eq!(updated.date_range, original.date_range);
+        assert_eq!(updated.days, original.days);
+
+        // Each field on its own.
+        let updated = TimePeriodUpdate { start_time: Some(start_time), ..Default::default() }
+            .apply(&original);
+        assert_eq!(updated.time_interval, "06:30-08:00".parse().unwrap());
+        assert_eq!(updated.date_range, original.date_range);
+        assert_eq!(updated.days, original.days);
+
+        let updated = TimePeriodUpdate { end_time: Some(end_time), ..Default::default() }
+            .apply(&original);
+        assert_eq!(updated.time_interval, "07:00-09:00".parse().unwrap());
+
+        let updated = TimePeriodUpdate { start_date: Some(start_date), ..Default::default() }
+            .apply(&original);
+        assert_eq!(updated.date_range,
+                   DateRange { start: start_date, end: original.date_range.end });
+        assert_eq!(updated.time_interval, original.time_interval);
+
+        let updated = TimePeriodUpdate { end_date: Some(end_date), ..Default::default() }
+            .apply(&original);
+        assert_eq!(updated.date_range,
+                   DateRange { start: original.date_range.start, end: end_date });
+
+        let updated = TimePeriodUpdate { days: Some(days), ..Default::default() }
+            .apply(&original);
+        assert_eq!(updated.days, days);
+        assert_eq!(updated.date_range, original.date_range);
+
+        // Combinations.
+        let update = TimePeriodUpdate {
+            end_time: Some(end_time),
+            start_date: Some(start_date),
+            days: Some(days),
+            ..Default::default()
+        };
+        let updated = update.apply(&original);
+        assert_eq!(updated.time_interval, "07:00-09:00".parse().unwrap());
+        assert_eq!(updated.date_range,
+                   DateRange { start: start_date, end: original.date_range.end });
+        assert_eq!(updated.days, days);
+
+        let update = TimePeriodUpdate {
+            start_time: Some(start_time),
+            end_time: Some(end_time),
+            start_date: Some(start_date),
+            end_date: Some(end_date),
+            days: Some(days),
+        };
+        let updated = update.apply(&original);
+        assert_eq!(updated.time_interval, "06:30-09:00".parse().unwrap());
+        assert_eq!(updated.date_range, DateRange { start: start_date, end: end_date });
+        assert_eq!(updated.days, days);
+
+        // An empty weekday set is applied as is, and caught by check().
+        let updated = TimePeriodUpdate { days: Some(WeekdaySet::empty()), ..Default::default() }
+            .apply(&original);
+        assert!(updated.check(DayStart::default()).is_err());
+    }
+
+    #[test]
+    fn overrides_only_overlap_on_the_days_of_their_timeslot() {
+        let ds = DayStart::default();
+        let mut time_slot = TimeSlot::new(true, ActuatorState::Toggle(true),
+                                          period("07:00-08:00", "-", "-", "mon-fri"));
+        time_slot.add_time_override(period("12:00-13:00", "-", "-", "all"));
+
+        assert!(!time_slot.overlaps(&period("12:00-13:00", "-", "-", "sat,sun"), ds));
+        assert!(time_slot.overlaps(&period("12:00-13:00", "-", "-", "mon"), ds));
+    }
+
+    #[test]
+    fn time_period_intersection() {
+        let ds = DayStart::default();
+        let weekdays = period("07:00-08:00", "-", "-", "mon-fri");
+
+        // (a, b, expected intersection), each checked both ways.
+        let cases = [
+            (weekdays.clone(), period("07:30-09:00", "01/01/2018", "31/01/2018", "fri-sun"),
+             Some(period("07:30-08:00", "01/01/2018", "31/01/2018", "fri"))),
+            // Only the weekdays that occur in the date range: 06/01/2018 is a Saturday.
+            (weekdays.clone(), period("06:00-07:30", "05/01/2018", "08/01/2018", "all"),
+             Some(period("07:00-07:30", "05/01/2018", "08/01/2018", "mon,fri"))),
+            // Across midnight, and up to the end of the day (Time::MAX).
+            (period("22:00-02:00", "-", "-", "all"), period("01:00-03:59", "-", "-", "all"),
+             Some(period("01:00-02:00", "-", "-", "all"))),
+            (period("20:00-03:59", "-", "-", "all"), period("03:00-03:59", "-", "-", "sat"),
+             Some(period("03:00-03:59", "-", "-", "sat"))),
+            // Adjacent time intervals, and no shared day.
+            (weekdays.clone(), period("08:00-09:00", "-", "-", "all"), None),
+            (period("22:00-03:59", "-", "-", "all"), period("04:00-05:00", "-", "-", "all"), None),
+            (weekdays.clone(), period("07:00-08:00", "06/01/2018", "07/01/2018", "all"), None),
+            (weekdays.clone(), period("07:00-08:00", "-", "-", "sat,sun"), None),
+        ];
+        // TimePeriod is not PartialEq.
+        let fields = |p: Option<TimePeriod>| p.map(|p| (p.time_interval, p.date_range, p.days));
+        for (a, b, expected) in cases.iter() {
+            assert_eq!(fields(a.intersection(b, ds)), fields(expected.clone()),
+                       "{:?} and {:?}", a, b);
+            assert_eq!(fields(b.intersection(a, ds)), fields(expected.clone()),
+                       "{:?} and {:?}", b, a);
+            assert_eq!(a.overlaps(b, ds), expected.is_some(), "{:?} and {:?}", a, b);
         }
+    }
+
+    #[test]
+    fn weekly_duration() {
+        let ds = DayStart::default();
+        let cases = [
+            (period("07:00-08:00", "-", "-", "mon-fri"), "5h"),
+            (period("22:00-03:59", "-", "-", "sat,sun"), "12h"),
+            (period("07:00-07:30", "-", "-", "all"), "3h30"),
+            // Only the weekdays within the date range: 05/01/2018 is a Friday.
+            (period("07:00-08:00", "05/01/2018", "08/01/2018", "mon-fri"), "2h"),
+            (period("07:00-08:00", "06/01/2018", "07/01/2018", "mon-fri"), "0min"),
+        ];
+        for (time_period, expected) in cases.iter() {
+            assert_eq!(time_period.weekly_duration(ds).to_string(), *expected, "{:?}",
+                       time_period);
+        }
+    }
+
+    #[test]
+    fn jitter_depends_on_date_and_id() {
+        let ds = DayStart::default();
+        let jittered = |time_interval: &str| {
+            let mut ts = TimeSlot::new(true, ActuatorState::Toggle(true),
+                                       period(time_interval, "-", "-", "all"));
+            ts.jitter_minutes = 15;
+            ts
+        };
+        let (ts, later) = (jittered("12:00-14:00"), jittered("13:00-15:00"));
+        let dates = DateRange::starting_on("01/01/2018".parse().unwrap(), 60);
+        let offsets = |ts: &TimeSlot, id: u32| -> Vec<(i32, i32)> {
+            dates.iter_days().map(|date| {
+                let (time_interval, _) = ts.time_interval_on(id, date, ds).unwrap();
+                (ds.minutes_until(ts.time_period.time_interval.start, time_interval.start),
+                 ds.minutes_until(ts.time_period.time_interval.end, time_interval.end))
+            }).collect()
+        };
 
-        return false
+        // Not on the times: the same timeslot at other times moves by the same amounts.
+        assert_eq!(offsets(&ts, 3), offsets(&later, 3));
+        // But each timeslot on its own, and differently every day.
+        assert_ne!(offsets(&ts, 3), offsets(&ts, 4));
+        let offsets = offsets(&ts, 3);
+        assert!(offsets.iter().any(|offset| *offset != offsets[0]));
+        assert!(offsets.iter().all(|&(start, end)| start.abs() <= 15 && end.abs() <= 15));
     }
 }
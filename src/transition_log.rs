@@ -0,0 +1,178 @@
+// Plain CSV record of the transitions of an actuator, in a file of its own that external tools can
+// follow, e.g.:
+//   01/05/2018 08:00,18.5,timeslot 2 override 1,2,1,ok
+// with the time, the state, where it comes from (see EventSource), the IDs of the timeslot and
+// override if any, and either "ok", "simulated" or "error: <message>". It is written by the
+// actuator thread as the states are applied, and must never get in its way: failures are reported
+// once, and the lines that could not be written are retried with the next transition.
+//
+// The file can be rotated from outside: it is reopened if it was renamed or removed (and written
+// in append mode, which copytruncate relies on).
+
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+use actuator::ActuatorState;
+use events::EventSource;
+use time::DateTime;
+use utils::csv_field;
+
+// Lines kept while the file cannot be written, the oldest being dropped beyond that.
+const MAX_PENDING_LINES: usize = 100;
+
+pub struct TransitionLog {
+    path: PathBuf,
+    // Closed after a failure, and reopened on the next write.
+    file: Option<File>,
+    // Written in order, once the file can be written again.
+    pending: VecDeque<String>,
+    // Whether the last write failed, so that the failure is only reported once.
+    failing: bool,
+}
+
+impl TransitionLog {
+    // The file is only opened (and created if needed) by the first write.
+    pub fn new(path: PathBuf) -> TransitionLog {
+        TransitionLog {
+            path,
+            file: None,
+            pending: VecDeque::new(),
+            failing: false,
+        }
+    }
+
+    pub fn record(&mut self, now: &DateTime, state: &ActuatorState, source: &EventSource,
+                  result: &io::Result<()>, simulated: bool) {
+        let (timeslot_id, override_id) = match *source {
+            EventSource::TimeSlot { id, override_id } =>
+                (id.to_string(), override_id.map(|id| id.to_string()).unwrap_or_default()),
+            _ => (String::new(), String::new()),
+        };
+        let result = match *result {
+            Err(ref e) => format!("error: {}", e),
+            Ok(()) if simulated => "simulated".to_string(),
+            Ok(()) => "ok".to_string(),
+        };
+
+        if self.pending.len() == MAX_PENDING_LINES {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(format!("{},{},{},{},{},{}\n", now, csv_field(&state.to_string()),
+                                       source, timeslot_id, override_id, csv_field(&result)));
+
+        match self.write_pending() {
+            Ok(()) => if self.failing {
+                info!("Writing to {} again", self.path.display());
+                self.failing = false;
+            },
+            Err(e) => {
+                if !self.failing {
+                    warn!("Failed to write to {}, will retry with the next transition: {}",
+                          self.path.display(), e);
+                    self.failing = true;
+                }
+                self.file = None;
+            },
+        }
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        if !self.file_is_current() {
+            self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        }
+
+        let file = self.file.as_mut().unwrap();
+        while let Some(line) = self.pending.pop_front() {
+            // Each line is written at once (File is not buffered), so that a crash loses nothing
+            // that was written.
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                self.pending.push_front(line);
+                return Err(e)
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the file is open, and still the one at path (it was not rotated by renaming it).
+    fn file_is_current(&self) -> bool {
+        match self.file {
+            Some(ref file) => match (file.metadata(), fs::metadata(&self.path)) {
+                (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+                _ => false,
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // A directory in the temporary directory, removed (with its contents) when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("servoscheduler-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn format_and_rotation() {
+        let dir = TempDir::new("transition-log");
+        let path = dir.0.join("heater.csv");
+        let read = || fs::read_to_string(&path).unwrap();
+        let now: DateTime = "01/05/2018 08:00".parse().unwrap();
+        let error = || Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied, again"));
+        let mut log = TransitionLog::new(path.clone());
+
+        // Created by the first write, fields quoted as needed.
+        log.record(&now, &ActuatorState::FloatValue(18.5),
+                   &EventSource::TimeSlot { id: 2, override_id: Some(1) }, &Ok(()), false);
+        log.record(&now, &ActuatorState::Toggle(true), &EventSource::Manual, &error(), false);
+        log.record(&now, &ActuatorState::Enum("low, quiet".to_string()),
+                   &EventSource::TimeSlot { id: 3, override_id: None }, &Ok(()), true);
+        assert_eq!(read(), "01/05/2018 08:00,18.5,timeslot 2 override 1,2,1,ok\n\
+                            01/05/2018 08:00,On,manual,,,\"error: denied, again\"\n\
+                            01/05/2018 08:00,\"low, quiet\",timeslot 3,3,,simulated\n");
+
+        // Rotated by renaming it: the next line goes to a new file.
+        fs::rename(&path, dir.0.join("heater.csv.1")).unwrap();
+        log.record(&now, &ActuatorState::Toggle(false), &EventSource::Default, &Ok(()), false);
+        assert_eq!(read(), "01/05/2018 08:00,Off,default,,,ok\n");
+        assert_eq!(fs::read_to_string(dir.0.join("heater.csv.1")).unwrap().lines().count(), 3);
+
+        // Rotated by copytruncate: written from the start again.
+        fs::write(&path, "").unwrap();
+        log.record(&now, &ActuatorState::Toggle(true), &EventSource::Vacation, &Ok(()), false);
+        assert_eq!(read(), "01/05/2018 08:00,On,vacation,,,ok\n");
+
+        // Removed along with its directory: nothing can be written, but the lines are kept and
+        // written (in order) once the directory is back.
+        fs::remove_dir_all(&dir.0).unwrap();
+        log.record(&now, &ActuatorState::Toggle(false), &EventSource::Default, &Ok(()), false);
+        assert!(log.failing);
+        fs::create_dir(&dir.0).unwrap();
+        log.record(&now, &ActuatorState::Toggle(true), &EventSource::Default, &Ok(()), false);
+        assert!(!log.failing);
+        assert_eq!(read(), "01/05/2018 08:00,Off,default,,,ok\n\
+                            01/05/2018 08:00,On,default,,,ok\n");
+    }
+}
@@ -0,0 +1,88 @@
+// Actuators reacting to the state changes of other actuators, rather than only to the time, e.g.
+// turning an exhaust fan on for 20 minutes whenever the shower (a sensor-backed Toggle actuator)
+// turns on. The source is watched through its StateNotifier, from a thread per trigger.
+
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actuator::{Actuator, ActuatorHandle, ActuatorState, StateNotifierHandle};
+use events::EventSource;
+
+// The threads check at least this often whether the target still exists, to exit once it has been
+// dropped (see spawn()).
+const MAX_WAIT_SEC: u64 = 60;
+
+// When the source turns on, turn the target on as well for duration_sec, after which it goes back
+// to the state its schedule calls for. Both must be Toggle actuators. As for a manual state, a
+// transition of the target's schedule in the meantime takes over right away. The source turning on
+// again restarts the delay.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Trigger {
+    pub source: u32,
+    pub target: u32,
+    pub duration_sec: u32,
+}
+
+// Start reacting to the source's changes (the state it is in at this point is ignored). The thread
+// does not keep the target alive, and exits some time after it has been dropped.
+pub fn spawn(trigger: Trigger, source: &ActuatorHandle, target: &ActuatorHandle) {
+    let notifier = source.read().unwrap().state_notifier();
+    let name = format!("{} -> {}", source.read().unwrap().info.name,
+                       target.read().unwrap().info.name);
+    let target = Arc::downgrade(target);
+
+    thread::spawn(move || trigger_thread(trigger, name, notifier, target));
+}
+
+fn trigger_thread(trigger: Trigger, name: String, notifier: StateNotifierHandle,
+                  target: Weak<RwLock<Actuator>>) {
+    let mut seq = notifier.current().seq;
+    // When to revert the target to its schedule, if it was turned on.
+    let mut revert_at: Option<Instant> = None;
+
+    loop {
+        let max_wait = Duration::from_secs(MAX_WAIT_SEC);
+        let wait = match revert_at {
+            Some(revert_at) => {
+                let now = Instant::now();
+                if revert_at > now { (revert_at - now).min(max_wait) } else { Duration::new(0, 0) }
+            },
+            None => max_wait,
+        };
+        let current = notifier.wait_change(seq, wait);
+
+        let target_handle = match target.upgrade() {
+            Some(target_handle) => target_handle,
+            None => return,
+        };
+        let actuator = target_handle.read().unwrap();
+
+        if current.seq != seq {
+            seq = current.seq;
+
+            if current.state == Some(ActuatorState::Toggle(true)) {
+                info!("[TR {}] source turned on, turning the target on for {}s", name,
+                      trigger.duration_sec);
+
+                let source = EventSource::Trigger { actuator_id: trigger.source };
+                match actuator.set_state_from(ActuatorState::Toggle(true), source) {
+                    Ok(_) => {
+                        revert_at = Some(Instant::now() +
+                                         Duration::from_secs(trigger.duration_sec as u64));
+                    },
+                    Err(e) => warn!("[TR {}] failed to turn the target on: {}", name, e),
+                }
+                continue;
+            }
+        }
+
+        if let Some(deadline) = revert_at {
+            if Instant::now() >= deadline {
+                info!("[TR {}] delay elapsed, reverting the target to its schedule", name);
+                actuator.recompute_active_timeslot();
+                revert_at = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+// RPC over a Unix domain socket, which tarpc does not support. The framing is our own, but the
+// methods are the same as the tarpc service's (see rpc_methods!).
+//
+// Each message is a 4-byte big-endian length followed by that many bytes of bincode. A request is
+// (method name, serialized arguments as a tuple), the response a Result<Vec<u8>, String> where the
+// Ok payload is the method's serialized Result, and Err a transport-level error (e.g. unknown
+// method).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::result;
+use std::sync::Mutex;
+use std::thread;
+
+use bincode;
+use tarpc;
+
+use actuator::{ActiveOverrideInfo, ActuatorInfo, ActuatorState, CurrentState};
+use follow::Follow;
+use history::HistoryEntry;
+use metadata::Metadata;
+use rpc::{ActuatorConfigSummary, ActuatorDetails, Error, GroupResult, PingReply, ServerInfo,
+          SimulationStatus, SyncClient, SyncService, VacationStatus};
+use schedule::{DefaultSchedule, ResolvedState, ScheduleSlot};
+use time::{Date, DateTime, Duration, Time, TimeInterval, WeekdaySet};
+use time_slot::*;
+use trigger::Trigger;
+
+// Way more than any legitimate message, to avoid allocating arbitrary amounts of memory.
+const MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
+
+fn read_message(stream: &mut Read) -> io::Result<Vec<u8>> {
+    let mut size = [0u8; 4];
+    stream.read_exact(&mut size)?;
+    let size = (size[0] as u32) << 24 | (size[1] as u32) << 16 | (size[2] as u32) << 8 |
+        size[3] as u32;
+
+    if size > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("message too large ({} B)", size)))
+    }
+
+    let mut message = vec![0; size as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn write_message(stream: &mut Write, message: &[u8]) -> io::Result<()> {
+    let size = message.len() as u32;
+    stream.write_all(&[(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8])?;
+    stream.write_all(message)?;
+    stream.flush()
+}
+
+fn to_io_error(error: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+macro_rules! unix_service {
+    ($( $(#[$attr:meta])* rpc $name:ident ( $( $arg:ident : $ty:ty ),* ) -> $ret:ty | $err:ty ; )*) => {
+        // Call method on service, with the arguments serialized in params. Returns the serialized
+        // result.
+        fn dispatch<S: SyncService>(service: &S, method: &str,
+                                    params: &[u8]) -> result::Result<Vec<u8>, String> {
+            $(
+                if method == stringify!($name) {
+                    let ($($arg,)*): ($($ty,)*) = bincode::deserialize(params)
+                        .map_err(|e| format!("invalid arguments for {}: {}", method, e))?;
+                    return bincode::serialize(&service.$name($($arg),*))
+                        .map_err(|e| e.to_string())
+                }
+            )*
+
+            Err(format!("unknown method {}", method))
+        }
+
+        impl UnixClient {
+            $(
+                pub fn $name(&self, $($arg: $ty),*)
+                    -> result::Result<$ret, tarpc::Error<$err>> {
+                    let params = bincode::serialize(&($($arg,)*))
+                        .map_err(|e| tarpc::Error::RequestDeserialize(e.to_string()))?;
+                    let response = self.call(stringify!($name), params)?;
+                    let result: result::Result<$ret, $err> = bincode::deserialize(&response)
+                        .map_err(|e| tarpc::Error::ResponseDeserialize(e.to_string()))?;
+                    result.map_err(tarpc::Error::App)
+                }
+            )*
+        }
+
+        impl Client {
+            $(
+                pub fn $name(&self, $($arg: $ty),*)
+                    -> result::Result<$ret, tarpc::Error<$err>> {
+                    match *self {
+                        Client::Tcp(ref client) => client.$name($($arg),*),
+                        Client::Unix(ref client) => client.$name($($arg),*),
+                    }
+                }
+            )*
+        }
+    }
+}
+
+rpc_methods!(unix_service);
+
+pub struct UnixClient {
+    // Calls are serialized, the same way as with tarpc's SyncClient.
+    stream: Mutex<UnixStream>,
+}
+
+impl UnixClient {
+    pub fn connect(path: &Path) -> io::Result<UnixClient> {
+        Ok(UnixClient {
+            stream: Mutex::new(UnixStream::connect(path)?),
+        })
+    }
+
+    fn call(&self, method: &str, params: Vec<u8>) -> result::Result<Vec<u8>, tarpc::Error<Error>> {
+        let mut stream = self.stream.lock().unwrap();
+
+        let request = bincode::serialize(&(method, params))
+            .map_err(|e| tarpc::Error::RequestDeserialize(e.to_string()))?;
+        write_message(&mut *stream, &request).map_err(tarpc::Error::Io)?;
+
+        let response = read_message(&mut *stream).map_err(tarpc::Error::Io)?;
+        let response: result::Result<Vec<u8>, String> = bincode::deserialize(&response)
+            .map_err(|e| tarpc::Error::ResponseDeserialize(e.to_string()))?;
+        response.map_err(tarpc::Error::RequestDeserialize)
+    }
+}
+
+// A client over either transport.
+pub enum Client {
+    Tcp(SyncClient),
+    Unix(UnixClient),
+}
+
+// Serve service on a Unix socket at path, with permissions mode, from a dedicated thread (plus one
+// per client). A stale socket (left by a server that did not exit cleanly) is removed first.
+pub fn listen<S: SyncService>(service: S, path: &Path, mode: u32) -> io::Result<()> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                      format!("{} exists and is not a socket", path.display())))
+        }
+
+        // Only remove the socket if nobody is listening on it anymore.
+        match UnixStream::connect(path) {
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                               format!("{} is in use", path.display()))),
+            Err(_) => fs::remove_file(path)?,
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let service = service.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_client(stream, &service) {
+                            warn!("Unix socket client failed: {}", e);
+                        }
+                    });
+                },
+                Err(e) => warn!("Failed to accept Unix socket client: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_client<S: SyncService>(mut stream: UnixStream, service: &S) -> io::Result<()> {
+    loop {
+        let request = match read_message(&mut stream) {
+            Ok(request) => request,
+            // The client disconnected.
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let (method, params): (String, Vec<u8>) = bincode::deserialize(&request)
+            .map_err(to_io_error)?;
+        let response = bincode::serialize(&dispatch(service, &method, &params))
+            .map_err(to_io_error)?;
+
+        write_message(&mut stream, &response)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    use config;
+    use rpc::InvalArgError;
+    use rpc_server::RpcServer;
+    use server::Server;
+    use time::DateRange;
+
+    fn service() -> RpcServer {
+        let yaml = "actuators:\n\
+                    - name: heater\n  \
+                      actuator_type: { type: Toggle }\n  \
+                      default_state: { type: Toggle, value: false }\n  \
+                      controller: { type: File, path: /dev/null }\n";
+        let config = config::parse(yaml.as_bytes(), config::ConfigFormat::Yaml).unwrap();
+        RpcServer::new(Server::new(config).unwrap())
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("servoscheduler-{}-{}.sock", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = socket_path("round-trip");
+        listen(service(), &path, 0o600).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let client = Client::Unix(UnixClient::connect(&path).unwrap());
+        assert_eq!(client.list_actuators().unwrap()[0].name, "heater");
+
+        let time_period = TimePeriod {
+            time_interval: "07:00-08:30".parse().unwrap(),
+            date_range: DateRange { start: Date::MIN, end: Date::MAX },
+            days: "mon-fri".parse().unwrap(),
+        };
+        let time_slot = NewTimeSlot { label: Some("morning".to_string()),
+                                      ..NewTimeSlot::new(time_period.clone(),
+                                                         ActuatorState::Toggle(true), true) };
+        let id = client.add_time_slot(0, time_slot.clone()).unwrap();
+        let timeslots = client.list_timeslots(0).unwrap();
+        assert_eq!(timeslots.keys().collect::<Vec<_>>(), vec![&id]);
+        assert_eq!(format!("{:?}", timeslots[&id].time_period), format!("{:?}", time_period));
+        assert_eq!(timeslots[&id].actuator_state, ActuatorState::Toggle(true));
+        assert_eq!(timeslots[&id].label, Some("morning".to_string()));
+
+        // Application errors come back as such.
+        match client.add_time_slot(0, time_slot) {
+            Err(tarpc::Error::App(Error::TimeSlotOverlap { id: overlap_id, .. })) =>
+                assert_eq!(overlap_id, id),
+            result => panic!("{:?}", result),
+        }
+        match client.list_timeslots(7) {
+            Err(tarpc::Error::App(Error::ActuatorNotFound(7))) => (),
+            result => panic!("{:?}", result),
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn stale_socket() {
+        let path = socket_path("stale");
+
+        // Left behind by a server that did not exit cleanly.
+        drop(UnixListener::bind(&path).unwrap());
+        listen(service(), &path, 0o660).unwrap();
+        assert!(UnixClient::connect(&path).unwrap().list_actuators().is_ok());
+
+        // But not while a server is listening on it.
+        assert_eq!(listen(service(), &path, 0o660).err().unwrap().kind(),
+                   io::ErrorKind::AddrInUse);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn non_finite_states() {
+        let yaml = "actuators:\n\
+                    - name: heater\n  \
+                      actuator_type: { type: FloatValue, min: 15, max: 25 }\n  \
+                      clamp: true\n  \
+                      default_state: { type: FloatValue, value: 18 }\n  \
+                      controller: { type: File, path: /dev/null }\n";
+        let config = config::parse(yaml.as_bytes(), config::ConfigFormat::Yaml).unwrap();
+        let path = socket_path("non-finite");
+        listen(RpcServer::new(Server::new(config).unwrap()), &path, 0o600).unwrap();
+        let client = Client::Unix(UnixClient::connect(&path).unwrap());
+
+        let time_period = TimePeriod {
+            time_interval: "07:00-08:30".parse().unwrap(),
+            date_range: DateRange { start: Date::MIN, end: Date::MAX },
+            days: WeekdaySet::all(),
+        };
+        let id = client.add_time_slot(0, NewTimeSlot::new(time_period.clone(),
+                                                          ActuatorState::FloatValue(20.0),
+                                                          true)).unwrap();
+
+        // bincode carries them as is: rejected by the server, even though clamping would turn
+        // infinities into a bound.
+        for &value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY].iter() {
+            let state = ActuatorState::FloatValue(value);
+            let expected = format!("{} is not a finite number", state);
+            let check = |result: result::Result<(), tarpc::Error<Error>>| match result {
+                Err(tarpc::Error::App(Error::InvalidArgument(InvalArgError::ActuatorState,
+                                                             ref detail))) =>
+                    assert_eq!(*detail, expected),
+                result => panic!("{}: {:?}", value, result),
+            };
+
+            check(client.set_state(0, state.clone()).map(|_| ()));
+            check(client.set_default_state(0, state.clone()).map(|_| ()));
+            check(client.set_default_change(0, "12:00".parse().unwrap(), state.clone()));
+            check(client.add_time_slot(0, NewTimeSlot::new(time_period.clone(), state.clone(),
+                                                           true)).map(|_| ()));
+            check(client.time_slot_set_actuator_state(0, id, state.clone()).map(|_| ()));
+            check(client.save_template("t".to_string(), time_period.clone(), state.clone()));
+        }
+        assert_eq!(client.get_default_state(0).unwrap(), ActuatorState::FloatValue(18.0));
+        assert_eq!(client.list_timeslots(0).unwrap()[&id].actuator_state,
+                   ActuatorState::FloatValue(20.0));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn conditions_on_add() {
+        let yaml = "actuators:\n\
+                    - name: boiler\n  \
+                      actuator_type: { type: Toggle }\n  \
+                      default_state: { type: Toggle, value: false }\n  \
+                      controller: { type: File, path: /dev/null }\n\
+                    - name: pump\n  \
+                      actuator_type: { type: Toggle }\n  \
+                      default_state: { type: Toggle, value: false }\n  \
+                      controller: { type: File, path: /dev/null }\n";
+        let config = config::parse(yaml.as_bytes(), config::ConfigFormat::Yaml).unwrap();
+        let path = socket_path("conditions");
+        listen(RpcServer::new(Server::new(config).unwrap()), &path, 0o600).unwrap();
+        let client = Client::Unix(UnixClient::connect(&path).unwrap());
+
+        let time_period = TimePeriod {
+            time_interval: "06:00-08:00".parse().unwrap(),
+            date_range: DateRange { start: Date::MIN, end: Date::MAX },
+            days: WeekdaySet::all(),
+        };
+        let conditional = |actuator_id: u32, state: ActuatorState| NewTimeSlot {
+            condition: Some(TimeSlotCondition { actuator_id, state }),
+            ..NewTimeSlot::new(time_period.clone(), ActuatorState::Toggle(true), true)
+        };
+
+        // Checked along with the rest of the timeslot: nothing is added if it is invalid.
+        let invalid = [
+            (conditional(1, ActuatorState::Toggle(true)), "its own actuator"),
+            (conditional(7, ActuatorState::Toggle(true)), "no actuator with ID 7"),
+            (conditional(0, ActuatorState::IntValue(1)), "does not match type Toggle"),
+        ];
+        for (time_slot, expected) in invalid.iter() {
+            match client.add_time_slot(1, time_slot.clone()) {
+                Err(tarpc::Error::App(Error::InvalidArgument(InvalArgError::Condition,
+                                                             ref detail))) =>
+                    assert!(detail.contains(expected), "{}", detail),
+                result => panic!("{:?}", result),
+            }
+        }
+        assert!(client.list_timeslots(1).unwrap().is_empty());
+
+        let id = client.add_time_slot(1, conditional(0, ActuatorState::Toggle(true))).unwrap();
+        assert_eq!(client.list_timeslots(1).unwrap()[&id].condition,
+                   Some(TimeSlotCondition { actuator_id: 0, state: ActuatorState::Toggle(true) }));
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -49,6 +49,17 @@ impl<T: Ord + Copy> ExclusiveRange<T> {
         self.start < other.end && other.start < self.end
     }
 
+    // Adjacent ranges (e.g. [1, 2) and [2, 3)) do not intersect.
+    pub fn intersection(&self, other: &ExclusiveRange<T>) -> Option<ExclusiveRange<T>> {
+        let start = max(self.start, other.start);
+        let end = min(self.end, other.end);
+        if start < end {
+            Some(ExclusiveRange { start, end })
+        } else {
+            None
+        }
+    }
+
     pub fn contains(&self, elem: &T) -> bool {
         self.start <= *elem && *elem < self.end
     }
@@ -64,3 +75,71 @@ impl<T: ValidCheck + Ord> ValidCheck for ExclusiveRange<T> {
 pub fn bit_range<T: Num + Shl<u32, Output=T>>(start: u32, end: u32) -> T {
     ((T::one() << (end - start + 1)) - T::one()) << start
 }
+
+// Mix the bits of x (SplitMix64's finalizer): deterministic, but consecutive inputs give unrelated
+// outputs.
+pub fn hash_u64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Quote a CSV field if needed, e.g. for free-form names.
+pub fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn excl(start: u32, end: u32) -> ExclusiveRange<u32> {
+        ExclusiveRange { start, end }
+    }
+
+    fn incl(start: u32, end: u32) -> InclusiveRange<u32> {
+        InclusiveRange { start, end }
+    }
+
+    #[test]
+    fn exclusive_intersection() {
+        // (a, b, intersection of a and b), checked both ways round.
+        let cases = [
+            (excl(1, 5), excl(3, 8), Some(excl(3, 5))),
+            (excl(1, 2), excl(2, 3), None),
+            (excl(1, 2), excl(3, 4), None),
+            (excl(1, 9), excl(3, 5), Some(excl(3, 5))),
+            (excl(1, 5), excl(1, 3), Some(excl(1, 3))),
+            (excl(1, 5), excl(3, 5), Some(excl(3, 5))),
+            (excl(1, 5), excl(1, 5), Some(excl(1, 5))),
+        ];
+        for (a, b, expected) in cases.iter() {
+            assert_eq!(a.intersection(b), *expected, "{:?} {:?}", a, b);
+            assert_eq!(b.intersection(a), *expected, "{:?} {:?}", b, a);
+            assert_eq!(a.overlaps(b), expected.is_some(), "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn inclusive_intersection() {
+        let cases = [
+            (incl(1, 5), incl(3, 8), Some(incl(3, 5))),
+            // Unlike exclusive ranges, adjacent ones share their bound.
+            (incl(1, 2), incl(2, 3), Some(incl(2, 2))),
+            (incl(1, 2), incl(3, 4), None),
+            (incl(1, 9), incl(3, 5), Some(incl(3, 5))),
+            (incl(1, 5), incl(1, 3), Some(incl(1, 3))),
+            (incl(1, 5), incl(5, 5), Some(incl(5, 5))),
+        ];
+        for (a, b, expected) in cases.iter() {
+            assert_eq!(a.intersection(b), *expected, "{:?} {:?}", a, b);
+            assert_eq!(b.intersection(a), *expected, "{:?} {:?}", b, a);
+            assert_eq!(a.overlaps(b), expected.is_some(), "{:?} {:?}", a, b);
+        }
+    }
+}